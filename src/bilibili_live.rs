@@ -0,0 +1,204 @@
+//! B站直播来源：房间歌单里的 `live.bilibili.com/<房间号>` 链接投送直播间的 FLV 直播流，
+//! 唱歌的间隙可以放一场演唱会直播
+//!
+//! 代理路径为 `live-<房间号>`。直播没有时长，放到房间切歌为止；直播流经媒体代理原样转发，
+//! 其他 CDN 节点的地址作为备用链接。
+
+use crate::bilibili_parser::{SongInfo, VideoCodec, VideoSegment, VideoStream, api_get, api_json};
+use crate::song_source::SongSource;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::Value;
+
+const SONG_PREFIX: &str = "live-";
+
+// 直播流画质：原画
+const LIVE_QN: u32 = 10000;
+
+/// B站直播间
+pub struct BilibiliLiveSource;
+
+impl SongSource for BilibiliLiveSource {
+    fn name(&self) -> &'static str {
+        "B站直播"
+    }
+
+    fn match_url(&self, url: &str) -> Option<String> {
+        parse_live_url(url).map(|room| format!("{}{}", SONG_PREFIX, room))
+    }
+
+    fn owns(&self, song: &str) -> bool {
+        room_id(song).is_some()
+    }
+
+    fn resolve<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>> {
+        async move {
+            let room = live_room(parse(song)?).await?;
+            let url = format!(
+                "https://api.live.bilibili.com/room/v1/Room/playUrl?cid={}&qn={}&platform=web",
+                room, LIVE_QN
+            );
+            let json = api_json(api_get(&Client::new(), &url), "请求直播流").await?;
+            check_code(&json)?;
+            parse_live_durl(&json["data"])
+        }
+        .boxed()
+    }
+
+    fn info<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>> {
+        async move {
+            let room = parse(song)?;
+            let url = format!(
+                "https://api.live.bilibili.com/room/v1/Room/get_info?room_id={}",
+                room
+            );
+            let json = api_json(api_get(&Client::new(), &url), "请求直播间信息").await?;
+            check_code(&json)?;
+            let title = json["data"]["title"]
+                .as_str()
+                .ok_or_else(|| "无法获取直播间标题".to_string())?;
+            Ok(SongInfo {
+                title: title.to_string(),
+                uploader: anchor_name(room)
+                    .await
+                    .unwrap_or_else(|| format!("直播间{}", room)),
+                duration_secs: 0,
+                cover: json["data"]["user_cover"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+        }
+        .boxed()
+    }
+
+    fn is_live(&self, _song: &str) -> bool {
+        true
+    }
+
+    fn page_url(&self, song: &str) -> Option<String> {
+        Some(format!("https://live.bilibili.com/{}", room_id(song)?))
+    }
+}
+
+fn room_id(song: &str) -> Option<u64> {
+    song.strip_prefix(SONG_PREFIX)?.parse().ok()
+}
+
+fn parse(song: &str) -> Result<u64, String> {
+    room_id(song).ok_or_else(|| format!("不是B站直播间: {}", song))
+}
+
+/// 直播间链接中的房间号，如 `https://live.bilibili.com/21452505?spm_id_from=...`、
+/// `https://live.bilibili.com/h5/21452505`
+fn parse_live_url(url: &str) -> Option<u64> {
+    let start = url.find("live.bilibili.com/")? + "live.bilibili.com/".len();
+    let rest = &url[start..];
+    let rest = rest.strip_prefix("h5/").unwrap_or(rest);
+    rest[..rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len())]
+        .parse()
+        .ok()
+}
+
+fn check_code(json: &Value) -> Result<(), String> {
+    if json["code"].as_i64() != Some(0) {
+        return Err(format!(
+            "API错误: {}",
+            json["message"]
+                .as_str()
+                .or_else(|| json["msg"].as_str())
+                .unwrap_or("未知错误")
+        ));
+    }
+    Ok(())
+}
+
+// 链接里常用的是短号，取流要用真实房间号；未开播时返回错误
+async fn live_room(room: u64) -> Result<u64, String> {
+    let url = format!(
+        "https://api.live.bilibili.com/room/v1/Room/room_init?id={}",
+        room
+    );
+    let json = api_json(api_get(&Client::new(), &url), "请求直播间").await?;
+    check_code(&json)?;
+    if json["data"]["live_status"].as_i64() != Some(1) {
+        return Err(format!("直播间{}未开播", room));
+    }
+    json["data"]["room_id"]
+        .as_u64()
+        .ok_or_else(|| "无法获取直播间房间号".to_string())
+}
+
+// 主播昵称，取不到时为 None
+async fn anchor_name(room: u64) -> Option<String> {
+    let url = format!(
+        "https://api.live.bilibili.com/live_user/v1/UserInfo/get_anchor_in_room?roomid={}",
+        room
+    );
+    let json = api_json(api_get(&Client::new(), &url), "请求主播信息")
+        .await
+        .ok()?;
+    json["data"]["info"]["uname"].as_str().map(str::to_string)
+}
+
+// playUrl 接口的 data：durl 中的各项是不同 CDN 节点上的同一路直播流
+fn parse_live_durl(data: &Value) -> Result<VideoStream, String> {
+    let mut urls = data["durl"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|item| item["url"].as_str().map(str::to_string));
+    let url = urls.next().ok_or_else(|| "无法获取直播流".to_string())?;
+    Ok(VideoStream {
+        // 直播流的 qn 与视频的画质代码不是一套，不报告画质
+        quality: 0,
+        codec: VideoCodec::Unknown,
+        segments: vec![VideoSegment {
+            url,
+            size: 0,
+            length_ms: 0,
+            backup_urls: urls.collect(),
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_live() {
+        assert_eq!(
+            parse_live_url("https://live.bilibili.com/21452505?spm_id_from=333"),
+            Some(21452505)
+        );
+        assert_eq!(parse_live_url("https://live.bilibili.com/h5/6"), Some(6));
+        assert_eq!(parse_live_url("https://live.bilibili.com/"), None);
+        assert_eq!(
+            BilibiliLiveSource.match_url("https://live.bilibili.com/6"),
+            Some("live-6".to_string())
+        );
+        assert!(!BilibiliLiveSource.owns("BV1xx411c7mD"));
+
+        let data = serde_json::json!({
+            "durl": [
+                {"url": "https://cn-gotcha01.bilivideo.com/live/6.flv", "order": 1},
+                {"url": "https://cn-gotcha02.bilivideo.com/live/6.flv", "order": 2}
+            ]
+        });
+        let stream = parse_live_durl(&data).unwrap();
+        assert_eq!(stream.quality, 0);
+        assert_eq!(
+            stream.segments[0].urls(),
+            [
+                "https://cn-gotcha01.bilivideo.com/live/6.flv",
+                "https://cn-gotcha02.bilivideo.com/live/6.flv"
+            ]
+        );
+        assert!(parse_live_durl(&serde_json::json!({"durl": []})).is_err());
+    }
+}
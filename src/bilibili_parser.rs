@@ -1,5 +1,662 @@
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder};
 use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 默认的画质偏好（qn）：1080P60
+pub const DEFAULT_QUALITY: u32 = 116;
+
+// playurl 的画质代码（qn）及显示名称，从高到低
+const QUALITIES: [(u32, &str); 7] = [
+    (120, "4K"),
+    (116, "1080P60"),
+    (112, "1080P+"),
+    (80, "1080P"),
+    (64, "720P"),
+    (32, "480P"),
+    (16, "360P"),
+];
+
+// playurl 接口：WBI 签名的和未签名的
+const PLAYURL_WBI: &str = "https://api.bilibili.com/x/player/wbi/playurl";
+const PLAYURL: &str = "https://api.bilibili.com/x/player/playurl";
+
+// 播放链接在 deadline 之前这么多秒就不再复用，给渲染器留出开始拉流的时间
+const LINK_EXPIRY_MARGIN_SECS: u64 = 60;
+
+// 已解析的播放链接，按（BV号, 分P）缓存到链接的 deadline 为止；
+// 投屏（直链）和媒体代理会先后解析同一首歌，缓存后只请求一次 playurl
+static STREAM_CACHE: LazyLock<Mutex<HashMap<(String, u32), VideoStream>>> =
+    LazyLock::new(Default::default);
+
+// 接口限流：最多连续发出 8 个请求，之后每秒 4 个
+const API_BURST: u32 = 8;
+const API_PER_SEC: f64 = 4.0;
+// 风控（请求过于频繁）时接口返回的 code
+const RISK_CONTROL_CODE: i64 = -412;
+// 遭遇风控后的冷却时间（秒），连续遭遇时翻倍
+const RISK_COOLDOWN_SECS: u64 = 5;
+const MAX_RISK_COOLDOWN_SECS: u64 = 120;
+// 单个请求遭遇风控后最多重试的次数
+const MAX_RISK_RETRIES: u32 = 2;
+// 视频（或番剧）仅限部分地区观看时 playurl 接口返回的 code
+const REGION_LOCKED_CODE: i64 = -10403;
+
+/// 地区受限时取流错误的内容，会话据此跳过歌曲而不是反复重试
+pub const REGION_LOCKED: &str = "该视频地区受限";
+/// 充电专属视频（未给UP主充电只能看试看片段）取流错误的内容，同样会被跳过
+pub const CHARGING_EXCLUSIVE: &str = "需要充电专属，无法播放";
+
+// view 接口取得的视频信息，按BV号缓存
+static VIDEO_INFO_CACHE: LazyLock<Mutex<HashMap<String, VideoInfo>>> =
+    LazyLock::new(Default::default);
+
+// 所有B站接口请求共用的限流器
+static API_LIMITER: LazyLock<RateLimiter> =
+    LazyLock::new(|| RateLimiter::new(API_BURST, API_PER_SEC));
+
+// 当前的画质偏好，启动时由配置设置
+static PREFERRED_QUALITY: AtomicU32 = AtomicU32::new(DEFAULT_QUALITY);
+// 优先使用的 upos CDN 主机，启动时由配置设置；为空时不改写链接
+static CDN_HOSTS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+// 登录 Cookie（`SESSDATA=...; bili_jct=...`），启动时由配置设置；未登录时为 None
+static LOGIN_COOKIE: RwLock<Option<String>> = RwLock::new(None);
+
+/// B站登录 Cookie：登录后可以获取 1080P 等高画质和仅限会员的视频
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BiliCookies {
+    pub sessdata: Option<String>,
+    pub bili_jct: Option<String>,
+}
+
+impl BiliCookies {
+    /// 从浏览器导出的 cookies.txt（Netscape 格式）中读取 bilibili.com 的 `SESSDATA` 和 `bili_jct`
+    pub fn from_cookie_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("读取Cookie文件{}失败: {}", path.display(), e))?;
+        Ok(Self::parse_cookie_file(&text))
+    }
+
+    fn parse_cookie_file(text: &str) -> Self {
+        let mut cookies = Self::default();
+        for line in text.lines() {
+            // HttpOnly 的 Cookie 以 `#HttpOnly_` 开头，其余 # 开头的是注释
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, _, _, _, _, name, value] = fields[..] else {
+                continue;
+            };
+            if !domain.trim_start_matches('.').ends_with("bilibili.com") {
+                continue;
+            }
+            match name {
+                "SESSDATA" => cookies.sessdata = Some(value.trim().to_string()),
+                "bili_jct" => cookies.bili_jct = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+        cookies
+    }
+
+    /// 缺少的项用 `other` 中的补上
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            sessdata: self.sessdata.or(other.sessdata),
+            bili_jct: self.bili_jct.or(other.bili_jct),
+        }
+    }
+
+    // Cookie 请求头的值；没有 SESSDATA 时不算登录
+    fn header(&self) -> Option<String> {
+        let sessdata = self.sessdata.as_deref().filter(|s| !s.is_empty())?;
+        let mut header = format!("SESSDATA={}", sessdata);
+        if let Some(bili_jct) = self.bili_jct.as_deref().filter(|s| !s.is_empty()) {
+            header.push_str(&format!("; bili_jct={}", bili_jct));
+        }
+        Some(header)
+    }
+}
+
+/// 设置登录 Cookie，启动时调用一次；之后对B站接口的请求都会带上
+pub fn set_cookies(cookies: &BiliCookies) {
+    *LOGIN_COOKIE.write().unwrap() = cookies.header();
+}
+
+// WBI 签名的混合密钥表：img_key + sub_key 按此顺序重排后取前 32 位
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+
+// 缓存的 WBI 混合密钥及其获取日期；B站每天更换密钥，跨天后重新获取
+static WBI_KEY: Mutex<Option<(String, chrono::NaiveDate)>> = Mutex::new(None);
+
+// 由 img_key 和 sub_key 得到签名用的混合密钥
+fn mixin_key(img_key: &str, sub_key: &str) -> String {
+    let raw: Vec<char> = format!("{}{}", img_key, sub_key).chars().collect();
+    MIXIN_KEY_ENC_TAB
+        .iter()
+        .filter_map(|&i| raw.get(i))
+        .take(32)
+        .collect()
+}
+
+// 查询参数按原顺序编码（encodeURIComponent 的规则）
+fn encode_query(params: &[(&str, String)]) -> String {
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// WBI 签名：加上时间戳 wts，按参数名排序、去掉值中的 `!'()*` 后编码，
+// 再把 md5(查询串 + 混合密钥) 作为 w_rid 附在最后
+fn wbi_sign(params: &[(&str, String)], mixin_key: &str, wts: u64) -> String {
+    let mut params: Vec<(&str, String)> = params
+        .iter()
+        .map(|(key, value)| {
+            let value = value.chars().filter(|c| !"!'()*".contains(*c)).collect();
+            (*key, value)
+        })
+        .collect();
+    params.push(("wts", wts.to_string()));
+    params.sort_by(|a, b| a.0.cmp(b.0));
+    let query = encode_query(&params);
+    let w_rid = md5::compute(format!("{}{}", query, mixin_key));
+    format!("{}&w_rid={:x}", query, w_rid)
+}
+
+// 今天的 WBI 混合密钥，缓存过期时从 nav 接口的 wbi_img 重新获取
+async fn wbi_mixin_key(client: &Client) -> Result<String, String> {
+    let today = chrono::Local::now().date_naive();
+    if let Some((key, day)) = WBI_KEY.lock().unwrap().as_ref()
+        && *day == today
+    {
+        return Ok(key.clone());
+    }
+    // 未登录时 nav 接口返回 code -101，但 wbi_img 照常给出
+    let json: Value = api_json(
+        api_get(client, "https://api.bilibili.com/x/web-interface/nav"),
+        "获取WBI密钥",
+    )
+    .await?;
+    // 密钥为图片地址的文件名（不含扩展名）
+    let file_stem = |field: &str| {
+        json["data"]["wbi_img"][field]
+            .as_str()
+            .and_then(|url| url.rsplit('/').next())
+            .and_then(|name| name.split('.').next())
+            .filter(|stem| !stem.is_empty())
+            .map(str::to_string)
+    };
+    let (Some(img_key), Some(sub_key)) = (file_stem("img_url"), file_stem("sub_url")) else {
+        return Err("nav 接口没有返回WBI密钥".to_string());
+    };
+    let key = mixin_key(&img_key, &sub_key);
+    log::debug!("已更新WBI密钥");
+    *WBI_KEY.lock().unwrap() = Some((key.clone(), today));
+    Ok(key)
+}
+
+// 接口地址：能拿到 WBI 密钥时使用签名的 `signed` 接口，否则退回未签名的 `unsigned` 接口
+async fn wbi_url(
+    client: &Client,
+    signed: &str,
+    unsigned: &str,
+    params: &[(&str, String)],
+) -> String {
+    match wbi_mixin_key(client).await {
+        Ok(key) => {
+            format!("{}?{}", signed, wbi_sign(params, &key, unix_now()))
+        }
+        Err(e) => {
+            log::warn!("{}，使用未签名的接口", e);
+            format!("{}?{}", unsigned, encode_query(params))
+        }
+    }
+}
+
+// 短时间内多次遭遇风控时，冷却时间按 5、10、20…秒增长，最长 2 分钟
+fn risk_cooldown(strikes: u32) -> Duration {
+    let base_ms =
+        (RISK_COOLDOWN_SECS << strikes.saturating_sub(1).min(5)).min(MAX_RISK_COOLDOWN_SECS) * 1000;
+    // 再随机延长最多一半，避免冷却结束时积压的请求同时发出
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    Duration::from_millis(base_ms + seed % (base_ms / 2 + 1))
+}
+
+/// B站接口请求的限流器（令牌桶）
+///
+/// 桶里最多攒 `burst` 个令牌，每秒补充 `per_sec` 个，每个请求消耗一个；
+/// 遭遇风控后进入冷却，冷却结束前所有请求都等待。
+struct RateLimiter {
+    burst: f64,
+    per_sec: f64,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    tokens: f64,
+    refilled_at: Instant,
+    cooldown_until: Option<Instant>,
+    // 连续遭遇风控的次数，请求成功后清零
+    strikes: u32,
+}
+
+impl RateLimiter {
+    fn new(burst: u32, per_sec: f64) -> Self {
+        Self {
+            burst: burst as f64,
+            per_sec,
+            state: Mutex::new(LimiterState {
+                tokens: burst as f64,
+                refilled_at: Instant::now(),
+                cooldown_until: None,
+                strikes: 0,
+            }),
+        }
+    }
+
+    // 取一个令牌；不需要等待时返回 None，否则返回需要等待的时间
+    fn try_acquire(&self, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(until) = state.cooldown_until.filter(|until| *until > now) {
+            return Some(until - now);
+        }
+        let elapsed = now.saturating_duration_since(state.refilled_at);
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * self.per_sec).min(self.burst);
+        state.refilled_at = now;
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - state.tokens) / self.per_sec))
+        }
+    }
+
+    async fn acquire(&self) {
+        while let Some(wait) = self.try_acquire(Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // 遭遇风控：清空令牌并进入冷却，返回冷却时长
+    fn cool_down(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        state.strikes += 1;
+        let cooldown = risk_cooldown(state.strikes);
+        state.tokens = 0.0;
+        state.refilled_at = now + cooldown;
+        state.cooldown_until = Some(now + cooldown);
+        cooldown
+    }
+
+    fn succeeded(&self) {
+        self.state.lock().unwrap().strikes = 0;
+    }
+}
+
+// 经限流发出B站接口请求并解析 JSON；遭遇风控（HTTP 412 或 code -412）时冷却后重试，
+// 冷却期间其他请求一并等待。`what` 为错误信息中的操作名称，如“请求分P列表”
+pub(crate) async fn api_json(request: RequestBuilder, what: &str) -> Result<Value, String> {
+    let mut retries = 0;
+    loop {
+        let attempt = request
+            .try_clone()
+            .ok_or_else(|| format!("{}失败: 请求无法重发", what))?;
+        API_LIMITER.acquire().await;
+        let response = attempt
+            .send()
+            .await
+            .map_err(|e| format!("{}失败: {}", what, e))?;
+        let throttled = response.status() == reqwest::StatusCode::PRECONDITION_FAILED;
+        let json = if throttled {
+            Value::Null
+        } else {
+            response
+                .json::<Value>()
+                .await
+                .map_err(|e| format!("解析JSON失败: {}", e))?
+        };
+        if !throttled && json["code"].as_i64() != Some(RISK_CONTROL_CODE) {
+            API_LIMITER.succeeded();
+            return Ok(json);
+        }
+        if retries >= MAX_RISK_RETRIES {
+            return Err(format!("{}失败: 触发了B站风控（-412），请稍后再试", what));
+        }
+        retries += 1;
+        let cooldown = API_LIMITER.cool_down(Instant::now());
+        log::warn!("{}触发了B站风控（-412），冷却{:?}后重试", what, cooldown);
+    }
+}
+
+// 带上浏览器 User-Agent 和登录 Cookie（已设置时）的 GET 请求
+pub(crate) fn api_get(client: &Client, url: &str) -> RequestBuilder {
+    let request = client.get(url).header("User-Agent", "Mozilla/5.0");
+    match LOGIN_COOKIE.read().unwrap().as_deref() {
+        Some(cookie) => request.header("Cookie", cookie),
+        None => request,
+    }
+}
+
+/// 登录的B站账号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BiliAccount {
+    pub name: String,
+    /// 是否为大会员
+    pub vip: bool,
+}
+
+/// 用当前的登录 Cookie 查询登录状态；未登录或 Cookie 已失效时返回 `None`
+pub async fn login_status() -> Result<Option<BiliAccount>, String> {
+    let json: Value = api_json(
+        api_get(
+            &Client::new(),
+            "https://api.bilibili.com/x/web-interface/nav",
+        ),
+        "查询登录状态",
+    )
+    .await?;
+    Ok(parse_account(&json["data"]))
+}
+
+// nav 接口的 data；未登录时接口返回 code -101，data.isLogin 为 false
+fn parse_account(data: &Value) -> Option<BiliAccount> {
+    if !data["isLogin"].as_bool().unwrap_or(false) {
+        return None;
+    }
+    Some(BiliAccount {
+        name: data["uname"].as_str().unwrap_or("").to_string(),
+        vip: data["vipStatus"].as_i64() == Some(1),
+    })
+}
+
+/// 解析画质偏好：名称（如 `1080p`、`720P`，不区分大小写）或 playurl 的 qn 代码（如 `80`）
+pub fn parse_quality(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if let Ok(qn) = text.parse() {
+        return Some(qn);
+    }
+    QUALITIES
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(text))
+        .map(|&(qn, _)| qn)
+}
+
+/// 画质代码的显示名称，例如 80 为 `1080P`；不认识的代码显示为 `qn=<代码>`
+pub fn quality_name(qn: u32) -> String {
+    match QUALITIES.iter().find(|&&(code, _)| code == qn) {
+        Some((_, name)) => name.to_string(),
+        None => format!("qn={}", qn),
+    }
+}
+
+/// 设置画质偏好，启动时调用一次；之后获取的视频链接取不高于该画质的最高可用画质
+pub fn set_preferred_quality(qn: u32) {
+    PREFERRED_QUALITY.store(qn, Ordering::Relaxed);
+}
+
+/// 当前的画质偏好
+pub fn preferred_quality() -> u32 {
+    PREFERRED_QUALITY.load(Ordering::Relaxed)
+}
+
+/// 在视频提供的画质（`accept_quality`）中选择：不高于偏好的最高画质，
+/// 都比偏好高时取最低的；没有可选画质时返回 `None`
+fn choose_quality(accept: &[u32], preferred: u32) -> Option<u32> {
+    accept
+        .iter()
+        .copied()
+        .filter(|&qn| qn <= preferred)
+        .max()
+        .or_else(|| accept.iter().copied().min())
+}
+
+/// 视频的一个分段（playurl 返回的 `durl` 数组中的一项）
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoSegment {
+    pub url: String,
+    /// 分段大小（字节），接口未返回时为 0
+    pub size: u64,
+    /// 分段时长（毫秒），接口未返回时为 0
+    pub length_ms: u64,
+    /// 其他 CDN 上的备用链接（`backup_url`），主链接不可用时依次尝试
+    pub backup_urls: Vec<String>,
+}
+
+impl VideoSegment {
+    /// 主链接和备用链接，按尝试顺序排列
+    pub fn urls(&self) -> Vec<String> {
+        std::iter::once(self.url.clone())
+            .chain(self.backup_urls.iter().cloned())
+            .collect()
+    }
+}
+
+/// 视频编码，来自 playurl 的 `video_codecid`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VideoCodec {
+    /// 接口没有给出（或不是视频）
+    #[default]
+    Unknown,
+    /// H.264
+    Avc,
+    /// H.265
+    Hevc,
+    Av1,
+}
+
+impl VideoCodec {
+    fn from_codecid(id: u64) -> Self {
+        match id {
+            7 => VideoCodec::Avc,
+            12 => VideoCodec::Hevc,
+            13 => VideoCodec::Av1,
+            _ => VideoCodec::Unknown,
+        }
+    }
+}
+
+/// 视频的播放链接及实际得到的画质
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoStream {
+    /// 画质代码（qn），见 [`quality_name`]；没有画质的来源（如纯音频）为 0
+    pub quality: u32,
+    /// 视频编码
+    pub codec: VideoCodec,
+    /// 至少包含一个分段
+    pub segments: Vec<VideoSegment>,
+}
+
+impl VideoStream {
+    /// 链接的过期时间（Unix 秒）：各分段链接中 `deadline` 参数的最小值，没有该参数时为 None
+    pub fn deadline(&self) -> Option<u64> {
+        self.segments
+            .iter()
+            .filter_map(|segment| link_deadline(&segment.url))
+            .min()
+    }
+
+    // 在 `now`（Unix 秒）时是否还能复用；不知道过期时间的链接不复用
+    fn is_fresh(&self, now: u64) -> bool {
+        self.deadline()
+            .is_some_and(|deadline| deadline > now + LINK_EXPIRY_MARGIN_SECS)
+    }
+}
+
+/// 设置优先使用的 CDN 主机，如 `upos-sz-mirrorcos.bilivideo.com`（可省略 `.bilivideo.com`）
+///
+/// 解析出的 upos 链接会改写到这些主机，按顺序排在原来的链接之前；
+/// 一些家用路由器会限速特定的B站 CDN，改用其他节点是常见的解决办法。
+pub fn set_cdn_hosts(hosts: &[String]) {
+    *CDN_HOSTS.write().unwrap() = hosts
+        .iter()
+        .map(|host| host.trim())
+        .filter(|host| !host.is_empty())
+        .map(|host| {
+            if host.contains('.') {
+                host.to_string()
+            } else {
+                format!("{}.bilivideo.com", host)
+            }
+        })
+        .collect();
+}
+
+// 把 upos CDN 链接的主机换成 `host`；其他链接（如 PCDN 节点）路径格式不同，返回 None
+fn rewrite_cdn_host(url: &str, host: &str) -> Option<String> {
+    let mut parsed = url::Url::parse(url).ok()?;
+    if !parsed.host_str()?.starts_with("upos-") {
+        return None;
+    }
+    parsed.set_host(Some(host)).ok()?;
+    Some(parsed.into())
+}
+
+// 按 CDN 偏好重排分段的链接：改写到各偏好主机的链接在前，原来的主链接和备用链接在后
+fn prefer_cdn(segment: &mut VideoSegment, hosts: &[String]) {
+    if hosts.is_empty() {
+        return;
+    }
+    let original = segment.urls();
+    let mut urls: Vec<String> = hosts
+        .iter()
+        .filter_map(|host| original.iter().find_map(|url| rewrite_cdn_host(url, host)))
+        .collect();
+    for url in original {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    segment.url = urls.remove(0);
+    segment.backup_urls = urls;
+}
+
+// 播放链接的 `deadline` 查询参数
+fn link_deadline(url: &str) -> Option<u64> {
+    url::Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "deadline")?
+        .1
+        .parse()
+        .ok()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// 歌曲的显示信息，来自视频的 view 接口
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongInfo {
+    /// 视频标题；多P视频取该分P的名称
+    pub title: String,
+    /// UP主昵称
+    pub uploader: String,
+    /// 该分P的时长（秒）
+    pub duration_secs: u32,
+    /// 封面图片地址，未知时为空
+    pub cover: String,
+}
+
+/// 视频的基本信息，来自视频的 view 接口
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoInfo {
+    pub title: String,
+    /// 封面图片地址（`pic`）
+    pub cover: String,
+    /// 整个视频的时长（秒）
+    pub duration_secs: u32,
+    /// UP主昵称
+    pub owner: String,
+    /// 各分P的名称和时长（秒）
+    pub pages: Vec<(String, u32)>,
+    /// 充电专属且当前账号不能完整观看，playurl 只会给出试看片段
+    pub charging_exclusive: bool,
+}
+
+impl VideoInfo {
+    /// 第 `page` 个分P（从 0 开始）的显示信息
+    pub fn song_info(&self, page: u32) -> SongInfo {
+        let part = self.pages.get(page as usize);
+        // 单P视频的分P名称通常只是重复标题或文件名，只有多P时才用分P名称
+        let title = match part {
+            Some((name, _)) if self.pages.len() > 1 && !name.is_empty() => name,
+            _ => &self.title,
+        };
+        SongInfo {
+            title: title.clone(),
+            uploader: self.owner.clone(),
+            duration_secs: part.map_or(self.duration_secs, |(_, duration)| *duration),
+            cover: self.cover.clone(),
+        }
+    }
+}
+
+impl SongInfo {
+    /// 界面显示的名称，例如 `《想見你想見你想見你》- 八三夭`
+    pub fn display_name(&self) -> String {
+        format!("《{}》- {}", self.title, self.uploader)
+    }
+}
+
+/// 搜索到的一个视频
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub bv_id: String,
+    /// 标题（已去掉关键词高亮）、UP主和时长
+    pub info: SongInfo,
+}
+
+/// 把代理路径（如 `BV1xx411c7mD-page2`）拆成 BV 号和从 0 开始的分P
+pub fn parse_song(song: &str) -> (&str, Option<u32>) {
+    match song.split_once("-page") {
+        Some((bv_id, page)) => (bv_id, page.parse().ok()),
+        None => (song, None),
+    }
+}
+
+/// BV 号形如 `BV1xx411c7mD`：`BV` 加 10 位字母数字；番剧剧集形如 `ep123456`
+pub fn is_valid_bv(id: &str) -> bool {
+    (id.len() == 12 && id.starts_with("BV") && id.chars().all(|c| c.is_ascii_alphanumeric()))
+        || episode_id(id).is_some()
+}
+
+/// 番剧（PGC）剧集的代理路径形如 `ep123456`，返回剧集 ID；普通视频返回 None
+pub fn episode_id(song: &str) -> Option<u64> {
+    song.strip_prefix("ep")?.parse().ok()
+}
+
+/// 代理路径对应的B站视频页面链接，用于分享
+///
+/// 代理路径中的分P从 0 开始，网页的 `?p=` 从 1 开始。
+pub fn video_page_url(song: &str) -> String {
+    if let Some(ep_id) = episode_id(song) {
+        return format!("https://www.bilibili.com/bangumi/play/ep{}", ep_id);
+    }
+    let (bv_id, page) = parse_song(song);
+    match page {
+        Some(page) if page > 0 => {
+            format!("https://www.bilibili.com/video/{}?p={}", bv_id, page + 1)
+        }
+        _ => format!("https://www.bilibili.com/video/{}", bv_id),
+    }
+}
 
 /// 获取BiliBili视频直链
 ///
@@ -10,31 +667,416 @@ use serde_json::Value;
 /// # Returns
 /// * `Result<String, String>` - 返回直链URL或错误信息
 pub async fn get_bilibili_direct_link(bv_id: &str, page: Option<u32>) -> Result<String, String> {
+    let segments = get_bilibili_segments(bv_id, page).await?;
+    Ok(segments[0].url.clone())
+}
+
+/// 获取BiliBili视频的全部分段
+///
+/// 大多数视频只有一个分段；一些较老的视频会被切成多段，需要按顺序播放全部分段。
+/// 返回的列表至少包含一个分段。
+pub async fn get_bilibili_segments(
+    bv_id: &str,
+    page: Option<u32>,
+) -> Result<Vec<VideoSegment>, String> {
+    Ok(get_bilibili_stream(bv_id, page).await?.segments)
+}
+
+/// 获取BiliBili视频的全部分段及实际画质
+///
+/// 按 [`preferred_quality`] 选择画质：偏好的画质不可用时依次降级（如 1080P → 720P → 480P）。
+/// `bv_id` 为番剧剧集（`ep123456`）时改用 PGC 的 playurl 接口。
+/// 解析结果缓存到链接过期（`deadline` 参数）之前，过期后重新解析。
+pub async fn get_bilibili_stream(bv_id: &str, page: Option<u32>) -> Result<VideoStream, String> {
+    let key = (bv_id.to_string(), page.unwrap_or(0));
+    let now = unix_now();
+    {
+        let mut cache = STREAM_CACHE.lock().unwrap();
+        cache.retain(|_, stream| stream.is_fresh(now));
+        if let Some(stream) = cache.get(&key) {
+            log::debug!("复用未过期的播放链接: {} P{}", key.0, key.1);
+            return Ok(stream.clone());
+        }
+    }
+    let mut stream = resolve_stream(bv_id, page).await?;
+    let hosts = CDN_HOSTS.read().unwrap().clone();
+    for segment in &mut stream.segments {
+        prefer_cdn(segment, &hosts);
+    }
+    if stream.is_fresh(now) {
+        STREAM_CACHE.lock().unwrap().insert(key, stream.clone());
+    }
+    Ok(stream)
+}
+
+// 向 playurl 接口解析播放链接，不经过缓存
+async fn resolve_stream(bv_id: &str, page: Option<u32>) -> Result<VideoStream, String> {
     let client = Client::new();
+    if let Some(ep_id) = episode_id(bv_id) {
+        return select_quality(|qn| {
+            request_pgc_playurl(&client, ep_id, format!("qn={}&fnval=0&fourk=1", qn))
+        })
+        .await;
+    }
     let page = page.unwrap_or(0);
 
+    // 充电专属视频的 playurl 只有一分钟左右的试看，投上去没有意义；
+    // view 接口的结果有缓存，取不到时照常解析
+    if get_video_info(bv_id)
+        .await
+        .is_ok_and(|info| info.charging_exclusive)
+    {
+        return Err(CHARGING_EXCLUSIVE.to_string());
+    }
+
     // 第一步：获取CID
     let cid = get_video_cid(&client, bv_id, page).await?;
 
     // 第二步：获取视频直链
-    get_video_url(&client, bv_id, &cid).await
+    get_video_stream(&client, bv_id, &cid).await
 }
 
-/// 获取视频的CID（分集ID）
-async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String, String> {
-    let url = format!("https://api.bilibili.com/x/player/pagelist?bvid={}", bv_id);
+/// 获取视频的纯音频流地址（DASH 音轨，m4a），用于把声音单独投到音箱
+///
+/// 有多条音轨时选择码率最高的一条。
+pub async fn get_bilibili_audio_url(bv_id: &str, page: Option<u32>) -> Result<String, String> {
+    let client = Client::new();
+    if let Some(ep_id) = episode_id(bv_id) {
+        let json = request_pgc_playurl(&client, ep_id, "fnval=16".to_string()).await?;
+        return parse_dash_audio(&json);
+    }
+    let cid = get_video_cid(&client, bv_id, page.unwrap_or(0)).await?;
+
+    // fnval=16 请求 DASH 格式，音视频分离
+    let params = [
+        ("bvid", bv_id.to_string()),
+        ("cid", cid),
+        ("fnval", "16".to_string()),
+        ("fnver", "0".to_string()),
+        ("fourk", "0".to_string()),
+    ];
+    let url = wbi_url(&client, PLAYURL_WBI, PLAYURL, &params).await;
+    let json: Value = api_json(api_get(&client, &url), "请求音频链接").await?;
+    check_playurl(&json)?;
+
+    parse_dash_audio(&json)
+}
+
+/// 获取歌曲的标题、UP主、时长和封面
+pub async fn get_song_info(bv_id: &str, page: Option<u32>) -> Result<SongInfo, String> {
+    if let Some(ep_id) = episode_id(bv_id) {
+        return get_episode_info(ep_id).await;
+    }
+    Ok(get_video_info(bv_id).await?.song_info(page.unwrap_or(0)))
+}
+
+/// 获取视频的标题、封面、时长、UP主和分P列表
+///
+/// 结果按BV号缓存：会话显示歌曲信息和媒体代理预填时长都会用到同一个视频的信息。
+pub async fn get_video_info(bv_id: &str) -> Result<VideoInfo, String> {
+    if let Some(info) = VIDEO_INFO_CACHE.lock().unwrap().get(bv_id) {
+        return Ok(info.clone());
+    }
+    let url = format!(
+        "https://api.bilibili.com/x/web-interface/view?bvid={}",
+        bv_id
+    );
+    let json: Value = api_json(api_get(&Client::new(), &url), "请求视频信息").await?;
+    if json["code"].as_i64() != Some(0) {
+        return Err(format!(
+            "API错误: {}",
+            json["message"].as_str().unwrap_or("未知错误")
+        ));
+    }
+    let info = parse_video_info(&json["data"])?;
+    VIDEO_INFO_CACHE
+        .lock()
+        .unwrap()
+        .insert(bv_id.to_string(), info.clone());
+    Ok(info)
+}
+
+/// 按关键词搜索B站视频，返回第一页结果
+///
+/// 搜索接口要求带 `buvid3` Cookie，先从 spi 接口取一个；取不到时不带 Cookie 直接搜索。
+pub async fn search_videos(keyword: &str) -> Result<Vec<SearchResult>, String> {
+    let client = Client::new();
+    let spi = client
+        .get("https://api.bilibili.com/x/frontend/finger/spi")
+        .header("User-Agent", "Mozilla/5.0");
+    let buvid3 = match api_json(spi, "获取 buvid3").await {
+        Ok(json) => json["data"]["b_3"].as_str().map(str::to_string),
+        Err(e) => {
+            log::warn!("获取 buvid3 失败，不带 Cookie 搜索: {}", e);
+            None
+        }
+    };
 
-    let response = client
+    let params = [
+        ("search_type", "video".to_string()),
+        ("keyword", keyword.to_string()),
+    ];
+    let url = wbi_url(
+        &client,
+        "https://api.bilibili.com/x/web-interface/wbi/search/type",
+        "https://api.bilibili.com/x/web-interface/search/type",
+        &params,
+    )
+    .await;
+    let mut request = client
         .get(&url)
         .header("User-Agent", "Mozilla/5.0")
+        .header("Referer", "https://search.bilibili.com/");
+    let login = LOGIN_COOKIE.read().unwrap().clone();
+    let cookie = match (login, buvid3) {
+        (Some(login), Some(buvid3)) => Some(format!("{}; buvid3={}", login, buvid3)),
+        (login, buvid3) => login.or(buvid3.map(|buvid3| format!("buvid3={}", buvid3))),
+    };
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    let json = api_json(request, "搜索").await?;
+    if json["code"].as_i64() != Some(0) {
+        return Err(format!(
+            "API错误: {}",
+            json["message"].as_str().unwrap_or("未知错误")
+        ));
+    }
+    Ok(parse_search_results(&json["data"]))
+}
+
+/// 从搜索接口的 `data` 中取出视频列表；缺少 BV 号的条目会被跳过
+fn parse_search_results(data: &Value) -> Vec<SearchResult> {
+    data["result"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|item| {
+            let bv_id = item["bvid"].as_str().filter(|id| !id.is_empty())?;
+            Some(SearchResult {
+                bv_id: bv_id.to_string(),
+                info: SongInfo {
+                    title: strip_highlight(item["title"].as_str().unwrap_or_default()),
+                    uploader: item["author"].as_str().unwrap_or_default().to_string(),
+                    duration_secs: parse_duration(item["duration"].as_str().unwrap_or_default())
+                        .unwrap_or(0),
+                    cover: cover_url(item["pic"].as_str().unwrap_or_default()),
+                },
+            })
+        })
+        .collect()
+}
+
+// 去掉搜索结果标题中的关键词高亮标签（`<em class="keyword">`）并还原常见的 HTML 实体
+fn strip_highlight(title: &str) -> String {
+    let mut text = String::with_capacity(title.len());
+    let mut in_tag = false;
+    for c in title.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+// 解析搜索结果的时长：`4:05` 或 `1:02:03`
+fn parse_duration(text: &str) -> Option<u32> {
+    text.split(':').try_fold(0u32, |total, part| {
+        Some(total * 60 + part.parse::<u32>().ok()?)
+    })
+}
+
+/// 从 view 接口的 `data` 中取出某个分P（从 0 开始）的信息
+// view 接口的 data
+fn parse_video_info(data: &Value) -> Result<VideoInfo, String> {
+    let title = data["title"]
+        .as_str()
+        .ok_or_else(|| "无法获取视频标题".to_string())?;
+    let duration_secs = data["duration"].as_u64().unwrap_or(0) as u32;
+    let pages = data["pages"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|p| {
+            (
+                p["part"].as_str().unwrap_or_default().to_string(),
+                p["duration"].as_u64().map_or(duration_secs, |d| d as u32),
+            )
+        })
+        .collect();
+    Ok(VideoInfo {
+        title: title.to_string(),
+        cover: cover_url(data["pic"].as_str().unwrap_or_default()),
+        duration_secs,
+        owner: data["owner"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        pages,
+        // 给UP主充过电的登录账号 `is_upower_play` 为 true，可以完整播放
+        charging_exclusive: data["is_upower_exclusive"].as_bool().unwrap_or(false)
+            && !data["is_upower_play"].as_bool().unwrap_or(false),
+    })
+}
+
+// 封面地址补全协议（搜索接口给出的是 `//i0.hdslb.com/...`），并统一为 https
+fn cover_url(pic: &str) -> String {
+    if let Some(rest) = pic.strip_prefix("//") {
+        format!("https://{}", rest)
+    } else if let Some(rest) = pic.strip_prefix("http://") {
+        format!("https://{}", rest)
+    } else {
+        pic.to_string()
+    }
+}
+
+// 番剧剧集的标题、番剧名和时长，来自 PGC 的 season 接口
+async fn get_episode_info(ep_id: u64) -> Result<SongInfo, String> {
+    let url = format!(
+        "https://api.bilibili.com/pgc/view/web/season?ep_id={}",
+        ep_id
+    );
+    let json: Value = api_json(api_get(&Client::new(), &url), "请求番剧信息").await?;
+    if json["code"].as_i64() != Some(0) {
+        return Err(format!(
+            "API错误: {}",
+            json["message"].as_str().unwrap_or("未知错误")
+        ));
+    }
+    parse_episode_info(&json["result"], ep_id)
+}
+
+// season 接口的 result：在 episodes 中找到该剧集；标题优先用单集标题（如 OP 的曲名）
+fn parse_episode_info(result: &Value, ep_id: u64) -> Result<SongInfo, String> {
+    let episode = result["episodes"]
+        .as_array()
+        .and_then(|episodes| {
+            episodes
+                .iter()
+                .find(|ep| ep["ep_id"].as_u64().or_else(|| ep["id"].as_u64()) == Some(ep_id))
+        })
+        .ok_or_else(|| format!("番剧中没有剧集 ep{}", ep_id))?;
+    let title = ["long_title", "show_title", "title"]
+        .iter()
+        .find_map(|key| episode[*key].as_str().filter(|t| !t.is_empty()))
+        .ok_or_else(|| "无法获取剧集标题".to_string())?;
+    Ok(SongInfo {
+        title: title.to_string(),
+        uploader: result["season_title"]
+            .as_str()
+            .or_else(|| result["title"].as_str())
+            .unwrap_or_default()
+            .to_string(),
+        // 剧集时长以毫秒为单位
+        duration_secs: (episode["duration"].as_u64().unwrap_or(0) / 1000) as u32,
+        cover: cover_url(episode["cover"].as_str().unwrap_or_default()),
+    })
+}
+
+/// 从 DASH 格式的 playurl 响应中选出码率最高的音轨地址
+fn parse_dash_audio(json: &Value) -> Result<String, String> {
+    playurl_data(json)["dash"]["audio"]
+        .as_array()
+        .and_then(|tracks| {
+            tracks
+                .iter()
+                .max_by_key(|track| track["bandwidth"].as_u64().unwrap_or(0))
+        })
+        .and_then(|track| {
+            track["baseUrl"]
+                .as_str()
+                .or_else(|| track["base_url"].as_str())
+        })
+        .map(|url| {
+            // 音轨只有一个链接，直接改写到第一个可用的偏好主机
+            CDN_HOSTS
+                .read()
+                .unwrap()
+                .iter()
+                .find_map(|host| rewrite_cdn_host(url, host))
+                .unwrap_or_else(|| url.to_string())
+        })
+        .ok_or_else(|| "无法获取音频链接".to_string())
+}
+
+/// 获取视频的CC字幕（BCC JSON，`body` 中每项含 `from`/`to`/`content`），没有字幕时返回 None
+///
+/// 优先选择中文字幕，其次是AI生成的中文字幕，都没有时取第一条。
+pub async fn get_bilibili_subtitle(
+    bv_id: &str,
+    page: Option<u32>,
+) -> Result<Option<Value>, String> {
+    // 番剧的字幕走另一套接口，暂不支持
+    if episode_id(bv_id).is_some() {
+        return Ok(None);
+    }
+    let client = Client::new();
+    let cid = get_video_cid(&client, bv_id, page.unwrap_or(0)).await?;
+
+    let url = format!(
+        "https://api.bilibili.com/x/player/v2?bvid={}&cid={}",
+        bv_id, cid
+    );
+    let json: Value = api_json(api_get(&client, &url), "请求字幕列表").await?;
+    if json["code"].as_i64() != Some(0) {
+        return Err(format!(
+            "API错误: {}",
+            json["message"].as_str().unwrap_or("未知错误")
+        ));
+    }
+
+    let subtitles = json["data"]["subtitle"]["subtitles"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let preferred = ["zh-CN", "zh-Hans", "ai-zh"]
+        .iter()
+        .find_map(|lan| subtitles.iter().find(|s| s["lan"] == *lan))
+        .or_else(|| subtitles.first());
+    let Some(subtitle_url) = preferred.and_then(|s| s["subtitle_url"].as_str()) else {
+        return Ok(None);
+    };
+    // 接口返回的是省略协议的地址（//aisubtitle.hdslb.com/...）
+    let subtitle_url = if subtitle_url.starts_with("//") {
+        format!("https:{}", subtitle_url)
+    } else {
+        subtitle_url.to_string()
+    };
+
+    let body = client
+        .get(&subtitle_url)
+        .header("User-Agent", "Mozilla/5.0")
         .send()
         .await
-        .map_err(|e| format!("请求CID失败: {}", e))?;
-
-    let json: Value = response
+        .map_err(|e| format!("下载字幕失败: {}", e))?
         .json()
         .await
-        .map_err(|e| format!("解析JSON失败: {}", e))?;
+        .map_err(|e| format!("解析字幕失败: {}", e))?;
+    Ok(Some(body))
+}
+
+/// 视频的分P数量；番剧剧集总是 1
+pub async fn get_page_count(bv_id: &str) -> Result<u32, String> {
+    if episode_id(bv_id).is_some() {
+        return Ok(1);
+    }
+    Ok(get_pagelist(&Client::new(), bv_id).await?.len() as u32)
+}
+
+// pagelist 接口返回的全部分P
+async fn get_pagelist(client: &Client, bv_id: &str) -> Result<Vec<Value>, String> {
+    let url = format!("https://api.bilibili.com/x/player/pagelist?bvid={}", bv_id);
+
+    let json = api_json(api_get(client, &url), "请求分P列表").await?;
 
     // 检查API返回状态
     if json["code"].as_i64() != Some(0) {
@@ -46,11 +1088,15 @@ async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String
         ));
     }
 
-    // 检查分P是否存在
-    let data = json
-        .get("data")
+    json.get("data")
         .and_then(|d| d.as_array())
-        .ok_or_else(|| "无效的数据格式".to_string())?;
+        .cloned()
+        .ok_or_else(|| "无效的数据格式".to_string())
+}
+
+/// 获取视频的CID（分集ID）
+async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String, String> {
+    let data = get_pagelist(client, bv_id).await?;
 
     if data.is_empty() {
         return Err("该视频没有可用的分P数据".to_string());
@@ -76,50 +1122,557 @@ async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String
 }
 
 /// 获取视频播放链接
-async fn get_video_url(client: &Client, bv_id: &str, cid: &str) -> Result<String, String> {
+async fn get_video_stream(client: &Client, bv_id: &str, cid: &str) -> Result<VideoStream, String> {
+    select_quality(|qn| request_playurl(client, bv_id, cid, qn)).await
+}
+
+// 按画质偏好请求 playurl（`request` 以 qn 发出请求）：接口给出的画质与 `accept_quality`
+// 中应选的不同时，按应选的画质再请求一次
+async fn select_quality<F, Fut>(request: F) -> Result<VideoStream, String>
+where
+    F: Fn(u32) -> Fut,
+    Fut: std::future::Future<Output = Result<Value, String>>,
+{
+    let preferred = preferred_quality();
+    let mut json = request(preferred).await?;
+    let mut quality = playurl_quality(&json).unwrap_or(preferred);
+    let accept: Vec<u32> = playurl_data(&json)["accept_quality"]
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .filter_map(|qn| qn.as_u64())
+                .map(|qn| qn as u32)
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(target) = choose_quality(&accept, preferred)
+        && target != quality
+    {
+        log::info!(
+            "画质{}不可用，改为请求{}",
+            quality_name(preferred),
+            quality_name(target)
+        );
+        json = request(target).await?;
+        quality = playurl_quality(&json).unwrap_or(target);
+    }
+    log::info!("视频画质: {}", quality_name(quality));
+    Ok(VideoStream {
+        quality,
+        codec: VideoCodec::from_codecid(playurl_data(&json)["video_codecid"].as_u64().unwrap_or(0)),
+        segments: parse_durl(&json)?,
+    })
+}
+
+// playurl 响应的主体：普通视频在 `data` 中，番剧（PGC）在 `result` 中
+fn playurl_data(json: &Value) -> &Value {
+    match json.get("data") {
+        Some(data) if !data.is_null() => data,
+        _ => &json["result"],
+    }
+}
+
+// playurl 响应中实际返回的画质
+fn playurl_quality(json: &Value) -> Option<u32> {
+    playurl_data(json)["quality"].as_u64().map(|qn| qn as u32)
+}
+
+// 请求番剧剧集的 PGC playurl 接口，`query` 为 ep_id 之外的参数；返回检查过状态码的响应
+async fn request_pgc_playurl(client: &Client, ep_id: u64, query: String) -> Result<Value, String> {
     let url = format!(
-        "https://api.bilibili.com/x/player/playurl?bvid={}&cid={}&qn=116&type=&otype=json&platform=html5&high_quality=1",
-        bv_id, cid
+        "https://api.bilibili.com/pgc/player/web/playurl?ep_id={}&{}",
+        ep_id, query
     );
+    let json: Value = api_json(
+        api_get(client, &url).header("Referer", "https://www.bilibili.com/"),
+        "请求番剧链接",
+    )
+    .await?;
+    check_playurl(&json)?;
+    Ok(json)
+}
 
-    let response = client
-        .get(&url)
-        .header("User-Agent", "Mozilla/5.0")
-        .send()
-        .await
-        .map_err(|e| format!("请求视频链接失败: {}", e))?;
+// 以画质 `qn` 请求 playurl 接口，返回检查过状态码的响应
+async fn request_playurl(
+    client: &Client,
+    bv_id: &str,
+    cid: &str,
+    qn: u32,
+) -> Result<Value, String> {
+    let params = [
+        ("bvid", bv_id.to_string()),
+        ("cid", cid.to_string()),
+        ("qn", qn.to_string()),
+        ("type", String::new()),
+        ("otype", "json".to_string()),
+        ("platform", "html5".to_string()),
+        ("high_quality", "1".to_string()),
+    ];
+    let url = wbi_url(client, PLAYURL_WBI, PLAYURL, &params).await;
 
-    let json: Value = response
-        .json()
-        .await
-        .map_err(|e| format!("解析JSON失败:  {}", e))?;
+    let json = api_json(api_get(client, &url), "请求视频链接").await?;
+    check_playurl(&json)?;
 
-    // 检查API返回状态
-    if json["code"].as_i64() != Some(0) {
-        return Err(format!(
+    Ok(json)
+}
+
+// 检查 playurl 响应的状态码；地区受限时错误为 [`REGION_LOCKED`]
+fn check_playurl(json: &Value) -> Result<(), String> {
+    match json["code"].as_i64() {
+        Some(0) => Ok(()),
+        Some(REGION_LOCKED_CODE) => Err(REGION_LOCKED.to_string()),
+        _ => Err(format!(
             "API错误: {}",
-            json.get("message")
-                .and_then(|v| v.as_str())
-                .unwrap_or("未知错误")
-        ));
+            json["message"].as_str().unwrap_or("未知错误")
+        )),
     }
+}
+
+/// 取流错误是否为地区受限
+pub fn is_region_locked(error: &str) -> bool {
+    error == REGION_LOCKED
+}
+
+/// 取流错误是否为充电专属
+pub fn is_charging_exclusive(error: &str) -> bool {
+    error == CHARGING_EXCLUSIVE
+}
 
-    // 提取直链
-    let video_url = json
-        .get("data")
-        .and_then(|d| d.get("durl"))
-        .and_then(|d| d.get(0))
-        .and_then(|d| d.get("url"))
-        .and_then(|u| u.as_str())
+/// 从 playurl 响应中提取 `durl` 分段
+fn parse_durl(json: &Value) -> Result<Vec<VideoSegment>, String> {
+    let durl = playurl_data(json)
+        .get("durl")
+        .and_then(|d| d.as_array())
         .ok_or_else(|| "无法获取视频链接".to_string())?;
 
-    Ok(video_url.to_string())
+    let mut segments = Vec::with_capacity(durl.len());
+    for (i, item) in durl.iter().enumerate() {
+        let url = item
+            .get("url")
+            .and_then(|u| u.as_str())
+            .ok_or_else(|| "无法获取视频链接".to_string())?;
+        // 分段的播放顺序以 `order`（从 1 开始）为准，不一定与数组顺序相同
+        let order = item
+            .get("order")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(i as u64 + 1);
+        segments.push((
+            order,
+            VideoSegment {
+                url: url.to_string(),
+                size: item.get("size").and_then(|v| v.as_u64()).unwrap_or(0),
+                length_ms: item.get("length").and_then(|v| v.as_u64()).unwrap_or(0),
+                backup_urls: item
+                    .get("backup_url")
+                    .and_then(|v| v.as_array())
+                    .map(|urls| {
+                        urls.iter()
+                            .filter_map(|u| u.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+        ));
+    }
+    segments.sort_by_key(|(order, _)| *order);
+    let segments: Vec<VideoSegment> = segments.into_iter().map(|(_, s)| s).collect();
+
+    if segments.is_empty() {
+        return Err("无法获取视频链接".to_string());
+    }
+    if segments.len() > 1 {
+        log::info!("视频被分为{}段", segments.len());
+    }
+    Ok(segments)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_song_info() {
+        let data = serde_json::json!({
+            "title": "【KTV】合集",
+            "duration": 500,
+            "owner": {"name": "八三夭"},
+            "pages": [
+                {"page": 1, "part": "想見你想見你想見你", "duration": 260},
+                {"page": 2, "part": "東區東區", "duration": 240}
+            ]
+        });
+        let info = parse_video_info(&data).unwrap().song_info(1);
+        assert_eq!(info.display_name(), "《東區東區》- 八三夭");
+        assert_eq!(info.duration_secs, 240);
+
+        let single = serde_json::json!({
+            "title": "想見你想見你想見你",
+            "duration": 260,
+            "owner": {"name": "八三夭"},
+            "pages": [{"page": 1, "part": "ktv_final.mp4", "duration": 260}]
+        });
+        assert_eq!(
+            parse_video_info(&single).unwrap().song_info(0).title,
+            "想見你想見你想見你"
+        );
+        assert!(parse_video_info(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_parse_video_info() {
+        let data = serde_json::json!({
+            "title": "【KTV】合集",
+            "pic": "http://i0.hdslb.com/bfs/archive/cover.jpg",
+            "duration": 500,
+            "owner": {"name": "八三夭"},
+            "pages": [
+                {"page": 1, "part": "想見你想見你想見你", "duration": 260},
+                {"page": 2, "part": "東區東區", "duration": 240}
+            ]
+        });
+        let info = parse_video_info(&data).unwrap();
+        assert_eq!(info.cover, "https://i0.hdslb.com/bfs/archive/cover.jpg");
+        assert_eq!(info.duration_secs, 500);
+        assert_eq!(info.pages[1], ("東區東區".to_string(), 240));
+        assert_eq!(info.song_info(0).cover, info.cover);
+        // 超出范围的分P取整个视频的时长
+        assert_eq!(info.song_info(5).duration_secs, 500);
+        assert!(!info.charging_exclusive);
+        assert_eq!(
+            cover_url("//i0.hdslb.com/a.jpg"),
+            "https://i0.hdslb.com/a.jpg"
+        );
+    }
+
+    #[test]
+    fn test_charging_exclusive() {
+        let mut data = serde_json::json!({
+            "title": "【充电专属】完整版",
+            "is_upower_exclusive": true,
+            "is_upower_play": false
+        });
+        assert!(parse_video_info(&data).unwrap().charging_exclusive);
+        // 已充电的账号可以完整播放
+        data["is_upower_play"] = true.into();
+        assert!(!parse_video_info(&data).unwrap().charging_exclusive);
+        assert!(is_charging_exclusive(CHARGING_EXCLUSIVE));
+        assert!(!is_charging_exclusive(REGION_LOCKED));
+    }
+
+    #[test]
+    fn test_parse_search_results() {
+        let data = serde_json::json!({
+            "result": [
+                {
+                    "bvid": "BV1xx411c7mD",
+                    "title": "【KTV】<em class=\"keyword\">晴天</em> - 周杰伦 &amp; 伴奏",
+                    "author": "KTV字幕组",
+                    "duration": "4:30"
+                },
+                {"bvid": "", "title": "广告", "author": "", "duration": "0:15"},
+                {"bvid": "BV1yy411c7mE", "title": "晴天 完整版", "author": "某UP", "duration": "1:02:03"}
+            ]
+        });
+        let results = parse_search_results(&data);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].bv_id, "BV1xx411c7mD");
+        assert_eq!(results[0].info.title, "【KTV】晴天 - 周杰伦 & 伴奏");
+        assert_eq!(results[0].info.duration_secs, 270);
+        assert_eq!(results[1].info.duration_secs, 3723);
+        assert!(parse_search_results(&serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_parse_dash_audio_picks_highest_bandwidth() {
+        let json: Value = serde_json::json!({
+            "code": 0,
+            "data": {
+                "dash": {
+                    "audio": [
+                        { "id": 30216, "bandwidth": 67000, "baseUrl": "https://cdn/low.m4s" },
+                        { "id": 30280, "bandwidth": 319000, "baseUrl": "https://cdn/high.m4s" }
+                    ]
+                }
+            }
+        });
+        assert_eq!(parse_dash_audio(&json).unwrap(), "https://cdn/high.m4s");
+        assert!(parse_dash_audio(&serde_json::json!({ "data": {} })).is_err());
+    }
+
+    #[test]
+    fn test_parse_durl_segments() {
+        let json: Value = serde_json::json!({
+            "code": 0,
+            "data": {
+                "durl": [
+                    { "order": 1, "length": 360000, "size": 1000, "url": "https://cdn/1.mp4" },
+                    {
+                        "order": 2, "length": 120500, "size": 400, "url": "https://cdn/2.mp4",
+                        "backup_url": ["https://backup1/2.mp4", "https://backup2/2.mp4"]
+                    }
+                ]
+            }
+        });
+        let segments = parse_durl(&json).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].url, "https://cdn/2.mp4");
+        assert_eq!(segments[1].size, 400);
+        assert_eq!(segments[0].length_ms, 360000);
+        assert!(segments[0].backup_urls.is_empty());
+        assert_eq!(
+            segments[1].urls(),
+            [
+                "https://cdn/2.mp4",
+                "https://backup1/2.mp4",
+                "https://backup2/2.mp4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_durl_order() {
+        let json: Value = serde_json::json!({
+            "data": {
+                "durl": [
+                    { "order": 3, "size": 300, "url": "https://cdn/3.flv" },
+                    { "order": 1, "size": 100, "url": "https://cdn/1.flv" },
+                    { "order": 2, "size": 200, "url": "https://cdn/2.flv" }
+                ]
+            }
+        });
+        let urls: Vec<String> = parse_durl(&json)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.url)
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://cdn/1.flv",
+                "https://cdn/2.flv",
+                "https://cdn/3.flv"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_choose_quality() {
+        let accept = [80, 64, 32, 16];
+        assert_eq!(choose_quality(&accept, 116), Some(80));
+        assert_eq!(choose_quality(&accept, 64), Some(64));
+        // 偏好介于两档之间时降到较低的一档
+        assert_eq!(choose_quality(&accept, 48), Some(32));
+        // 都比偏好高时取最低的
+        assert_eq!(choose_quality(&[64, 32], 16), Some(32));
+        assert_eq!(choose_quality(&[], 80), None);
+
+        assert_eq!(parse_quality("1080p"), Some(80));
+        assert_eq!(parse_quality(" 720P "), Some(64));
+        assert_eq!(parse_quality("32"), Some(32));
+        assert_eq!(parse_quality("8K"), None);
+        assert_eq!(quality_name(112), "1080P+");
+        assert_eq!(quality_name(6), "qn=6");
+    }
+
+    #[test]
+    fn test_cookie_file_and_account() {
+        let text = "# Netscape HTTP Cookie File\n\
+            #HttpOnly_.bilibili.com\tTRUE\t/\tTRUE\t1900000000\tSESSDATA\tabc%2C123\n\
+            .bilibili.com\tTRUE\t/\tFALSE\t1900000000\tbili_jct\tdef\n\
+            .example.com\tTRUE\t/\tFALSE\t1900000000\tSESSDATA\tother\n";
+        let cookies = BiliCookies::parse_cookie_file(text);
+        assert_eq!(cookies.sessdata.as_deref(), Some("abc%2C123"));
+        assert_eq!(
+            cookies.header().as_deref(),
+            Some("SESSDATA=abc%2C123; bili_jct=def")
+        );
+        // 配置里的值优先于文件
+        let configured = BiliCookies {
+            sessdata: Some("mine".to_string()),
+            bili_jct: None,
+        };
+        assert_eq!(
+            configured.or(cookies).header().as_deref(),
+            Some("SESSDATA=mine; bili_jct=def")
+        );
+        assert_eq!(BiliCookies::default().header(), None);
+
+        assert_eq!(parse_account(&serde_json::json!({"isLogin": false})), None);
+        assert_eq!(
+            parse_account(&serde_json::json!({"isLogin": true, "uname": "小明", "vipStatus": 1})),
+            Some(BiliAccount {
+                name: "小明".to_string(),
+                vip: true
+            })
+        );
+    }
+
+    #[test]
+    fn test_wbi_sign() {
+        // bilibili-API-collect 文档中的示例
+        let key = mixin_key(
+            "7cd084941338484aae1ad9425b84077c",
+            "4932caff0ff746eab6f01bf08b70ac45",
+        );
+        assert_eq!(key, "ea1db124af3c7062474693fa704f4ff8");
+        let params = [
+            ("foo", "114".to_string()),
+            ("bar", "514".to_string()),
+            ("zab", "1919810".to_string()),
+        ];
+        assert_eq!(
+            wbi_sign(&params, &key, 1702204169),
+            "bar=514&foo=114&wts=1702204169&zab=1919810&w_rid=8f6f2b5b3d485fe1886cec6a0be8c5d4"
+        );
+        // 值中的 !'()* 被去掉，其余按 encodeURIComponent 编码
+        assert!(
+            wbi_sign(&[("keyword", "晴天 (KTV)!".to_string())], &key, 1)
+                .starts_with("keyword=%E6%99%B4%E5%A4%A9%20KTV&wts=1&w_rid=")
+        );
+    }
+
+    #[test]
+    fn test_stream_deadline() {
+        let segment = |url: &str| VideoSegment {
+            url: url.to_string(),
+            size: 0,
+            length_ms: 0,
+            backup_urls: Vec::new(),
+        };
+        let stream = VideoStream {
+            quality: 80,
+            codec: VideoCodec::Avc,
+            segments: vec![
+                segment("https://upos.bilivideo.com/a.mp4?e=ig8&deadline=1700007200&os=bcache"),
+                segment("https://upos.bilivideo.com/b.mp4?deadline=1700003600"),
+            ],
+        };
+        assert_eq!(stream.deadline(), Some(1700003600));
+        assert!(stream.is_fresh(1700000000));
+        // 临近过期（不足余量）时不再复用
+        assert!(!stream.is_fresh(1700003600 - LINK_EXPIRY_MARGIN_SECS));
+        let unknown = VideoStream {
+            quality: 80,
+            codec: VideoCodec::Avc,
+            segments: vec![segment("https://upos.bilivideo.com/c.mp4")],
+        };
+        assert!(!unknown.is_fresh(0));
+    }
+
+    #[test]
+    fn test_prefer_cdn() {
+        let mut segment = VideoSegment {
+            url: "https://xy1x2x3x4xy.mcdn.bilivideo.cn:4483/upgcxcode/1.mp4?deadline=1"
+                .to_string(),
+            size: 0,
+            length_ms: 0,
+            backup_urls: vec![
+                "https://upos-sz-estgcos.bilivideo.com/upgcxcode/1.mp4?deadline=1".to_string(),
+            ],
+        };
+        let hosts = [
+            "upos-sz-mirrorcos.bilivideo.com".to_string(),
+            "upos-sz-estgcos.bilivideo.com".to_string(),
+        ];
+        prefer_cdn(&mut segment, &hosts);
+        // PCDN 链接不改写，改写 upos 备用链接；原来的链接排在后面且不重复
+        assert_eq!(
+            segment.urls(),
+            [
+                "https://upos-sz-mirrorcos.bilivideo.com/upgcxcode/1.mp4?deadline=1",
+                "https://upos-sz-estgcos.bilivideo.com/upgcxcode/1.mp4?deadline=1",
+                "https://xy1x2x3x4xy.mcdn.bilivideo.cn:4483/upgcxcode/1.mp4?deadline=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, 4.0);
+        let now = Instant::now();
+        assert_eq!(limiter.try_acquire(now), None);
+        assert_eq!(limiter.try_acquire(now), None);
+        // 令牌用完后按每秒 4 个补充
+        assert_eq!(limiter.try_acquire(now), Some(Duration::from_millis(250)));
+        assert_eq!(limiter.try_acquire(now + Duration::from_millis(250)), None);
+
+        // 冷却期间所有请求都要等到冷却结束，连续遭遇风控时冷却变长
+        let later = now + Duration::from_secs(10);
+        let first = limiter.cool_down(later);
+        assert!(first >= Duration::from_secs(RISK_COOLDOWN_SECS));
+        assert_eq!(limiter.try_acquire(later), Some(first));
+        let second = limiter.cool_down(later + first);
+        assert!(second >= Duration::from_secs(2 * RISK_COOLDOWN_SECS));
+        limiter.succeeded();
+        assert!(risk_cooldown(10) <= Duration::from_secs(MAX_RISK_COOLDOWN_SECS * 3 / 2));
+    }
+
+    #[test]
+    fn test_is_valid_bv() {
+        assert!(is_valid_bv("BV1xx411c7mD"));
+        assert!(!is_valid_bv("favicon.ico"));
+        assert!(!is_valid_bv("BV1xx411c7m"));
+        assert!(is_valid_bv("ep123456"));
+        assert!(!is_valid_bv("ep"));
+    }
+
+    #[test]
+    fn test_region_locked() {
+        let locked = serde_json::json!({"code": -10403, "message": "抱歉您所在地区不可观看！"});
+        assert!(is_region_locked(&check_playurl(&locked).unwrap_err()));
+        let missing = serde_json::json!({"code": -404, "message": "啥都木有"});
+        assert_eq!(check_playurl(&missing).unwrap_err(), "API错误: 啥都木有");
+        assert!(check_playurl(&serde_json::json!({"code": 0})).is_ok());
+    }
+
+    #[test]
+    fn test_episode() {
+        assert_eq!(episode_id("ep123456"), Some(123456));
+        assert_eq!(episode_id("BV1xx411c7mD"), None);
+        assert_eq!(
+            video_page_url("ep123456"),
+            "https://www.bilibili.com/bangumi/play/ep123456"
+        );
+        // PGC 的 playurl 把主体放在 result 中
+        let json = serde_json::json!({
+            "code": 0,
+            "result": {"quality": 80, "durl": [{"url": "https://upos/ep.mp4", "size": 10, "length": 1000}]}
+        });
+        assert_eq!(playurl_quality(&json), Some(80));
+        assert_eq!(parse_durl(&json).unwrap()[0].url, "https://upos/ep.mp4");
+
+        let season = serde_json::json!({
+            "season_title": "孤独摇滚！",
+            "episodes": [
+                {"ep_id": 1, "long_title": "", "show_title": "第1话", "duration": 1440000},
+                {"ep_id": 2, "long_title": "青春コンプレックス", "duration": 90500}
+            ]
+        });
+        assert_eq!(
+            parse_episode_info(&season, 2).unwrap(),
+            SongInfo {
+                title: "青春コンプレックス".to_string(),
+                uploader: "孤独摇滚！".to_string(),
+                duration_secs: 90,
+                cover: String::new(),
+            }
+        );
+        assert_eq!(parse_episode_info(&season, 1).unwrap().title, "第1话");
+        assert!(parse_episode_info(&season, 3).is_err());
+    }
+
+    #[test]
+    fn test_video_page_url() {
+        assert_eq!(
+            video_page_url("BV1xx411c7mD"),
+            "https://www.bilibili.com/video/BV1xx411c7mD"
+        );
+        assert_eq!(
+            video_page_url("BV1xx411c7mD-page2"),
+            "https://www.bilibili.com/video/BV1xx411c7mD?p=3"
+        );
+    }
+
     #[tokio::test]
     async fn test_get_bilibili_direct_link() {
         // 示例：测试获取视频直链
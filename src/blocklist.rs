@@ -0,0 +1,119 @@
+//! 设备黑名单
+//!
+//! 有些“渲染器”（打印机、NAS 自带的 DMR 等）每次都会被搜索到，却从来放不了视频。
+//! 选择设备时可以把它们永久隐藏，按 UDN 保存在当前目录的 `ktv-casting-blocklist.json`，
+//! 以后的搜索结果中不再列出；在选择界面中可以查看并取消隐藏。
+//!
+//! 与配置文件中的 `hide_devices`（按名称、型号、IP 匹配）不同，黑名单只认 UDN，
+//! 设备换了 IP 或改了名也不会重新出现。
+
+use crate::dlna_controller::DlnaDevice;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// 默认的黑名单文件（当前目录）
+const DEFAULT_BLOCKLIST_FILE: &str = "ktv-casting-blocklist.json";
+
+/// 被永久隐藏的设备：UDN → 隐藏时的设备名称（仅用于显示）
+#[derive(Debug, Clone)]
+pub struct DeviceBlocklist {
+    path: PathBuf,
+    devices: BTreeMap<String, String>,
+}
+
+impl DeviceBlocklist {
+    /// 默认的黑名单文件：当前目录下的 `ktv-casting-blocklist.json`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(DEFAULT_BLOCKLIST_FILE)
+    }
+
+    /// 读取默认位置的黑名单；文件不存在时为空
+    pub fn load_default() -> Result<Self> {
+        Self::load(&Self::default_path())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let devices = if path.exists() {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("无法读取设备黑名单 {}", path.display()))?;
+            serde_json::from_str(&text)
+                .with_context(|| format!("设备黑名单格式错误 {}", path.display()))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            devices,
+        })
+    }
+
+    pub fn contains(&self, device: &DlnaDevice) -> bool {
+        self.devices.contains_key(&device.udn)
+    }
+
+    /// 已隐藏的设备：(UDN, 名称)，按 UDN 排序
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.devices
+            .iter()
+            .map(|(udn, name)| (udn.as_str(), name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// 隐藏设备并写回文件
+    pub fn block(&mut self, device: &DlnaDevice) -> Result<()> {
+        self.devices
+            .insert(device.udn.clone(), device.friendly_name.clone());
+        self.save()
+    }
+
+    /// 取消隐藏并写回文件，返回该 UDN 是否在黑名单中
+    pub fn unblock(&mut self, udn: &str) -> Result<bool> {
+        let removed = self.devices.remove(udn).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self.devices)?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("无法写入设备黑名单 {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist_persists_by_udn() {
+        let path =
+            std::env::temp_dir().join(format!("ktv-casting-blocklist-{}.json", std::process::id()));
+        let mut printer = DlnaDevice::dry_run();
+        printer.udn = "uuid:printer".to_string();
+        printer.friendly_name = "Office Printer".to_string();
+
+        let mut blocklist = DeviceBlocklist::load(&path).unwrap();
+        assert!(blocklist.is_empty());
+        blocklist.block(&printer).unwrap();
+
+        // 改名后按 UDN 仍然隐藏
+        printer.friendly_name = "Printer (renamed)".to_string();
+        let mut reloaded = DeviceBlocklist::load(&path).unwrap();
+        assert!(reloaded.contains(&printer));
+        assert_eq!(
+            reloaded.entries().collect::<Vec<_>>(),
+            vec![("uuid:printer", "Office Printer")]
+        );
+
+        assert!(reloaded.unblock("uuid:printer").unwrap());
+        assert!(!reloaded.unblock("uuid:printer").unwrap());
+        assert!(!DeviceBlocklist::load(&path).unwrap().contains(&printer));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
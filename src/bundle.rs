@@ -0,0 +1,138 @@
+//! 应用状态的导入/导出
+//!
+//! 把配置文件、音量曲线、设备黑名单等本地状态打包成一个 JSON 文件，换机器时
+//! `--export` 一次、`--import` 一次即可。导入只写回已知的状态文件，
+//! 覆盖前会把原文件备份为 `*.bak`。
+
+use crate::blocklist::DeviceBlocklist;
+use crate::config::Config;
+use crate::volume_curve::VolumeCurveStore;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// 包格式版本，格式不兼容时递增
+const BUNDLE_VERSION: u32 = 1;
+
+/// 参与导入/导出的状态文件：(包内名称, 本机路径)
+pub fn state_files() -> Vec<(&'static str, PathBuf)> {
+    vec![
+        ("config", Config::path()),
+        ("volume_curves", VolumeCurveStore::default_path()),
+        ("device_blocklist", DeviceBlocklist::default_path()),
+    ]
+}
+
+/// 状态包：包内名称 → 文件内容
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateBundle {
+    version: u32,
+    /// 导出时使用的程序版本，仅供参考
+    app_version: String,
+    files: BTreeMap<String, String>,
+}
+
+impl StateBundle {
+    /// 收集存在的状态文件，不存在的跳过
+    pub fn collect(files: &[(&str, PathBuf)]) -> Result<Self> {
+        let mut bundle = Self {
+            version: BUNDLE_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            files: BTreeMap::new(),
+        };
+        for (name, path) in files {
+            if !path.exists() {
+                continue;
+            }
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("无法读取 {}", path.display()))?;
+            bundle.files.insert(name.to_string(), text);
+        }
+        Ok(bundle)
+    }
+
+    /// 包内的文件名称
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text).with_context(|| format!("无法写入 {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("无法读取 {}", path.display()))?;
+        let bundle: Self = serde_json::from_str(&text)
+            .with_context(|| format!("状态包格式错误 {}", path.display()))?;
+        if bundle.version > BUNDLE_VERSION {
+            bail!(
+                "状态包版本 {} 高于当前支持的版本 {}，请先升级程序",
+                bundle.version,
+                BUNDLE_VERSION
+            );
+        }
+        Ok(bundle)
+    }
+
+    /// 把包内的文件写回本机路径，返回写入的路径；未知名称会被忽略
+    pub fn restore(&self, files: &[(&str, PathBuf)]) -> Result<Vec<PathBuf>> {
+        let mut written = Vec::new();
+        for (name, text) in &self.files {
+            let Some((_, path)) = files.iter().find(|(known, _)| known == name) else {
+                log::warn!("忽略状态包中未知的文件: {}", name);
+                continue;
+            };
+            if path.exists() {
+                let mut backup = path.clone().into_os_string();
+                backup.push(".bak");
+                std::fs::copy(path, &backup)
+                    .with_context(|| format!("无法备份 {}", path.display()))?;
+            }
+            std::fs::write(path, text).with_context(|| format!("无法写入 {}", path.display()))?;
+            written.push(path.clone());
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("ktv-casting-bundle-{}", std::process::id()));
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::create_dir_all(&to).unwrap();
+        std::fs::write(from.join("ktv-casting.toml"), "[update]\ncheck = true\n").unwrap();
+        std::fs::write(to.join("ktv-casting.toml"), "# old\n").unwrap();
+
+        let files = |dir: &Path| {
+            vec![
+                ("config", dir.join("ktv-casting.toml")),
+                ("volume_curves", dir.join("ktv-casting-volume.json")),
+            ]
+        };
+        let bundle = StateBundle::collect(&files(&from)).unwrap();
+        assert_eq!(bundle.names().collect::<Vec<_>>(), ["config"]);
+        bundle.save(&dir.join("bundle.json")).unwrap();
+
+        let loaded = StateBundle::load(&dir.join("bundle.json")).unwrap();
+        assert_eq!(loaded, bundle);
+        let written = loaded.restore(&files(&to)).unwrap();
+        assert_eq!(written, [to.join("ktv-casting.toml")]);
+        assert_eq!(
+            std::fs::read_to_string(to.join("ktv-casting.toml")).unwrap(),
+            "[update]\ncheck = true\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(to.join("ktv-casting.toml.bak")).unwrap(),
+            "# old\n"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
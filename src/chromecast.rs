@@ -0,0 +1,632 @@
+//! Chromecast（CASTv2）渲染器后端
+//!
+//! 通过 mDNS 查询 `_googlecast._tcp.local` 发现设备，用 CASTv2 协议控制默认媒体接收器
+//! （Default Media Receiver）。CASTv2 是 TLS 上的长度前缀 protobuf 消息，消息体为 JSON。
+//!
+//! [`DlnaController`](crate::dlna_controller::DlnaController) 会把 AVTransport/RenderingControl
+//! 动作翻译成这里的 [`CastCommand`]，因此会话层不需要区分两种设备。每条命令使用一个新连接，
+//! 媒体接收器在连接断开后会继续播放。
+
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls;
+
+/// Chromecast 的默认控制端口
+pub const CAST_PORT: u16 = 8009;
+
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+const SERVICE_NAME: &str = "_googlecast._tcp.local";
+
+// 默认媒体接收器的应用ID
+const DEFAULT_MEDIA_RECEIVER: &str = "CC1AD845";
+const SENDER_ID: &str = "sender-0";
+const RECEIVER_ID: &str = "receiver-0";
+const NS_CONNECTION: &str = "urn:x-cast:com.google.cast.tp.connection";
+const NS_HEARTBEAT: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+const NS_RECEIVER: &str = "urn:x-cast:com.google.cast.receiver";
+const NS_MEDIA: &str = "urn:x-cast:com.google.cast.media";
+
+// 单条消息的长度上限，防止异常数据导致分配过大的缓冲区
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// 通过 mDNS 发现的 Chromecast 设备
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastDevice {
+    /// 设备名（TXT 记录的 `fn`）
+    pub name: String,
+    /// 型号（TXT 记录的 `md`），例如 `Chromecast`、`Google TV`
+    pub model: String,
+    /// 设备ID（TXT 记录的 `id`），用作 UDN
+    pub id: String,
+    pub address: SocketAddr,
+}
+
+/// 发往默认媒体接收器的命令
+#[derive(Debug, Clone, PartialEq)]
+pub enum CastCommand {
+    Load {
+        url: String,
+        title: String,
+        content_type: String,
+    },
+    Play,
+    Pause,
+    Stop,
+    Seek(f64),
+    /// 音量 0.0-1.0
+    SetVolume(f32),
+    Status,
+}
+
+/// 设备状态（命令执行后的最新状态）
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CastStatus {
+    /// `PLAYING`、`PAUSED`、`BUFFERING`、`IDLE`；没有媒体时为 None
+    pub player_state: Option<String>,
+    pub current_time: f64,
+    pub duration: f64,
+    /// 音量 0.0-1.0
+    pub volume: f32,
+}
+
+/// 在局域网中搜索 Chromecast 设备，持续 `timeout`
+pub async fn discover(timeout: Duration) -> Result<Vec<CastDevice>, String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .map_err(|e| format!("无法创建mDNS套接字: {}", e))?;
+    socket
+        .send_to(&build_mdns_query(), MDNS_ADDR)
+        .await
+        .map_err(|e| format!("发送mDNS查询失败: {}", e))?;
+
+    let mut devices: Vec<CastDevice> = Vec::new();
+    let mut buf = [0u8; 4096];
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, source) = received.map_err(|e| format!("接收mDNS响应失败: {}", e))?;
+        let Some(device) = parse_mdns_response(&buf[..len], source.ip()) else {
+            continue;
+        };
+        if !devices.iter().any(|d| d.id == device.id) {
+            log::info!(
+                "发现Chromecast设备: {} [{}] ({})",
+                device.name,
+                device.model,
+                device.address
+            );
+            devices.push(device);
+        }
+    }
+    Ok(devices)
+}
+
+// PTR 查询 `_googlecast._tcp.local`
+fn build_mdns_query() -> Vec<u8> {
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in SERVICE_NAME.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    // QTYPE=PTR, QCLASS=IN
+    packet.extend_from_slice(&[0, 12, 0, 1]);
+    packet
+}
+
+// 读取（可能被压缩的）域名，返回域名和紧随其后的位置
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // 限制跳转次数，防止指针成环
+    for _ in 0..32 {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        }
+        if len & 0xC0 == 0xC0 {
+            let pointer = ((len & 0x3F) << 8) | *packet.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = pointer;
+            continue;
+        }
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += 1 + len;
+    }
+    None
+}
+
+// 从一个 mDNS 响应中取出设备信息：SRV 给出端口，TXT 给出名称/型号/ID，A 给出地址
+fn parse_mdns_response(packet: &[u8], source: IpAddr) -> Option<CastDevice> {
+    let count = |i: usize| -> Option<usize> {
+        Some(u16::from_be_bytes([*packet.get(i)?, *packet.get(i + 1)?]) as usize)
+    };
+    let questions = count(4)?;
+    let records = count(6)? + count(8)? + count(10)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(packet, pos)?.1 + 4;
+    }
+
+    let mut is_cast = false;
+    let mut port = CAST_PORT;
+    let mut ip = source;
+    let mut txt: HashMap<String, String> = HashMap::new();
+    for _ in 0..records {
+        let (name, next) = read_name(packet, pos)?;
+        let header = packet.get(next..next + 10)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let rdlen = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let rdata_start = next + 10;
+        let rdata = packet.get(rdata_start..rdata_start + rdlen)?;
+        pos = rdata_start + rdlen;
+
+        if name.contains("_googlecast._tcp") {
+            is_cast = true;
+        }
+        match rtype {
+            // A
+            1 if rdlen == 4 => {
+                ip = IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]))
+            }
+            // TXT：若干个长度前缀的 key=value
+            16 => {
+                let mut i = 0;
+                while i < rdata.len() {
+                    let len = rdata[i] as usize;
+                    let Some(entry) = rdata.get(i + 1..i + 1 + len) else {
+                        break;
+                    };
+                    let entry = String::from_utf8_lossy(entry);
+                    if let Some((key, value)) = entry.split_once('=') {
+                        txt.insert(key.to_string(), value.to_string());
+                    }
+                    i += 1 + len;
+                }
+            }
+            // SRV：priority, weight, port, target
+            33 if rdlen >= 6 => port = u16::from_be_bytes([rdata[4], rdata[5]]),
+            _ => {}
+        }
+    }
+
+    let id = txt.get("id")?.clone();
+    if !is_cast {
+        return None;
+    }
+    Some(CastDevice {
+        name: txt
+            .get("fn")
+            .cloned()
+            .unwrap_or_else(|| "Chromecast".to_string()),
+        model: txt.get("md").cloned().unwrap_or_default(),
+        id,
+        address: SocketAddr::new(ip, port),
+    })
+}
+
+// protobuf 中的 varint
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// 一条 CastMessage（只包含本程序用到的字符串负载）
+#[derive(Debug, Clone, PartialEq)]
+struct CastMessage {
+    source: String,
+    destination: String,
+    namespace: String,
+    payload: String,
+}
+
+impl CastMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        // protocol_version = CASTV2_1_0
+        buf.extend_from_slice(&[0x08, 0x00]);
+        for (field, value) in [
+            (2u8, &self.source),
+            (3, &self.destination),
+            (4, &self.namespace),
+        ] {
+            buf.push(field << 3 | 2);
+            write_varint(&mut buf, value.len() as u64);
+            buf.extend_from_slice(value.as_bytes());
+        }
+        // payload_type = STRING
+        buf.extend_from_slice(&[0x28, 0x00]);
+        buf.push(6 << 3 | 2);
+        write_varint(&mut buf, self.payload.len() as u64);
+        buf.extend_from_slice(self.payload.as_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut message = CastMessage {
+            source: String::new(),
+            destination: String::new(),
+            namespace: String::new(),
+            payload: String::new(),
+        };
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos)?;
+            match key & 0x7 {
+                0 => {
+                    read_varint(buf, &mut pos)?;
+                }
+                1 => pos += 8,
+                5 => pos += 4,
+                2 => {
+                    let len = read_varint(buf, &mut pos)? as usize;
+                    let value = buf.get(pos..pos + len)?;
+                    pos += len;
+                    let value = String::from_utf8_lossy(value).into_owned();
+                    match key >> 3 {
+                        2 => message.source = value,
+                        3 => message.destination = value,
+                        4 => message.namespace = value,
+                        6 => message.payload = value,
+                        _ => {}
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(message)
+    }
+}
+
+// Chromecast 使用自签名证书，只能跳过证书校验
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// 到一台设备的 CASTv2 连接
+struct CastConnection {
+    stream: TlsStream<TcpStream>,
+    next_request_id: u64,
+}
+
+impl CastConnection {
+    async fn connect(address: SocketAddr) -> Result<Self, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+            .with_no_client_auth();
+        let tcp = TcpStream::connect(address)
+            .await
+            .map_err(|e| format!("无法连接Chromecast {}: {}", address, e))?;
+        let server_name = rustls::pki_types::ServerName::IpAddress(address.ip().into());
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(server_name, tcp)
+            .await
+            .map_err(|e| format!("Chromecast TLS握手失败: {}", e))?;
+
+        let mut connection = Self {
+            stream,
+            next_request_id: 1,
+        };
+        connection
+            .send(RECEIVER_ID, NS_CONNECTION, json!({ "type": "CONNECT" }))
+            .await?;
+        Ok(connection)
+    }
+
+    async fn send(
+        &mut self,
+        destination: &str,
+        namespace: &str,
+        payload: Value,
+    ) -> Result<(), String> {
+        let body = CastMessage {
+            source: SENDER_ID.to_string(),
+            destination: destination.to_string(),
+            namespace: namespace.to_string(),
+            payload: payload.to_string(),
+        }
+        .encode();
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&body);
+        self.stream
+            .write_all(&frame)
+            .await
+            .map_err(|e| format!("发送Cast消息失败: {}", e))
+    }
+
+    async fn recv(&mut self) -> Result<CastMessage, String> {
+        let mut len = [0u8; 4];
+        self.stream
+            .read_exact(&mut len)
+            .await
+            .map_err(|e| format!("读取Cast消息失败: {}", e))?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(format!("Cast消息过长: {}字节", len));
+        }
+        let mut body = vec![0u8; len];
+        self.stream
+            .read_exact(&mut body)
+            .await
+            .map_err(|e| format!("读取Cast消息失败: {}", e))?;
+        CastMessage::decode(&body).ok_or_else(|| "无法解析Cast消息".to_string())
+    }
+
+    // 发送带 requestId 的请求并等待对应的响应；期间收到的心跳会被应答
+    async fn request(
+        &mut self,
+        destination: &str,
+        namespace: &str,
+        mut payload: Value,
+    ) -> Result<Value, String> {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        payload["requestId"] = json!(request_id);
+        self.send(destination, namespace, payload).await?;
+
+        loop {
+            let message = self.recv().await?;
+            let response: Value = serde_json::from_str(&message.payload).unwrap_or(Value::Null);
+            if message.namespace == NS_HEARTBEAT && response["type"] == "PING" {
+                self.send(&message.source, NS_HEARTBEAT, json!({ "type": "PONG" }))
+                    .await?;
+                continue;
+            }
+            if response["requestId"].as_u64() != Some(request_id) {
+                continue;
+            }
+            return match response["type"].as_str() {
+                Some("LOAD_FAILED" | "LOAD_CANCELLED" | "INVALID_REQUEST" | "LAUNCH_ERROR") => {
+                    Err(format!("Chromecast拒绝了请求: {}", response))
+                }
+                _ => Ok(response),
+            };
+        }
+    }
+}
+
+// 从 MEDIA_STATUS 中取第一个媒体会话
+fn media_session(status: &Value) -> Option<&Value> {
+    status["status"].as_array()?.first()
+}
+
+/// 连接设备并执行一条命令，返回执行后的状态
+pub async fn execute(address: SocketAddr, command: CastCommand) -> Result<CastStatus, String> {
+    log::debug!("Chromecast {} <- {:?}", address, command);
+    let mut connection = CastConnection::connect(address).await?;
+
+    let mut receiver = connection
+        .request(RECEIVER_ID, NS_RECEIVER, json!({ "type": "GET_STATUS" }))
+        .await?;
+    if let CastCommand::SetVolume(level) = command {
+        receiver = connection
+            .request(
+                RECEIVER_ID,
+                NS_RECEIVER,
+                json!({ "type": "SET_VOLUME", "volume": { "level": level.clamp(0.0, 1.0) } }),
+            )
+            .await?;
+    }
+    let mut status = CastStatus {
+        volume: receiver["status"]["volume"]["level"]
+            .as_f64()
+            .unwrap_or(0.0) as f32,
+        ..CastStatus::default()
+    };
+    if matches!(command, CastCommand::SetVolume(_)) {
+        return Ok(status);
+    }
+
+    let find_app = |receiver: &Value| {
+        receiver["status"]["applications"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|app| app["appId"] == DEFAULT_MEDIA_RECEIVER)
+            .and_then(|app| app["transportId"].as_str())
+            .map(str::to_string)
+    };
+    let transport_id = match (find_app(&receiver), &command) {
+        (Some(id), _) => id,
+        (None, CastCommand::Load { .. }) => {
+            let launched = connection
+                .request(
+                    RECEIVER_ID,
+                    NS_RECEIVER,
+                    json!({ "type": "LAUNCH", "appId": DEFAULT_MEDIA_RECEIVER }),
+                )
+                .await?;
+            find_app(&launched).ok_or("Chromecast未能启动媒体接收器")?
+        }
+        // 没有运行媒体接收器：停止视为成功，其余命令没有可操作的媒体
+        (None, CastCommand::Status | CastCommand::Stop) => return Ok(status),
+        (None, _) => return Err("Chromecast上没有正在播放的媒体".to_string()),
+    };
+    connection
+        .send(&transport_id, NS_CONNECTION, json!({ "type": "CONNECT" }))
+        .await?;
+
+    let media = match command {
+        CastCommand::Load {
+            url,
+            title,
+            content_type,
+        } => {
+            connection
+                .request(
+                    &transport_id,
+                    NS_MEDIA,
+                    json!({
+                        "type": "LOAD",
+                        "autoplay": true,
+                        "media": {
+                            "contentId": url,
+                            "streamType": "BUFFERED",
+                            "contentType": content_type,
+                            "metadata": { "metadataType": 0, "title": title },
+                        },
+                    }),
+                )
+                .await?
+        }
+        command => {
+            let current = connection
+                .request(&transport_id, NS_MEDIA, json!({ "type": "GET_STATUS" }))
+                .await?;
+            let Some(session_id) =
+                media_session(&current).and_then(|s| s["mediaSessionId"].as_u64())
+            else {
+                return match command {
+                    CastCommand::Status | CastCommand::Stop => Ok(status),
+                    _ => Err("Chromecast上没有正在播放的媒体".to_string()),
+                };
+            };
+            let mut payload = match command {
+                CastCommand::Play => json!({ "type": "PLAY" }),
+                CastCommand::Pause => json!({ "type": "PAUSE" }),
+                CastCommand::Stop => json!({ "type": "STOP" }),
+                CastCommand::Seek(secs) => json!({ "type": "SEEK", "currentTime": secs }),
+                _ => return Ok(parse_media_status(&current, status)),
+            };
+            payload["mediaSessionId"] = json!(session_id);
+            connection.request(&transport_id, NS_MEDIA, payload).await?
+        }
+    };
+    status = parse_media_status(&media, status);
+    Ok(status)
+}
+
+fn parse_media_status(media: &Value, mut status: CastStatus) -> CastStatus {
+    if let Some(session) = media_session(media) {
+        status.player_state = session["playerState"].as_str().map(str::to_string);
+        status.current_time = session["currentTime"].as_f64().unwrap_or(0.0);
+        status.duration = session["media"]["duration"].as_f64().unwrap_or(0.0);
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cast_message_roundtrip() {
+        let message = CastMessage {
+            source: SENDER_ID.to_string(),
+            destination: RECEIVER_ID.to_string(),
+            namespace: NS_RECEIVER.to_string(),
+            payload: r#"{"type":"GET_STATUS","requestId":1}"#.to_string(),
+        };
+        assert_eq!(CastMessage::decode(&message.encode()), Some(message));
+    }
+
+    #[test]
+    fn test_parse_mdns_response() {
+        // 响应：PTR 应答 + SRV/TXT/A 附加记录，实例名通过压缩指针引用服务名
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 3];
+        let service_offset = packet.len();
+        for label in SERVICE_NAME.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&[0, 12, 0, 1, 0, 0, 0, 120]);
+        let instance = b"Chromecast-abc";
+        packet.extend_from_slice(&[0, (instance.len() + 3) as u8, instance.len() as u8]);
+        packet.extend_from_slice(instance);
+        packet.extend_from_slice(&[0xC0, service_offset as u8]);
+        // SRV
+        packet.extend_from_slice(&[0xC0, service_offset as u8, 0, 33, 0, 1, 0, 0, 0, 120, 0, 7]);
+        packet.extend_from_slice(&[0, 0, 0, 0, 0x1F, 0x49, 0]);
+        // TXT
+        let entries = ["id=abc123", "fn=客厅电视", "md=Google TV"];
+        let txt: Vec<u8> = entries
+            .iter()
+            .flat_map(|e| std::iter::once(e.len() as u8).chain(e.bytes()))
+            .collect();
+        packet.extend_from_slice(&[0xC0, service_offset as u8, 0, 16, 0, 1, 0, 0, 0, 120, 0]);
+        packet.push(txt.len() as u8);
+        packet.extend_from_slice(&txt);
+        // A
+        packet.extend_from_slice(&[0xC0, service_offset as u8, 0, 1, 0, 1, 0, 0, 0, 120, 0, 4]);
+        packet.extend_from_slice(&[192, 168, 1, 30]);
+
+        let device = parse_mdns_response(&packet, "192.168.1.99".parse().unwrap()).unwrap();
+        assert_eq!(device.id, "abc123");
+        assert_eq!(device.name, "客厅电视");
+        assert_eq!(device.model, "Google TV");
+        assert_eq!(device.address, "192.168.1.30:8009".parse().unwrap());
+    }
+}
@@ -0,0 +1,406 @@
+//! 应用配置
+//!
+//! 配置文件为可选的 TOML 文件，默认读取当前目录下的 `ktv-casting.toml`，
+//! 也可以通过环境变量 `KTV_CASTING_CONFIG` 指定路径。未出现的字段使用默认值。
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_CONFIG_FILE: &str = "ktv-casting.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub discovery: DiscoveryConfig,
+    pub dlna: DlnaConfig,
+    pub proxy: ProxyConfig,
+    pub session: SessionConfig,
+    pub features: FeaturesConfig,
+    pub update: UpdateConfig,
+    pub remote: RemoteConfig,
+    pub room: RoomConfig,
+    pub bilibili: BilibiliConfig,
+    pub ui: UiConfig,
+}
+
+/// SSDP 设备发现参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// 整个搜索过程的时长（秒）
+    pub timeout_secs: u64,
+    /// M-SEARCH 的 MX 值：设备会在 0..MX 秒内随机延迟后响应
+    pub mx: usize,
+    /// 同一 USN 的重复响应在该窗口内只处理一次（毫秒）
+    pub dedupe_window_ms: u64,
+    /// 同时下载设备描述文档（description.xml）的最大数量
+    pub max_concurrent_fetches: usize,
+    /// 同时通过 mDNS 搜索 Chromecast 设备
+    pub chromecast: bool,
+    /// 不显示在设备列表中的设备：按设备名、“厂商 型号”、UDN（子串匹配）或 IP/网段（如 `10.0.3.0/24`）
+    pub hide_devices: Vec<String>,
+    /// 置顶显示的设备，按模式的先后顺序排列，匹配方式同 `hide_devices`
+    pub pin_devices: Vec<String>,
+    /// 已知设备的描述文档地址（如 `http://10.0.3.20:49152/description.xml`），用于搜索不到的设备
+    /// （例如在另一个网段）；选择设备前会并发检查一遍，离线的设备灰色显示
+    pub known_devices: Vec<String>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 5,
+            mx: 3,
+            dedupe_window_ms: 5000,
+            max_concurrent_fetches: 4,
+            chromecast: true,
+            hide_devices: Vec::new(),
+            pin_devices: Vec::new(),
+            known_devices: Vec::new(),
+        }
+    }
+}
+
+/// DLNA 控制（SOAP 动作）参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DlnaConfig {
+    /// 每个动作的默认总超时（毫秒），备用地址和兼容路径共用
+    pub action_timeout_ms: u64,
+    /// 按动作名覆盖超时，例如 `SetAVTransportURI = 10000`
+    pub action_timeouts_ms: HashMap<String, u64>,
+    /// 投屏时给渲染器的媒体地址形式
+    pub url_strategy: UrlStrategy,
+    /// `auto` 策略下可以直接拉取B站 CDN 的渲染器，按 UDN、设备名或“厂商 型号”匹配（不区分大小写的子串）
+    pub direct_link_devices: Vec<String>,
+    /// 是否经 ffmpeg 转码成 H.264/AAC 的 MP4 再投送
+    pub transcode: TranscodeStrategy,
+    /// `auto` 策略下总是转码的渲染器（解码不了 HEVC 或高 Profile 却不在 protocolInfo 中说明的电视），
+    /// 匹配方式同 `direct_link_devices`
+    pub transcode_devices: Vec<String>,
+}
+
+/// 转码策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscodeStrategy {
+    /// 从不转码
+    #[default]
+    Off,
+    /// `transcode_devices` 中的设备总是转码；其余设备的 protocolInfo（GetProtocolInfo 的 Sink）
+    /// 不支持视频的封装格式或编码时转码
+    Auto,
+    /// 总是转码
+    Always,
+}
+
+/// 媒体地址策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlStrategy {
+    /// 在 `direct_link_devices` 中的设备使用直链，其余走本地代理
+    #[default]
+    Auto,
+    /// 总是走本地代理（改写 Referer/User-Agent，支持卡顿续传）
+    Proxy,
+    /// 总是使用B站 CDN 直链，起播更快，但很多电视会因缺少 Referer 被 CDN 拒绝
+    Direct,
+}
+
+impl Default for DlnaConfig {
+    fn default() -> Self {
+        Self {
+            action_timeout_ms: 5000,
+            action_timeouts_ms: HashMap::new(),
+            url_strategy: UrlStrategy::Auto,
+            direct_link_devices: Vec::new(),
+            transcode: TranscodeStrategy::Off,
+            transcode_devices: Vec::new(),
+        }
+    }
+}
+
+impl DlnaConfig {
+    /// 获取指定动作的超时时间
+    pub fn action_timeout(&self, action: &str) -> Duration {
+        let ms = self
+            .action_timeouts_ms
+            .get(action)
+            .copied()
+            .unwrap_or(self.action_timeout_ms);
+        Duration::from_millis(ms)
+    }
+}
+
+/// 本地媒体代理参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// 等待上游（B站 CDN）返回响应头的超时（毫秒）
+    pub upstream_timeout_ms: u64,
+    /// 上游超过该时间没有送来任何数据即视为卡住（毫秒）
+    pub stall_timeout_ms: u64,
+    /// 卡住/断开后，从已发送位置用 Range 续传的最大连续重连次数
+    pub max_reconnects: u32,
+    /// 发给渲染器的每个数据块大小（字节）
+    pub chunk_size: usize,
+    /// 预读缓冲区容量（字节），上游数据先进入该缓冲区再发给渲染器
+    pub read_ahead_bytes: usize,
+    /// 缓冲数据达到该值时暂停读取上游（字节）
+    pub high_watermark_bytes: usize,
+    /// 暂停后缓冲数据回落到该值以下才恢复读取上游（字节）
+    pub low_watermark_bytes: usize,
+    /// 转码使用的 ffmpeg 可执行文件，见 [`TranscodeStrategy`]
+    pub ffmpeg: String,
+    /// 经转码投送的相邻歌曲之间交叉淡化的秒数，0 表示关闭
+    pub crossfade_secs: u32,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            upstream_timeout_ms: 10000,
+            stall_timeout_ms: 8000,
+            max_reconnects: 3,
+            // 电视的 Wi-Fi 网卡更适合较大的块和数秒码率的预读，避免频繁小包引起卡顿
+            chunk_size: 256 * 1024,
+            read_ahead_bytes: 16 * 1024 * 1024,
+            high_watermark_bytes: 12 * 1024 * 1024,
+            low_watermark_bytes: 4 * 1024 * 1024,
+            ffmpeg: "ffmpeg".to_string(),
+            crossfade_secs: 0,
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// 数据块大小，至少 1 字节
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size.max(1)
+    }
+
+    /// 预读缓冲区能容纳的数据块数量，至少 1 块
+    pub fn read_ahead_chunks(&self) -> usize {
+        (self.read_ahead_bytes / self.chunk_size()).max(1)
+    }
+
+    /// 修正后的 (低水位, 高水位)：高水位不超过缓冲区容量，低水位不超过高水位
+    pub fn watermarks(&self) -> (usize, usize) {
+        let high = self.high_watermark_bytes.min(self.read_ahead_bytes);
+        (self.low_watermark_bytes.min(high), high)
+    }
+}
+
+/// 投屏会话参数
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// 主持人审核模式：房间里新点的歌先进入待审核列表，同意后才会投屏
+    pub require_approval: bool,
+    /// 音视频分离：声音投到该设备（按 UDN、设备名或“厂商 型号”匹配），画面仍投到所选设备
+    pub audio_device: Option<String>,
+    /// 退出时除了停止播放，再清除渲染器的媒体地址（部分电视会因此回到主界面）
+    pub clear_on_exit: bool,
+    /// 控制台输入 `s` 切歌前先确认，避免在副歌中途误切
+    pub confirm_skip: bool,
+    /// 房间里 10 秒内有这么多人投票切歌时才切到下一首；0 表示不接受投票
+    pub skip_votes: u32,
+}
+
+/// 功能开关的初始值，运行中可通过控制台切换（见 [`crate::features`]）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FeaturesConfig {
+    pub compat_soap_only: bool,
+    pub transport_watchdog: bool,
+    pub proxy_read_ahead: bool,
+    pub auto_advance: bool,
+    pub direct_link: bool,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            compat_soap_only: false,
+            transport_watchdog: true,
+            proxy_read_ahead: true,
+            auto_advance: true,
+            direct_link: true,
+        }
+    }
+}
+
+/// 启动时的新版本检查
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    /// 是否在启动时查询 GitHub 上的最新版本（默认关闭）
+    pub check: bool,
+    /// 发布所在的仓库
+    pub repository: String,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            check: false,
+            repository: "aspromise/ktv-casting".to_string(),
+        }
+    }
+}
+
+/// Web 遥控（HTTP 控制接口，与媒体代理共用端口）
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RemoteConfig {
+    /// 是否开启遥控接口（默认关闭）
+    pub enabled: bool,
+    /// 固定的主持人令牌；不设置时每次启动随机生成
+    pub host_token: Option<String>,
+    /// 固定的访客令牌；不设置时每次启动随机生成
+    pub guest_token: Option<String>,
+}
+
+/// 连接房间（ktv-song-web）的参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RoomConfig {
+    /// 附加到每个房间请求（HTTP 和 WebSocket）的请求头，房间部署在 Cloudflare Access
+    /// 或 nginx 认证之后时用来带上凭据，也可以覆盖 `User-Agent`
+    pub headers: HashMap<String, String>,
+    /// WebSocket 心跳的发送间隔（秒）；反向代理空闲超时较短时调小
+    pub ping_interval_secs: u64,
+    /// 超过多少秒没有收到 pong 视为连接已断开并重连
+    pub pong_timeout_secs: u64,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        Self {
+            headers: HashMap::new(),
+            ping_interval_secs: 30,
+            pong_timeout_secs: 60,
+        }
+    }
+}
+
+/// B站视频链接的获取
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BilibiliConfig {
+    /// 画质偏好：名称（`1080P60`、`1080P`、`720P`、`480P`…）或 playurl 的 qn 代码；
+    /// 视频没有该画质时依次降级
+    pub quality: String,
+    /// 登录 Cookie `SESSDATA`，登录后可获取 1080P 和仅限会员的视频
+    pub sessdata: Option<String>,
+    /// 登录 Cookie `bili_jct`
+    pub bili_jct: Option<String>,
+    /// 浏览器导出的 cookies.txt（Netscape 格式），从中读取上面两项；配置里直接写的优先
+    pub cookie_file: Option<PathBuf>,
+    /// 优先使用的 upos CDN 主机（如 `upos-sz-mirrorcos`），解析出的链接按顺序改写到这些主机
+    pub cdn_hosts: Vec<String>,
+}
+
+impl Default for BilibiliConfig {
+    fn default() -> Self {
+        Self {
+            quality: "1080P60".to_string(),
+            sessdata: None,
+            bili_jct: None,
+            cookie_file: None,
+            cdn_hosts: Vec::new(),
+        }
+    }
+}
+
+/// 控制台界面
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// 界面语言：`zh-CN` 或 `en`；不设置时按环境变量 `LANG` 判断（见 [`crate::i18n`]）
+    pub language: Option<String>,
+}
+
+impl Config {
+    /// 配置文件路径：优先使用 `KTV_CASTING_CONFIG`，否则为当前目录下的 `ktv-casting.toml`
+    pub fn path() -> PathBuf {
+        std::env::var_os("KTV_CASTING_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE))
+    }
+
+    /// 读取配置文件；文件不存在时返回默认配置
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            log::debug!("未找到配置文件 {}，使用默认配置", path.display());
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("无法读取配置文件 {}", path.display()))?;
+        let config: Config = toml::from_str(&content)
+            .with_context(|| format!("无法解析配置文件 {}", path.display()))?;
+        log::info!("已加载配置文件 {}", path.display());
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [discovery]
+            mx = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.discovery.mx, 1);
+        assert_eq!(config.discovery.timeout_secs, 5);
+        assert_eq!(config.discovery.max_concurrent_fetches, 4);
+    }
+
+    #[test]
+    fn test_action_timeout_override() {
+        let config: Config = toml::from_str(
+            r#"
+            [dlna]
+            action_timeout_ms = 3000
+
+            [dlna.action_timeouts_ms]
+            SetAVTransportURI = 10000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.dlna.action_timeout("SetAVTransportURI"),
+            Duration::from_secs(10)
+        );
+        assert_eq!(config.dlna.action_timeout("Play"), Duration::from_secs(3));
+        assert_eq!(config.dlna.url_strategy, UrlStrategy::Auto);
+        assert_eq!(config.dlna.transcode, TranscodeStrategy::Off);
+    }
+
+    #[test]
+    fn test_proxy_watermarks_are_clamped() {
+        let config: Config = toml::from_str(
+            r#"
+            [proxy]
+            chunk_size = 65536
+            read_ahead_bytes = 1048576
+            high_watermark_bytes = 4194304
+            low_watermark_bytes = 2097152
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.proxy.read_ahead_chunks(), 16);
+        assert_eq!(config.proxy.watermarks(), (1048576, 1048576));
+    }
+}
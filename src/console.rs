@@ -0,0 +1,1934 @@
+//! 控制台交互：打印会话事件，读取用户输入的单字母命令
+
+use arboard::Clipboard;
+use ktv_casting::bilibili_parser::{SearchResult, quality_name, search_videos};
+use ktv_casting::blocklist::DeviceBlocklist;
+use ktv_casting::dlna_controller::{DlnaDevice, TransportActions, TransportState};
+use ktv_casting::features::Feature;
+use ktv_casting::history::{PartySummary, SongRecord};
+use ktv_casting::i18n::text;
+use ktv_casting::playlist_manager::{QueuedSong, RoomConnection, SongList};
+use ktv_casting::remote::RemoteTokens;
+use ktv_casting::session::{AudioSetting, CastingSession, SessionEvent, SkipReason, state_matches};
+use ktv_casting::session_manager::SessionManager;
+use ktv_casting::song_source;
+use ktv_casting::tr;
+use ktv_casting::update_check::Release;
+use ktv_casting::utils::{sanitize_title, truncate_to_width};
+use ktv_casting::volume_curve::{VolumeCalibration, VolumeCurveStore};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+
+// 每页提示的命令数量
+const HINTS_PER_PAGE: usize = 3;
+// 控制台中歌曲标题的最大显示宽度（列）
+const TITLE_WIDTH: usize = 40;
+
+// 适合在控制台显示的标题：去掉控制字符并按显示宽度截断
+fn display_title(title: &str) -> String {
+    truncate_to_width(&sanitize_title(title), TITLE_WIDTH)
+}
+
+// 把终端窗口标题设为正在播放的歌曲（OSC 0），输出被重定向时不做任何事
+fn set_terminal_title(song: &str) {
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        let _ = write!(stdout, "\x1b]0;ktv-casting · {}\x07", display_title(song));
+        let _ = stdout.flush();
+    }
+}
+
+// 音乐模式律动条使用的字符，从低到高
+const BEAT_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// 音乐模式进度条宽度（列）
+const BAR_WIDTH: usize = 24;
+// 音乐模式状态行的最小终端宽度（列），更窄时只显示提示
+const MIN_STATUS_WIDTH: usize = 20;
+// 无法获取终端宽度时假定的宽度（列）
+const DEFAULT_COLUMNS: usize = 80;
+// 音乐模式下重新获取终端宽度的间隔，调整窗口大小后随之变化
+const COLUMNS_REFRESH: Duration = Duration::from_secs(5);
+
+// 终端宽度（列）：优先读 `COLUMNS`，否则问 `stty size`，都不行时为 80
+fn terminal_columns() -> usize {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|v| v.parse().ok()) {
+        return columns;
+    }
+    std::process::Command::new("stty")
+        .arg("size")
+        .stdin(std::process::Stdio::inherit())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|v| v.parse().ok())
+        })
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+// 秒数格式化为 `m:ss`
+fn format_clock(secs: u32) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// 解析跳转目标：`1:30`、`90` 为绝对位置，`+10`、`-10` 相对 `current_secs`
+fn parse_seek_target(arg: &str, current_secs: u32) -> Option<u32> {
+    if let Some(delta) = arg.strip_prefix('+') {
+        return current_secs.checked_add(delta.parse().ok()?);
+    }
+    if let Some(delta) = arg.strip_prefix('-') {
+        return Some(current_secs.saturating_sub(delta.parse().ok()?));
+    }
+    match arg.split_once(':') {
+        Some((minutes, secs)) => {
+            let secs: u32 = secs.parse().ok()?;
+            if secs >= 60 {
+                return None;
+            }
+            minutes
+                .parse::<u32>()
+                .ok()?
+                .checked_mul(60)?
+                .checked_add(secs)
+        }
+        None => arg.parse().ok(),
+    }
+}
+
+// `width` 列宽的进度条；已播放的部分为实心，媒体代理已缓存（`buffered_percent`）
+// 但尚未播放的部分用较暗的填充，跳转到这个范围内不会卡顿
+fn progress_bar(current_secs: u32, total_secs: u32, buffered_percent: u8, width: usize) -> String {
+    let filled = if total_secs == 0 {
+        0
+    } else {
+        (current_secs.min(total_secs) as usize * width) / total_secs as usize
+    };
+    let buffered = (buffered_percent.min(100) as usize * width / 100).max(filled);
+    "█".repeat(filled) + &"▓".repeat(buffered - filled) + &"░".repeat(width - buffered)
+}
+
+// 音量滑块的列宽
+const VOLUME_SLIDER_WIDTH: usize = 20;
+
+// 音量滑块，例如“🔊 音量 ████████░░░░░░░░░░░░ 40%”
+fn volume_slider(volume: u32) -> String {
+    let volume = volume.min(100);
+    let filled = volume as usize * VOLUME_SLIDER_WIDTH / 100;
+    tr!(
+        "🔊 音量 {}{} {}%",
+        "🔊 Volume {}{} {}%",
+        "█".repeat(filled),
+        "░".repeat(VOLUME_SLIDER_WIDTH - filled),
+        volume
+    )
+}
+
+/// 音乐模式的一行状态：进度条、时间和随播放时间变化的律动条
+///
+/// 律动条由当前秒数推算，同一秒总是画出相同的形状，暂停时画面随之静止。
+/// 终端宽度 `columns` 放不下完整的一行时换成只有缩短的进度条和时间的紧凑版，
+/// 窄于 [`MIN_STATUS_WIDTH`] 时只显示提示；状态行折行后就无法原地刷新了。
+fn visualizer_line(
+    current_secs: u32,
+    total_secs: u32,
+    buffered_percent: u8,
+    columns: usize,
+) -> String {
+    let clock = format!(
+        "{} / {}",
+        format_clock(current_secs),
+        format_clock(total_secs)
+    );
+    // 留出最后一列，光标停在行尾时部分终端会自动换行
+    let usable = columns.saturating_sub(1);
+    if usable < MIN_STATUS_WIDTH {
+        return text("终端太窄", "Too narrow").to_string();
+    }
+    if usable < BAR_WIDTH + clock.len() + BEAT_LEVELS.len() + 2 {
+        let width = usable - clock.len() - 1;
+        return format!(
+            "{} {}",
+            progress_bar(current_secs, total_secs, buffered_percent, width),
+            clock
+        );
+    }
+    let bar = progress_bar(current_secs, total_secs, buffered_percent, BAR_WIDTH);
+    let beat: String = (0..BEAT_LEVELS.len() as u32)
+        .map(|i| {
+            let mut x = current_secs.wrapping_mul(2_654_435_761) ^ i.wrapping_mul(40_503);
+            x ^= x >> 13;
+            x = x.wrapping_mul(0x5bd1_e995);
+            x ^= x >> 15;
+            BEAT_LEVELS[x as usize % BEAT_LEVELS.len()]
+        })
+        .collect();
+    format!("{} {} {}", bar, clock, beat)
+}
+
+// 状态行放得下时在行尾加上房间的心跳往返时间
+fn with_latency(line: String, rtt_ms: Option<u32>, columns: usize) -> String {
+    let Some(rtt_ms) = rtt_ms else {
+        return line;
+    };
+    let latency = format!(" {}ms", rtt_ms);
+    if line.chars().count() + latency.len() < columns {
+        line + &latency
+    } else {
+        line
+    }
+}
+
+// 选择设备时可用的输入（见 main.rs 的 select_device），每项为（中文，英文）
+const SELECT_DEVICE_HINTS: [(&str, &str); 5] = [
+    ("<编号> 选择设备", "<number> select device"),
+    (
+        "i <编号> 设备详情（型号、服务、地址）",
+        "i <number> device details (model, services, location)",
+    ),
+    (
+        "回车 等搜索结束后选择置顶设备",
+        "Enter  wait for the search and pick the top device",
+    ),
+    (
+        "b <编号> 永久隐藏设备",
+        "b <number> hide device permanently",
+    ),
+    (
+        "u [序号] 查看/取消隐藏的设备",
+        "u [index] list/unhide hidden devices",
+    ),
+];
+// 投屏中始终可用、但不在分页提示里的命令，每项为（中文，英文）
+const GENERAL_HINTS: [(&str, &str); 4] = [
+    ("h 分页提示", "h paged hints"),
+    ("? 全部命令", "? all commands"),
+    ("u 新版本说明", "u release notes"),
+    ("q / Ctrl-C 退出", "q / Ctrl-C quit"),
+];
+
+// 投屏中可用的命令，渲染器不允许的动作不会出现
+fn command_hints(actions: TransportActions, approval: bool) -> Vec<&'static str> {
+    let mut hints = Vec::new();
+    if actions.pause {
+        hints.push(text("p 暂停", "p pause"));
+    }
+    if actions.play {
+        hints.push(text("r 继续", "r resume"));
+    }
+    if actions.seek {
+        hints.push(text("e <m:ss|±秒> 跳转", "e <m:ss|±secs> seek"));
+    }
+    hints.push(text("s 下一首", "s next song"));
+    hints.push(text("v [0-100] 音量（滑块）", "v [0-100] volume (slider)"));
+    hints.extend([
+        text("= / - 音量加减", "= / - volume up/down"),
+        text("+ / _ 音量微调", "+ / _ fine volume"),
+    ]);
+    hints.extend([
+        text("b <-10..10> 低音", "b <-10..10> bass"),
+        text("t <-10..10> 高音", "t <-10..10> treble"),
+        text("c <-100..100> 左右平衡", "c <-100..100> balance"),
+        text("f [名称] 功能开关", "f [name] feature toggles"),
+        text(
+            "g [序号 目标 | ^ 序号 | - 序号 | +] 歌单（调整顺序/置顶/删除/撤销删除）",
+            "g [index target | ^ index | - index | +] queue (reorder/pin/remove/undo)",
+        ),
+        text(
+            "d [序号] 搜索设备（带序号: 切换到该设备）",
+            "d [index] search devices (with index: switch to it)",
+        ),
+        text("/<关键词> 搜索B站", "/<keywords> search Bilibili"),
+        text("o <序号> 点搜索结果", "o <index> request a search result"),
+        text(
+            "j [序号] 已唱列表（带序号: 再点一次）",
+            "j [index] sung songs (with index: request again)",
+        ),
+        text("i 播放统计", "i playback stats"),
+        text(
+            "S 本场统计（首数、时长、切歌、每人点歌数）",
+            "S party stats (songs, time, skips, per requester)",
+        ),
+        text(
+            "z 播放状态（预期/电视实际）",
+            "z playback state (expected/actual)",
+        ),
+        text("R 立即重连房间", "R reconnect to the room now"),
+        text(
+            "F / F5 与房间重新同步歌单",
+            "F / F5 resync the queue with the room",
+        ),
+        text(
+            "P 重放上一首（不改动房间歌单）",
+            "P replay the previous song (room queue untouched)",
+        ),
+        text(
+            "T [序号 | 房间链接 设备序号] 多房间（切换/新开）",
+            "T [index | room link device] rooms (switch/open)",
+        ),
+        text("k 校准音量曲线", "k calibrate volume curve"),
+        text("w 歌词显示开关", "w toggle lyrics"),
+        text(
+            "m 音乐模式（进度条与律动）",
+            "m music mode (progress and beat)",
+        ),
+        text("a 遥控令牌", "a remote tokens"),
+        text(
+            "x [b] 复制房间链接（b: 当前歌曲链接）",
+            "x [b] copy room link (b: current song link)",
+        ),
+    ]);
+    if approval {
+        hints.extend([
+            text("y [序号] 同意", "y [index] approve"),
+            text("n [序号] 拒绝", "n [index] reject"),
+            text("l 待审核列表", "l pending list"),
+        ]);
+    }
+    hints
+}
+
+/// 按当前可用的动作生成分页的命令提示，渲染器不允许的动作不会出现
+fn hint_pages(actions: TransportActions, approval: bool) -> Vec<String> {
+    command_hints(actions, approval)
+        .chunks(HINTS_PER_PAGE)
+        .map(|page| {
+            tr!(
+                "{} | h 更多 | ? 全部",
+                "{} | h more | ? all",
+                page.join(" | ")
+            )
+        })
+        .collect()
+}
+
+// 完整帮助：按阶段列出全部命令
+fn print_help(actions: TransportActions, approval: bool) {
+    println!("{}", text("选择设备时:", "While selecting a device:"));
+    for (zh, en) in SELECT_DEVICE_HINTS {
+        println!("  {}", text(zh, en));
+    }
+    println!(
+        "{}",
+        text(
+            "投屏中（当前可用的命令）:",
+            "While casting (commands available now):"
+        )
+    );
+    for hint in command_hints(actions, approval)
+        .into_iter()
+        .chain(GENERAL_HINTS.map(|(zh, en)| text(zh, en)))
+    {
+        println!("  {}", hint);
+    }
+}
+
+// 一条历史记录的统计行，例如 `23:14 BV1xx411c7mD 健康分 70（起播 4.2s，卡顿 1，重试 0，降级 0，恢复 0）`
+fn format_record(record: &SongRecord) -> String {
+    let latency = match record.start_latency {
+        Some(latency) => format!("{:.1}s", latency.as_secs_f32()),
+        None => "-".to_string(),
+    };
+    tr!(
+        "{} {} 健康分 {}（起播 {}，卡顿 {}，重试 {}，降级 {}，恢复 {}）",
+        "{} {} health {} (start {}, stalls {}, retries {}, downgrades {}, recoveries {})",
+        record.started_at.format("%H:%M"),
+        display_title(&record.song),
+        record.health_score(),
+        latency,
+        record.stalls,
+        record.retries,
+        record.downgrades,
+        record.recoveries
+    )
+}
+
+// 渲染器传输状态的显示文字
+fn transport_state_name(state: &TransportState) -> String {
+    match state {
+        TransportState::Playing => text("播放中", "playing").to_string(),
+        TransportState::PausedPlayback => text("已暂停", "paused").to_string(),
+        TransportState::Stopped => text("已停止", "stopped").to_string(),
+        TransportState::Transitioning => text("缓冲中", "transitioning").to_string(),
+        TransportState::NoMediaPresent => text("没有媒体", "no media").to_string(),
+        TransportState::Other(other) => other.clone(),
+    }
+}
+
+fn expected_state_name(expected_playing: bool) -> &'static str {
+    if expected_playing {
+        text("播放中", "playing")
+    } else {
+        text("不播放（暂停/切歌中）", "not playing (paused/switching)")
+    }
+}
+
+// 开关状态的显示文字
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        text("开", "on")
+    } else {
+        text("关", "off")
+    }
+}
+
+fn audio_setting_name(setting: AudioSetting) -> &'static str {
+    match setting {
+        AudioSetting::Bass => text("低音", "Bass"),
+        AudioSetting::Treble => text("高音", "Treble"),
+        AudioSetting::Balance => text("左右平衡", "Balance"),
+    }
+}
+
+// 写入系统剪贴板；剪贴板对象需要一直保留，否则 Linux 下内容会随之消失
+fn copy_to_clipboard(clipboard: &mut Option<Clipboard>, text: &str) -> Result<(), arboard::Error> {
+    if clipboard.is_none() {
+        *clipboard = Some(Clipboard::new()?);
+    }
+    clipboard.as_mut().unwrap().set_text(text)
+}
+
+// 校准时每次按 =/- 调整的设备音量
+const CALIBRATION_STEP: u32 = 2;
+
+fn print_calibration_prompt(calibration: &VolumeCalibration) {
+    let (step, total) = calibration.progress();
+    println!(
+        "{}",
+        tr!(
+            "音量校准 {}/{}：调到你觉得相当于 {}% 的响度（当前设备音量 {}）。=/- 调整，输入数值直接设置，回车确认，q 退出",
+            "Volume calibration {}/{}: adjust until it sounds like {}% (device volume {}). =/- to adjust, type a number to set it, Enter to confirm, q to quit",
+            step,
+            total,
+            calibration.target(),
+            calibration.device_volume()
+        )
+    );
+}
+
+// 校准过程中的一行输入；返回 false 表示校准结束（完成或放弃）
+async fn handle_calibration_input(
+    session: &CastingSession,
+    curves: &mut VolumeCurveStore,
+    calibration: &mut VolumeCalibration,
+    line: &str,
+) -> anyhow::Result<bool> {
+    match line {
+        "q" => {
+            println!(
+                "{}",
+                text(
+                    "已退出音量校准，音量曲线未改变",
+                    "Calibration cancelled, volume curve unchanged"
+                )
+            );
+            return Ok(false);
+        }
+        "" => {
+            if let Some(curve) = calibration.confirm() {
+                let udn = session.volume_device().udn.clone();
+                session.set_volume_curve(curve.clone());
+                curves.save(&udn, curve)?;
+                println!(
+                    "{}",
+                    text("音量校准完成，已保存", "Calibration finished and saved")
+                );
+                return Ok(false);
+            }
+        }
+        "=" => calibration.set_device_volume(calibration.device_volume() + CALIBRATION_STEP),
+        "-" => calibration
+            .set_device_volume(calibration.device_volume().saturating_sub(CALIBRATION_STEP)),
+        other => match other.parse() {
+            Ok(volume) => calibration.set_device_volume(volume),
+            Err(_) => println!(
+                "{}",
+                text(
+                    "校准中只接受 =、-、数值、回车或 q",
+                    "During calibration only =, -, a number, Enter or q are accepted"
+                )
+            ),
+        },
+    }
+    session
+        .set_device_volume(calibration.device_volume())
+        .await?;
+    print_calibration_prompt(calibration);
+    Ok(true)
+}
+
+// 打印遥控接口地址和两种令牌
+fn print_remote_tokens(session: &CastingSession, tokens: &RemoteTokens) {
+    println!(
+        "{}",
+        tr!(
+            "遥控接口: POST http://{}:{}/api/remote/<动作>?token=<令牌>",
+            "Remote API: POST http://{}:{}/api/remote/<action>?token=<token>",
+            session.server_ip(),
+            session.server_port()
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "  主持人令牌: {}（全部动作，包括暂停/继续/跳转/音量/切歌/切换设备）",
+            "  Host token: {} (all actions, incl. pause/resume/seek/volume/skip/switch device)",
+            tokens.host
+        )
+    );
+    println!(
+        "{}",
+        tr!(
+            "  访客令牌: {}（仅点歌、投票切歌）",
+            "  Guest token: {} (add songs and vote-skip only)",
+            tokens.guest
+        )
+    );
+}
+
+// 歌单中一首歌的显示名称：有标题时为“标题 (BV号)”
+fn queued_title(song: &QueuedSong) -> String {
+    match &song.title {
+        Some(title) => format!("{} ({})", display_title(title), song.song),
+        None => song.song.clone(),
+    }
+}
+
+// 点歌人后缀，例如“（点歌人: 小明）”；房间没有提供昵称时为空
+fn requester_suffix(requester: Option<&str>) -> String {
+    requester
+        .map(|name| tr!("（点歌人: {}）", " (requested by {})", sanitize_title(name)))
+        .unwrap_or_default()
+}
+
+// 打印正在演唱的歌曲（醒目显示）和待唱列表
+fn print_song_list(list: &SongList) {
+    match list.current() {
+        Some(current) if std::io::stdout().is_terminal() => println!(
+            "\x1b[1m▶ {}{}\x1b[0m",
+            queued_title(current),
+            requester_suffix(current.requester.as_deref())
+        ),
+        Some(current) => println!(
+            "▶ {}{}",
+            queued_title(current),
+            requester_suffix(current.requester.as_deref())
+        ),
+        None => println!("{}", text("还没有开始演唱", "Nothing sung yet")),
+    }
+    if list.pending.is_empty() {
+        println!("{}", text("  （没有待唱的歌曲）", "  (queue is empty)"));
+    }
+    for (index, song) in list.pending.iter().enumerate() {
+        println!(
+            "  {}. {}{}",
+            index + 1,
+            queued_title(song),
+            requester_suffix(song.requester.as_deref())
+        );
+    }
+    if !list.pending.is_empty() {
+        println!(
+            "{}",
+            text(
+                "输入 g <序号> <目标序号> 调整顺序，g ^ <序号> 置顶，g - <序号> 删除",
+                "Type g <index> <target> to reorder, g ^ <index> to pin as next, g - <index> to remove"
+            )
+        );
+    }
+}
+
+// 打印搜索结果，序号从 1 开始
+fn print_search_results(results: &[SearchResult]) {
+    if results.is_empty() {
+        println!("{}", text("没有搜索到视频", "No videos found"));
+        return;
+    }
+    for (index, result) in results.iter().enumerate() {
+        println!(
+            "  {}. {} ({})",
+            index + 1,
+            display_title(&result.info.display_name()),
+            format_clock(result.info.duration_secs)
+        );
+    }
+    println!(
+        "{}",
+        text("输入 o <序号> 点歌", "Type o <index> to request a song")
+    );
+}
+
+// 打印可以切换到的设备，序号从 1 开始
+fn print_switch_candidates(devices: &[DlnaDevice]) {
+    if devices.is_empty() {
+        println!("{}", text("没有找到其他设备", "No other devices found"));
+        return;
+    }
+    for (index, device) in devices.iter().enumerate() {
+        println!(
+            "  {}. {} ({})",
+            index + 1,
+            device.friendly_name,
+            device.model_summary()
+        );
+    }
+    println!(
+        "{}",
+        text(
+            "输入 d <序号> 切换设备，歌单和播放进度保持不变；T <房间链接> <序号> 在该设备上跟随另一个房间",
+            "Type d <index> to switch, keeping the queue and position; T <room link> <index> follows another room on it"
+        )
+    );
+}
+
+// 列出所有房间（标签页），序号从 1 开始，当前选中的标上 ▶
+fn print_tabs(sessions: &SessionManager) {
+    let active = sessions.active_index();
+    for (index, tab) in sessions.tabs().iter().enumerate() {
+        println!(
+            "{} {}. {} → {}",
+            if index == active { "▶" } else { " " },
+            index + 1,
+            tr!("房间 {}", "Room {}", tab.session.playlist().room_id()),
+            tab.session.device().friendly_name
+        );
+    }
+}
+
+// T <房间链接> <设备序号>：在 `device` 上跟随另一个房间，新房间成为当前房间
+async fn open_room_tab(
+    sessions: &SessionManager,
+    session: &CastingSession,
+    curves: &VolumeCurveStore,
+    link: &str,
+    device: DlnaDevice,
+) -> anyhow::Result<()> {
+    if sessions
+        .tabs()
+        .iter()
+        .any(|tab| tab.session.device().udn == device.udn)
+    {
+        return Err(anyhow::anyhow!(text(
+            "该设备已在播放另一个房间",
+            "That device is already playing another room"
+        )));
+    }
+    let room = crate::parse_room_link(link)?;
+    let playlist = session.playlist().for_room(&room.base_url, room.room_id);
+    let volume_curve = curves.get(&device.udn);
+    let new_session = session
+        .for_room(device, Arc::new(playlist))
+        .with_volume_curve(volume_curve);
+    let index = sessions.add(room.normalized_url, new_session);
+    // 先选中再开始跟随，连接房间时的事件直接显示
+    sessions.select(index);
+    sessions.start(index).await;
+    print_tabs(sessions);
+    Ok(())
+}
+
+// 未选中的房间的事件：只提示需要留意的几类，前面标上房间号
+fn print_background_event(room_id: &str, tab: usize, event: &SessionEvent) {
+    let message = match event {
+        SessionEvent::Casting { song } => {
+            tr!("正在投屏: {}", "Casting: {}", display_title(song))
+        }
+        SessionEvent::ApprovalRequested { song } => tr!(
+            "待审核: {}（输入 T {} 切换过去处理）",
+            "Pending approval: {} (T {} switches there)",
+            display_title(song),
+            tab + 1
+        ),
+        SessionEvent::RendererLost => text(
+            "⚠ 渲染器无法访问，重新上线后会从断开处继续播放",
+            "⚠ Renderer unreachable, playback resumes where it left off once it is back",
+        )
+        .to_string(),
+        SessionEvent::RoomConnectionStatus {
+            status: RoomConnection::Closed { .. },
+        } => tr!(
+            "⚠ 房间已关闭（输入 T {} 切换过去）",
+            "⚠ The room was closed (T {} switches there)",
+            tab + 1
+        ),
+        SessionEvent::Error { operation, message } => {
+            tr!("{}失败: {}", "{} failed: {}", operation, message)
+        }
+        _ => return,
+    };
+    println!("[{}] {}", tr!("房间 {}", "Room {}", room_id), message);
+}
+
+// 打印已唱列表，序号从 1 开始，最近唱的在最后
+fn print_sung_list(list: &SongList) {
+    if list.sung.is_empty() {
+        println!("{}", text("还没有已唱的歌曲", "No songs sung yet"));
+    }
+    for (index, song) in list.sung.iter().enumerate() {
+        println!("  {}. {}", index + 1, queued_title(song));
+    }
+    if !list.sung.is_empty() {
+        println!(
+            "{}",
+            text(
+                "输入 j <序号> 再点一次",
+                "Type j <index> to request a song again"
+            )
+        );
+    }
+}
+
+/// 控制台 `q` 命令的退出请求
+#[derive(Debug, Clone, Copy)]
+pub struct QuitRequest {
+    /// 是否停止渲染器上的播放；为 false 时不操作渲染器
+    pub stop_renderer: bool,
+}
+
+/// 打印派对汇总：唱了几首、唱了多久、切歌次数、点得最多的歌、每人点了几首和平均健康分
+pub fn print_party_summary(summary: &PartySummary) {
+    let minutes = summary.duration.as_secs() / 60;
+    let played_minutes = summary.played.as_secs() / 60;
+    let (song, count) = &summary.most_requested;
+    println!("{}", text("──── 本场 KTV ────", "──── Tonight's KTV ────"));
+    println!(
+        "{}",
+        tr!(
+            "  共唱 {} 首，历时 {} 小时 {} 分（实际播放 {} 小时 {} 分），切歌 {} 次",
+            "  {} songs in {} h {} min ({} h {} min played), {} skipped",
+            summary.songs,
+            minutes / 60,
+            minutes % 60,
+            played_minutes / 60,
+            played_minutes % 60,
+            summary.skips
+        )
+    );
+    if *count > 1 {
+        println!(
+            "{}",
+            tr!(
+                "  点得最多: {}（{} 次）",
+                "  Most requested: {} ({} times)",
+                display_title(song),
+                count
+            )
+        );
+    }
+    for (name, count) in &summary.requesters {
+        println!(
+            "{}",
+            tr!("  {}: {} 首", "  {}: {} songs", sanitize_title(name), count)
+        );
+    }
+    println!(
+        "{}",
+        tr!(
+            "  平均健康分: {}",
+            "  Average health: {}",
+            summary.average_health
+        )
+    );
+}
+
+/// 启动控制台：事件打印任务和命令读取任务
+///
+/// `sessions` 为所有房间的会话，命令作用于当前选中的一个（`T` 命令切换），
+/// 其他房间只提示需要留意的事件；`curves` 用于保存 `k` 命令校准出的音量曲线；
+/// `remote` 为开启遥控时的令牌，启动时和 `a` 命令会打印出来。
+///
+/// 返回的接收端在用户输入 `q` 并确认后收到退出请求。
+pub fn spawn(
+    sessions: SessionManager,
+    mut curves: VolumeCurveStore,
+    remote: Option<RemoteTokens>,
+) -> oneshot::Receiver<QuitRequest> {
+    let (quit_tx, quit_rx) = oneshot::channel();
+    let mut events = sessions.subscribe();
+    // 最近一次更新检查发现的新版本，u 命令显示其更新说明
+    let update: Arc<Mutex<Option<Release>>> = Default::default();
+    let latest = update.clone();
+    // 是否逐行打印歌词（w 命令切换）
+    let show_lyrics = Arc::new(AtomicBool::new(true));
+    let lyrics_enabled = show_lyrics.clone();
+    // 音乐模式（m 命令切换）：没有电视画面时，在终端原地刷新进度条和律动条
+    let music_mode = Arc::new(AtomicBool::new(false));
+    let visualizer_enabled = music_mode.clone();
+    // 最近一次报告的播放位置（秒），e 命令的相对跳转以它为准
+    let position = Arc::new(AtomicU32::new(0));
+    let last_position = position.clone();
+    let tabs = sessions.clone();
+    tokio::spawn(async move {
+        // 状态行已画出且未换行时，打印其他事件前需要先清掉
+        let mut status_drawn = false;
+        let mut last_total = 0;
+        // 媒体代理已缓存的百分比，换歌时清零
+        let mut buffered_percent = 0;
+        // 当前房间最近一次的心跳往返时间（毫秒），切换房间后清空
+        let mut room_latency: Option<u32> = None;
+        let mut latency_tab = tabs.active_index();
+        // 每个房间上一次的待唱列表，用于提示新点的歌；收到第一份歌单前没有
+        let mut known_pending: HashMap<usize, Vec<QueuedSong>> = HashMap::new();
+        // 终端宽度及其获取时间，音乐模式下定期刷新
+        let mut columns = DEFAULT_COLUMNS;
+        let mut columns_checked: Option<Instant> = None;
+        loop {
+            let (tab, event) = match events.recv().await {
+                Ok((tab, event)) => (tab, Ok(event)),
+                Err(e) => (tabs.active_index(), Err(e)),
+            };
+            // 未选中的房间：记下歌单，只提示需要留意的事件；新版本提示与房间无关
+            if tab != tabs.active_index() && !matches!(event, Ok(SessionEvent::UpdateAvailable(_)))
+            {
+                match event {
+                    Ok(SessionEvent::QueueUpdated { list }) => {
+                        known_pending.insert(tab, list.pending);
+                    }
+                    Ok(event) => {
+                        if let Some(room) = tabs.get(tab) {
+                            let room_id = room.session.playlist().room_id();
+                            print_background_event(&room_id, tab, &event);
+                        }
+                    }
+                    Err(_) => {}
+                }
+                continue;
+            }
+            if latency_tab != tab {
+                latency_tab = tab;
+                room_latency = None;
+            }
+            if let Ok(SessionEvent::Progress {
+                current_secs,
+                total_secs,
+            }) = &event
+            {
+                last_position.store(*current_secs, Ordering::Relaxed);
+                last_total = *total_secs;
+            }
+            if status_drawn
+                && !matches!(
+                    event,
+                    Ok(SessionEvent::Progress { .. }
+                        | SessionEvent::Seeking { .. }
+                        | SessionEvent::Buffered { .. }
+                        | SessionEvent::RoomLatency { .. })
+                )
+            {
+                print!("\r\x1b[K");
+                status_drawn = false;
+            }
+            if visualizer_enabled.load(Ordering::Relaxed)
+                && columns_checked.is_none_or(|t| t.elapsed() >= COLUMNS_REFRESH)
+            {
+                columns = terminal_columns();
+                columns_checked = Some(Instant::now());
+            }
+            match event {
+                Ok(SessionEvent::Progress {
+                    current_secs,
+                    total_secs,
+                }) if visualizer_enabled.load(Ordering::Relaxed) => {
+                    let mut stdout = std::io::stdout();
+                    let _ = write!(
+                        stdout,
+                        "\r\x1b[K{}",
+                        with_latency(
+                            visualizer_line(current_secs, total_secs, buffered_percent, columns),
+                            room_latency,
+                            columns
+                        )
+                    );
+                    let _ = stdout.flush();
+                    status_drawn = true;
+                }
+                // 先把进度画到目标位置，渲染器确认后下一次进度报告会校正
+                Ok(SessionEvent::Seeking { position_secs }) => {
+                    last_position.store(position_secs, Ordering::Relaxed);
+                    if visualizer_enabled.load(Ordering::Relaxed) {
+                        let mut stdout = std::io::stdout();
+                        let _ = write!(
+                            stdout,
+                            "\r\x1b[K{}",
+                            with_latency(
+                                visualizer_line(
+                                    position_secs,
+                                    last_total,
+                                    buffered_percent,
+                                    columns
+                                ),
+                                room_latency,
+                                columns
+                            )
+                        );
+                        let _ = stdout.flush();
+                        status_drawn = true;
+                    } else {
+                        println!(
+                            "{}",
+                            tr!("跳转到 {}", "Seeking to {}", format_clock(position_secs))
+                        );
+                    }
+                }
+                Ok(SessionEvent::Buffered { percent }) => buffered_percent = percent,
+                Ok(SessionEvent::RoomLatency { rtt_ms }) => room_latency = Some(rtt_ms),
+                Ok(SessionEvent::RoomConnectionStatus { status }) => match status {
+                    RoomConnection::Connected => {
+                        println!("{}", text("已连接到房间", "Connected to the room"))
+                    }
+                    RoomConnection::Reconnecting {
+                        attempt,
+                        retry_in_secs,
+                    } => println!(
+                        "{}",
+                        tr!(
+                            "⚠ 与房间的连接已断开，{} 秒后第 {} 次重连（输入 R 立即重连）",
+                            "⚠ Lost the room connection, retry #{1} in {0}s (R reconnects now)",
+                            retry_in_secs,
+                            attempt
+                        )
+                    ),
+                    RoomConnection::Closed { reason } => {
+                        match reason {
+                            Some(reason) => println!(
+                                "{}",
+                                tr!("⚠ 房间已关闭: {}", "⚠ The room was closed: {}", reason)
+                            ),
+                            None => println!("{}", text("⚠ 房间已关闭", "⚠ The room was closed")),
+                        }
+                        println!(
+                            "{}",
+                            text(
+                                "粘贴新的房间链接进入新房间，或输入 q 退出",
+                                "Paste a new room link to join another room, or q to quit"
+                            )
+                        );
+                    }
+                },
+                Ok(SessionEvent::QueueUpdated { list }) => {
+                    // 第一份歌单里的歌不算新点的
+                    if let Some(known) = known_pending.get(&tab) {
+                        for song in list.pending.iter().filter(|song| !known.contains(song)) {
+                            println!(
+                                "{}{}",
+                                tr!("新点歌: {}", "New request: {}", queued_title(song)),
+                                requester_suffix(song.requester.as_deref())
+                            );
+                        }
+                    }
+                    known_pending.insert(tab, list.pending);
+                }
+                Ok(SessionEvent::Casting { song }) => {
+                    buffered_percent = 0;
+                    set_terminal_title(&song);
+                    println!(
+                        "{}",
+                        tr!("正在投屏: {}", "Casting: {}", display_title(&song))
+                    )
+                }
+                Ok(SessionEvent::SongInfo { info, .. }) => {
+                    let name = info.display_name();
+                    set_terminal_title(&name);
+                    let requester = match tabs.get(tab) {
+                        Some(room) => room.session.playlist().current_requester().await,
+                        None => None,
+                    };
+                    println!(
+                        "{}{}",
+                        tr!("正在播放: {}", "Now playing: {}", display_title(&name)),
+                        requester_suffix(requester.as_deref())
+                    )
+                }
+                Ok(SessionEvent::Recovering {
+                    song,
+                    position_secs,
+                }) => println!(
+                    "{}",
+                    tr!(
+                        "渲染器意外停止，正在从{}秒处恢复: {}",
+                        "Renderer stopped unexpectedly, resuming at {}s: {}",
+                        position_secs,
+                        display_title(&song)
+                    )
+                ),
+                Ok(SessionEvent::RendererLost) => {
+                    println!(
+                        "{}",
+                        text(
+                            "⚠ 渲染器无法访问，重新上线后会从断开处继续播放",
+                            "⚠ Renderer unreachable, playback resumes where it left off once it is back"
+                        )
+                    )
+                }
+                // 只提示与预期不一致的状态，例如有人用电视遥控器暂停了
+                Ok(SessionEvent::TransportStateChanged {
+                    state,
+                    expected_playing,
+                }) if !state_matches(&state, expected_playing) => println!(
+                    "{}",
+                    tr!(
+                        "⚠ 电视实际状态: {}，预期: {}（可能被电视遥控器操作了）",
+                        "⚠ The TV is actually {}, expected {} (maybe someone used the TV remote)",
+                        transport_state_name(&state),
+                        expected_state_name(expected_playing)
+                    )
+                ),
+                Ok(SessionEvent::DeviceSwitched { device }) => {
+                    println!("{}", tr!("已切换到 {}", "Switched to {}", device))
+                }
+                Ok(SessionEvent::RendererReconnected) => {
+                    println!("{}", text("渲染器已重新上线", "Renderer is back online"))
+                }
+                Ok(SessionEvent::ApprovalRequested { song }) => {
+                    println!(
+                        "{}",
+                        tr!(
+                            "待审核: {}（输入 y 同意，n 拒绝，l 查看列表后可用 y/n <序号> 指定）",
+                            "Pending approval: {} (y approve, n reject, l list, then y/n <index> for a specific one)",
+                            display_title(&song)
+                        )
+                    )
+                }
+                Ok(SessionEvent::QualitySelected { quality, .. }) => {
+                    println!("{}", tr!("画质: {}", "Quality: {}", quality_name(quality)))
+                }
+                Ok(SessionEvent::SkipVoted { votes, needed }) => {
+                    println!(
+                        "{}",
+                        tr!("投票切歌 {}/{}", "Skip votes {}/{}", votes, needed)
+                    )
+                }
+                Ok(SessionEvent::SongRejected { song }) => {
+                    println!(
+                        "{}",
+                        tr!("已拒绝: {}", "Rejected: {}", display_title(&song))
+                    )
+                }
+                Ok(SessionEvent::SongSkipped { song, reason }) => {
+                    let reason = match reason {
+                        SkipReason::RegionLocked => {
+                            text("该视频地区受限，已跳过", "Region-locked, skipped")
+                        }
+                        SkipReason::ChargingExclusive => text(
+                            "需要充电专属，无法播放，已跳过",
+                            "Charging-exclusive, skipped",
+                        ),
+                        _ => text("无法播放，已跳过", "Unplayable, skipped"),
+                    };
+                    println!("{}: {}", reason, display_title(&song))
+                }
+                Ok(SessionEvent::Paused) => println!("{}", text("已暂停", "Paused")),
+                Ok(SessionEvent::Resumed) => println!("{}", text("已继续播放", "Resumed")),
+                Ok(SessionEvent::VolumeChanged { volume }) => println!("{}", volume_slider(volume)),
+                Ok(SessionEvent::VolumeDbChanged { db }) => {
+                    println!("{}", tr!("音量: {:.1} dB", "Volume: {:.1} dB", db))
+                }
+                Ok(SessionEvent::AudioSettingChanged { setting, value }) => {
+                    println!("{}: {}", audio_setting_name(setting), value)
+                }
+                Ok(SessionEvent::ProxyDown { message }) => {
+                    println!(
+                        "{}",
+                        tr!(
+                            "⚠ 媒体代理不可用，正在自动重启: {}",
+                            "⚠ Media proxy is down, restarting: {}",
+                            message
+                        )
+                    )
+                }
+                Ok(SessionEvent::ProxyRestored { port }) => {
+                    println!(
+                        "{}",
+                        tr!(
+                            "媒体代理已恢复（端口 {}）",
+                            "Media proxy restored (port {})",
+                            port
+                        )
+                    )
+                }
+                Ok(SessionEvent::FeatureToggled { feature, enabled }) => println!(
+                    "{}",
+                    tr!(
+                        "{}（{}）: {}",
+                        "{} ({}): {}",
+                        feature.name(),
+                        feature.description(),
+                        on_off(enabled)
+                    )
+                ),
+                Ok(SessionEvent::Error { operation, message }) => {
+                    println!("{}", tr!("{}失败: {}", "{} failed: {}", operation, message))
+                }
+                Ok(SessionEvent::LyricsLoaded { lines, .. }) => println!(
+                    "{}",
+                    tr!(
+                        "已加载歌词（{} 行，输入 w 开关歌词显示）",
+                        "Lyrics loaded ({} lines, w toggles them)",
+                        lines
+                    )
+                ),
+                Ok(SessionEvent::LyricLine { text, next, .. })
+                    if lyrics_enabled.load(Ordering::Relaxed) =>
+                {
+                    // 当前行醒目显示，下一行缩进预览，方便提前看词
+                    println!("♪ {}", text);
+                    if let Some(next) = next {
+                        println!("    {}", next);
+                    }
+                }
+                Ok(SessionEvent::UpdateAvailable(release)) => {
+                    println!(
+                        "{}",
+                        tr!(
+                            "新版本 {} 可用（输入 u 查看更新说明）",
+                            "Version {} is available (u shows the release notes)",
+                            release.version
+                        )
+                    );
+                    *latest.lock().unwrap() = Some(release);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let session = sessions.active().session;
+    if session.requires_approval() {
+        println!(
+            "{}",
+            text(
+                "已开启审核模式：新歌需要同意后才会投屏",
+                "Approval mode is on: new songs are cast only after you approve them"
+            )
+        );
+    }
+    if let Some(tokens) = &remote {
+        print_remote_tokens(&session, tokens);
+    }
+
+    tokio::spawn(async move {
+        let mut hint_page = 0;
+        let mut clipboard = None;
+        let mut calibration: Option<VolumeCalibration> = None;
+        // 最近一次搜索的结果，o 命令按序号点歌
+        let mut search_results: Vec<SearchResult> = Vec::new();
+        // 最近一次 d 命令搜索到的其他设备
+        let mut switch_candidates: Vec<DlnaDevice> = Vec::new();
+        // 最近一次用 g - 删除的歌曲及其在待唱列表中的位置，g + 撤销
+        let mut removed: Option<(QueuedSong, usize)> = None;
+        // 正在投屏时输入 q 需要再确认一次
+        let mut confirming_quit = false;
+        // 开启切歌确认时，输入 s 后等待确认
+        let mut confirming_skip = false;
+        // 单独输入 v 后，下一行的数字直接作为音量
+        let mut entering_volume = false;
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            // 命令作用于当前选中的房间
+            let session = sessions.active().session;
+            if confirming_quit {
+                confirming_quit = false;
+                let stop_renderer = match line.trim() {
+                    "y" => true,
+                    "k" => false,
+                    _ => {
+                        println!("{}", text("已取消退出", "Quit cancelled"));
+                        continue;
+                    }
+                };
+                let _ = quit_tx.send(QuitRequest { stop_renderer });
+                break;
+            }
+            if std::mem::take(&mut confirming_skip) {
+                match line.trim() {
+                    "y" => {
+                        if let Err(e) = session.next().await {
+                            println!("{}", e);
+                        }
+                    }
+                    _ => println!("{}", text("已取消切歌", "Skip cancelled")),
+                }
+                continue;
+            }
+            if std::mem::take(&mut entering_volume)
+                && let Ok(volume) = line.trim().parse::<u32>()
+            {
+                if let Err(e) = session.set_volume(volume).await {
+                    println!("{}", e);
+                }
+                continue;
+            }
+            if let Some(active) = calibration.as_mut() {
+                match handle_calibration_input(&session, &mut curves, active, line.trim()).await {
+                    Ok(true) => {}
+                    Ok(false) => calibration = None,
+                    Err(e) => {
+                        println!("{}", e);
+                        calibration = None;
+                    }
+                }
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let result = match command {
+                "" => Ok(()),
+                "h" => {
+                    if !session.proxy_healthy() {
+                        println!(
+                            "{}",
+                            text(
+                                "⚠ 媒体代理不可用，正在自动重启",
+                                "⚠ Media proxy is down, restarting"
+                            )
+                        );
+                    }
+                    // 每次输入 h 翻到下一页提示；页数随可用动作变化
+                    let pages = hint_pages(
+                        session.transport_actions().await,
+                        session.requires_approval(),
+                    );
+                    println!("{}", pages[hint_page % pages.len()]);
+                    hint_page = (hint_page + 1) % pages.len();
+                    Ok(())
+                }
+                "?" => {
+                    print_help(
+                        session.transport_actions().await,
+                        session.requires_approval(),
+                    );
+                    Ok(())
+                }
+                "q" => {
+                    if session.playlist().get_song_playing().await.is_none() {
+                        let _ = quit_tx.send(QuitRequest {
+                            stop_renderer: true,
+                        });
+                        break;
+                    }
+                    println!(
+                        "{}",
+                        text(
+                            "确定退出？当前正在投屏（y 退出并停止电视上的播放，k 退出但不操作电视，回车取消）",
+                            "Quit? A song is being cast (y quit and stop the TV, k quit and leave the TV alone, Enter cancels)"
+                        )
+                    );
+                    confirming_quit = true;
+                    Ok(())
+                }
+                "p" => session.pause().await,
+                "r" => session.resume().await,
+                "e" => match words
+                    .next()
+                    .and_then(|arg| parse_seek_target(arg, position.load(Ordering::Relaxed)))
+                {
+                    Some(target) => session.seek(target).await,
+                    None => {
+                        println!(
+                            "{}",
+                            text(
+                                "用法: e 1:30（绝对位置）或 e +10 / e -10（相对秒数）",
+                                "Usage: e 1:30 (absolute) or e +10 / e -10 (relative seconds)"
+                            )
+                        );
+                        Ok(())
+                    }
+                },
+                "s" if session.confirms_skip() => {
+                    println!(
+                        "{}",
+                        text(
+                            "确定切到下一首？（y 切歌，回车取消）",
+                            "Skip to the next song? (y skips, Enter cancels)"
+                        )
+                    );
+                    confirming_skip = true;
+                    Ok(())
+                }
+                "s" => session.next().await,
+                "v" => match words.next().and_then(|v| v.parse().ok()) {
+                    Some(volume) => session.set_volume(volume).await,
+                    None => session.volume().await.map(|v| {
+                        println!("{}", volume_slider(v));
+                        println!(
+                            "{}",
+                            text(
+                                "输入 0-100 直接设置音量，其他输入照常作为命令",
+                                "Type 0-100 to set the volume; anything else runs as a command"
+                            )
+                        );
+                        entering_volume = true;
+                    }),
+                },
+                // Shift 加 =/- 即 +/_，用于微调
+                "=" | "-" => session.step_volume(command == "=", false).await,
+                "+" | "_" => session.step_volume(command == "+", true).await,
+                "b" | "t" | "c" => {
+                    let setting = match command {
+                        "b" => AudioSetting::Bass,
+                        "t" => AudioSetting::Treble,
+                        _ => AudioSetting::Balance,
+                    };
+                    match words.next().and_then(|v| v.parse().ok()) {
+                        Some(value) => session.set_audio_setting(setting, value).await,
+                        None => {
+                            println!("{}", tr!("用法: {} <数值>", "Usage: {} <value>", command));
+                            Ok(())
+                        }
+                    }
+                }
+                "f" => match words.next() {
+                    // 不带参数时列出全部开关
+                    None => {
+                        for feature in Feature::ALL {
+                            println!(
+                                "  {} [{}] {}",
+                                feature.name(),
+                                on_off(session.feature_enabled(feature)),
+                                feature.description()
+                            );
+                        }
+                        Ok(())
+                    }
+                    Some(name) => match Feature::from_name(name) {
+                        Some(feature) => {
+                            session.set_feature(feature, !session.feature_enabled(feature));
+                            Ok(())
+                        }
+                        None => {
+                            println!("{}", tr!("未知的功能开关: {}", "Unknown feature: {}", name));
+                            Ok(())
+                        }
+                    },
+                },
+                "g" if words.clone().next() == Some("+") => match removed.take() {
+                    None => Err(anyhow::anyhow!(text(
+                        "没有可以撤销的删除",
+                        "Nothing to undo"
+                    ))),
+                    Some((song, index)) => session
+                        .playlist()
+                        .restore_song(&song, index)
+                        .await
+                        .map(|()| {
+                            println!("{}", tr!("已恢复: {}", "Restored: {}", queued_title(&song)))
+                        })
+                        .map_err(|e| anyhow::anyhow!(tr!("撤销失败: {}", "Failed to undo: {}", e))),
+                },
+                "g" if words.clone().next() == Some("^") => {
+                    let index = words.nth(1).map(|v| v.parse::<usize>());
+                    match session.playlist().fetch_song_list().await {
+                        Err(e) => Err(anyhow::anyhow!(tr!(
+                            "获取歌单失败: {}",
+                            "Failed to fetch the queue: {}",
+                            e
+                        ))),
+                        Ok(list) => match index {
+                            Some(Ok(index)) if (1..=list.pending.len()).contains(&index) => {
+                                let song = &list.pending[index - 1];
+                                match &song.id {
+                                    None => Err(anyhow::anyhow!(text(
+                                        "房间没有提供歌曲编号，无法置顶",
+                                        "The room does not provide song ids; cannot pin"
+                                    ))),
+                                    Some(id) => session
+                                        .playlist()
+                                        .prioritize(id)
+                                        .await
+                                        .map(|()| {
+                                            println!(
+                                                "{}",
+                                                tr!(
+                                                    "已置顶，下一首: {}",
+                                                    "Pinned, up next: {}",
+                                                    queued_title(song)
+                                                )
+                                            )
+                                        })
+                                        .map_err(|e| {
+                                            anyhow::anyhow!(tr!(
+                                                "置顶失败: {}",
+                                                "Failed to pin: {}",
+                                                e
+                                            ))
+                                        }),
+                                }
+                            }
+                            _ => Err(anyhow::anyhow!(text(
+                                "序号有误，用法: g ^ <序号>（先输入 g 查看歌单）",
+                                "Invalid index; usage: g ^ <index> (type g to list the queue)"
+                            ))),
+                        },
+                    }
+                }
+                "g" if words.clone().next() == Some("-") => {
+                    let index = words.nth(1).map(|v| v.parse::<usize>());
+                    match session.playlist().fetch_song_list().await {
+                        Err(e) => Err(anyhow::anyhow!(tr!(
+                            "获取歌单失败: {}",
+                            "Failed to fetch the queue: {}",
+                            e
+                        ))),
+                        Ok(list) => match index {
+                            Some(Ok(index)) if (1..=list.pending.len()).contains(&index) => {
+                                let song = &list.pending[index - 1];
+                                match &song.id {
+                                    None => Err(anyhow::anyhow!(text(
+                                        "房间没有提供歌曲编号，无法删除",
+                                        "The room does not provide song ids; cannot remove"
+                                    ))),
+                                    Some(id) => match session.playlist().remove_song(id).await {
+                                        Ok(()) => {
+                                            println!(
+                                                "{}",
+                                                tr!(
+                                                    "已删除: {}（输入 g + 撤销）",
+                                                    "Removed: {} (type g + to undo)",
+                                                    queued_title(song)
+                                                )
+                                            );
+                                            removed = Some((song.clone(), index - 1));
+                                            Ok(())
+                                        }
+                                        Err(e) => Err(anyhow::anyhow!(tr!(
+                                            "删除失败: {}",
+                                            "Failed to remove: {}",
+                                            e
+                                        ))),
+                                    },
+                                }
+                            }
+                            _ => Err(anyhow::anyhow!(text(
+                                "序号有误，用法: g - <序号>（先输入 g 查看歌单）",
+                                "Invalid index; usage: g - <index> (type g to list the queue)"
+                            ))),
+                        },
+                    }
+                }
+                "g" => {
+                    let from = words.next().map(|v| v.parse::<usize>());
+                    let to = words.next().map(|v| v.parse::<usize>());
+                    match session.playlist().fetch_song_list().await {
+                        Err(e) => Err(anyhow::anyhow!(tr!(
+                            "获取歌单失败: {}",
+                            "Failed to fetch the queue: {}",
+                            e
+                        ))),
+                        Ok(list) => match (from, to) {
+                            (None, _) => {
+                                print_song_list(&list);
+                                Ok(())
+                            }
+                            (Some(Ok(from)), Some(Ok(to)))
+                                if (1..=list.pending.len()).contains(&from)
+                                    && (1..=list.pending.len()).contains(&to) =>
+                            {
+                                let song = &list.pending[from - 1];
+                                match &song.id {
+                                    None => Err(anyhow::anyhow!(text(
+                                        "房间没有提供歌曲编号，无法调整顺序",
+                                        "The room does not provide song ids; cannot reorder"
+                                    ))),
+                                    Some(id) => session
+                                        .playlist()
+                                        .move_song(id, to - 1)
+                                        .await
+                                        .map(|()| {
+                                            println!(
+                                                "{}",
+                                                tr!(
+                                                    "已移到第 {} 位: {}",
+                                                    "Moved to #{}: {}",
+                                                    to,
+                                                    queued_title(song)
+                                                )
+                                            )
+                                        })
+                                        .map_err(|e| {
+                                            anyhow::anyhow!(tr!(
+                                                "调整顺序失败: {}",
+                                                "Failed to reorder: {}",
+                                                e
+                                            ))
+                                        }),
+                                }
+                            }
+                            _ => Err(anyhow::anyhow!(text(
+                                "序号有误，用法: g <序号> <目标序号>（先输入 g 查看歌单）",
+                                "Invalid index; usage: g <index> <target> (type g to list the queue)"
+                            ))),
+                        },
+                    }
+                }
+                "j" => {
+                    let index = words.next().map(|v| v.parse::<usize>());
+                    match session.playlist().fetch_song_list().await {
+                        Err(e) => Err(anyhow::anyhow!(tr!(
+                            "获取歌单失败: {}",
+                            "Failed to fetch the queue: {}",
+                            e
+                        ))),
+                        Ok(list) => match index {
+                            None => {
+                                print_sung_list(&list);
+                                Ok(())
+                            }
+                            Some(Ok(index)) if (1..=list.sung.len()).contains(&index) => {
+                                let song = &list.sung[index - 1];
+                                session
+                                    .playlist()
+                                    .add_song(&song.url)
+                                    .await
+                                    .map(|()| {
+                                        println!(
+                                            "{}",
+                                            tr!(
+                                                "已重新点歌: {}",
+                                                "Requested again: {}",
+                                                queued_title(song)
+                                            )
+                                        )
+                                    })
+                                    .map_err(|e| {
+                                        anyhow::anyhow!(tr!(
+                                            "点歌失败: {}",
+                                            "Failed to request the song: {}",
+                                            e
+                                        ))
+                                    })
+                            }
+                            Some(_) => Err(anyhow::anyhow!(text(
+                                "序号有误（输入 j 查看已唱列表）",
+                                "Invalid index (type j to list sung songs)"
+                            ))),
+                        },
+                    }
+                }
+                // 关键词可以紧跟在 / 后面，也可以隔一个空格
+                search if search.starts_with('/') => {
+                    let keyword = line.trim_start()[1..].trim();
+                    if keyword.is_empty() {
+                        println!(
+                            "{}",
+                            text(
+                                "用法: /<关键词>，例如 /晴天 KTV",
+                                "Usage: /<keywords>, e.g. /晴天 KTV"
+                            )
+                        );
+                        Ok(())
+                    } else {
+                        search_videos(keyword)
+                            .await
+                            .map(|results| {
+                                print_search_results(&results);
+                                search_results = results;
+                            })
+                            .map_err(|e| {
+                                anyhow::anyhow!(tr!("搜索失败: {}", "Search failed: {}", e))
+                            })
+                    }
+                }
+                "d" => match words.next().map(|v| v.parse::<usize>()) {
+                    None => {
+                        println!("{}", text("正在搜索设备…", "Searching for devices…"));
+                        // 黑名单读不出来时不过滤，不影响切换
+                        let blocklist = DeviceBlocklist::load_default().ok();
+                        let current = session.device().udn;
+                        match session.renderer().discover_devices().await {
+                            Ok(devices) => {
+                                switch_candidates = devices
+                                    .into_iter()
+                                    .filter(|d| {
+                                        d.udn != current
+                                            && !blocklist.as_ref().is_some_and(|b| b.contains(d))
+                                    })
+                                    .collect();
+                                print_switch_candidates(&switch_candidates);
+                                Ok(())
+                            }
+                            Err(e) => Err(anyhow::anyhow!(tr!(
+                                "搜索设备失败: {}",
+                                "Device search failed: {}",
+                                e
+                            ))),
+                        }
+                    }
+                    Some(Ok(index)) if (1..=switch_candidates.len()).contains(&index) => {
+                        let result = session
+                            .switch_device(switch_candidates[index - 1].clone())
+                            .await;
+                        // 音量曲线按设备校准，换成新设备的曲线
+                        session.set_volume_curve(curves.get(&session.volume_device().udn));
+                        result
+                    }
+                    Some(_) => Err(anyhow::anyhow!(text(
+                        "序号有误（先输入 d 搜索设备）",
+                        "Invalid index (type d to search devices first)"
+                    ))),
+                },
+                "o" => match words.next().map(|v| v.parse::<usize>()) {
+                    Some(Ok(index)) if (1..=search_results.len()).contains(&index) => {
+                        let result = &search_results[index - 1];
+                        session
+                            .playlist()
+                            .add_song(&result.bv_id)
+                            .await
+                            .map(|()| {
+                                println!(
+                                    "{}",
+                                    tr!(
+                                        "已点歌: {}",
+                                        "Requested: {}",
+                                        display_title(&result.info.display_name())
+                                    )
+                                )
+                            })
+                            .map_err(|e| {
+                                anyhow::anyhow!(tr!(
+                                    "点歌失败: {}",
+                                    "Failed to request the song: {}",
+                                    e
+                                ))
+                            })
+                    }
+                    _ => Err(anyhow::anyhow!(text(
+                        "序号有误（先用 /<关键词> 搜索）",
+                        "Invalid index (search with /<keywords> first)"
+                    ))),
+                },
+                "R" => {
+                    session.reconnect_room();
+                    Ok(())
+                }
+                // F5 键在多数终端里送出 ESC [ 1 5 ~
+                "F" | "F5" | "\u{1b}[15~" => session.resync_room().await.map(|song| match song {
+                    Some(song) => println!(
+                        "{}",
+                        tr!(
+                            "已与房间同步，当前歌曲: {}",
+                            "Resynced with the room, current song: {}",
+                            display_title(&song)
+                        )
+                    ),
+                    None => println!(
+                        "{}",
+                        text(
+                            "已与房间同步，房间里没有正在演唱的歌曲",
+                            "Resynced with the room, nothing is being sung"
+                        )
+                    ),
+                }),
+                "P" => session.replay_previous().await,
+                "T" => match (words.next(), words.next()) {
+                    (None, _) => {
+                        print_tabs(&sessions);
+                        Ok(())
+                    }
+                    (Some(link), index) if link.contains('/') => {
+                        match index.map(|v| v.parse::<usize>()) {
+                            Some(Ok(index)) if (1..=switch_candidates.len()).contains(&index) => {
+                                let device = switch_candidates[index - 1].clone();
+                                open_room_tab(&sessions, &session, &curves, link, device).await
+                            }
+                            _ => Err(anyhow::anyhow!(text(
+                                "用法: T <房间链接> <设备序号>（先输入 d 搜索设备）",
+                                "Usage: T <room link> <device index> (type d to search devices first)"
+                            ))),
+                        }
+                    }
+                    (Some(index), _) => match index.parse::<usize>() {
+                        Ok(index) if index >= 1 && sessions.select(index - 1).is_some() => {
+                            print_tabs(&sessions);
+                            Ok(())
+                        }
+                        _ => Err(anyhow::anyhow!(text(
+                            "序号有误（输入 T 列出房间）",
+                            "Invalid index (type T to list rooms)"
+                        ))),
+                    },
+                },
+                // 粘贴房间链接：进入新房间
+                link if link.starts_with("http://") || link.starts_with("https://") => {
+                    match crate::parse_room_link(link) {
+                        Ok(room) => {
+                            println!("{}", tr!("进入房间 {}", "Joining room {}", room.room_id));
+                            session.switch_room(&room.base_url, room.room_id).await;
+                            sessions.set_room_url(sessions.active_index(), room.normalized_url);
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                "S" => {
+                    match PartySummary::from_records(&session.history().await, chrono::Local::now())
+                    {
+                        Some(summary) => print_party_summary(&summary),
+                        None => println!("{}", text("还没有播放记录", "No playback history yet")),
+                    }
+                    Ok(())
+                }
+                "i" => {
+                    let history = session.history().await;
+                    if history.is_empty() {
+                        println!("{}", text("还没有播放记录", "No playback history yet"));
+                    }
+                    for record in &history {
+                        println!("  {}", format_record(record));
+                    }
+                    Ok(())
+                }
+                "z" => {
+                    let expected_playing = session.expects_playing();
+                    match session.transport_state().await {
+                        Some(state) => {
+                            let line = tr!(
+                                "预期: {} | 电视实际: {}",
+                                "Expected: {} | TV actually: {}",
+                                expected_state_name(expected_playing),
+                                transport_state_name(&state)
+                            );
+                            if state_matches(&state, expected_playing) {
+                                println!("{}", line);
+                            } else if std::io::stdout().is_terminal() {
+                                println!("\x1b[1;33m⚠ {}\x1b[0m", line);
+                            } else {
+                                println!("⚠ {}", line);
+                            }
+                        }
+                        None => println!(
+                            "{}",
+                            tr!(
+                                "预期: {} | 电视实际: 尚未查询",
+                                "Expected: {} | TV actually: not queried yet",
+                                expected_state_name(expected_playing)
+                            )
+                        ),
+                    }
+                    Ok(())
+                }
+                "x" => {
+                    let link = match words.next() {
+                        Some("b") => session
+                            .playlist()
+                            .get_song_playing()
+                            .await
+                            .and_then(|song| song_source::page_url(&song)),
+                        _ => Some(sessions.active().room_url),
+                    };
+                    match link {
+                        Some(link) => match copy_to_clipboard(&mut clipboard, &link) {
+                            Ok(()) => println!("{}", tr!("已复制: {}", "Copied: {}", link)),
+                            Err(e) => println!(
+                                "{}",
+                                tr!(
+                                    "无法访问剪贴板（{}），请手动复制: {}",
+                                    "Clipboard unavailable ({}), copy it manually: {}",
+                                    e,
+                                    link
+                                )
+                            ),
+                        },
+                        None => println!(
+                            "{}",
+                            text("当前没有正在播放的歌曲", "Nothing is playing right now")
+                        ),
+                    }
+                    Ok(())
+                }
+                "k" => {
+                    let active = VolumeCalibration::start(&session.volume_curve());
+                    let result = session.set_device_volume(active.device_volume()).await;
+                    if result.is_ok() {
+                        print_calibration_prompt(&active);
+                        calibration = Some(active);
+                    }
+                    result
+                }
+                "w" => {
+                    let enabled = !show_lyrics.fetch_xor(true, Ordering::Relaxed);
+                    println!("{}", tr!("歌词显示: {}", "Lyrics: {}", on_off(enabled)));
+                    Ok(())
+                }
+                "m" => {
+                    if std::io::stdout().is_terminal() {
+                        let enabled = !music_mode.fetch_xor(true, Ordering::Relaxed);
+                        println!("{}", tr!("音乐模式: {}", "Music mode: {}", on_off(enabled)));
+                    } else {
+                        println!(
+                            "{}",
+                            text("音乐模式需要在终端中运行", "Music mode needs a terminal")
+                        );
+                    }
+                    Ok(())
+                }
+                "a" => {
+                    match &remote {
+                        Some(tokens) => print_remote_tokens(&session, tokens),
+                        None => println!(
+                            "{}",
+                            text(
+                                "遥控未开启（在配置文件中设置 [remote] enabled = true）",
+                                "Remote control is off (set [remote] enabled = true in the config file)"
+                            )
+                        ),
+                    }
+                    Ok(())
+                }
+                "u" => {
+                    match update.lock().unwrap().as_ref() {
+                        Some(release) => {
+                            println!(
+                                "{}",
+                                tr!(
+                                    "新版本 {}: {}",
+                                    "Version {}: {}",
+                                    release.version,
+                                    release.url
+                                )
+                            );
+                            println!("{}", release.summary);
+                        }
+                        None => println!("{}", text("没有发现新版本", "No new version found")),
+                    }
+                    Ok(())
+                }
+                "l" => {
+                    let pending = session.pending_songs().await;
+                    if pending.is_empty() {
+                        println!(
+                            "{}",
+                            text("没有待审核的歌曲", "No songs are waiting for approval")
+                        );
+                    }
+                    for (index, song) in pending.iter().enumerate() {
+                        let title = match session.song_info(song).await {
+                            Some(info) => {
+                                format!("{} ({})", display_title(&info.display_name()), song)
+                            }
+                            None => song.clone(),
+                        };
+                        println!("  {}. {}", index + 1, title);
+                    }
+                    Ok(())
+                }
+                "y" | "n" => {
+                    let pending = session.pending_songs().await;
+                    let index = words.next().map(|v| v.parse::<usize>());
+                    let song = match index {
+                        None => pending.first(),
+                        Some(Ok(index)) if index >= 1 => pending.get(index - 1),
+                        Some(_) => None,
+                    };
+                    match song {
+                        Some(song) if command == "y" => session.approve(song).await,
+                        Some(song) => session.reject(song).await,
+                        None if pending.is_empty() => {
+                            println!(
+                                "{}",
+                                text("没有待审核的歌曲", "No songs are waiting for approval")
+                            );
+                            Ok(())
+                        }
+                        None => Err(anyhow::anyhow!(text(
+                            "序号有误，用法: y/n [序号]（先输入 l 查看待审核列表）",
+                            "Invalid index; usage: y/n [index] (type l to list pending songs)"
+                        ))),
+                    }
+                }
+                other => {
+                    println!(
+                        "{}",
+                        tr!(
+                            "未知命令: {}（输入 h 查看可用命令，? 查看全部命令）",
+                            "Unknown command: {} (h for hints, ? for all commands)",
+                            other
+                        )
+                    );
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+        }
+    });
+    quit_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hint_pages_hide_disallowed_actions() {
+        let actions = TransportActions {
+            pause: false,
+            ..TransportActions::ALL
+        };
+        let pages = hint_pages(actions, false);
+        assert!(pages.iter().all(|page| !page.contains("暂停")));
+        assert_eq!(pages.len(), 9);
+
+        assert_eq!(hint_pages(TransportActions::ALL, true).len(), 11);
+    }
+
+    #[test]
+    fn test_parse_seek_target() {
+        assert_eq!(parse_seek_target("1:30", 0), Some(90));
+        assert_eq!(parse_seek_target("75", 10), Some(75));
+        assert_eq!(parse_seek_target("+10", 50), Some(60));
+        assert_eq!(parse_seek_target("-10", 5), Some(0));
+        assert_eq!(parse_seek_target("1:75", 0), None);
+        assert_eq!(parse_seek_target("abc", 0), None);
+    }
+
+    #[test]
+    fn test_volume_slider() {
+        let slider = volume_slider(40);
+        assert!(slider.contains(&("█".repeat(8) + &"░".repeat(12))));
+        assert!(slider.ends_with("40%"));
+        assert!(volume_slider(150).ends_with("100%"));
+    }
+
+    #[test]
+    fn test_status_line_latency() {
+        let line = visualizer_line(90, 180, 0, 80);
+        assert!(with_latency(line.clone(), Some(42), 80).ends_with(" 42ms"));
+        assert_eq!(with_latency(line.clone(), None, 80), line);
+        // 放不下时不显示
+        let compact = visualizer_line(90, 180, 0, 30);
+        assert_eq!(with_latency(compact.clone(), Some(42), 30), compact);
+    }
+
+    #[test]
+    fn test_visualizer_line() {
+        let line = visualizer_line(90, 180, 0, 80);
+        assert!(line.starts_with(&"█".repeat(BAR_WIDTH / 2)));
+        assert!(line.contains("1:30 / 3:00"));
+        // 同一秒画出相同的律动条，暂停时画面静止
+        assert_eq!(line, visualizer_line(90, 180, 0, 80));
+        assert!(visualizer_line(0, 0, 0, 80).starts_with(&"░".repeat(BAR_WIDTH)));
+
+        // 窄终端：缩短进度条、去掉律动条，整行不超过终端宽度
+        let compact = visualizer_line(90, 180, 0, 30);
+        assert_eq!(compact.chars().count(), 29);
+        assert!(compact.ends_with("1:30 / 3:00"));
+        assert!(!visualizer_line(90, 180, 0, 12).contains("1:30"));
+
+        // 已缓存到 75%：播放位置之后到缓存末尾画成较暗的填充
+        let bar = progress_bar(90, 180, 75, 20);
+        assert_eq!(bar, "█".repeat(10) + &"▓".repeat(5) + &"░".repeat(5));
+        // 跳转到缓存之外时不画缓存填充
+        assert_eq!(progress_bar(180, 180, 30, 4), "█".repeat(4));
+    }
+}
@@ -1,4 +1,12 @@
-use chrono::{NaiveTime, Timelike};
+use crate::bilibili_parser::VideoStream;
+use crate::chromecast::{self, CastCommand, CastDevice};
+use crate::config::{DiscoveryConfig, DlnaConfig, TranscodeStrategy, UrlStrategy};
+use crate::features::{Feature, FeatureFlags};
+use crate::media_server::{AUDIO_SUFFIX, JOINED_SUFFIX, TRANSCODE_SUFFIX};
+use crate::tr;
+use crate::transcode::{sink_supports, stream_mime};
+use crate::utils::sanitize_title;
+use chrono::{DateTime, Local, NaiveTime, Timelike};
 use futures::future::try_join_all;
 use futures::stream::StreamExt;
 use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
@@ -6,8 +14,10 @@ use rupnp::Device;
 use rupnp::http::Uri;
 use rupnp::ssdp::{SearchTarget, URN};
 use std::collections::HashMap;
-use std::net::IpAddr;
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 fn extract_xml_tag_value(xml: &str, tag: &str) -> Option<String> {
     // 解析XML标签值，支持带命名空间属性的标签
@@ -31,6 +41,14 @@ fn extract_xml_tag_value(xml: &str, tag: &str) -> Option<String> {
     None
 }
 
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
 fn xml_escape(s: &str) -> String {
     // Minimal XML escaping for element text nodes.
     // (Enough to keep SOAP XML well-formed when URLs contain & and friends.)
@@ -41,29 +59,147 @@ fn xml_escape(s: &str) -> String {
         .replace('\'', "&apos;")
 }
 
-fn build_didl_lite_metadata(title: &str, media_url: &str, protocol_info: Option<&str>) -> String {
+/// protocolInfo 第四个字段中的 DLNA 参数（DLNA.ORG_OP / DLNA.ORG_FLAGS）
+///
+/// 很多电视只有在这里声明了支持 Range 请求时才允许拖动进度条。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DlnaFlags {
+    /// 支持按字节区间请求（Range），对应 DLNA.ORG_OP 的第二位
+    pub byte_seek: bool,
+    /// 支持按时间区间请求（TimeSeekRange.dlna.org），对应 DLNA.ORG_OP 的第一位
+    pub time_seek: bool,
+    /// 流式传输模式（适合音视频）
+    pub streaming_transfer: bool,
+    /// 后台传输模式
+    pub background_transfer: bool,
+    /// 允许连接暂停（渲染器暂停时不会断开）
+    pub connection_stall: bool,
+    /// 遵循 DLNA 1.5
+    pub dlna_v15: bool,
+}
+
+impl DlnaFlags {
+    const STREAMING_TRANSFER_MODE: u32 = 1 << 24;
+    const BACKGROUND_TRANSFER_MODE: u32 = 1 << 22;
+    const CONNECTION_STALL: u32 = 1 << 21;
+    const DLNA_V15: u32 = 1 << 20;
+
+    /// 经过本地代理的地址：代理会转发 Range 请求，可以按字节跳转
+    pub const PROXIED: DlnaFlags = DlnaFlags {
+        byte_seek: true,
+        time_seek: false,
+        streaming_transfer: true,
+        background_transfer: true,
+        connection_stall: true,
+        dlna_v15: true,
+    };
+
+    /// 不保证支持 Range 的地址：不声明任何跳转能力
+    pub const NO_SEEK: DlnaFlags = DlnaFlags {
+        byte_seek: false,
+        ..Self::PROXIED
+    };
+
+    /// DLNA.ORG_OP 的值，例如 `01`
+    pub fn op(&self) -> String {
+        format!("{}{}", self.time_seek as u8, self.byte_seek as u8)
+    }
+
+    /// DLNA.ORG_FLAGS 的值：8 位主标志 + 24 位保留的 0
+    pub fn flags(&self) -> String {
+        let mut primary = 0u32;
+        for (enabled, bit) in [
+            (self.streaming_transfer, Self::STREAMING_TRANSFER_MODE),
+            (self.background_transfer, Self::BACKGROUND_TRANSFER_MODE),
+            (self.connection_stall, Self::CONNECTION_STALL),
+            (self.dlna_v15, Self::DLNA_V15),
+        ] {
+            if enabled {
+                primary |= bit;
+            }
+        }
+        format!("{:08X}{}", primary, "0".repeat(24))
+    }
+
+    /// 完整的 protocolInfo，例如 `http-get:*:video/mp4:DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=...`
+    pub fn protocol_info(&self, mime: &str) -> String {
+        format!(
+            "http-get:*:{}:DLNA.ORG_OP={};DLNA.ORG_CI=0;DLNA.ORG_FLAGS={}",
+            mime,
+            self.op(),
+            self.flags()
+        )
+    }
+}
+
+fn build_didl_lite_metadata(
+    title: &str,
+    media_url: &str,
+    flags: DlnaFlags,
+    subtitle_url: Option<&str>,
+) -> String {
+    build_didl_lite(title, media_url, flags, subtitle_url, MediaKind::Video)
+}
+
+// 媒体类型，决定 DIDL 中的 MIME 与 upnp:class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaKind {
+    Video,
+    // 纯音频（DASH 音轨），投给音箱/回音壁
+    Audio,
+}
+
+fn build_didl_lite(
+    title: &str,
+    media_url: &str,
+    flags: DlnaFlags,
+    subtitle_url: Option<&str>,
+    kind: MediaKind,
+) -> String {
     // Build a minimal DIDL-Lite and then XML-escape it for embedding into <CurrentURIMetaData>.
     // Many renderers require at least: upnp:class + res@protocolInfo.
     // NOTE: avoid strict DLNA.ORG_PN profile binding; some renderers reject when profile ≠ actual.
     // Start permissive, then tighten if needed.
-    let protocol = protocol_info.unwrap_or("http-get:*:video/mp4:*");
+    let (mime, class) = match kind {
+        MediaKind::Video => ("video/mp4", "object.item.videoItem"),
+        MediaKind::Audio => ("audio/mp4", "object.item.audioItem.musicTrack"),
+    };
+    let protocol = flags.protocol_info(mime);
 
     // Important: the <res> inner URL should be XML-escaped *once* (so & -> &amp;).
     let res_url = xml_escape(media_url);
 
+    // 字幕：三星等电视读取 sec:CaptionInfoEx，另一部分渲染器读取 res 上的 pv:subtitleFileUri
+    let (res_subtitle_attrs, caption_info) = match subtitle_url {
+        Some(url) => {
+            let url = xml_escape(url);
+            (
+                format!(r#" pv:subtitleFileType="srt" pv:subtitleFileUri="{}""#, url),
+                format!(
+                    r#"<sec:CaptionInfoEx sec:type="srt">{0}</sec:CaptionInfoEx><sec:CaptionInfo sec:type="srt">{0}</sec:CaptionInfo>"#,
+                    url
+                ),
+            )
+        }
+        None => (String::new(), String::new()),
+    };
+
     let didl = format!(
-        r#"<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">
+        r#"<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\" xmlns:sec=\"http://www.sec.co.kr/\" xmlns:pv=\"http://www.pv.com/pvns/\">
         <item id=\"0\" parentID=\"-1\" restricted=\"1\">
         <dc:title>{}</dc:title>
         <upnp:storageMedium>UNKNOWN</upnp:storageMedium>
         <upnp:writeStatus>UNKNOWN</upnp:writeStatus>
-        <res protocolInfo=\"{}\">{}</res>
-        <upnp:class>object.item.videoItem</upnp:class>
+        <res protocolInfo=\"{}\"{}>{}</res>{}
+        <upnp:class>{}</upnp:class>
         </item>
         </DIDL-Lite>"#,
-        xml_escape(title),
+        xml_escape(&sanitize_title(title)),
         protocol,
-        res_url
+        res_subtitle_attrs,
+        res_url,
+        caption_info,
+        class
     );
 
     // Embed metadata as escaped XML text nodes: <CurrentURIMetaData>&lt;DIDL-Lite ...&gt;...
@@ -71,6 +207,10 @@ fn build_didl_lite_metadata(title: &str, media_url: &str, protocol_info: Option<
 }
 
 fn build_soap_envelope(action: &str, args_xml: &str) -> String {
+    build_soap_envelope_for("AVTransport", action, args_xml)
+}
+
+fn build_soap_envelope_for(service: &str, action: &str, args_xml: &str) -> String {
     // Keep the shape consistent with what most renderers accept (and close to your B站抓包).
     // Note: `rupnp` will build its own envelope too, but we log a best-effort equivalent
     // so you can diff with a packet capture.
@@ -78,21 +218,66 @@ fn build_soap_envelope(action: &str, args_xml: &str) -> String {
         r#"<?xml version="1.0" encoding="UTF-8"?>
 <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
   <s:Body>
-        <u:{action} xmlns:u="urn:schemas-upnp-org:service:AVTransport:1">{args}</u:{action}>
+        <u:{action} xmlns:u="urn:schemas-upnp-org:service:{service}:1">{args}</u:{action}>
   </s:Body>
 </s:Envelope>"#,
+        service = service,
         action = action,
         args = args_xml
     )
 }
 
-fn device_location_uri(device: &DlnaDevice) -> Result<Uri, rupnp::Error> {
-    device
-        .location
+fn parse_location(location: &str) -> Result<Uri, rupnp::Error> {
+    location
         .parse::<Uri>()
         .map_err(|_| rupnp::Error::ParseError("无法解析设备location为Uri"))
 }
 
+// 依次用设备的各个location作为base url发送动作，主地址失败时尝试备用地址
+// 所有地址共用一个截止时间：每次发送最多等到 `deadline`，过了就不再尝试其余地址，
+// 备用地址不会让最长等待时间成倍增加
+async fn with_fallback_locations<F, Fut>(
+    device: &DlnaDevice,
+    action: &str,
+    deadline: tokio::time::Instant,
+    mut send: F,
+) -> Result<HashMap<String, String>, rupnp::Error>
+where
+    F: FnMut(Uri) -> Fut,
+    Fut: Future<Output = Result<HashMap<String, String>, rupnp::Error>>,
+{
+    let mut last_err = None;
+    for location in device.locations() {
+        if tokio::time::Instant::now() >= deadline {
+            log::warn!("{} 已超时，不再尝试其余地址", action);
+            return Err(rupnp::Error::ParseError("UPnP动作超时"));
+        }
+        if last_err.is_some() {
+            log::warn!("{} 改用备用地址重试: {}", action, location);
+        }
+        let url = match parse_location(location) {
+            Ok(url) => url,
+            Err(e) => {
+                log::warn!("{} 跳过无法解析的地址: {}", action, location);
+                last_err = Some(e);
+                continue;
+            }
+        };
+        match tokio::time::timeout_at(deadline, send(url)).await {
+            Ok(Ok(response)) => return Ok(response),
+            Ok(Err(e)) => {
+                log::warn!("{} 经由 {} 失败: {}", action, location, e);
+                last_err = Some(e);
+            }
+            Err(_) => {
+                log::warn!("{} 经由 {} 超时，不再尝试其余地址", action, location);
+                return Err(rupnp::Error::ParseError("UPnP动作超时"));
+            }
+        }
+    }
+    Err(last_err.unwrap_or(rupnp::Error::ParseError("设备没有可用的location")))
+}
+
 fn log_upnp_action(service: &rupnp::Service, base_url: &Uri, action: &str, args_xml: &str) {
     // `service.action()` internally ends up using control_url(base_url).
     // We can't call the private control_url() here, so we log the base_url and also log
@@ -120,25 +305,50 @@ fn log_upnp_action(service: &rupnp::Service, base_url: &Uri, action: &str, args_
 /// `rupnp`'s internal URL replacement may produce the wrong path for such devices.
 /// To make behavior explicit (and loggable), we send the SOAP request ourselves to:
 /// `{scheme}://{host}:{port}/{control_path}`.
+///
+/// All attempts (native and every candidate path) must finish before `deadline`, which
+/// the caller shares across the device's locations, so a hung renderer can't multiply
+/// the wait by the number of paths or locations. The native attempt gets at most half of
+/// the remaining time, leaving the rest for compatibility mode. The future holds no locks
+/// across `.await` and is safe to drop (cancel) at any point.
+///
+/// With `compat_only` the native attempt is skipped entirely (troubleshooting switch).
 async fn avtransport_action_compat(
     service: &rupnp::Service,
     base_url: &Uri,
     action: &str,
     args_xml: &str,
+    deadline: tokio::time::Instant,
+    compat_only: bool,
 ) -> Result<HashMap<String, String>, rupnp::Error> {
     // 首先尝试使用 rupnp 原生的 action 方法（适用于Windows Media Player等标准设备）
-    match service.action(base_url, action, args_xml).await {
-        Ok(response) => {
+    // 原生请求最多用掉剩余时间的一半，卡住时兼容模式仍有机会
+    let native_budget = deadline.saturating_duration_since(tokio::time::Instant::now()) / 2;
+    let native = if compat_only {
+        log::debug!("已开启 compat_soap_only，跳过原生请求");
+        None
+    } else {
+        Some(tokio::time::timeout(native_budget, service.action(base_url, action, args_xml)).await)
+    };
+    match native {
+        None => {}
+        Some(Ok(Ok(response))) => {
             log::info!("UPnP Action (native) succeeded");
             log::debug!("UPnP Action (native) response: {:?}", response);
             return Ok(response);
         }
-        Err(e) => {
+        Some(Ok(Err(e))) => {
             log::warn!(
                 "UPnP Action (native) failed: {}, trying compatibility mode",
                 e
             );
         }
+        Some(Err(_)) => {
+            log::warn!(
+                "UPnP Action (native) timed out after {:?}, trying compatibility mode",
+                native_budget
+            );
+        }
     }
 
     // 原生方法失败，尝试兼容性模式
@@ -188,6 +398,11 @@ async fn avtransport_action_compat(
 
     // 尝试匹配可能的路径模式
     for path in possible_paths {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            log::warn!("UPnP Action {} 已超时，放弃其余路径", action);
+            break;
+        }
         let final_url = if path.starts_with("http://") || path.starts_with("https://") {
             path
         } else {
@@ -218,6 +433,7 @@ async fn avtransport_action_compat(
 
         let client = reqwest::Client::builder()
             .no_proxy()
+            .timeout(remaining)
             .build()
             .map_err(|_| rupnp::Error::ParseError("创建reqwest client失败"))?;
 
@@ -250,6 +466,9 @@ async fn avtransport_action_compat(
                         "AbsTime",
                         "RelCount",
                         "AbsCount",
+                        "Actions",
+                        "CurrentTransportState",
+                        "CurrentTransportStatus",
                     ] {
                         if let Some(v) = extract_xml_tag_value(&text, k) {
                             log::debug!("提取到字段 '{}' 的值: '{}'", k, v);
@@ -305,297 +524,1360 @@ fn extract_control_endpoint_from_debug(service_debug: &str) -> Option<String> {
         None
     }
 }
-
 // AVTransport服务URN
 const AV_TRANSPORT: URN = URN::service("schemas-upnp-org", "AVTransport", 1);
+// RenderingControl服务URN
+const RENDERING_CONTROL: URN = URN::service("schemas-upnp-org", "RenderingControl", 1);
+// ConnectionManager服务URN
+const CONNECTION_MANAGER: URN = URN::service("schemas-upnp-org", "ConnectionManager", 1);
+
+// 检查已知设备时下载描述文档的超时
+const KNOWN_DEVICE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Dry-run 模式下模拟的曲目时长（秒）
+const DRY_RUN_TRACK_SECS: u32 = 90;
+
+/// 配置中已知设备（`known_devices`）的检查结果
+#[derive(Debug, Clone)]
+pub enum KnownDevice {
+    Online(Box<DlnaDevice>),
+    /// 描述文档下载失败或超时，`reason` 为原因
+    Offline {
+        location: String,
+        reason: String,
+    },
+}
 
 // DLNA设备信息
 #[derive(Debug, Clone)]
 pub struct DlnaDevice {
-    pub device: Device,
+    /// 真实的UPnP设备；dry-run 模式下的虚拟设备为 None
+    pub device: Option<Device>,
     pub friendly_name: String,
+    /// 设备描述文档URL，多个地址可用时为响应最快的那个
     pub location: String,
+    /// 同一设备（相同UDN）的其他地址，例如电视同时连着 Wi-Fi 和有线时的另一个IP
+    pub fallback_locations: Vec<String>,
     pub services: Vec<URN>,
+    // 以下字段来自设备描述文档，用于区分同名设备
+    pub manufacturer: String,
+    pub model_name: String,
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub udn: String,
+    /// Some 表示这是 Chromecast 设备（CASTv2 控制地址），动作会被翻译成 Cast 命令
+    pub cast_address: Option<SocketAddr>,
+    /// 最近一次收到该设备响应（搜索结果或设备描述）的时间
+    pub last_seen: DateTime<Local>,
 }
 
-#[derive(Clone)]
-pub struct DlnaController;
-
-impl DlnaController {
-    pub fn new() -> Self {
-        Self
+impl DlnaDevice {
+    /// 按 UDN、设备名或“厂商 型号”做不区分大小写的子串匹配
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        [
+            self.udn.clone(),
+            self.friendly_name.clone(),
+            format!("{} {}", self.manufacturer, self.model_name),
+        ]
+        .iter()
+        .any(|field| field.to_lowercase().contains(&pattern))
     }
 
-    // 发现网络中的DLNA渲染器设备
-    pub async fn discover_devices(&self) -> Result<Vec<DlnaDevice>, rupnp::Error> {
-        log::info!("正在搜索DLNA设备...");
+    /// 从设备描述文档构建
+    pub fn from_device(device: Device) -> Self {
+        Self {
+            friendly_name: device.friendly_name().to_string(),
+            location: device.url().to_string(),
+            fallback_locations: Vec::new(),
+            services: device
+                .services()
+                .iter()
+                .map(|s| s.service_type().clone())
+                .collect(),
+            manufacturer: device.manufacturer().to_string(),
+            model_name: device.model_name().to_string(),
+            model_number: device.model_number().map(str::to_string),
+            serial_number: device.serial_number().map(str::to_string),
+            udn: device.udn().to_string(),
+            cast_address: None,
+            last_seen: Local::now(),
+            device: Some(device),
+        }
+    }
 
-        // 使用正确的SearchTarget构造方法 - 搜索AVTransport服务
-        let search_target = SearchTarget::URN(AV_TRANSPORT);
-        let devices_stream = rupnp::discover(&search_target, Duration::from_secs(5), None).await?;
+    /// 由 mDNS 发现的 Chromecast 设备构建
+    pub fn from_cast(device: CastDevice) -> Self {
+        Self {
+            device: None,
+            friendly_name: device.name,
+            location: format!("cast://{}", device.address),
+            fallback_locations: Vec::new(),
+            services: Vec::new(),
+            manufacturer: "Google".to_string(),
+            model_name: device.model,
+            model_number: None,
+            serial_number: None,
+            udn: format!("uuid:{}", device.id),
+            cast_address: Some(device.address),
+            last_seen: Local::now(),
+        }
+    }
 
-        // 将Stream转换为Vec
-        let devices: Vec<Result<Device, rupnp::Error>> = devices_stream.collect().await;
+    /// dry-run 模式使用的虚拟渲染器，不对应任何真实设备
+    pub fn dry_run() -> Self {
+        Self {
+            device: None,
+            friendly_name: "Dry-run Renderer".to_string(),
+            location: "http://127.0.0.1:0/dry-run/description.xml".to_string(),
+            fallback_locations: Vec::new(),
+            services: vec![AV_TRANSPORT, RENDERING_CONTROL],
+            manufacturer: "ktv-casting".to_string(),
+            model_name: "Dry-run".to_string(),
+            model_number: None,
+            serial_number: None,
+            udn: "uuid:ktv-casting-dry-run".to_string(),
+            cast_address: None,
+            last_seen: Local::now(),
+        }
+    }
 
-        let mut dlna_devices = Vec::new();
+    /// 设备的 IP 地址（来自各 location 与 Chromecast 控制地址）
+    pub fn ip_addresses(&self) -> Vec<IpAddr> {
+        self.locations()
+            .filter_map(|location| location.parse::<Uri>().ok())
+            .filter_map(|uri| {
+                uri.host().map(|host| {
+                    host.trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .to_string()
+                })
+            })
+            .filter_map(|host| host.parse().ok())
+            .chain(self.cast_address.map(|address| address.ip()))
+            .collect()
+    }
 
-        for device_result in devices {
-            match device_result {
-                Ok(device) => {
-                    // 检查是否是媒体渲染器设备
-                    let device_type_str = device.device_type().to_string();
-                    if device_type_str.contains("MediaRenderer") {
-                        let friendly_name = device.friendly_name().to_string();
-                        let location = device.url().to_string();
-
-                        // 检查设备是否支持AVTransport服务
-                        let services: Vec<URN> = device
-                            .services()
-                            .iter()
-                            .map(|s| s.service_type().clone())
-                            .collect();
-
-                        log::info!("发现设备: {} (位置: {})", friendly_name, location);
-                        log::debug!("支持的服务: {:?}", services);
-
-                        dlna_devices.push(DlnaDevice {
-                            device,
-                            friendly_name,
-                            location,
-                            services,
-                        });
-                    }
-                }
-                Err(e) => {
-                    log::error!("设备发现错误: {}", e);
-                }
-            }
+    /// 设备过滤模式：IP/网段（如 `192.168.1.20`、`10.0.3.0/24`）按设备地址匹配，
+    /// 其余按 [`matches`](Self::matches) 匹配设备名、型号或 UDN
+    pub fn matches_filter(&self, pattern: &str) -> bool {
+        match parse_ip_range(pattern) {
+            Some((network, prefix)) => self
+                .ip_addresses()
+                .iter()
+                .any(|ip| ip_in_range(*ip, network, prefix)),
+            None => self.matches(pattern),
         }
+    }
 
-        Ok(dlna_devices)
+    /// 主地址在前、备用地址在后的全部location
+    pub fn locations(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.location.as_str())
+            .chain(self.fallback_locations.iter().map(String::as_str))
     }
 
-    pub async fn get_devices_from_urls(
-        &self,
-        urls: &Vec<&'static str>,
-    ) -> Result<Vec<DlnaDevice>, rupnp::Error> {
-        let devices = try_join_all(urls.iter().map(|url| {
-            let uri = Uri::from_static(url);
-            Device::from_url(uri)
-        }))
-        .await?;
+    /// 设备提供的服务及版本，例如 `["AVTransport v1", "RenderingControl v1"]`
+    pub fn service_summary(&self) -> Vec<String> {
+        self.services
+            .iter()
+            .map(|urn| service_label(&urn.to_string()))
+            .collect()
+    }
 
-        let dlna_devices: Vec<DlnaDevice> = devices
-            .into_iter()
-            .map(|device| DlnaDevice {
-                device: device.clone(),
-                friendly_name: device.friendly_name().to_string(),
-                location: device.url().to_string(),
-                services: device
-                    .services()
-                    .iter()
-                    .map(|s| s.service_type().clone())
-                    .collect(),
-            })
-            .collect();
-        Ok(dlna_devices)
+    /// 型号描述，例如 `Samsung QN55Q60 (型号 QN55Q60RAFXZA, 序列号 0A1B2C)`，随界面语言显示
+    pub fn model_summary(&self) -> String {
+        let mut summary = format!("{} {}", self.manufacturer, self.model_name)
+            .trim()
+            .to_string();
+        let extras: Vec<String> = [
+            self.model_number
+                .as_ref()
+                .map(|n| tr!("型号 {}", "model {}", n)),
+            self.serial_number
+                .as_ref()
+                .map(|n| tr!("序列号 {}", "serial {}", n)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !extras.is_empty() {
+            summary.push_str(&format!(" ({})", extras.join(", ")));
+        }
+        summary
     }
+}
 
-    // 获取设备的AVTransport服务
-    fn get_avtransport_service<'a>(&'a self, device: &'a DlnaDevice) -> Option<&'a rupnp::Service> {
-        device
-            .device
-            .services()
-            .iter()
-            .find(|s| *s.service_type() == AV_TRANSPORT)
+// 服务类型 URN 的简短写法：`urn:schemas-upnp-org:service:AVTransport:1` → `AVTransport v1`
+fn service_label(urn: &str) -> String {
+    let mut parts = urn.rsplitn(3, ':');
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(name)) if version.parse::<u32>().is_ok() => {
+            format!("{} v{}", name, version)
+        }
+        _ => urn.to_string(),
     }
+}
 
-    // 设置AVTransport URI（发送媒体URL给设备）
-    pub async fn set_avtransport_uri(
-        &self,
-        device: &DlnaDevice,
-        current_uri: &str,
-        current_uri_metadata: &str,
-        server_ip: IpAddr,
-        server_port: u16,
-    ) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+// 解析 `a.b.c.d` 或 `a.b.c.d/n` 形式的地址/网段；不是地址时返回 None
+fn parse_ip_range(pattern: &str) -> Option<(IpAddr, u32)> {
+    let (address, prefix) = match pattern.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix.parse::<u32>().ok()?)),
+        None => (pattern, None),
+    };
+    let network: IpAddr = address.trim().parse().ok()?;
+    let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max_prefix);
+    (prefix <= max_prefix).then_some((network, prefix))
+}
 
-        // 构建完整的媒体URL
-        let media_url = format!("http://{}:{}/{}", server_ip, server_port, current_uri);
+fn ip_in_range(ip: IpAddr, network: IpAddr, prefix: u32) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
 
-        log::info!("设置媒体URI: {}", media_url);
-        log::debug!("元数据(传入): {}", current_uri_metadata);
+// 设备是否被配置隐藏
+fn is_hidden(device: &DlnaDevice, config: &DiscoveryConfig) -> bool {
+    config
+        .hide_devices
+        .iter()
+        .any(|pattern| device.matches_filter(pattern))
+}
 
-        // If caller didn't provide metadata, generate a minimal DIDL-Lite for compatibility.
-        let metadata = if current_uri_metadata.trim().is_empty() {
-            // Title can be anything; devices often only care about protocolInfo.
-            build_didl_lite_metadata(current_uri, &media_url, None)
-        } else {
-            current_uri_metadata.to_string()
+// 按配置隐藏设备，并把置顶的设备按模式顺序排到最前面（其余保持发现顺序）
+fn apply_device_filters(devices: Vec<DlnaDevice>, config: &DiscoveryConfig) -> Vec<DlnaDevice> {
+    let (mut devices, hidden): (Vec<_>, Vec<_>) = devices
+        .into_iter()
+        .partition(|device| !is_hidden(device, config));
+    if !hidden.is_empty() {
+        log::info!("按配置隐藏了{}台设备", hidden.len());
+    }
+    devices.sort_by_key(|device| {
+        config
+            .pin_devices
+            .iter()
+            .position(|pattern| device.matches_filter(pattern))
+            .unwrap_or(usize::MAX)
+    });
+    devices
+}
+
+// 按UDN合并同一设备的多个地址：延迟更低的地址作为主地址，其余作为备用
+fn merge_by_udn(devices: &mut Vec<(DlnaDevice, Duration)>, device: DlnaDevice, latency: Duration) {
+    let Some((existing, existing_latency)) = devices.iter_mut().find(|(d, _)| d.udn == device.udn)
+    else {
+        devices.push((device, latency));
+        return;
+    };
+
+    if latency < *existing_latency {
+        let mut fallbacks = std::mem::take(&mut existing.fallback_locations);
+        fallbacks.push(existing.location.clone());
+        *existing = DlnaDevice {
+            fallback_locations: fallbacks,
+            ..device
         };
+        *existing_latency = latency;
+    } else {
+        existing.last_seen = existing.last_seen.max(device.last_seen);
+        if !existing.locations().any(|l| l == device.location) {
+            existing.fallback_locations.push(device.location);
+        }
+    }
+    log::info!(
+        "合并同一设备的多个地址: {} -> 主地址 {}，备用 {:?}",
+        existing.udn,
+        existing.location,
+        existing.fallback_locations
+    );
+}
 
-        // 准备SOAP请求参数 - 只使用标准参数以提高兼容性
-        let action = "SetAVTransportURI";
-        let args_str = format!(
-            "<InstanceID>0</InstanceID><CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
-            xml_escape(&media_url),
-            metadata
-        );
+/// dry-run 模式下的本地播放状态：一个可暂停的播放时钟和音量
+#[derive(Debug)]
+struct DryRunState {
+    playing_since: Option<Instant>,
+    elapsed: Duration,
+    has_media: bool,
+    volume: u32,
+    bass: i32,
+    treble: i32,
+    // 单位为 1/256 dB，与 SOAP 参数一致
+    volume_db: i32,
+}
 
-        // 发送SOAP请求 - 统一使用设备描述文档URL(location)作为base url
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, &args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, &args_str).await?;
+impl DryRunState {
+    fn new() -> Self {
+        Self {
+            playing_since: None,
+            elapsed: Duration::ZERO,
+            has_media: false,
+            volume: 50,
+            bass: 0,
+            treble: 0,
+            volume_db: -20 * 256,
+        }
+    }
 
-        log::debug!("SetAVTransportURI响应: {:?}", response);
+    fn position(&self) -> Duration {
+        self.elapsed + self.playing_since.map(|t| t.elapsed()).unwrap_or_default()
+    }
 
-        Ok(())
+    fn play(&mut self) {
+        if self.playing_since.is_none() {
+            self.playing_since = Some(Instant::now());
+        }
     }
 
-    // 设置下一个AVTransport URI（用于播放列表）
-    pub async fn set_next_avtransport_uri(
-        &self,
-        device: &DlnaDevice,
-        next_uri: &str,
-        next_uri_metadata: &str,
-        server_ip: IpAddr,
-        server_port: u16,
-    ) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+    fn pause(&mut self) {
+        if let Some(since) = self.playing_since.take() {
+            self.elapsed += since.elapsed();
+        }
+    }
 
-        let action = "SetNextAVTransportURI";
-        let media_url = format!("http://{}:{}/{}", server_ip, server_port, next_uri);
-        let metadata = if next_uri_metadata.trim().is_empty() {
-            build_didl_lite_metadata(next_uri, &media_url, None)
-        } else {
-            next_uri_metadata.to_string()
-        };
+    fn reset(&mut self) {
+        self.playing_since = None;
+        self.elapsed = Duration::ZERO;
+    }
 
-        let args_str = format!(
-            "<InstanceID>0</InstanceID><NextURI>{}</NextURI><NextURIMetaData>{}</NextURIMetaData>",
-            xml_escape(&media_url),
-            metadata
-        );
+    fn seek(&mut self, position: Duration) {
+        self.elapsed = position;
+        if self.playing_since.is_some() {
+            self.playing_since = Some(Instant::now());
+        }
+    }
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, &args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, &args_str).await?;
+    fn transport_state(&self) -> &'static str {
+        if !self.has_media {
+            "NO_MEDIA_PRESENT"
+        } else if self.playing_since.is_some() {
+            "PLAYING"
+        } else if self.elapsed > Duration::ZERO {
+            "PAUSED_PLAYBACK"
+        } else {
+            "STOPPED"
+        }
+    }
+}
 
-        log::debug!("SetNextAVTransportURI响应: {:?}", response);
+fn format_hms(secs: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
 
-        Ok(())
+/// 渲染器支持的分贝音量范围（GetVolumeDBRange），单位 dB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VolumeDbRange {
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+impl VolumeDbRange {
+    pub fn clamp(&self, db: f32) -> f32 {
+        db.clamp(self.min_db, self.max_db)
     }
+}
 
-    // 播放媒体
-    pub async fn play(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+// SOAP 中的 VolumeDB 是以 1/256 dB 为单位的 i2
+fn db_to_upnp(db: f32) -> i16 {
+    (db * 256.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
 
-        log::info!("正在发送Play指令...");
-        let action = "Play";
-        let args_str = "<InstanceID>0</InstanceID><Speed>1</Speed>";
+fn upnp_to_db(value: &str) -> Option<f32> {
+    value.parse::<i16>().ok().map(|v| v as f32 / 256.0)
+}
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
-        log::debug!("Play响应: {:?}", response);
+/// 渲染器的传输状态（GetTransportInfo 的 CurrentTransportState）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportState {
+    Playing,
+    PausedPlayback,
+    Stopped,
+    Transitioning,
+    NoMediaPresent,
+    /// 厂商自定义的状态值
+    Other(String),
+}
 
-        Ok(())
+impl TransportState {
+    pub fn parse(value: &str) -> Self {
+        match value.trim() {
+            "PLAYING" => TransportState::Playing,
+            "PAUSED_PLAYBACK" => TransportState::PausedPlayback,
+            "STOPPED" => TransportState::Stopped,
+            "TRANSITIONING" => TransportState::Transitioning,
+            "NO_MEDIA_PRESENT" => TransportState::NoMediaPresent,
+            other => TransportState::Other(other.to_string()),
+        }
     }
 
-    // 暂停播放
-    pub async fn pause(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+    /// 渲染器已经不在播放任何内容（停止或丢失了媒体）
+    pub fn is_idle(&self) -> bool {
+        matches!(
+            self,
+            TransportState::Stopped | TransportState::NoMediaPresent
+        )
+    }
+}
 
-        log::info!("正在发送Pause指令...");
-        let action = "Pause";
-        let args_str = "<InstanceID>0</InstanceID>";
+/// 渲染器当前允许的传输动作（GetCurrentTransportActions）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportActions {
+    pub play: bool,
+    pub pause: bool,
+    pub stop: bool,
+    pub seek: bool,
+    pub next: bool,
+    pub previous: bool,
+}
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
-        log::debug!("Pause响应: {:?}", response);
+impl TransportActions {
+    /// 全部允许：设备不支持 GetCurrentTransportActions 时使用
+    pub const ALL: Self = Self {
+        play: true,
+        pause: true,
+        stop: true,
+        seek: true,
+        next: true,
+        previous: true,
+    };
+
+    /// 解析逗号分隔的动作列表，例如 `Play,Stop,Pause,Seek`
+    pub fn parse(actions: &str) -> Self {
+        let mut out = Self {
+            play: false,
+            pause: false,
+            stop: false,
+            seek: false,
+            next: false,
+            previous: false,
+        };
+        for action in actions.split(',').map(str::trim) {
+            match action {
+                "Play" => out.play = true,
+                "Pause" => out.pause = true,
+                "Stop" => out.stop = true,
+                "Seek" => out.seek = true,
+                "Next" => out.next = true,
+                "Previous" => out.previous = true,
+                _ => {}
+            }
+        }
+        out
+    }
+}
 
-        Ok(())
+impl Default for TransportActions {
+    fn default() -> Self {
+        Self::ALL
     }
+}
 
-    // 停止播放
-    pub async fn stop(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+#[derive(Clone)]
+pub struct DlnaController {
+    // Some 表示 dry-run 模式：只打印SOAP请求，不实际发送
+    dry_run: Option<Arc<Mutex<DryRunState>>>,
+    discovery: DiscoveryConfig,
+    config: DlnaConfig,
+    features: FeatureFlags,
+    // 各设备（按 UDN）的 Sink protocolInfo，查询一次后缓存
+    sink_protocols: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
 
-        log::info!("正在发送Stop指令...");
-        let action = "Stop";
-        let args_str = "<InstanceID>0</InstanceID>";
+impl Default for DlnaController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
-        log::debug!("Stop响应: {:?}", response);
+impl DlnaController {
+    pub fn new() -> Self {
+        Self {
+            dry_run: None,
+            discovery: DiscoveryConfig::default(),
+            config: DlnaConfig::default(),
+            features: FeatureFlags::default(),
+            sink_protocols: Arc::default(),
+        }
+    }
 
-        Ok(())
+    /// 创建 dry-run 控制器：所有动作只记录日志（含完整SOAP请求体），播放进度由本地时钟模拟
+    pub fn new_dry_run() -> Self {
+        Self {
+            dry_run: Some(Arc::new(Mutex::new(DryRunState::new()))),
+            ..Self::new()
+        }
     }
 
-    // 下一首
-    pub async fn next(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+    /// 使用自定义的SSDP发现参数
+    pub fn with_discovery_config(mut self, discovery: DiscoveryConfig) -> Self {
+        self.discovery = discovery;
+        self
+    }
 
-        let action = "Next";
-        let args_str = "<InstanceID>0</InstanceID>";
+    /// 使用自定义的SOAP动作参数（超时等）
+    pub fn with_dlna_config(mut self, config: DlnaConfig) -> Self {
+        self.config = config;
+        self
+    }
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
-        log::debug!("Next响应: {:?}", response);
+    /// 使用共享的运行时功能开关
+    pub fn with_features(mut self, features: FeatureFlags) -> Self {
+        self.features = features;
+        self
+    }
 
-        Ok(())
+    /// 控制器使用的功能开关
+    pub fn features(&self) -> &FeatureFlags {
+        &self.features
     }
 
-    // 获取传输信息
-    pub async fn get_transport_info(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
-        let avtransport = self
-            .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+    /// 该渲染器是否使用B站直链（而不是本地代理），见 [`UrlStrategy`]；
+    /// 关闭 [`Feature::DirectLink`] 时一律经本地代理
+    pub fn prefers_direct_link(&self, device: &DlnaDevice) -> bool {
+        if !self.features.is_enabled(Feature::DirectLink) {
+            return false;
+        }
+        match self.config.url_strategy {
+            UrlStrategy::Proxy => false,
+            UrlStrategy::Direct => true,
+            UrlStrategy::Auto => self
+                .config
+                .direct_link_devices
+                .iter()
+                .any(|pattern| device.matches(pattern)),
+        }
+    }
 
-        let action = "GetTransportInfo";
-        let args_str = "<InstanceID>0</InstanceID>";
+    /// 是否应把该视频经 ffmpeg 转码后再交给渲染器，见 [`TranscodeStrategy`]；纯音频从不转码
+    pub async fn needs_transcode(&self, device: &DlnaDevice, stream: &VideoStream) -> bool {
+        let mime = stream_mime(stream);
+        if !mime.starts_with("video/") {
+            return false;
+        }
+        match self.config.transcode {
+            TranscodeStrategy::Off => false,
+            TranscodeStrategy::Always => true,
+            TranscodeStrategy::Auto => {
+                if self
+                    .config
+                    .transcode_devices
+                    .iter()
+                    .any(|pattern| device.matches(pattern))
+                {
+                    return true;
+                }
+                // 查不到 protocolInfo 的渲染器（如 Chromecast）按支持处理
+                match self.get_sink_protocol_info(device).await {
+                    Ok(sink) => !sink.is_empty() && !sink_supports(&sink, mime, stream.codec),
+                    Err(e) => {
+                        log::debug!("获取 protocolInfo 失败: {}", e);
+                        false
+                    }
+                }
+            }
+        }
+    }
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
-        log::debug!("传输信息: {:?}", response);
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
+    }
 
-        Ok(())
+    // dry-run 模式下记录本应发送的SOAP请求，并根据动作推进本地模拟状态
+    fn dry_run_action(
+        &self,
+        service: &str,
+        action: &str,
+        args_xml: &str,
+    ) -> Option<HashMap<String, String>> {
+        let state = self.dry_run.as_ref()?;
+        log::info!(
+            "[dry-run] UPnP Action SOAPAction=\"urn:schemas-upnp-org:service:{}:1#{}\" (未发送)",
+            service,
+            action
+        );
+        log::info!(
+            "[dry-run] body => {}",
+            build_soap_envelope_for(service, action, args_xml)
+        );
+
+        let mut state = state.lock().unwrap();
+        let mut out = HashMap::new();
+        match action {
+            "SetAVTransportURI" => {
+                state.reset();
+                state.has_media = true;
+            }
+            "Play" => state.play(),
+            "Pause" => state.pause(),
+            "Stop" => state.reset(),
+            "Seek" => {
+                if let Some(target) = extract_xml_tag_value(args_xml, "Target") {
+                    let secs = target
+                        .split(':')
+                        .try_fold(0u64, |acc, part| part.parse::<u64>().map(|v| acc * 60 + v));
+                    if let Ok(secs) = secs {
+                        state.seek(Duration::from_secs(secs));
+                    }
+                }
+            }
+            "GetPositionInfo" => {
+                let position = (state.position().as_secs() as u32).min(DRY_RUN_TRACK_SECS);
+                out.insert("RelTime".to_string(), format_hms(position));
+                out.insert("TrackDuration".to_string(), format_hms(DRY_RUN_TRACK_SECS));
+            }
+            "GetCurrentTransportActions" => {
+                out.insert(
+                    "Actions".to_string(),
+                    "Play,Stop,Pause,Seek,Next,Previous".to_string(),
+                );
+            }
+            "GetTransportInfo" => {
+                out.insert(
+                    "CurrentTransportState".to_string(),
+                    state.transport_state().to_string(),
+                );
+            }
+            "SetVolume"
+                if extract_xml_tag_value(args_xml, "Channel").as_deref() == Some("Master") =>
+            {
+                if let Some(v) =
+                    extract_xml_tag_value(args_xml, "DesiredVolume").and_then(|v| v.parse().ok())
+                {
+                    state.volume = v;
+                }
+            }
+            "GetVolume" => {
+                out.insert("CurrentVolume".to_string(), state.volume.to_string());
+            }
+            "SetBass" | "SetTreble" => {
+                let tag = if action == "SetBass" {
+                    "DesiredBass"
+                } else {
+                    "DesiredTreble"
+                };
+                if let Some(v) = extract_xml_tag_value(args_xml, tag).and_then(|v| v.parse().ok()) {
+                    if action == "SetBass" {
+                        state.bass = v;
+                    } else {
+                        state.treble = v;
+                    }
+                }
+            }
+            "GetBass" => {
+                out.insert("CurrentBass".to_string(), state.bass.to_string());
+            }
+            "GetVolumeDBRange" => {
+                out.insert("MinValue".to_string(), (-80 * 256).to_string());
+                out.insert("MaxValue".to_string(), "0".to_string());
+            }
+            "SetVolumeDB" => {
+                if let Some(v) =
+                    extract_xml_tag_value(args_xml, "DesiredVolume").and_then(|v| v.parse().ok())
+                {
+                    state.volume_db = v;
+                }
+            }
+            "GetVolumeDB" => {
+                out.insert("CurrentVolume".to_string(), state.volume_db.to_string());
+            }
+            "GetTreble" => {
+                out.insert("CurrentTreble".to_string(), state.treble.to_string());
+            }
+            "GetProtocolInfo" => {
+                out.insert("Sink".to_string(), "http-get:*:video/mp4:*".to_string());
+            }
+            _ => {}
+        }
+        Some(out)
     }
 
-    // 获取位置信息
-    pub async fn get_position_info(
+    // 发现网络中的渲染器：SSDP 搜索DLNA设备，同时（如果开启）用 mDNS 搜索 Chromecast
+    pub async fn discover_devices(&self) -> Result<Vec<DlnaDevice>, rupnp::Error> {
+        self.discover(None).await
+    }
+
+    /// 与 [`discover_devices`](Self::discover_devices) 相同，但每发现一台新设备（按 UDN，
+    /// 已按配置过滤掉隐藏的设备）就立即通过 `found` 发出，界面不必等整个搜索结束
+    ///
+    /// 返回值仍是搜索结束后完整、置顶排序后的列表；`found` 在返回时被关闭。
+    pub async fn discover_devices_streaming(
+        &self,
+        found: mpsc::UnboundedSender<DlnaDevice>,
+    ) -> Result<Vec<DlnaDevice>, rupnp::Error> {
+        self.discover(Some(&found)).await
+    }
+
+    async fn discover(
+        &self,
+        found: Option<&mpsc::UnboundedSender<DlnaDevice>>,
+    ) -> Result<Vec<DlnaDevice>, rupnp::Error> {
+        log::info!("正在搜索DLNA设备...");
+
+        if self.is_dry_run() {
+            log::info!("[dry-run] 跳过SSDP发现，使用虚拟渲染器");
+            if let Some(found) = found {
+                let _ = found.send(DlnaDevice::dry_run());
+            }
+            return Ok(vec![DlnaDevice::dry_run()]);
+        }
+
+        let timeout = Duration::from_secs(self.discovery.timeout_secs);
+        let cast = async {
+            if !self.discovery.chromecast {
+                return Vec::new();
+            }
+            let devices: Vec<DlnaDevice> = chromecast::discover(timeout)
+                .await
+                .unwrap_or_else(|e| {
+                    log::warn!("Chromecast搜索失败: {}", e);
+                    Vec::new()
+                })
+                .into_iter()
+                .map(DlnaDevice::from_cast)
+                .collect();
+            if let Some(found) = found {
+                for device in devices.iter().filter(|d| !is_hidden(d, &self.discovery)) {
+                    let _ = found.send(device.clone());
+                }
+            }
+            devices
+        };
+        let (dlna_devices, cast_devices) = tokio::join!(self.discover_dlna_devices(found), cast);
+        let mut devices = dlna_devices?;
+        devices.extend(cast_devices);
+        Ok(apply_device_filters(devices, &self.discovery))
+    }
+
+    async fn discover_dlna_devices(
+        &self,
+        found: Option<&mpsc::UnboundedSender<DlnaDevice>>,
+    ) -> Result<Vec<DlnaDevice>, rupnp::Error> {
+        let config = &self.discovery;
+
+        // 使用正确的SearchTarget构造方法 - 搜索AVTransport服务
+        let search_target = SearchTarget::URN(AV_TRANSPORT);
+        let responses = rupnp::ssdp::search(
+            &search_target,
+            Duration::from_secs(config.timeout_secs),
+            config.mx,
+            None,
+        )
+        .await
+        .map_err(|e| {
+            rupnp::Error::ParseError(Box::leak(format!("SSDP搜索失败: {}", e).into_boxed_str()))
+        })?;
+
+        // 有些渲染器会重复发送大量相同的SSDP响应：按USN+location在窗口内去重，避免重复下载描述文档。
+        // 同一设备从不同IP发来的响应仍然保留，稍后按UDN合并
+        let dedupe_window = Duration::from_millis(config.dedupe_window_ms);
+        let mut last_seen: HashMap<(String, String), Instant> = HashMap::new();
+        let locations = responses.filter_map(move |response| {
+            let location = match response {
+                Ok(response) => {
+                    let now = Instant::now();
+                    let key = (response.usn().to_string(), response.location().to_string());
+                    match last_seen.get(&key) {
+                        Some(seen_at) if now.duration_since(*seen_at) < dedupe_window => {
+                            log::debug!("忽略重复的SSDP响应: {} ({})", key.0, key.1);
+                            None
+                        }
+                        _ => {
+                            let location = key.1.clone();
+                            last_seen.insert(key, now);
+                            Some(location)
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("SSDP响应错误: {}", e);
+                    None
+                }
+            };
+            futures::future::ready(location)
+        });
+
+        // 限制同时下载描述文档的数量，对繁忙的网络更友好；同时记录下载耗时作为该地址的延迟
+        let mut devices = std::pin::pin!(
+            locations
+                .map(|location| async move {
+                    let uri = parse_location(&location)?;
+                    let started = Instant::now();
+                    let device = Device::from_url(uri).await?;
+                    Ok::<_, rupnp::Error>((device, started.elapsed()))
+                })
+                .buffer_unordered(config.max_concurrent_fetches.max(1))
+        );
+
+        let mut dlna_devices: Vec<(DlnaDevice, Duration)> = Vec::new();
+
+        // 边下载边处理，新设备可以立即通知界面
+        while let Some(device_result) = devices.next().await {
+            match device_result {
+                Ok((device, latency)) => {
+                    // 检查是否是媒体渲染器设备
+                    let device_type_str = device.device_type().to_string();
+                    if device_type_str.contains("MediaRenderer") {
+                        let dlna_device = DlnaDevice::from_device(device);
+
+                        log::info!(
+                            "发现设备: {} [{}] (位置: {}, UDN: {}, 延迟: {:?})",
+                            dlna_device.friendly_name,
+                            dlna_device.model_summary(),
+                            dlna_device.location,
+                            dlna_device.udn,
+                            latency
+                        );
+                        log::debug!("支持的服务: {:?}", dlna_device.services);
+
+                        if let Some(found) = found
+                            && !dlna_devices.iter().any(|(d, _)| d.udn == dlna_device.udn)
+                            && !is_hidden(&dlna_device, config)
+                        {
+                            let _ = found.send(dlna_device.clone());
+                        }
+                        merge_by_udn(&mut dlna_devices, dlna_device, latency);
+                    }
+                }
+                Err(e) => {
+                    log::error!("设备发现错误: {}", e);
+                }
+            }
+        }
+
+        Ok(dlna_devices.into_iter().map(|(device, _)| device).collect())
+    }
+
+    /// 并发检查配置中的已知设备：每个地址在短超时内下载一次描述文档
+    ///
+    /// 结果按配置顺序排列；按配置隐藏的设备不会出现在结果中。
+    pub async fn check_known_devices(&self) -> Vec<KnownDevice> {
+        if self.is_dry_run() {
+            return Vec::new();
+        }
+        let checks = self
+            .discovery
+            .known_devices
+            .iter()
+            .map(|location| async move {
+                let result = match parse_location(location) {
+                    Ok(uri) => tokio::time::timeout(KNOWN_DEVICE_TIMEOUT, Device::from_url(uri))
+                        .await
+                        .map_err(|_| "连接超时".to_string())
+                        .and_then(|result| result.map_err(|e| e.to_string())),
+                    Err(e) => Err(e.to_string()),
+                };
+                match result {
+                    Ok(device) => KnownDevice::Online(Box::new(DlnaDevice::from_device(device))),
+                    Err(reason) => {
+                        log::warn!("已知设备不可用: {} ({})", location, reason);
+                        KnownDevice::Offline {
+                            location: location.clone(),
+                            reason,
+                        }
+                    }
+                }
+            });
+        futures::future::join_all(checks)
+            .await
+            .into_iter()
+            .filter(|known| match known {
+                KnownDevice::Online(device) => !is_hidden(device, &self.discovery),
+                KnownDevice::Offline { .. } => true,
+            })
+            .collect()
+    }
+
+    pub async fn get_devices_from_urls(
+        &self,
+        urls: &Vec<&'static str>,
+    ) -> Result<Vec<DlnaDevice>, rupnp::Error> {
+        let devices = try_join_all(urls.iter().map(|url| {
+            let uri = Uri::from_static(url);
+            Device::from_url(uri)
+        }))
+        .await?;
+
+        let dlna_devices: Vec<DlnaDevice> =
+            devices.into_iter().map(DlnaDevice::from_device).collect();
+        Ok(dlna_devices)
+    }
+
+    // 获取设备的指定服务
+    fn find_service<'a>(&'a self, device: &'a DlnaDevice, urn: &URN) -> Option<&'a rupnp::Service> {
+        device
+            .device
+            .as_ref()?
+            .services()
+            .iter()
+            .find(|s| s.service_type() == urn)
+    }
+
+    // 发送AVTransport动作（dry-run 模式下只记录日志）
+    async fn avtransport_action(
         &self,
         device: &DlnaDevice,
+        action: &str,
+        args_xml: &str,
     ) -> Result<HashMap<String, String>, rupnp::Error> {
+        if let Some(response) = self.dry_run_action("AVTransport", action, args_xml) {
+            return Ok(response);
+        }
+        if let Some(address) = device.cast_address {
+            return self.cast_action(address, action, args_xml).await;
+        }
+
         let avtransport = self
-            .get_avtransport_service(device)
+            .find_service(device, &AV_TRANSPORT)
             .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
 
-        let action = "GetPositionInfo";
+        // 发送SOAP请求 - 统一使用设备描述文档URL(location)作为base url
+        // 截止时间只算一次，主地址和备用地址共用
+        let deadline = tokio::time::Instant::now() + self.config.action_timeout(action);
+        let compat_only = self.features.is_enabled(Feature::CompatSoapOnly);
+        with_fallback_locations(device, action, deadline, |base_url| async move {
+            log_upnp_action(avtransport, &base_url, action, args_xml);
+            avtransport_action_compat(
+                avtransport,
+                &base_url,
+                action,
+                args_xml,
+                deadline,
+                compat_only,
+            )
+            .await
+        })
+        .await
+    }
+
+    // 把 AVTransport/RenderingControl 动作翻译成 Cast 命令，响应转换成与 SOAP 相同的字段
+    async fn cast_action(
+        &self,
+        address: SocketAddr,
+        action: &str,
+        args_xml: &str,
+    ) -> Result<HashMap<String, String>, rupnp::Error> {
+        let command = match action {
+            "SetAVTransportURI" => {
+                let url = extract_xml_tag_value(args_xml, "CurrentURI")
+                    .map(|url| xml_unescape(&url))
+                    .ok_or(rupnp::Error::ParseError("SetAVTransportURI缺少CurrentURI"))?;
+                // 元数据在 SOAP 中被转义过一次，DIDL 中的标题又转义过一次
+                let title = extract_xml_tag_value(args_xml, "CurrentURIMetaData")
+                    .and_then(|metadata| {
+                        extract_xml_tag_value(&xml_unescape(&metadata), "dc:title")
+                    })
+                    .map(|title| xml_unescape(&title))
+                    .unwrap_or_default();
+                let content_type = if url.contains(".flv") {
+                    "video/x-flv"
+                } else if url.ends_with(AUDIO_SUFFIX) {
+                    "audio/mp4"
+                } else {
+                    "video/mp4"
+                };
+                CastCommand::Load {
+                    url,
+                    title,
+                    content_type: content_type.to_string(),
+                }
+            }
+            "Play" => CastCommand::Play,
+            "Pause" => CastCommand::Pause,
+            "Stop" => CastCommand::Stop,
+            "Seek" => {
+                let secs = extract_xml_tag_value(args_xml, "Target")
+                    .and_then(|target| {
+                        target.split(':').try_fold(0u64, |acc, part| {
+                            part.parse::<u64>().ok().map(|v| acc * 60 + v)
+                        })
+                    })
+                    .ok_or(rupnp::Error::ParseError("Seek缺少有效的Target"))?;
+                CastCommand::Seek(secs as f64)
+            }
+            "SetVolume"
+                if extract_xml_tag_value(args_xml, "Channel").as_deref() == Some("Master") =>
+            {
+                let volume: u32 = extract_xml_tag_value(args_xml, "DesiredVolume")
+                    .and_then(|v| v.parse().ok())
+                    .ok_or(rupnp::Error::ParseError("SetVolume缺少DesiredVolume"))?;
+                CastCommand::SetVolume(volume.min(100) as f32 / 100.0)
+            }
+            "GetPositionInfo" | "GetTransportInfo" | "GetVolume" => CastCommand::Status,
+            "GetCurrentTransportActions" => {
+                return Ok(HashMap::from([(
+                    "Actions".to_string(),
+                    "Play,Stop,Pause,Seek".to_string(),
+                )]));
+            }
+            _ => return Err(rupnp::Error::ParseError("Chromecast不支持该动作")),
+        };
+
+        let timeout = self.config.action_timeout(action);
+        let status = tokio::time::timeout(timeout, chromecast::execute(address, command))
+            .await
+            .map_err(|_| rupnp::Error::ParseError("Chromecast命令超时"))?
+            .map_err(|e| rupnp::Error::InvalidResponse(e.into()))?;
+
+        let transport_state = match status.player_state.as_deref() {
+            Some("PLAYING") => "PLAYING",
+            Some("PAUSED") => "PAUSED_PLAYBACK",
+            Some("BUFFERING") => "TRANSITIONING",
+            Some(_) => "STOPPED",
+            None => "NO_MEDIA_PRESENT",
+        };
+        Ok(HashMap::from([
+            (
+                "RelTime".to_string(),
+                format_hms(status.current_time as u32),
+            ),
+            (
+                "TrackDuration".to_string(),
+                format_hms(status.duration as u32),
+            ),
+            (
+                "CurrentTransportState".to_string(),
+                transport_state.to_string(),
+            ),
+            (
+                "CurrentVolume".to_string(),
+                ((status.volume * 100.0).round() as u32).to_string(),
+            ),
+        ]))
+    }
+
+    // 发送RenderingControl动作（dry-run 模式下只记录日志）
+    async fn rendering_control_action(
+        &self,
+        device: &DlnaDevice,
+        action: &str,
+        args_xml: &str,
+    ) -> Result<HashMap<String, String>, rupnp::Error> {
+        if let Some(response) = self.dry_run_action("RenderingControl", action, args_xml) {
+            return Ok(response);
+        }
+        if let Some(address) = device.cast_address {
+            return self.cast_action(address, action, args_xml).await;
+        }
+
+        let rendering_control = self
+            .find_service(device, &RENDERING_CONTROL)
+            .ok_or(rupnp::Error::ParseError("设备不支持RenderingControl服务"))?;
+
+        let deadline = tokio::time::Instant::now() + self.config.action_timeout(action);
+        with_fallback_locations(device, action, deadline, |base_url| async move {
+            // RenderingControl uses a different service; still log with a reasonable SOAPAction.
+            log::info!(
+                "UPnP Action -> base_url={} service_id={} service_type={} SOAPAction=\"urn:schemas-upnp-org:service:RenderingControl:1#{}\"",
+                base_url,
+                rendering_control.service_id(),
+                rendering_control.service_type(),
+                action
+            );
+            log::debug!(
+                "UPnP Action body (approx) => {}",
+                build_soap_envelope_for("RenderingControl", action, args_xml)
+            );
+
+            rendering_control.action(&base_url, action, args_xml).await
+        })
+        .await
+    }
+
+    // 设置AVTransport URI（发送媒体URL给设备）
+    // `subtitle_uri` 与 `current_uri` 一样是本地服务上的路径，仅在自动生成元数据时生效
+    pub async fn set_avtransport_uri(
+        &self,
+        device: &DlnaDevice,
+        current_uri: &str,
+        current_uri_metadata: &str,
+        server_ip: IpAddr,
+        server_port: u16,
+        subtitle_uri: Option<&str>,
+    ) -> Result<(), rupnp::Error> {
+        // 构建完整的媒体URL
+        let media_url = format!("http://{}:{}/{}", server_ip, server_port, current_uri);
+        let subtitle_url =
+            subtitle_uri.map(|uri| format!("http://{}:{}/{}", server_ip, server_port, uri));
+
+        log::info!("设置媒体URI: {}", media_url);
+        log::debug!("元数据(传入): {}", current_uri_metadata);
+
+        // If caller didn't provide metadata, generate a minimal DIDL-Lite for compatibility.
+        let metadata = if current_uri_metadata.trim().is_empty() {
+            // Title can be anything; devices often only care about protocolInfo.
+            // 媒体地址指向本地代理，代理支持 Range
+            build_didl_lite_metadata(
+                current_uri,
+                &media_url,
+                DlnaFlags::PROXIED,
+                subtitle_url.as_deref(),
+            )
+        } else {
+            current_uri_metadata.to_string()
+        };
+
+        self.send_avtransport_uri(device, &media_url, &metadata)
+            .await
+    }
+
+    // 直接把完整的媒体地址（如B站 CDN 直链）交给渲染器，字幕地址同样需为完整URL
+    pub async fn set_avtransport_url(
+        &self,
+        device: &DlnaDevice,
+        title: &str,
+        media_url: &str,
+        subtitle_url: Option<&str>,
+    ) -> Result<(), rupnp::Error> {
+        log::info!("设置媒体地址: {}", media_url);
+        // CDN 同样支持 Range 请求；转码与多段合并的输出没有总长度，不能按字节跳转
+        let flags = if media_url.ends_with(TRANSCODE_SUFFIX) || media_url.ends_with(JOINED_SUFFIX) {
+            DlnaFlags::NO_SEEK
+        } else {
+            DlnaFlags::PROXIED
+        };
+        let metadata = build_didl_lite_metadata(title, media_url, flags, subtitle_url);
+        self.send_avtransport_uri(device, media_url, &metadata)
+            .await
+    }
+
+    // 把纯音频地址交给音箱类渲染器，元数据标为音乐曲目
+    pub async fn set_avtransport_audio_url(
+        &self,
+        device: &DlnaDevice,
+        title: &str,
+        media_url: &str,
+    ) -> Result<(), rupnp::Error> {
+        log::info!("设置音频地址: {}", media_url);
+        let metadata =
+            build_didl_lite(title, media_url, DlnaFlags::PROXIED, None, MediaKind::Audio);
+        self.send_avtransport_uri(device, media_url, &metadata)
+            .await
+    }
+
+    // 把媒体地址设为空，部分电视会因此回到主界面而不是停在最后一帧
+    pub async fn clear_avtransport_uri(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+        log::info!("清除媒体地址");
+        self.send_avtransport_uri(device, "", "").await
+    }
+
+    async fn send_avtransport_uri(
+        &self,
+        device: &DlnaDevice,
+        media_url: &str,
+        metadata: &str,
+    ) -> Result<(), rupnp::Error> {
+        // 准备SOAP请求参数 - 只使用标准参数以提高兼容性
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+            xml_escape(media_url),
+            metadata
+        );
+
+        let response = self
+            .avtransport_action(device, "SetAVTransportURI", &args_str)
+            .await?;
+
+        log::debug!("SetAVTransportURI响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 设置下一个AVTransport URI（用于播放列表）
+    pub async fn set_next_avtransport_uri(
+        &self,
+        device: &DlnaDevice,
+        next_uri: &str,
+        next_uri_metadata: &str,
+        server_ip: IpAddr,
+        server_port: u16,
+    ) -> Result<(), rupnp::Error> {
+        let media_url = format!("http://{}:{}/{}", server_ip, server_port, next_uri);
+        let metadata = if next_uri_metadata.trim().is_empty() {
+            build_didl_lite_metadata(next_uri, &media_url, DlnaFlags::PROXIED, None)
+        } else {
+            next_uri_metadata.to_string()
+        };
+
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><NextURI>{}</NextURI><NextURIMetaData>{}</NextURIMetaData>",
+            xml_escape(&media_url),
+            metadata
+        );
+
+        let response = self
+            .avtransport_action(device, "SetNextAVTransportURI", &args_str)
+            .await?;
+
+        log::debug!("SetNextAVTransportURI响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 播放媒体
+    pub async fn play(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+        log::info!("正在发送Play指令...");
+        let args_str = "<InstanceID>0</InstanceID><Speed>1</Speed>";
+
+        let response = self.avtransport_action(device, "Play", args_str).await?;
+        log::debug!("Play响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 暂停播放
+    pub async fn pause(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+        log::info!("正在发送Pause指令...");
+        let args_str = "<InstanceID>0</InstanceID>";
+
+        let response = self.avtransport_action(device, "Pause", args_str).await?;
+        log::debug!("Pause响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 停止播放
+    pub async fn stop(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+        log::info!("正在发送Stop指令...");
+        let args_str = "<InstanceID>0</InstanceID>";
+
+        let response = self.avtransport_action(device, "Stop", args_str).await?;
+        log::debug!("Stop响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 下一首
+    pub async fn next(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+        let args_str = "<InstanceID>0</InstanceID>";
+
+        let response = self.avtransport_action(device, "Next", args_str).await?;
+        log::debug!("Next响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 跳转到指定位置（秒）
+    pub async fn seek(&self, device: &DlnaDevice, position_secs: u32) -> Result<(), rupnp::Error> {
+        log::info!("正在发送Seek指令: {}秒", position_secs);
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><Unit>REL_TIME</Unit><Target>{}</Target>",
+            format_hms(position_secs)
+        );
+
+        let response = self.avtransport_action(device, "Seek", &args_str).await?;
+        log::debug!("Seek响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 获取传输信息
+    pub async fn get_transport_info(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<TransportState, rupnp::Error> {
+        let args_str = "<InstanceID>0</InstanceID>";
+
+        let response = self
+            .avtransport_action(device, "GetTransportInfo", args_str)
+            .await?;
+        log::debug!("传输信息: {:?}", response);
+
+        let state = response
+            .get("CurrentTransportState")
+            .ok_or(rupnp::Error::ParseError(
+                "响应中缺少CurrentTransportState字段",
+            ))?;
+        Ok(TransportState::parse(state))
+    }
+
+    // 获取渲染器当前允许的传输动作
+    pub async fn get_current_transport_actions(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<TransportActions, rupnp::Error> {
         let args_str = "<InstanceID>0</InstanceID>";
 
-        let base_url = device_location_uri(device)?;
-        log_upnp_action(avtransport, &base_url, action, args_str);
+        let response = self
+            .avtransport_action(device, "GetCurrentTransportActions", args_str)
+            .await?;
+        log::debug!("GetCurrentTransportActions响应: {:?}", response);
+
+        let actions = response
+            .get("Actions")
+            .ok_or(rupnp::Error::ParseError("响应中缺少Actions字段"))?;
+        Ok(TransportActions::parse(actions))
+    }
+
+    /// 渲染器能播放的格式（ConnectionManager 的 GetProtocolInfo 中的 Sink），按设备缓存
+    pub async fn get_sink_protocol_info(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<Vec<String>, rupnp::Error> {
+        if let Some(sink) = self.sink_protocols.lock().unwrap().get(&device.udn) {
+            return Ok(sink.clone());
+        }
+        let response = match self.dry_run_action("ConnectionManager", "GetProtocolInfo", "") {
+            Some(response) => response,
+            None => {
+                if device.cast_address.is_some() {
+                    return Err(rupnp::Error::ParseError("Chromecast不支持GetProtocolInfo"));
+                }
+                let connection_manager = self
+                    .find_service(device, &CONNECTION_MANAGER)
+                    .ok_or(rupnp::Error::ParseError("设备不支持ConnectionManager服务"))?;
+                let deadline =
+                    tokio::time::Instant::now() + self.config.action_timeout("GetProtocolInfo");
+                with_fallback_locations(
+                    device,
+                    "GetProtocolInfo",
+                    deadline,
+                    |base_url| async move {
+                        connection_manager
+                            .action(&base_url, "GetProtocolInfo", "")
+                            .await
+                    },
+                )
+                .await?
+            }
+        };
+        let sink: Vec<String> = response
+            .get("Sink")
+            .ok_or(rupnp::Error::ParseError("响应中缺少Sink字段"))?
+            .split(',')
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        log::debug!("{} 的 Sink protocolInfo: {:?}", device.friendly_name, sink);
+        self.sink_protocols
+            .lock()
+            .unwrap()
+            .insert(device.udn.clone(), sink.clone());
+        Ok(sink)
+    }
+
+    // 获取位置信息
+    pub async fn get_position_info(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<HashMap<String, String>, rupnp::Error> {
+        let args_str = "<InstanceID>0</InstanceID>";
 
         // 获取响应
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = self
+            .avtransport_action(device, "GetPositionInfo", args_str)
+            .await?;
 
         log::debug!("GetPositionInfo响应: {:?}", response);
 
@@ -667,14 +1949,6 @@ impl DlnaController {
 
     // 设置渲染器音量
     pub async fn set_volume(&self, device: &DlnaDevice, volume: u32) -> Result<(), rupnp::Error> {
-        let rendering_control = device
-            .device
-            .services()
-            .iter()
-            .find(|s| *s.service_type() == URN::service("schemas-upnp-org", "RenderingControl", 1))
-            .ok_or(rupnp::Error::ParseError("设备不支持RenderingControl服务"))?;
-
-        let action = "SetVolume";
         let args_str = format!(
             r#"
             <InstanceID>0</InstanceID>
@@ -684,31 +1958,8 @@ impl DlnaController {
             volume
         );
 
-        let base_url = device_location_uri(device)?;
-        // RenderingControl uses a different service; still log with a reasonable SOAPAction.
-        log::info!(
-            "UPnP Action -> base_url={} service_id={} service_type={} SOAPAction=\"urn:schemas-upnp-org:service:RenderingControl:1#{}\"",
-            base_url,
-            rendering_control.service_id(),
-            rendering_control.service_type(),
-            action
-        );
-        log::debug!(
-            "UPnP Action body (approx) => {}",
-            format_args!(
-                r#"<?xml version="1.0" encoding="UTF-8"?>
-                <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
-                <s:Body>
-                    <u:{action} xmlns:u="urn:schemas-upnp-org:service:RenderingControl:1">{args}</u:{action}>
-                </s:Body>
-                </s:Envelope>"#,
-                action = action,
-                args = args_str
-            )
-        );
-
-        let response = rendering_control
-            .action(&base_url, action, &args_str)
+        let response = self
+            .rendering_control_action(device, "SetVolume", &args_str)
             .await?;
         log::debug!("SetVolume响应: {:?}", response);
 
@@ -717,43 +1968,13 @@ impl DlnaController {
 
     // 获取渲染器音量
     pub async fn get_volume(&self, device: &DlnaDevice) -> Result<u32, rupnp::Error> {
-        let rendering_control = device
-            .device
-            .services()
-            .iter()
-            .find(|s| *s.service_type() == URN::service("schemas-upnp-org", "RenderingControl", 1))
-            .ok_or(rupnp::Error::ParseError("设备不支持RenderingControl服务"))?;
-
-        let action = "GetVolume";
         let args_str = r#"
             <InstanceID>0</InstanceID>
             <Channel>Master</Channel>
             "#;
 
-        let base_url = device_location_uri(device)?;
-        log::info!(
-            "UPnP Action -> base_url={} service_id={} service_type={} SOAPAction=\"urn:schemas-upnp-org:service:RenderingControl:1#{}\"",
-            base_url,
-            rendering_control.service_id(),
-            rendering_control.service_type(),
-            action
-        );
-        log::debug!(
-            "UPnP Action body (approx) => {}",
-            format_args!(
-                r#"<?xml version="1.0" encoding="UTF-8"?>
-                <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
-                <s:Body>
-                    <u:{action} xmlns:u="urn:schemas-upnp-org:service:RenderingControl:1">{args}</u:{action}>
-                </s:Body>
-                </s:Envelope>"#,
-                action = action,
-                args = args_str
-            )
-        );
-
-        let response = rendering_control
-            .action(&base_url, action, args_str)
+        let response = self
+            .rendering_control_action(device, "GetVolume", args_str)
             .await?;
 
         // 解析音量值
@@ -763,6 +1984,143 @@ impl DlnaController {
 
         Ok(volume)
     }
+
+    // 查询渲染器支持的分贝音量范围；不支持 VolumeDB 的渲染器会返回错误
+    pub async fn get_volume_db_range(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<VolumeDbRange, rupnp::Error> {
+        let response = self
+            .rendering_control_action(
+                device,
+                "GetVolumeDBRange",
+                "<InstanceID>0</InstanceID><Channel>Master</Channel>",
+            )
+            .await?;
+
+        let min_db = response.get("MinValue").and_then(|v| upnp_to_db(v));
+        let max_db = response.get("MaxValue").and_then(|v| upnp_to_db(v));
+        match (min_db, max_db) {
+            (Some(min_db), Some(max_db)) if min_db < max_db => Ok(VolumeDbRange { min_db, max_db }),
+            _ => Err(rupnp::Error::ParseError("GetVolumeDBRange响应中的范围无效")),
+        }
+    }
+
+    // 获取渲染器音量（dB）
+    pub async fn get_volume_db(&self, device: &DlnaDevice) -> Result<f32, rupnp::Error> {
+        let response = self
+            .rendering_control_action(
+                device,
+                "GetVolumeDB",
+                "<InstanceID>0</InstanceID><Channel>Master</Channel>",
+            )
+            .await?;
+
+        response
+            .get("CurrentVolume")
+            .and_then(|v| upnp_to_db(v))
+            .ok_or(rupnp::Error::ParseError("响应中缺少CurrentVolume字段"))
+    }
+
+    // 按分贝设置渲染器音量，精度为 1/256 dB；功放等设备上比 0-100 的音量更细
+    pub async fn set_volume_db(&self, device: &DlnaDevice, db: f32) -> Result<(), rupnp::Error> {
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><Channel>Master</Channel><DesiredVolume>{}</DesiredVolume>",
+            db_to_upnp(db)
+        );
+
+        let response = self
+            .rendering_control_action(device, "SetVolumeDB", &args_str)
+            .await?;
+        log::debug!("SetVolumeDB响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 设置单个声道的音量（LF/RF 等），用于调节左右平衡
+    async fn set_channel_volume(
+        &self,
+        device: &DlnaDevice,
+        channel: &str,
+        volume: u32,
+    ) -> Result<(), rupnp::Error> {
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><Channel>{}</Channel><DesiredVolume>{}</DesiredVolume>",
+            channel, volume
+        );
+
+        let response = self
+            .rendering_control_action(device, "SetVolume", &args_str)
+            .await?;
+        log::debug!("SetVolume({})响应: {:?}", channel, response);
+
+        Ok(())
+    }
+
+    // 设置左右声道平衡：-100 为完全偏左，0 为居中，100 为完全偏右
+    pub async fn set_balance(&self, device: &DlnaDevice, balance: i32) -> Result<(), rupnp::Error> {
+        let balance = balance.clamp(-100, 100);
+        let left = (100 - balance.max(0)) as u32;
+        let right = (100 + balance.min(0)) as u32;
+        log::info!("设置声道平衡: {} (左{} 右{})", balance, left, right);
+
+        self.set_channel_volume(device, "LF", left).await?;
+        self.set_channel_volume(device, "RF", right).await
+    }
+
+    // 设置低音（SetBass，常见于音箱/回音壁，范围 -10..10）
+    pub async fn set_bass(&self, device: &DlnaDevice, level: i32) -> Result<(), rupnp::Error> {
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><DesiredBass>{}</DesiredBass>",
+            level.clamp(-10, 10)
+        );
+
+        let response = self
+            .rendering_control_action(device, "SetBass", &args_str)
+            .await?;
+        log::debug!("SetBass响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 获取低音
+    pub async fn get_bass(&self, device: &DlnaDevice) -> Result<i32, rupnp::Error> {
+        let response = self
+            .rendering_control_action(device, "GetBass", "<InstanceID>0</InstanceID>")
+            .await?;
+
+        response
+            .get("CurrentBass")
+            .and_then(|v| v.parse().ok())
+            .ok_or(rupnp::Error::ParseError("响应中缺少CurrentBass字段"))
+    }
+
+    // 设置高音（SetTreble，范围 -10..10）
+    pub async fn set_treble(&self, device: &DlnaDevice, level: i32) -> Result<(), rupnp::Error> {
+        let args_str = format!(
+            "<InstanceID>0</InstanceID><DesiredTreble>{}</DesiredTreble>",
+            level.clamp(-10, 10)
+        );
+
+        let response = self
+            .rendering_control_action(device, "SetTreble", &args_str)
+            .await?;
+        log::debug!("SetTreble响应: {:?}", response);
+
+        Ok(())
+    }
+
+    // 获取高音
+    pub async fn get_treble(&self, device: &DlnaDevice) -> Result<i32, rupnp::Error> {
+        let response = self
+            .rendering_control_action(device, "GetTreble", "<InstanceID>0</InstanceID>")
+            .await?;
+
+        response
+            .get("CurrentTreble")
+            .and_then(|v| v.parse().ok())
+            .ok_or(rupnp::Error::ParseError("响应中缺少CurrentTreble字段"))
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -806,4 +2164,277 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_dry_run_does_not_need_renderer() {
+        let controller = DlnaController::new_dry_run();
+        let device = DlnaDevice::dry_run();
+
+        controller
+            .set_avtransport_uri(
+                &device,
+                "BV1AP411x7YW",
+                "",
+                "127.0.0.1".parse().unwrap(),
+                8080,
+                None,
+            )
+            .await
+            .expect("dry-run SetAVTransportURI 不应失败");
+        controller
+            .play(&device)
+            .await
+            .expect("dry-run Play 不应失败");
+
+        let (_, total) = controller.get_secs(&device).await.unwrap();
+        assert_eq!(total, DRY_RUN_TRACK_SECS);
+
+        controller.set_volume(&device, 30).await.unwrap();
+        assert_eq!(controller.get_volume(&device).await.unwrap(), 30);
+
+        controller.set_bass(&device, 15).await.unwrap();
+        assert_eq!(controller.get_bass(&device).await.unwrap(), 10);
+        controller.set_balance(&device, -40).await.unwrap();
+        assert_eq!(controller.get_volume(&device).await.unwrap(), 30);
+
+        let range = controller.get_volume_db_range(&device).await.unwrap();
+        assert_eq!(range.min_db, -80.0);
+        controller.set_volume_db(&device, -12.5).await.unwrap();
+        assert_eq!(controller.get_volume_db(&device).await.unwrap(), -12.5);
+    }
+
+    #[test]
+    fn test_prefers_direct_link_by_quirk() {
+        let device = DlnaDevice::dry_run();
+        let config = DlnaConfig {
+            direct_link_devices: vec!["KTV-CASTING dry".to_string()],
+            ..DlnaConfig::default()
+        };
+        let controller = DlnaController::new().with_dlna_config(config.clone());
+        assert!(controller.prefers_direct_link(&device));
+        // 运行中关闭直链开关后改走本地代理
+        controller.features().set(Feature::DirectLink, false);
+        assert!(!controller.prefers_direct_link(&device));
+        controller.features().set(Feature::DirectLink, true);
+
+        let controller = DlnaController::new().with_dlna_config(DlnaConfig {
+            url_strategy: UrlStrategy::Proxy,
+            ..config
+        });
+        assert!(!controller.prefers_direct_link(&device));
+        assert!(!DlnaController::new().prefers_direct_link(&device));
+    }
+
+    #[test]
+    fn test_didl_lite_subtitle() {
+        let plain = build_didl_lite_metadata("t", "http://h/v", DlnaFlags::PROXIED, None);
+        assert!(!plain.contains("CaptionInfoEx"));
+
+        // 已转义的标题不会被再次转义
+        let escaped = build_didl_lite_metadata("A &amp; B", "http://h/v", DlnaFlags::PROXIED, None);
+        assert!(escaped.contains("&lt;dc:title&gt;A &amp;amp; B&lt;/dc:title&gt;"));
+
+        let didl = build_didl_lite_metadata(
+            "t",
+            "http://h/v",
+            DlnaFlags::PROXIED,
+            Some("http://h/subtitles/v.srt"),
+        );
+        assert!(
+            didl.contains("sec:CaptionInfoEx sec:type=&quot;srt&quot;&gt;http://h/subtitles/v.srt")
+        );
+        assert!(didl.contains("pv:subtitleFileUri=&quot;http://h/subtitles/v.srt&quot;"));
+    }
+
+    #[test]
+    fn test_dlna_flags_protocol_info() {
+        assert_eq!(
+            DlnaFlags::PROXIED.protocol_info("video/mp4"),
+            "http-get:*:video/mp4:DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000"
+        );
+        assert_eq!(DlnaFlags::NO_SEEK.op(), "00");
+    }
+
+    #[test]
+    fn test_hide_and_pin_devices() {
+        let printer = DlnaDevice {
+            friendly_name: "HP LaserJet MFP".to_string(),
+            udn: "uuid:printer".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let lab_tv = DlnaDevice {
+            friendly_name: "Lab TV".to_string(),
+            location: "http://10.0.3.15:49152/description.xml".to_string(),
+            udn: "uuid:lab".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let soundbar = DlnaDevice {
+            friendly_name: "Living Room Soundbar".to_string(),
+            udn: "uuid:soundbar".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let tv = DlnaDevice {
+            friendly_name: "Living Room TV".to_string(),
+            udn: "uuid:tv".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let config = DiscoveryConfig {
+            hide_devices: vec!["laserjet".to_string(), "10.0.3.0/24".to_string()],
+            pin_devices: vec!["Living Room TV".to_string(), "soundbar".to_string()],
+            ..Default::default()
+        };
+
+        let devices = apply_device_filters(vec![printer, lab_tv, soundbar, tv], &config);
+        let udns: Vec<&str> = devices.iter().map(|d| d.udn.as_str()).collect();
+        assert_eq!(udns, ["uuid:tv", "uuid:soundbar"]);
+
+        assert!(parse_ip_range("10.0.3.0/33").is_none());
+        assert!(ip_in_range(
+            "192.168.1.20".parse().unwrap(),
+            "192.168.1.20".parse().unwrap(),
+            32
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_known_devices_are_offline() {
+        let controller = DlnaController::new().with_discovery_config(DiscoveryConfig {
+            known_devices: vec![
+                "http://127.0.0.1:1/description.xml".to_string(),
+                "not a url".to_string(),
+            ],
+            ..Default::default()
+        });
+        let started = Instant::now();
+        let known = controller.check_known_devices().await;
+        assert!(started.elapsed() < KNOWN_DEVICE_TIMEOUT * 2);
+        assert_eq!(known.len(), 2);
+        assert!(matches!(
+            &known[0],
+            KnownDevice::Offline { location, .. } if location.ends_with(":1/description.xml")
+        ));
+        assert!(matches!(known[1], KnownDevice::Offline { .. }));
+        assert!(
+            DlnaController::new_dry_run()
+                .check_known_devices()
+                .await
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_merge_by_udn_prefers_lower_latency() {
+        let wifi = DlnaDevice {
+            location: "http://192.168.1.20:49152/description.xml".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let ethernet = DlnaDevice {
+            location: "http://192.168.1.21:49152/description.xml".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+
+        let mut devices = Vec::new();
+        merge_by_udn(&mut devices, wifi, Duration::from_millis(80));
+        merge_by_udn(&mut devices, ethernet, Duration::from_millis(5));
+
+        assert_eq!(devices.len(), 1);
+        let (device, _) = &devices[0];
+        assert_eq!(device.location, "http://192.168.1.21:49152/description.xml");
+        assert_eq!(
+            device.fallback_locations,
+            vec!["http://192.168.1.20:49152/description.xml"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback_skips_unparsable_location() {
+        let device = DlnaDevice {
+            location: "http://bad host/description.xml".to_string(),
+            fallback_locations: vec!["http://192.168.1.21:49152/description.xml".to_string()],
+            ..DlnaDevice::dry_run()
+        };
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(1);
+        let response = with_fallback_locations(&device, "Play", deadline, |base_url| async move {
+            Ok(HashMap::from([("base".to_string(), base_url.to_string())]))
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            response["base"],
+            "http://192.168.1.21:49152/description.xml"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback_locations_share_one_deadline() {
+        let device = DlnaDevice {
+            location: "http://192.168.1.20:49152/description.xml".to_string(),
+            fallback_locations: vec!["http://192.168.1.21:49152/description.xml".to_string()],
+            ..DlnaDevice::dry_run()
+        };
+        // 两个地址都不响应，总等待时间仍不超过一次超时
+        let timeout = Duration::from_millis(200);
+        let started = Instant::now();
+        let result = with_fallback_locations(
+            &device,
+            "Play",
+            tokio::time::Instant::now() + timeout,
+            |_| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(HashMap::new())
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(started.elapsed() < timeout * 3 / 2);
+    }
+
+    #[test]
+    fn test_service_label() {
+        assert_eq!(
+            service_label("urn:schemas-upnp-org:service:AVTransport:1"),
+            "AVTransport v1"
+        );
+        assert_eq!(
+            service_label("urn:schemas-upnp-org:service:RenderingControl:3"),
+            "RenderingControl v3"
+        );
+        assert_eq!(service_label("custom"), "custom");
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_seek_and_transport_state() {
+        let controller = DlnaController::new_dry_run();
+        let device = DlnaDevice::dry_run();
+        assert_eq!(
+            controller.get_transport_info(&device).await.unwrap(),
+            TransportState::NoMediaPresent
+        );
+
+        controller
+            .set_avtransport_uri(
+                &device,
+                "BV1xx411c7mD",
+                "",
+                "127.0.0.1".parse().unwrap(),
+                8080,
+                None,
+            )
+            .await
+            .unwrap();
+        controller.seek(&device, 42).await.unwrap();
+        let (current, _) = controller.get_secs(&device).await.unwrap();
+        assert_eq!(current, 42);
+        assert_eq!(
+            controller.get_transport_info(&device).await.unwrap(),
+            TransportState::PausedPlayback
+        );
+    }
+
+    #[test]
+    fn test_parse_transport_actions() {
+        let actions = TransportActions::parse("Play, Stop,X_DLNA_SeekTime");
+        assert!(actions.play && actions.stop);
+        assert!(!actions.pause && !actions.seek && !actions.next);
+    }
 }
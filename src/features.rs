@@ -0,0 +1,139 @@
+//! 运行时功能开关
+//!
+//! 排查问题时经常需要临时关掉某个模块（例如只走兼容 SOAP 路径），
+//! 这些开关可以在运行中通过控制台切换，不需要修改配置文件再重启。
+//! 初始值来自配置文件的 `[features]` 段。
+
+use crate::config::FeaturesConfig;
+use crate::i18n::text;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// 可切换的功能
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// 跳过 rupnp 原生请求，SOAP 动作只走兼容路径
+    CompatSoapOnly,
+    /// 渲染器中途停止时自动恢复播放
+    TransportWatchdog,
+    /// 媒体代理的预读缓冲（关闭后直接透传上游数据）
+    ProxyReadAhead,
+    /// 歌曲快结束时自动请求下一首
+    AutoAdvance,
+    /// 按 `url_strategy` 使用B站直链（关闭后一律经本地代理投送）
+    DirectLink,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::CompatSoapOnly,
+        Feature::TransportWatchdog,
+        Feature::ProxyReadAhead,
+        Feature::AutoAdvance,
+        Feature::DirectLink,
+    ];
+
+    /// 配置文件和控制台中使用的名字
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::CompatSoapOnly => "compat_soap_only",
+            Feature::TransportWatchdog => "transport_watchdog",
+            Feature::ProxyReadAhead => "proxy_read_ahead",
+            Feature::AutoAdvance => "auto_advance",
+            Feature::DirectLink => "direct_link",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Feature::CompatSoapOnly => text("SOAP 只走兼容路径", "SOAP via compat path only"),
+            Feature::TransportWatchdog => text(
+                "渲染器意外停止时自动恢复",
+                "resume when the renderer stops unexpectedly",
+            ),
+            Feature::ProxyReadAhead => text("媒体代理预读缓冲", "media proxy read-ahead"),
+            Feature::AutoAdvance => text("歌曲结束时自动切歌", "next song when one ends"),
+            Feature::DirectLink => text("使用B站直链投送", "cast Bilibili direct links"),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Feature> {
+        Feature::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// 功能开关集合；可廉价 `clone`，所有克隆共享同一组开关
+#[derive(Debug, Clone)]
+pub struct FeatureFlags {
+    bits: Arc<AtomicU32>,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self::new(&FeaturesConfig::default())
+    }
+}
+
+impl FeatureFlags {
+    pub fn new(config: &FeaturesConfig) -> Self {
+        let flags = Self {
+            bits: Arc::new(AtomicU32::new(0)),
+        };
+        flags.set(Feature::CompatSoapOnly, config.compat_soap_only);
+        flags.set(Feature::TransportWatchdog, config.transport_watchdog);
+        flags.set(Feature::ProxyReadAhead, config.proxy_read_ahead);
+        flags.set(Feature::AutoAdvance, config.auto_advance);
+        flags.set(Feature::DirectLink, config.direct_link);
+        flags
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.bits.load(Ordering::Acquire) & feature.bit() != 0
+    }
+
+    pub fn set(&self, feature: Feature, enabled: bool) {
+        if enabled {
+            self.bits.fetch_or(feature.bit(), Ordering::AcqRel);
+        } else {
+            self.bits.fetch_and(!feature.bit(), Ordering::AcqRel);
+        }
+    }
+
+    /// 切换开关，返回切换后的状态
+    pub fn toggle(&self, feature: Feature) -> bool {
+        let old = self.bits.fetch_xor(feature.bit(), Ordering::AcqRel);
+        old & feature.bit() == 0
+    }
+
+    /// 所有开关的当前状态
+    pub fn snapshot(&self) -> Vec<(Feature, bool)> {
+        Feature::ALL
+            .into_iter()
+            .map(|f| (f, self.is_enabled(f)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_is_shared_between_clones() {
+        let flags = FeatureFlags::default();
+        let other = flags.clone();
+        assert!(!flags.is_enabled(Feature::CompatSoapOnly));
+        assert!(flags.is_enabled(Feature::AutoAdvance));
+
+        assert!(other.toggle(Feature::CompatSoapOnly));
+        assert!(flags.is_enabled(Feature::CompatSoapOnly));
+        assert_eq!(
+            Feature::from_name("compat_soap_only"),
+            Some(Feature::CompatSoapOnly)
+        );
+    }
+}
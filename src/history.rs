@@ -0,0 +1,291 @@
+//! 播放历史与每首歌的播放质量评分
+//!
+//! 每次投屏会生成一条 [`SongRecord`]，记录起播耗时、投屏重试、代理卡顿续传、
+//! 画质降级和自动恢复次数，并折算成 0-100 的健康分。按时间排列后，
+//! 很容易看出“晚上 11 点以后每首都卡”这类规律。
+
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// 内存中最多保留的历史条数
+const MAX_RECORDS: usize = 500;
+// 起播耗时在该值以内不扣分
+const LATENCY_GRACE: Duration = Duration::from_secs(3);
+
+/// 一首歌的播放记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongRecord {
+    /// 代理路径，例如 `BV1xx411c7mD-page2`
+    pub song: String,
+    pub started_at: DateTime<Local>,
+    /// 从开始投屏到渲染器报告进度的耗时；尚未起播时为 None
+    pub start_latency: Option<Duration>,
+    /// 媒体代理检测到上游卡住并续传的次数
+    pub stalls: u32,
+    /// 投屏过程中 SOAP 动作的重试次数
+    pub retries: u32,
+    /// 画质/分段降级次数
+    pub downgrades: u32,
+    /// 渲染器意外停止后自动恢复的次数
+    pub recoveries: u32,
+    /// 点歌人的昵称，房间没有提供时为 None
+    pub requester: Option<String>,
+    /// 渲染器报告过的最远播放进度（秒），即这首歌实际唱了多久
+    pub played_secs: u32,
+    /// 是否在播完前被切到下一首
+    pub skipped: bool,
+}
+
+impl SongRecord {
+    pub fn new(song: &str) -> Self {
+        Self {
+            song: song.to_string(),
+            started_at: Local::now(),
+            start_latency: None,
+            stalls: 0,
+            retries: 0,
+            downgrades: 0,
+            recoveries: 0,
+            requester: None,
+            played_secs: 0,
+            skipped: false,
+        }
+    }
+
+    /// 健康分：100 为完全顺畅，每种问题按严重程度扣分
+    pub fn health_score(&self) -> u8 {
+        let latency_penalty = self
+            .start_latency
+            .map(|l| l.saturating_sub(LATENCY_GRACE).as_secs() * 2)
+            .unwrap_or(0)
+            .min(30);
+        let penalty = self.stalls as u64 * 15
+            + self.retries as u64 * 5
+            + self.downgrades as u64 * 20
+            + self.recoveries as u64 * 15
+            + latency_penalty;
+        100u64.saturating_sub(penalty) as u8
+    }
+}
+
+/// 播放历史，按投屏时间先后排列
+#[derive(Debug, Default)]
+pub struct PlaybackHistory {
+    records: Vec<SongRecord>,
+}
+
+impl PlaybackHistory {
+    /// 开始一条新记录，`requester` 为点歌人
+    pub fn start(&mut self, song: &str, requester: Option<String>) {
+        if self.records.len() >= MAX_RECORDS {
+            self.records.remove(0);
+        }
+        self.records.push(SongRecord {
+            requester,
+            ..SongRecord::new(song)
+        });
+    }
+
+    /// 正在播放的歌曲对应的记录
+    pub fn current_mut(&mut self, song: &str) -> Option<&mut SongRecord> {
+        self.records.last_mut().filter(|r| r.song == song)
+    }
+
+    pub fn records(&self) -> &[SongRecord] {
+        &self.records
+    }
+}
+
+/// 一场派对的汇总，结束会话时和 `S` 命令打印
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartySummary {
+    /// 投屏的歌曲数（重复点唱分别计数）
+    pub songs: usize,
+    /// 从第一首开始投屏到结束
+    pub duration: Duration,
+    /// 各首歌实际播放时长之和
+    pub played: Duration,
+    /// 播完前被切歌的次数
+    pub skips: usize,
+    /// 点唱次数最多的歌曲及次数；次数相同时取先唱的
+    pub most_requested: (String, usize),
+    /// 每位点歌人的点唱次数，从多到少；次数相同时先点的在前。房间没有提供昵称的歌曲不计入
+    pub requesters: Vec<(String, usize)>,
+    /// 平均健康分
+    pub average_health: u8,
+}
+
+// 按出现先后计数
+fn count_in_order<'a>(items: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for item in items {
+        match counts.iter_mut().find(|(seen, _)| *seen == item) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((item, 1)),
+        }
+    }
+    counts
+}
+
+impl PartySummary {
+    /// 根据播放记录生成汇总，没有记录时为 None
+    pub fn from_records(records: &[SongRecord], ended_at: DateTime<Local>) -> Option<Self> {
+        let first = records.first()?;
+        let counts = count_in_order(records.iter().map(|r| r.song.as_str()));
+        // max_by_key 在相等时取最后一个，倒序遍历使先唱的胜出
+        let (song, count) = counts.into_iter().rev().max_by_key(|(_, count)| *count)?;
+        let mut requesters = count_in_order(records.iter().filter_map(|r| r.requester.as_deref()));
+        // 稳定排序，次数相同时保持先点的在前
+        requesters.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        let total_health: u64 = records.iter().map(|r| r.health_score() as u64).sum();
+        Some(Self {
+            songs: records.len(),
+            duration: (ended_at - first.started_at).to_std().unwrap_or_default(),
+            played: Duration::from_secs(records.iter().map(|r| r.played_secs as u64).sum()),
+            skips: records.iter().filter(|r| r.skipped).count(),
+            most_requested: (song.to_string(), count),
+            requesters: requesters
+                .into_iter()
+                .map(|(name, count)| (name.to_string(), count))
+                .collect(),
+            average_health: (total_health / records.len() as u64) as u8,
+        })
+    }
+}
+
+/// 媒体代理记录的每首歌的问题次数，由会话在生成历史记录时取走；
+/// 同时记录每首歌已从上游下载到的位置，供进度条显示缓冲进度
+#[derive(Debug, Clone, Default)]
+pub struct ProxyStats {
+    inner: Arc<Mutex<HashMap<String, ProxyCounters>>>,
+    // 歌曲 -> (已下载到的字节位置, 文件总长度)
+    downloaded: Arc<Mutex<HashMap<String, (u64, u64)>>>,
+    // 歌曲 -> 代理拿到的画质代码（qn）
+    quality: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProxyCounters {
+    pub stalls: u32,
+    pub downgrades: u32,
+}
+
+impl ProxyStats {
+    pub fn record_stall(&self, song: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .entry(song.to_string())
+            .or_default()
+            .stalls += 1;
+    }
+
+    pub fn record_downgrade(&self, song: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .entry(song.to_string())
+            .or_default()
+            .downgrades += 1;
+    }
+
+    /// 某首歌目前的计数
+    pub fn peek(&self, song: &str) -> ProxyCounters {
+        self.inner
+            .lock()
+            .unwrap()
+            .get(song)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// 取走并清零某首歌的计数
+    pub fn take(&self, song: &str) -> ProxyCounters {
+        self.downloaded.lock().unwrap().remove(song);
+        self.quality.lock().unwrap().remove(song);
+        self.inner.lock().unwrap().remove(song).unwrap_or_default()
+    }
+
+    /// 记录某首歌已从上游下载到文件的 `end` 字节处，文件共 `total` 字节
+    ///
+    /// 渲染器跳转后会从新的位置重新请求，因此直接覆盖而不是取最大值。
+    pub fn record_downloaded(&self, song: &str, end: u64, total: u64) {
+        self.downloaded
+            .lock()
+            .unwrap()
+            .insert(song.to_string(), (end, total));
+    }
+
+    /// 记录代理为某首歌拿到的画质（qn）
+    pub fn record_quality(&self, song: &str, quality: u32) {
+        self.quality
+            .lock()
+            .unwrap()
+            .insert(song.to_string(), quality);
+    }
+
+    /// 代理为某首歌拿到的画质（qn），还没有解析过链接时为 None
+    pub fn quality(&self, song: &str) -> Option<u32> {
+        self.quality.lock().unwrap().get(song).copied()
+    }
+
+    /// 某首歌已下载到文件的百分比（0-100），还没有下载记录时为 None
+    pub fn downloaded_percent(&self, song: &str) -> Option<u8> {
+        let (end, total) = *self.downloaded.lock().unwrap().get(song)?;
+        (total > 0).then(|| (end.min(total) * 100 / total) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_score() {
+        let mut record = SongRecord::new("BV1xx411c7mD");
+        assert_eq!(record.health_score(), 100);
+
+        record.start_latency = Some(Duration::from_secs(8));
+        record.stalls = 2;
+        record.retries = 1;
+        assert_eq!(record.health_score(), 100 - 10 - 30 - 5);
+
+        record.stalls = 10;
+        assert_eq!(record.health_score(), 0);
+    }
+
+    #[test]
+    fn test_party_summary() {
+        assert_eq!(PartySummary::from_records(&[], Local::now()), None);
+
+        let mut records: Vec<SongRecord> = ["BV1a", "BV1b", "BV1b", "BV1a", "BV1c"]
+            .into_iter()
+            .map(SongRecord::new)
+            .collect();
+        records[2].stalls = 2;
+        records[2].skipped = true;
+        for (record, name) in records
+            .iter_mut()
+            .zip(["小明", "小红", "小红", "小明", "小红"])
+        {
+            record.requester = Some(name.to_string());
+            record.played_secs = 200;
+        }
+        let started_at = records[0].started_at;
+        let summary =
+            PartySummary::from_records(&records, started_at + chrono::Duration::minutes(95))
+                .unwrap();
+        assert_eq!(summary.songs, 5);
+        assert_eq!(summary.duration, Duration::from_secs(95 * 60));
+        assert_eq!(summary.most_requested, ("BV1a".to_string(), 2));
+        assert_eq!(summary.played, Duration::from_secs(1000));
+        assert_eq!(summary.skips, 1);
+        assert_eq!(
+            summary.requesters,
+            [("小红".to_string(), 3), ("小明".to_string(), 2)]
+        );
+        assert_eq!(summary.average_health, 94);
+    }
+}
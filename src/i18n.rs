@@ -0,0 +1,98 @@
+//! 界面语言
+//!
+//! 控制台里给用户看的文字有中文（zh-CN，默认）和英文两套，两种写法在调用处并排写出，
+//! 见 [`tr!`](crate::tr) 和 [`text`]。语言由配置文件的 `[ui] language` 决定，
+//! 未设置时按环境变量 `LC_ALL` / `LC_MESSAGES` / `LANG` 判断。
+//!
+//! 日志始终为中文，不经过这里。
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 界面语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    ZhCn,
+    En,
+}
+
+// 当前语言是否为英文；默认中文
+static ENGLISH: AtomicBool = AtomicBool::new(false);
+
+impl Lang {
+    /// 解析语言标签，如 `zh-CN`、`zh_CN.UTF-8`、`en`、`en_US.UTF-8`；不认识的返回 `None`
+    pub fn from_tag(tag: &str) -> Option<Lang> {
+        let tag = tag.trim().to_ascii_lowercase();
+        if tag.starts_with("zh") {
+            Some(Lang::ZhCn)
+        } else if tag.starts_with("en") {
+            Some(Lang::En)
+        } else {
+            None
+        }
+    }
+
+    /// 选择界面语言：优先使用配置，其次是第一个非空的 `LC_ALL` / `LC_MESSAGES` / `LANG`，
+    /// 都无法识别时为中文
+    pub fn detect(configured: Option<&str>) -> Lang {
+        if let Some(lang) = configured.and_then(Lang::from_tag) {
+            return lang;
+        }
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Lang::from_tag(&value))
+            .unwrap_or(Lang::ZhCn)
+    }
+}
+
+/// 设置全局界面语言，启动时调用一次
+pub fn set_lang(lang: Lang) {
+    ENGLISH.store(lang == Lang::En, Ordering::Relaxed);
+}
+
+/// 当前界面语言
+pub fn lang() -> Lang {
+    if ENGLISH.load(Ordering::Relaxed) {
+        Lang::En
+    } else {
+        Lang::ZhCn
+    }
+}
+
+/// 按当前语言在两段固定文字中选一段
+pub fn text(zh: &'static str, en: &'static str) -> &'static str {
+    match lang() {
+        Lang::ZhCn => zh,
+        Lang::En => en,
+    }
+}
+
+/// 按当前语言选择格式串并格式化，参数两种语言共用：
+/// `tr!("音量: {}", "Volume: {}", volume)`
+#[macro_export]
+macro_rules! tr {
+    ($zh:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        match $crate::i18n::lang() {
+            $crate::i18n::Lang::ZhCn => format!($zh $(, $arg)*),
+            $crate::i18n::Lang::En => format!($en $(, $arg)*),
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lang_from_tag() {
+        assert_eq!(Lang::from_tag("zh-CN"), Some(Lang::ZhCn));
+        assert_eq!(Lang::from_tag("zh_TW.UTF-8"), Some(Lang::ZhCn));
+        assert_eq!(Lang::from_tag("en_US.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::from_tag("EN"), Some(Lang::En));
+        assert_eq!(Lang::from_tag("C.UTF-8"), None);
+        // 配置优先于环境变量
+        assert_eq!(Lang::detect(Some("en")), Lang::En);
+        assert_eq!(Lang::detect(Some("zh-CN")), Lang::ZhCn);
+    }
+}
@@ -0,0 +1,60 @@
+//! ktv-casting：把 ktv-song-web 房间的当前曲目投屏到 DLNA 渲染器
+//!
+//! 命令行程序见 `src/main.rs`；需要在其他界面（GUI 等）中复用投屏逻辑时，
+//! 从 [`session::CastingSession`] 入手；同时跟随多个房间见 [`session_manager::SessionManager`]。
+
+use crate::config::ProxyConfig;
+use crate::features::FeatureFlags;
+use crate::history::ProxyStats;
+use crate::media_server::SubtitleStore;
+use crate::remote::RemoteHandle;
+use crate::transcode::{CrossfadeStore, FfmpegJobs};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub mod bilibili_live;
+pub mod bilibili_parser;
+pub mod blocklist;
+pub mod bundle;
+pub mod chromecast;
+pub mod config;
+pub mod dlna_controller;
+pub mod features;
+pub mod history;
+pub mod i18n;
+pub mod lyrics;
+pub mod media_server;
+pub mod mp4_util;
+pub mod netease;
+pub mod playlist_manager;
+pub mod proxy_watchdog;
+pub mod remote;
+pub mod renderer;
+pub mod session;
+pub mod session_manager;
+pub mod song_source;
+pub mod transcode;
+pub mod update_check;
+pub mod utils;
+pub mod volume_curve;
+
+/// 媒体代理（actix-web）共享的状态
+pub struct SharedState {
+    /// 已解析出的视频时长（秒），键为代理路径（如 `BV1xx411c7mD-page2`）
+    pub duration_cache: Arc<Mutex<HashMap<String, u32>>>,
+    /// 上游超时与续传参数
+    pub proxy: ProxyConfig,
+    /// 供渲染器拉取的字幕（SRT），见 [`media_server::subtitle_handler`]
+    pub subtitles: SubtitleStore,
+    /// 运行时功能开关（与控制器、会话共享）
+    pub features: FeatureFlags,
+    /// 每首歌的卡顿续传与降级次数，由会话取走写入播放历史
+    pub stats: ProxyStats,
+    /// Web 遥控，会话创建后设置，见 [`remote::remote_handler`]
+    pub remote: RemoteHandle,
+    /// 转码与多段合并正在运行的 ffmpeg，每个代理路径最多一个
+    pub ffmpeg_jobs: FfmpegJobs,
+    /// 会话写入的交叉淡化，转码时读取，见 [`transcode::crossfade_args`]
+    pub crossfades: CrossfadeStore,
+}
@@ -0,0 +1,228 @@
+//! 时间同步的歌词
+//!
+//! 歌词优先取B站的CC字幕（BCC JSON）；视频没有字幕时按歌名到网易云音乐搜索 LRC 歌词。
+//! 会话按播放进度找到当前行并发出
+//! [`SessionEvent::LyricLine`](crate::session::SessionEvent::LyricLine)，
+//! 同时转换成 SRT 放进媒体服务的字幕存储，支持外挂字幕的电视也能显示。
+
+use reqwest::Client;
+use serde_json::Value;
+
+// LRC 只有开始时间，最后一行按该时长结束
+const LRC_LAST_LINE_SECS: f64 = 5.0;
+
+/// 一行歌词
+#[derive(Debug, Clone, PartialEq)]
+pub struct LyricLine {
+    /// 开始/结束时间（秒）
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// 一首歌的歌词，按开始时间排列
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lyrics {
+    lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    /// 从 BCC JSON 解析；没有任何有效行时返回 None
+    pub fn from_bcc(json: &Value) -> Option<Self> {
+        let mut lines: Vec<LyricLine> = json["body"]
+            .as_array()?
+            .iter()
+            .filter_map(|item| {
+                Some(LyricLine {
+                    start: item["from"].as_f64()?,
+                    end: item["to"].as_f64()?,
+                    text: item["content"].as_str()?.trim().to_string(),
+                })
+            })
+            .filter(|line| !line.text.is_empty())
+            .collect();
+        lines.sort_by(|a, b| a.start.total_cmp(&b.start));
+        (!lines.is_empty()).then_some(Self { lines })
+    }
+
+    /// 从 LRC 文本解析（`[01:02.30]歌词`，一行可以有多个时间标签）；没有任何有效行时返回 None
+    ///
+    /// 每行在下一行开始时结束，`[ar:]` 等元数据标签会被忽略。
+    pub fn from_lrc(text: &str) -> Option<Self> {
+        let mut timed: Vec<(f64, String)> = Vec::new();
+        for raw in text.lines() {
+            let mut rest = raw.trim();
+            let mut starts = Vec::new();
+            while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                match parse_lrc_time(tag) {
+                    Some(start) => starts.push(start),
+                    None => break,
+                }
+                rest = after;
+            }
+            let text = rest.trim();
+            if !text.is_empty() {
+                timed.extend(starts.into_iter().map(|start| (start, text.to_string())));
+            }
+        }
+        timed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let ends: Vec<f64> = timed
+            .iter()
+            .skip(1)
+            .map(|(start, _)| *start)
+            .chain(timed.last().map(|(start, _)| start + LRC_LAST_LINE_SECS))
+            .collect();
+        let lines: Vec<LyricLine> = timed
+            .into_iter()
+            .zip(ends)
+            .map(|((start, text), end)| LyricLine { start, end, text })
+            .collect();
+        (!lines.is_empty()).then_some(Self { lines })
+    }
+
+    pub fn lines(&self) -> &[LyricLine] {
+        &self.lines
+    }
+
+    /// 播放到 `secs` 秒时应高亮的行：最后一个已经开始的行
+    pub fn line_at(&self, secs: f64) -> Option<usize> {
+        let started = self.lines.partition_point(|line| line.start <= secs);
+        started.checked_sub(1)
+    }
+
+    /// 转换为 SRT 文本
+    pub fn to_srt(&self) -> String {
+        let timestamp = |secs: f64| {
+            let ms = (secs.max(0.0) * 1000.0).round() as u64;
+            format!(
+                "{:02}:{:02}:{:02},{:03}",
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+                ms % 1000
+            )
+        };
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                format!(
+                    "{}\n{} --> {}\n{}\n",
+                    i + 1,
+                    timestamp(line.start),
+                    timestamp(line.end),
+                    line.text
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// 解析 LRC 时间标签 `mm:ss.xx`（秒的小数部分可省略）；元数据标签返回 None
+fn parse_lrc_time(tag: &str) -> Option<f64> {
+    let (minutes, secs) = tag.split_once(':')?;
+    let minutes: u32 = minutes.parse().ok()?;
+    let secs: f64 = secs.parse().ok()?;
+    Some(minutes as f64 * 60.0 + secs)
+}
+
+/// 从视频标题中提取用于搜索歌词的关键词：去掉【】、[]、() 等括号内的修饰（如“【KTV】”“（伴奏）”）
+pub fn search_keyword(title: &str) -> String {
+    let mut keyword = String::new();
+    let mut depth = 0usize;
+    for c in title.chars() {
+        match c {
+            '【' | '[' | '(' | '（' | '「' => depth += 1,
+            '】' | ']' | ')' | '）' | '」' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => keyword.push(c),
+            _ => {}
+        }
+    }
+    keyword.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// 按歌名在网易云音乐搜索歌词，返回第一个结果的 LRC 文本；没有结果或没有歌词时返回 None
+pub async fn search_netease_lrc(title: &str) -> Result<Option<String>, String> {
+    let keyword = search_keyword(title);
+    if keyword.is_empty() {
+        return Ok(None);
+    }
+    let client = Client::new();
+    let get_json = |url: String| {
+        let client = client.clone();
+        async move {
+            client
+                .get(&url)
+                .header("User-Agent", "Mozilla/5.0")
+                .header("Referer", "https://music.163.com/")
+                .send()
+                .await
+                .map_err(|e| format!("请求网易云音乐失败: {}", e))?
+                .json::<Value>()
+                .await
+                .map_err(|e| format!("解析JSON失败: {}", e))
+        }
+    };
+
+    let search = get_json(format!(
+        "https://music.163.com/api/search/get/web?s={}&type=1&limit=1",
+        urlencoding::encode(&keyword)
+    ))
+    .await?;
+    let Some(id) = search["result"]["songs"][0]["id"].as_u64() else {
+        return Ok(None);
+    };
+    let lyric = get_json(format!(
+        "https://music.163.com/api/song/lyric?id={}&lv=1",
+        id
+    ))
+    .await?;
+    Ok(lyric["lrc"]["lyric"]
+        .as_str()
+        .filter(|lrc| !lrc.trim().is_empty())
+        .map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bcc_lyrics() {
+        let json = serde_json::json!({
+            "body": [
+                { "from": 12.5, "to": 15.0, "content": "第二句" },
+                { "from": 3.0, "to": 6.25, "content": "第一句" },
+                { "from": 20.0, "to": 21.0, "content": "  " }
+            ]
+        });
+        let lyrics = Lyrics::from_bcc(&json).unwrap();
+        assert_eq!(lyrics.lines().len(), 2);
+        assert_eq!(lyrics.line_at(1.0), None);
+        assert_eq!(lyrics.line_at(13.0), Some(1));
+        assert!(
+            lyrics
+                .to_srt()
+                .starts_with("1\n00:00:03,000 --> 00:00:06,250\n第一句\n")
+        );
+    }
+
+    #[test]
+    fn test_lrc_lyrics() {
+        let lrc =
+            "[ar:八三夭]\n[00:12.50]第二句\n[00:03]第一句\n[01:00.00][00:20.00]副歌\n[00:30.00]\n";
+        let lyrics = Lyrics::from_lrc(lrc).unwrap();
+        let starts: Vec<f64> = lyrics.lines().iter().map(|l| l.start).collect();
+        assert_eq!(starts, [3.0, 12.5, 20.0, 60.0]);
+        assert_eq!(lyrics.lines()[0].end, 12.5);
+        assert_eq!(lyrics.lines()[3].end, 65.0);
+        assert_eq!(lyrics.line_at(25.0), Some(2));
+        assert!(Lyrics::from_lrc("[ti:只有标签]").is_none());
+
+        assert_eq!(
+            search_keyword("【KTV】周杰伦 - 晴天（伴奏）"),
+            "周杰伦 - 晴天"
+        );
+    }
+}
@@ -1,27 +1,375 @@
-use crate::dlna_controller::DlnaController;
-use actix_web::{App, HttpServer, web};
+use actix_web::web;
 use anyhow::{Context, Result, bail};
+use chrono::Local;
+use ktv_casting::SharedState;
+use ktv_casting::bilibili_parser::BiliCookies;
+use ktv_casting::blocklist::DeviceBlocklist;
+use ktv_casting::bundle::{self, StateBundle};
+use ktv_casting::config::Config;
+use ktv_casting::dlna_controller::{DlnaController, DlnaDevice, KnownDevice};
+use ktv_casting::features::FeatureFlags;
+use ktv_casting::history::PartySummary;
+use ktv_casting::i18n::{self, Lang, text};
+use ktv_casting::playlist_manager::PlaylistManager;
+use ktv_casting::remote::{Remote, RemoteTokens};
+use ktv_casting::session::CastingSession;
+use ktv_casting::session_manager::SessionManager;
+use ktv_casting::tr;
+use ktv_casting::volume_curve::VolumeCurveStore;
+use ktv_casting::{bilibili_parser, proxy_watchdog, update_check};
 use local_ip_address::local_ip;
-use log::{error, info};
-use playlist_manager::PlaylistManager;
+use log::{error, info, warn};
 use reqwest::Client;
-use std::io;
+use std::io::{self, IsTerminal};
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
 use url::{Position, Url};
-use crate::utils::retry_until_success;
 
-mod bilibili_parser;
-mod dlna_controller;
-mod media_server;
-mod mp4_util;
-mod playlist_manager;
-mod utils;
+mod console;
 
-pub struct SharedState {
-    pub duration_cache: Arc<Mutex<std::collections::HashMap<String, u32>>>,
+// 退出时停止渲染器等清理工作的最长等待时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// 打印一台设备（编号按发现顺序）
+fn print_device(index: usize, device: &DlnaDevice) {
+    println!("{}: {} at {}", index, device.friendly_name, device.location);
+    println!("   {} | UDN: {}", device.model_summary(), device.udn);
+    if !device.fallback_locations.is_empty() {
+        println!(
+            "{}",
+            tr!(
+                "   备用地址: {}",
+                "   Fallback addresses: {}",
+                device.fallback_locations.join(", ")
+            )
+        );
+    }
+}
+
+// 设备详情：型号、服务版本、全部地址和最近一次响应时间，用来区分同名设备
+fn print_device_details(device: &DlnaDevice) {
+    println!("{}: {}", text("名称", "Name"), device.friendly_name);
+    println!("  {}: {}", text("型号", "Model"), device.model_summary());
+    let services = if device.cast_address.is_some() {
+        "Chromecast (CASTv2)".to_string()
+    } else {
+        device.service_summary().join(", ")
+    };
+    println!("  {}: {}", text("服务", "Services"), services);
+    for location in device.locations() {
+        println!("  {}: {}", text("地址", "Location"), location);
+    }
+    println!("  UDN: {}", device.udn);
+    println!(
+        "  {}: {}",
+        text("最近响应", "Last seen"),
+        device.last_seen.format("%H:%M:%S")
+    );
+}
+
+// 离线的已知设备：不编号、灰色显示（输出被重定向时不加颜色）
+fn print_offline_device(location: &str, reason: &str) {
+    let line = tr!("-: {} 离线（{}）", "-: {} offline ({})", location, reason);
+    if io::stdout().is_terminal() {
+        println!("\x1b[2m{}\x1b[0m", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+// 是否应列出新发现的设备：不在黑名单中，也没有作为已知设备列出过
+fn should_list(device: &DlnaDevice, devices: &[DlnaDevice], blocklist: &DeviceBlocklist) -> bool {
+    !blocklist.contains(device) && !devices.iter().any(|d| d.udn == device.udn)
+}
+
+// 继续接收新发现的设备（跳过黑名单中的和已列出的），直到 `done` 成立或搜索结束
+async fn receive_until(
+    found_rx: &mut mpsc::UnboundedReceiver<DlnaDevice>,
+    devices: &mut Vec<DlnaDevice>,
+    blocklist: &DeviceBlocklist,
+    done: impl Fn(&[DlnaDevice]) -> bool,
+) {
+    while !done(devices) {
+        match found_rx.recv().await {
+            Some(device) if !should_list(&device, devices, blocklist) => {}
+            Some(device) => {
+                print_device(devices.len(), &device);
+                devices.push(device);
+            }
+            None => break,
+        }
+    }
+}
+
+// 解析后的房间链接
+struct RoomLink {
+    // 补全协议后的完整链接，`x` 命令复制的就是它
+    normalized_url: String,
+    base_url: String,
+    room_id: String,
+}
+
+// 解析用户输入的房间链接：没有协议时补上 `http://`，路径最后一个非空段为房间号
+fn parse_room_link(input: &str) -> Result<RoomLink> {
+    let mut normalized_url = input.trim().to_string();
+    if !normalized_url.contains("://") && !normalized_url.is_empty() {
+        normalized_url = format!("http://{}", normalized_url);
+    }
+    let parsed_url = Url::parse(&normalized_url)
+        .with_context(|| text("无法解析 URL", "Failed to parse the URL"))?;
+    let room_id = parsed_url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|seg| !seg.is_empty()))
+        .context(text("没有找到房间号", "No room number in the link"))?
+        .to_string();
+    Ok(RoomLink {
+        base_url: parsed_url[..Position::AfterPort].to_string(),
+        room_id,
+        normalized_url,
+    })
+}
+
+// 在后台线程读取一行输入，不阻塞设备搜索
+fn read_line() -> JoinHandle<io::Result<String>> {
+    tokio::task::spawn_blocking(|| {
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).map(|_| input)
+    })
+}
+
+// 选择界面中的设备命令：`i <编号>` 查看详情，`b <编号>` 隐藏设备，`u` 列出已隐藏的设备，`u <序号>` 取消隐藏
+//
+// 返回 false 表示输入不是设备命令。`hidden` 为本次搜索到但被隐藏的设备，取消隐藏后放回列表。
+fn device_command(
+    input: &str,
+    devices: &mut Vec<DlnaDevice>,
+    hidden: &mut Vec<DlnaDevice>,
+    blocklist: &mut DeviceBlocklist,
+) -> Result<bool> {
+    let mut words = input.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("i"), Some(index)) => {
+            let device = index
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| devices.get(index))
+                .context(text("编号有误", "Invalid device number"))?;
+            print_device_details(device);
+        }
+        (Some("b"), Some(index)) => {
+            let index: usize = index
+                .parse()
+                .context(text("编号有误", "Invalid device number"))?;
+            if index >= devices.len() {
+                bail!(text("编号有误", "Invalid device number"));
+            }
+            let device = devices.remove(index);
+            blocklist.block(&device)?;
+            println!(
+                "{}",
+                tr!(
+                    "已隐藏 {}（输入 u 查看已隐藏的设备）",
+                    "Hid {} (type u to list hidden devices)",
+                    device.friendly_name
+                )
+            );
+            hidden.push(device);
+            for (index, device) in devices.iter().enumerate() {
+                print_device(index, device);
+            }
+        }
+        (Some("u"), None) => {
+            if blocklist.is_empty() {
+                println!("{}", text("没有已隐藏的设备", "No hidden devices"));
+            }
+            for (index, (udn, name)) in blocklist.entries().enumerate() {
+                println!("{}: {} | UDN: {}", index, name, udn);
+            }
+        }
+        (Some("u"), Some(index)) => {
+            let index: usize = index.parse().context(text("序号有误", "Invalid index"))?;
+            let udn = blocklist
+                .entries()
+                .nth(index)
+                .map(|(udn, _)| udn.to_string())
+                .context(text("序号有误", "Invalid index"))?;
+            blocklist.unblock(&udn)?;
+            match hidden.iter().position(|d| d.udn == udn) {
+                Some(position) => {
+                    let device = hidden.remove(position);
+                    println!("{}", text("已取消隐藏:", "Unhidden:"));
+                    print_device(devices.len(), &device);
+                    devices.push(device);
+                }
+                None => println!(
+                    "{}",
+                    tr!(
+                        "已取消隐藏 {}，下次搜索到时会列出",
+                        "Unhid {}, it will be listed when it is found again",
+                        udn
+                    )
+                ),
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// 边搜索边列出设备，用户可以随时输入编号；直接回车则等搜索结束后选择置顶（没有置顶时为第一个）的设备
+//
+// 返回选中的设备和目前已发现的全部设备；配置了音频设备时会等到它出现（或搜索结束）再返回。
+// 黑名单中的设备不会列出，也不会被自动选中。
+async fn select_device(
+    controller: &DlnaController,
+    audio_pattern: Option<&str>,
+    blocklist: &mut DeviceBlocklist,
+) -> Result<(DlnaDevice, Vec<DlnaDevice>)> {
+    let (found_tx, mut found_rx) = mpsc::unbounded_channel();
+    let mut discovery = {
+        let controller = controller.clone();
+        tokio::spawn(async move { controller.discover_devices_streaming(found_tx).await })
+    };
+    println!(
+        "{}",
+        text(
+            "正在搜索DLNA设备，发现的设备会立即列出",
+            "Searching for DLNA devices, they are listed as soon as they are found"
+        )
+    );
+    println!(
+        "{}",
+        text(
+            "输入设备编号（直接回车等待搜索结束并选择置顶设备；i <编号> 查看详情，b <编号> 永久隐藏设备，u 管理已隐藏的设备）：",
+            "Enter a device number (Enter waits for the search and picks the top device; i <number> shows details, b <number> hides a device permanently, u manages hidden devices):"
+        )
+    );
+    println!(
+        "{}",
+        text(
+            "编号: 设备名称 at 设备地址",
+            "number: device name at device address"
+        )
+    );
+
+    // 已知设备先并发检查一遍（搜索在后台同时进行），在线的可以直接选择
+    let mut devices: Vec<DlnaDevice> = Vec::new();
+    let mut hidden: Vec<DlnaDevice> = Vec::new();
+    for known in controller.check_known_devices().await {
+        match known {
+            KnownDevice::Online(device) if blocklist.contains(&device) => hidden.push(*device),
+            KnownDevice::Online(device) => {
+                print_device(devices.len(), &device);
+                devices.push(*device);
+            }
+            KnownDevice::Offline { location, reason } => print_offline_device(&location, &reason),
+        }
+    }
+    let mut searching = true;
+    let mut line = read_line();
+    let choice = loop {
+        tokio::select! {
+            found = found_rx.recv(), if searching => match found {
+                Some(device) if blocklist.contains(&device) => hidden.push(device),
+                Some(device) if !should_list(&device, &devices, blocklist) => {}
+                Some(device) => {
+                    print_device(devices.len(), &device);
+                    devices.push(device);
+                }
+                None => {
+                    searching = false;
+                    if devices.is_empty() && hidden.is_empty() {
+                        (&mut discovery).await??;
+                        bail!("No DLNA Devices");
+                    }
+                    println!("{}", text("搜索结束", "Search finished"));
+                }
+            },
+            input = &mut line => {
+                let input = input?
+                    .context(text("读取编号失败", "Failed to read the device number"))?
+                    .trim()
+                    .to_string();
+                match device_command(&input, &mut devices, &mut hidden, blocklist) {
+                    Ok(true) => {}
+                    Ok(false) => break input,
+                    Err(e) => println!("{}", e),
+                }
+                line = read_line();
+            }
+        }
+    };
+
+    let device = if choice.is_empty() {
+        receive_until(&mut found_rx, &mut devices, blocklist, |_| false).await;
+        let sorted = discovery.await??;
+        sorted
+            .into_iter()
+            .find(|d| !blocklist.contains(d))
+            .or_else(|| devices.first().cloned())
+            .context(text("没有可用的设备", "No usable device"))?
+    } else {
+        let index: usize = choice
+            .parse()
+            .context(text("编号有误", "Invalid device number"))?;
+        receive_until(&mut found_rx, &mut devices, blocklist, |devices| {
+            index < devices.len()
+        })
+        .await;
+        devices
+            .get(index)
+            .cloned()
+            .context(text("编号有误", "Invalid device number"))?
+    };
+    if let Some(pattern) = audio_pattern {
+        receive_until(&mut found_rx, &mut devices, blocklist, |devices| {
+            devices
+                .iter()
+                .any(|d| d.udn != device.udn && d.matches(pattern))
+        })
+        .await;
+    }
+    Ok((device, devices))
+}
+
+// 启动时显示B站登录状态；配置了 Cookie 时向 nav 接口确认是否仍然有效
+async fn print_login_status(has_cookie: bool) {
+    if !has_cookie {
+        println!(
+            "{}",
+            text(
+                "B站: 未登录（可在配置文件 [bilibili] 中设置 Cookie 以获取更高画质）",
+                "Bilibili: not logged in (set cookies under [bilibili] in the config for higher quality)"
+            )
+        );
+        return;
+    }
+    match tokio::time::timeout(Duration::from_secs(5), bilibili_parser::login_status()).await {
+        Ok(Ok(Some(account))) => println!(
+            "{}",
+            if account.vip {
+                tr!(
+                    "B站: 已登录 {}（大会员）",
+                    "Bilibili: logged in as {} (VIP)",
+                    account.name
+                )
+            } else {
+                tr!("B站: 已登录 {}", "Bilibili: logged in as {}", account.name)
+            }
+        ),
+        Ok(Ok(None)) => println!(
+            "{}",
+            text(
+                "⚠ B站 Cookie 已失效，按未登录获取视频",
+                "⚠ Bilibili cookies have expired, fetching videos as a guest"
+            )
+        ),
+        Ok(Err(e)) => warn!("{}", e),
+        Err(_) => warn!("查询B站登录状态超时"),
+    }
 }
 
 #[tokio::main]
@@ -33,50 +381,169 @@ async fn main() -> Result<()> {
     }
     env_logger::init();
 
-    println!("=== KTV投屏DLNA应用启动 ===");
-    println!("输入房间链接，如 http://127.0.0.1:1145/102 或 https://ktv.example.com/102");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("无法读取输入");
-    let url_str = input.trim();
-    let mut normalized_url = url_str.to_string();
-    if !normalized_url.contains("://") && !normalized_url.is_empty() {
-        normalized_url = format!("http://{}", normalized_url);
-    }
-    // ② 使用 url crate 解析并提取 base URL 与 room_id
-    let parsed_url = Url::parse(&normalized_url).with_context(|| "无法解析 URL")?;
-
-    let base_url = parsed_url[..Position::AfterPort].to_string();
-    info!("Base URL: {}", base_url);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    // --dry-run: 不发送任何SOAP请求，只打印日志，并用本地时钟模拟播放进度
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    // --resync: 不恢复上次保存的房间状态，当前歌曲从头投屏
+    let resync = args.iter().any(|arg| arg == "--resync");
+    // 读取配置前先按 LANG 选择语言，导出/导入的提示也能显示为英文
+    i18n::set_lang(Lang::detect(None));
 
-    // ③ 从路径中取最后一段（非空）作为 room_id
-    let segments: Vec<&str> = parsed_url
-        .path_segments()
-        .map(|s| s.filter(|seg| !seg.is_empty()).collect())
-        .unwrap_or_default();
+    // --export <文件> / --import <文件>：导出或导入本地状态（配置、音量曲线等）后退出
+    if let Some(path) = option("--export") {
+        let bundle = StateBundle::collect(&bundle::state_files())?;
+        bundle.save(Path::new(path))?;
+        println!(
+            "{}",
+            tr!(
+                "已导出 {} 到 {}",
+                "Exported {} to {}",
+                bundle.names().collect::<Vec<_>>().join(text("、", ", ")),
+                path
+            )
+        );
+        return Ok(());
+    }
+    if let Some(path) = option("--import") {
+        let written = StateBundle::load(Path::new(path))?.restore(&bundle::state_files())?;
+        for path in written {
+            println!("{}", tr!("已导入 {}", "Imported {}", path.display()));
+        }
+        return Ok(());
+    }
 
-    if segments.is_empty() {
-        error!("错误：没有找到房间号");
-        bail!("No room id")
+    let config = Config::load()?;
+    i18n::set_lang(Lang::detect(config.ui.language.as_deref()));
+    match bilibili_parser::parse_quality(&config.bilibili.quality) {
+        Some(qn) => bilibili_parser::set_preferred_quality(qn),
+        None => warn!("无法识别的画质 {}，使用默认画质", config.bilibili.quality),
+    }
+    let mut cookies = BiliCookies {
+        sessdata: config.bilibili.sessdata.clone(),
+        bili_jct: config.bilibili.bili_jct.clone(),
+    };
+    if let Some(path) = &config.bilibili.cookie_file {
+        match BiliCookies::from_cookie_file(path) {
+            Ok(from_file) => cookies = cookies.or(from_file),
+            Err(e) => warn!("{}", e),
+        }
     }
+    bilibili_parser::set_cookies(&cookies);
+    bilibili_parser::set_cdn_hosts(&config.bilibili.cdn_hosts);
 
-    let room_str = segments.last().unwrap();
-    let room_id: String = room_str.to_string();
+    println!(
+        "{}",
+        text("=== KTV投屏DLNA应用启动 ===", "=== KTV casting ===")
+    );
+    print_login_status(cookies.sessdata.is_some()).await;
+    if dry_run {
+        println!(
+            "{}",
+            text(
+                "[dry-run] 不会向任何设备发送请求",
+                "[dry-run] no requests are sent to any device"
+            )
+        );
+    }
+    println!(
+        "{}",
+        text(
+            "输入房间链接，如 http://127.0.0.1:1145/102 或 https://ktv.example.com/102",
+            "Enter the room link, e.g. http://127.0.0.1:1145/102 or https://ktv.example.com/102"
+        )
+    );
+    let mut input = String::new();
+    // 链接有误时只重新输入链接，不必重启程序
+    let room = loop {
+        input.clear();
+        if io::stdin().read_line(&mut input).context("无法读取输入")? == 0 {
+            bail!("No room id");
+        }
+        match parse_room_link(&input) {
+            Ok(room) => break room,
+            Err(e) => {
+                error!("{}", e);
+                println!(
+                    "{}",
+                    tr!(
+                        "{}，请重新输入房间链接：",
+                        "{}, please enter the room link again:",
+                        e
+                    )
+                );
+            }
+        }
+    };
+    let RoomLink {
+        normalized_url,
+        base_url,
+        room_id,
+    } = room;
+    info!("Base URL: {}", base_url);
     info!("Parsed room_id: {}", room_id);
 
     // 询问用户昵称（可选）
-    println!("输入您的昵称（直接回车使用默认值 'ktv-casting'）：");
+    println!(
+        "{}",
+        text(
+            "输入您的昵称（直接回车使用默认值 'ktv-casting'）：",
+            "Enter your nickname (Enter uses the default 'ktv-casting'):"
+        )
+    );
     input.clear();
     io::stdin().read_line(&mut input).expect("无法读取输入");
     let nickname = input.trim().to_string();
-    let nickname = if nickname.is_empty() { None } else { Some(nickname) };
+    let nickname = if nickname.is_empty() {
+        None
+    } else {
+        Some(nickname)
+    };
+
+    // 私密房间需要令牌（可选）
+    println!(
+        "{}",
+        text(
+            "输入房间令牌（私密房间需要，直接回车跳过）：",
+            "Enter the room token (private rooms only, Enter to skip):"
+        )
+    );
+    input.clear();
+    io::stdin().read_line(&mut input).expect("无法读取输入");
+    let token = Some(input.trim().to_string()).filter(|token| !token.is_empty());
 
     let server_port = 8080;
-    let playlist_manager = Arc::new(PlaylistManager::new(&base_url, room_id.clone(), nickname.clone()));
+    let playlist_manager = Arc::new(
+        PlaylistManager::new(&base_url, room_id.clone(), nickname.clone())
+            .with_token(token)
+            .with_headers(&config.room.headers)
+            .with_heartbeat(
+                Duration::from_secs(config.room.ping_interval_secs),
+                Duration::from_secs(config.room.pong_timeout_secs),
+            )
+            .with_resync(resync),
+    );
 
+    let features = FeatureFlags::new(&config.features);
     let duration_cache = Arc::new(Mutex::new(std::collections::HashMap::new()));
     let shared_state = web::Data::new(SharedState {
         duration_cache: duration_cache.clone(),
+        proxy: config.proxy.clone(),
+        subtitles: Default::default(),
+        features: features.clone(),
+        stats: Default::default(),
+        remote: Default::default(),
+        ffmpeg_jobs: Default::default(),
+        crossfades: Default::default(),
     });
+    let subtitles = shared_state.subtitles.clone();
+    let proxy_stats = shared_state.stats.clone();
+    let remote = shared_state.remote.clone();
+    let crossfades = shared_state.crossfades.clone();
 
     // 1. 创建 Reqwest Client
     let client = Client::builder()
@@ -86,161 +553,222 @@ async fn main() -> Result<()> {
 
     let client_data = web::Data::new(client);
 
-    // 2. 配置 HttpServer，运行
-    let server = HttpServer::new(move || {
-        App::new()
-            .app_data(client_data.clone())
-            .app_data(shared_state.clone())
-            .service(media_server::proxy_handler)
-    })
-    .bind(("0.0.0.0", server_port))?
-    .run();
+    // 2. 在看门狗下运行 HttpServer；状态先缓存在通道里，会话创建后再转交
+    let (proxy_status_tx, mut proxy_status_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut server = tokio::spawn(proxy_watchdog::supervise(
+        shared_state,
+        client_data,
+        server_port,
+        move |status| {
+            let _ = proxy_status_tx.send(status);
+        },
+    ));
 
     let local_ip = local_ip()?;
-    let controller = DlnaController::new();
-    let devices = controller.discover_devices().await?;
-    if devices.is_empty() {
-        bail!("No DLNA Devices");
+    let controller = if dry_run {
+        DlnaController::new_dry_run()
+    } else {
+        DlnaController::new()
     }
-    println!("发现以下DLNA设备：");
-    println!("编号: 设备名称 at 设备地址");
-    for (i, device) in devices.iter().enumerate() {
-        println!("{}: {} at {}", i, device.friendly_name, device.location);
-    }
-    println!("输入设备编号：");
-    input.clear();
-    io::stdin().read_line(&mut input).expect("读取编号失败");
-    let device_num: usize = input.trim().parse()?;
-    if device_num > devices.len() {
-        bail!("编号有误");
-    }
-    let device = devices[device_num].clone(); // clone owned copy
-    let device_cloned = device.clone();
-
-    // 设置歌曲变化回调（需要克隆controller和device）
-    let controller_for_callback = controller.clone();
-    let device_for_callback = device.clone();
-    let callback_pm = PlaylistManager::new(&base_url, room_id.clone(), nickname.clone());
-    tokio::spawn(async move {
-        callback_pm.set_on_song_change(move |url| {
-            let controller = controller_for_callback.clone();
-            let device = device_for_callback.clone();
-            tokio::spawn(async move {
-                // 停止当前播放
-                retry_until_success("停止播放", 500, || async {
-                    controller.stop(&device).await.map_err(|e| e.to_string())
-                }).await.ok();
-                
-                // 设置AVTransport URI
-                retry_until_success("设置AVTransport URI", 500, || async {
-                    controller
-                        .set_avtransport_uri(&device, &url, "", local_ip, server_port)
-                        .await
-                        .map_err(|e| e.to_string())
-                }).await.ok();
-                
-                // 播放
-                retry_until_success("播放", 500, || async {
-                    controller.play(&device).await.map_err(|e| e.to_string())
-                }).await.ok();
-            });
-        }).await;
-    });
-
-    // 启动WebSocket监听（需要克隆playlist_manager）
-    let pm_ws = playlist_manager.clone();
-    match pm_ws.start_websocket_listener().await {
-        Ok(_) => info!("WebSocket监听已启动"),
-        Err(e) => {
-            error!("WebSocket连接失败: {}，将退回到轮询模式", e);
-            // 如果WebSocket连接失败，退回到轮询模式
-            let controller_for_poll = controller.clone();
-            let device_for_poll = device.clone();
-            playlist_manager.start_periodic_update_legacy(move |url| {
-                let controller = controller_for_poll.clone();
-                let device = device_for_poll.clone();
-                Box::pin(async move {
-                    // 停止当前播放
-                    retry_until_success("停止播放", 500, || async {
-                        controller.stop(&device).await.map_err(|e| e.to_string())
-                    }).await.ok();
-                    
-                    // 设置AVTransport URI
-                    retry_until_success("设置AVTransport URI", 500, || async {
-                        controller
-                            .set_avtransport_uri(&device, &url, "", local_ip, server_port)
-                            .await
-                            .map_err(|e| e.to_string())
-                    }).await.ok();
-                    
-                    // 播放
-                    retry_until_success("播放", 500, || async {
-                        controller.play(&device).await.map_err(|e| e.to_string())
-                    }).await.ok();
-                })
-            });
-        }
-    }
-
-    tokio::spawn(async move {
-        let controller = DlnaController::new();
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
-        let mut current_secs: u32 = 0;
-        let mut total_secs: u32 = 0;
+    .with_discovery_config(config.discovery.clone())
+    .with_dlna_config(config.dlna.clone())
+    .with_features(features);
+    let (device, devices) = if controller.is_dry_run() {
+        let devices = controller.discover_devices().await?;
+        (devices[0].clone(), devices)
+    } else {
+        let mut blocklist = DeviceBlocklist::load_default()?;
+        // 搜索失败时只重试这一步，房间链接和昵称保持不变
         loop {
-            interval.tick().await;
-
-            // 首先尝试从缓存中获取总长度
-            let mut cached_total = 0;
-            if let Some(playing) = playlist_manager.get_song_playing().await {
-                let cache = duration_cache.lock().await;
-                if let Some(&d) = cache.get(&playing) {
-                    cached_total = d;
+            match select_device(
+                &controller,
+                config.session.audio_device.as_deref(),
+                &mut blocklist,
+            )
+            .await
+            {
+                Ok(selected) => break selected,
+                Err(e) => {
+                    error!("选择设备失败: {}", e);
+                    println!(
+                        "{}",
+                        tr!(
+                            "选择设备失败: {}（回车重新搜索，q 退出）",
+                            "Device selection failed: {} (Enter searches again, q quits)",
+                            e
+                        )
+                    );
+                    match read_line().await?? {
+                        answer if answer.is_empty() || answer.trim() == "q" => return Err(e),
+                        _ => {}
+                    }
                 }
             }
+        }
+    };
+
+    // 音视频分离：声音投到配置中指定的音箱
+    let audio_device = config.session.audio_device.as_deref().and_then(|pattern| {
+        let found = devices
+            .iter()
+            .find(|d| d.udn != device.udn && d.matches(pattern))
+            .cloned();
+        if found.is_none() {
+            log::warn!("没有找到匹配 {} 的音频设备，声音仍由电视播放", pattern);
+        }
+        found
+    });
 
-            // 使用重试逻辑获取播放进度
-            let result = retry_until_success("获取播放进度", 500, || async {
-                controller.get_secs(&device_cloned).await.map_err(|e| e.to_string())
-            }).await;
+    let volume_curves = VolumeCurveStore::load_default()?;
+    let volume_curve = volume_curves.get(&audio_device.as_ref().unwrap_or(&device).udn);
+    let mut session =
+        CastingSession::new(controller, device, playlist_manager, local_ip, server_port)
+            .with_approval(config.session.require_approval)
+            .with_skip_confirmation(config.session.confirm_skip)
+            .with_skip_votes(config.session.skip_votes)
+            .with_subtitles(subtitles)
+            .with_proxy_stats(proxy_stats)
+            .with_volume_curve(volume_curve);
+    if config.proxy.crossfade_secs > 0 {
+        session = session.with_crossfade(crossfades, config.proxy.crossfade_secs);
+    }
+    if let Some(audio_device) = audio_device {
+        println!(
+            "{}",
+            tr!(
+                "音视频分离：声音投到 {}",
+                "Split audio: sound goes to {}",
+                audio_device.friendly_name
+            )
+        );
+        session = session.with_audio_device(audio_device);
+    }
 
-            match result {
-                Ok((current, _)) => {
-                    current_secs = current;
+    // 每个房间是一个会话，媒体代理由所有会话共用
+    let sessions = SessionManager::new(duration_cache);
+    sessions.add(normalized_url, session.clone());
+    {
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            while let Some(status) = proxy_status_rx.recv().await {
+                for tab in sessions.tabs() {
+                    tab.session.report_proxy_status(status.clone());
+                }
+            }
+        });
+    }
 
-                    // 如果从缓存拿到了长度，
-                    if cached_total > 0 {
-                        total_secs = cached_total;
-                        info!("使用缓存的视频时长: {}s", total_secs);
-                    }
+    let remote_tokens = config.remote.enabled.then(|| {
+        let tokens = RemoteTokens::from_config(&config.remote);
+        let _ = remote.set(Remote {
+            tokens: tokens.clone(),
+            session: session.clone(),
+        });
+        tokens
+    });
 
-                    let remaining_secs = total_secs.saturating_sub(current_secs);
+    let mut quit_rx = console::spawn(sessions.clone(), volume_curves, remote_tokens);
+    println!(
+        "{}",
+        text(
+            "输入 h 查看可用命令，? 查看全部命令",
+            "Type h for available commands, ? for all commands"
+        )
+    );
 
-                    info!(
-                        "获取播放进度成功，当前时间{}秒，总时间{}秒，剩余时间{}秒",
-                        current_secs, total_secs, remaining_secs
-                    );
+    if config.update.check {
+        let session = session.clone();
+        let repository = config.update.repository.clone();
+        tokio::spawn(async move {
+            match update_check::check_latest(&repository, env!("CARGO_PKG_VERSION")).await {
+                Ok(Some(release)) => session.announce_update(release),
+                Ok(None) => {}
+                Err(e) => log::warn!("检查新版本失败: {}", e),
+            }
+        });
+    }
 
-                    if remaining_secs <= 2 && total_secs > 0 {
-                        info!(
-                            "剩余时间{}秒，总时间{}秒，准备切歌",
-                            remaining_secs, total_secs
-                        );
-                        // 重试next_song
-                        retry_until_success("下一首歌曲", 500, || async {
-                            playlist_manager.next_song().await.map_err(|e| e.to_string())
-                        }).await.ok();
-                        sleep(Duration::from_secs(5)).await;
-                    }
-                }
-                Err(e) => {
-                    error!("获取播放进度失败: {}", e);
-                }
+    sessions.start(0).await;
+
+    // Ctrl-C 时 actix 也会关闭媒体代理，但要等渲染器断开连接；先停止渲染器再等它退出
+    let mut quit = None;
+    let stopped = tokio::select! {
+        result = &mut server => Some(result),
+        _ = tokio::signal::ctrl_c() => None,
+        Ok(request) = &mut quit_rx => {
+            quit = Some(request);
+            None
+        }
+    };
+    sessions.stop_monitors();
+    let tabs = sessions.tabs();
+    if quit.is_none_or(|request| request.stop_renderer) {
+        println!(
+            "{}",
+            text("正在退出，停止渲染器…", "Exiting, stopping the renderer…")
+        );
+        let shutdown = futures::future::join_all(
+            tabs.iter()
+                .map(|tab| tab.session.shutdown(config.session.clear_on_exit)),
+        );
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown)
+            .await
+            .is_err()
+        {
+            log::warn!("退出清理超时，渲染器可能仍在播放");
+        }
+    } else {
+        println!(
+            "{}",
+            text(
+                "正在退出，渲染器保持不变",
+                "Exiting, leaving the renderer alone"
+            )
+        );
+        for tab in &tabs {
+            tab.session.detach();
+        }
+    }
+    for tab in &tabs {
+        if let Some(summary) =
+            PartySummary::from_records(&tab.session.history().await, Local::now())
+        {
+            if tabs.len() > 1 {
+                println!(
+                    "{}",
+                    tr!("房间 {}：", "Room {}:", tab.session.playlist().room_id())
+                );
             }
+            console::print_party_summary(&summary);
         }
-    });
-    server.await?;
+    }
+    match stopped {
+        Some(result) => result??,
+        // q 退出时 actix 没有收到信号，直接结束媒体代理
+        None if quit.is_some() => server.abort(),
+        None => server.await??,
+    }
 
-    println!("应用已退出");
+    println!("{}", text("应用已退出", "Exited"));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_room_link() {
+        let room = parse_room_link("ktv.example.com/102/\n").unwrap();
+        assert_eq!(room.normalized_url, "http://ktv.example.com/102/");
+        assert_eq!(room.base_url, "http://ktv.example.com");
+        assert_eq!(room.room_id, "102");
+
+        let room = parse_room_link("https://127.0.0.1:1145/room/7").unwrap();
+        assert_eq!(room.base_url, "https://127.0.0.1:1145");
+        assert_eq!(room.room_id, "7");
+
+        assert!(parse_room_link("http://ktv.example.com/").is_err());
+        assert!(parse_room_link("").is_err());
+    }
+}
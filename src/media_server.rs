@@ -1,10 +1,569 @@
 // 使用示例
 use crate::SharedState;
-use crate::bilibili_parser::get_bilibili_direct_link;
+use crate::config::ProxyConfig;
+use crate::features::{Feature, FeatureFlags};
+use crate::history::ProxyStats;
 use crate::mp4_util::get_mp4_duration;
+use crate::song_source;
+use crate::transcode;
+use actix_web::web::{Bytes, BytesMut};
 use actix_web::{HttpRequest, HttpResponse, get, web};
-use futures_util::StreamExt;
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt};
 use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, Notify, mpsc};
+
+/// 字幕存储：键为代理路径（如 `BV1xx411c7mD-page2`），值为 SRT 文本
+pub type SubtitleStore = Arc<Mutex<HashMap<String, String>>>;
+
+/// 某首歌字幕在本地服务上的路径（相对于服务根目录）
+pub fn subtitle_path(song: &str) -> String {
+    format!("subtitles/{}.srt", song)
+}
+
+/// 纯音频代理路径的后缀，见 [`audio_path`]
+pub const AUDIO_SUFFIX: &str = "-audio";
+
+/// 某首歌纯音频流（DASH 音轨）的代理路径，用于把声音单独投到音箱
+pub fn audio_path(song: &str) -> String {
+    format!("{}{}", song, AUDIO_SUFFIX)
+}
+
+/// 转码代理路径的后缀，见 [`transcode_path`]
+pub const TRANSCODE_SUFFIX: &str = "-h264";
+
+/// 某首歌经 ffmpeg 转成 H.264/AAC MP4 后的代理路径，见 [`transcode`](crate::transcode)
+pub fn transcode_path(song: &str) -> String {
+    format!("{}{}", song, TRANSCODE_SUFFIX)
+}
+
+/// 多段合并代理路径的后缀，见 [`joined_path`]
+pub const JOINED_SUFFIX: &str = "-joined";
+
+/// 某首多段视频经 ffmpeg 合并成一个 MP4 后的代理路径；单段视频在该路径上按原样提供
+pub fn joined_path(song: &str) -> String {
+    format!("{}{}", song, JOINED_SUFFIX)
+}
+
+// 单个分段的代理路径，`index` 从 0 开始；只供合并时的 ffmpeg 读取
+const SEGMENT_SUFFIX: &str = "-seg";
+
+fn segment_path(song: &str, index: usize) -> String {
+    format!("{}{}{}", song, SEGMENT_SUFFIX, index)
+}
+
+// 从分段的代理路径中取出歌曲和分段序号
+fn parse_segment_path(path: &str) -> Option<(&str, usize)> {
+    let (song, index) = path.rsplit_once(SEGMENT_SUFFIX)?;
+    Some((song, index.parse().ok()?))
+}
+
+const UPSTREAM_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36";
+
+fn upstream_request(
+    client: &reqwest::Client,
+    method: &actix_web::http::Method,
+    url: &str,
+) -> reqwest::RequestBuilder {
+    let builder = match *method {
+        actix_web::http::Method::HEAD => client.head(url),
+        _ => client.get(url),
+    };
+    builder
+        .header("User-Agent", UPSTREAM_USER_AGENT)
+        .header("Referer", "https://www.bilibili.com/")
+}
+
+/// 上游返回这些状态时改用备用链接：403 多见于海外访问被 CDN 拒绝
+fn should_try_backup(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::FORBIDDEN || status.is_server_error()
+}
+
+// 依次向主链接和备用链接发出请求（`request` 由链接构造请求），返回第一个可用的响应及其链接；
+// 超时、连接失败或应改用备用链接的状态时尝试下一个，最后一个链接的响应原样返回
+async fn send_upstream(
+    urls: &[String],
+    request: impl Fn(&str) -> reqwest::RequestBuilder,
+    timeout: Duration,
+) -> Result<(String, reqwest::Response), ProxyError> {
+    let mut last_error = ProxyError::Upstream("没有可用的链接".to_string());
+    for (i, url) in urls.iter().enumerate() {
+        let is_last = i + 1 == urls.len();
+        let error = match tokio::time::timeout(timeout, request(url).send()).await {
+            Ok(Ok(response)) if is_last || !should_try_backup(response.status()) => {
+                return Ok((url.clone(), response));
+            }
+            Ok(Ok(response)) => ProxyError::Upstream(format!("上游返回 {}", response.status())),
+            Ok(Err(e)) => ProxyError::Upstream(e.to_string()),
+            Err(_) => ProxyError::UpstreamTimeout,
+        };
+        if !is_last {
+            log::warn!(
+                "{}，改用备用链接（{}/{}）",
+                error.message(false),
+                i + 1,
+                urls.len() - 1
+            );
+        }
+        last_error = error;
+    }
+    Err(last_error)
+}
+
+/// 解析 `Content-Range: bytes START-END/TOTAL`，返回 (START, END)
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, _total) = range.split_once('/')?;
+    let (start, end) = span.split_once('-')?;
+    Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+}
+
+/// 取出 `Content-Range: bytes START-END/TOTAL` 中的 TOTAL；总长度未知（`*`）时为 None
+fn content_range_total(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// 可续传的上游响应体
+///
+/// 上游超过 `stall_timeout_ms` 没有数据（或连接出错）时，用 `Range: bytes=<已发送位置>-`
+/// 重新请求并无缝接上，渲染器看到的仍是同一个连续的响应。
+struct ResumableBody {
+    client: reqwest::Client,
+    target_url: String,
+    stream: BoxStream<'static, reqwest::Result<Bytes>>,
+    // 下一个要发送给渲染器的字节在文件中的绝对位置；None 表示无法续传
+    next_offset: Option<u64>,
+    // 请求区间的结束位置（含）
+    range_end: Option<u64>,
+    reconnects: u32,
+    config: ProxyConfig,
+    finished: bool,
+    // 续传次数计入该歌曲的播放历史
+    song: String,
+    stats: ProxyStats,
+    // 文件总长度，用于记录已下载的进度；总长度未知时不记录
+    total: Option<u64>,
+}
+
+impl ResumableBody {
+    fn into_stream(self) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+        futures_util::stream::unfold(self, |mut body| async move {
+            if body.finished {
+                return None;
+            }
+            let stall_timeout = Duration::from_millis(body.config.stall_timeout_ms);
+            loop {
+                let failure = match tokio::time::timeout(stall_timeout, body.stream.next()).await {
+                    Ok(Some(Ok(bytes))) => {
+                        body.reconnects = 0;
+                        if let Some(offset) = body.next_offset.as_mut() {
+                            *offset += bytes.len() as u64;
+                            if let Some(total) = body.total {
+                                body.stats.record_downloaded(&body.song, *offset, total);
+                            }
+                        }
+                        return Some((Ok(bytes), body));
+                    }
+                    Ok(None) => return None,
+                    Ok(Some(Err(e))) => format!("上游连接出错: {}", e),
+                    Err(_) => format!("上游超过{:?}没有数据", stall_timeout),
+                };
+
+                if let Err(e) = body.reconnect(&failure).await {
+                    body.finished = true;
+                    return Some((Err(std::io::Error::other(e)), body));
+                }
+            }
+        })
+    }
+
+    async fn reconnect(&mut self, reason: &str) -> Result<(), String> {
+        let Some(offset) = self.next_offset else {
+            return Err(format!("{}，且无法确定续传位置", reason));
+        };
+        if self.reconnects >= self.config.max_reconnects {
+            return Err(format!("{}，已重连{}次，放弃", reason, self.reconnects));
+        }
+        self.reconnects += 1;
+        self.stats.record_stall(&self.song);
+
+        let range = match self.range_end {
+            Some(end) => format!("bytes={}-{}", offset, end),
+            None => format!("bytes={}-", offset),
+        };
+        log::warn!("{}，从 {} 续传（第{}次）", reason, range, self.reconnects);
+
+        let request = upstream_request(
+            &self.client,
+            &actix_web::http::Method::GET,
+            &self.target_url,
+        )
+        .header("Range", range);
+        let response = tokio::time::timeout(
+            Duration::from_millis(self.config.upstream_timeout_ms),
+            request.send(),
+        )
+        .await
+        .map_err(|_| "续传请求超时".to_string())?
+        .map_err(|e| format!("续传请求失败: {}", e))?;
+
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Err(format!("续传请求返回了非206状态: {}", response.status()));
+        }
+        self.stream = response.bytes_stream().boxed();
+        Ok(())
+    }
+}
+
+/// 预读缓冲：后台任务持续读取上游并按 `chunk_size` 重新分块放入缓冲区，
+/// 渲染器从缓冲区取数据。
+///
+/// 缓冲数据达到高水位时暂停读取上游，回落到低水位以下再恢复，
+/// 这样上游以较大的突发读取，渲染器端则得到大小均匀的数据块。
+fn read_ahead(
+    upstream: impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+    config: ProxyConfig,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    let chunk_size = config.chunk_size();
+    let (low_watermark, high_watermark) = config.watermarks();
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(config.read_ahead_chunks());
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let drained = Arc::new(Notify::new());
+
+    let producer_buffered = buffered.clone();
+    let producer_drained = drained.clone();
+    tokio::spawn(async move {
+        let mut upstream = std::pin::pin!(upstream);
+        let mut pending = BytesMut::new();
+        loop {
+            // 达到高水位后等渲染器消费到低水位再继续读上游
+            if producer_buffered.load(Ordering::Acquire) >= high_watermark {
+                while producer_buffered.load(Ordering::Acquire) > low_watermark {
+                    tokio::select! {
+                        _ = producer_drained.notified() => {}
+                        _ = tx.closed() => return,
+                    }
+                }
+            }
+
+            let item = match upstream.next().await {
+                Some(Ok(bytes)) => {
+                    pending.extend_from_slice(&bytes);
+                    if pending.len() < chunk_size {
+                        continue;
+                    }
+                    Ok(pending.split_to(chunk_size).freeze())
+                }
+                Some(Err(e)) => Err(e),
+                None => {
+                    if !pending.is_empty() {
+                        let rest = pending.split().freeze();
+                        producer_buffered.fetch_add(rest.len(), Ordering::AcqRel);
+                        let _ = tx.send(Ok(rest)).await;
+                    }
+                    return;
+                }
+            };
+
+            let is_err = item.is_err();
+            // 一次上游读取可能攒够多个块，剩余的在后续循环中继续切分
+            let mut items = vec![item];
+            while !is_err && pending.len() >= chunk_size {
+                items.push(Ok(pending.split_to(chunk_size).freeze()));
+            }
+            for item in items {
+                if let Ok(bytes) = &item {
+                    producer_buffered.fetch_add(bytes.len(), Ordering::AcqRel);
+                }
+                if tx.send(item).await.is_err() {
+                    // 渲染器已断开
+                    return;
+                }
+            }
+            if is_err {
+                return;
+            }
+        }
+    });
+
+    futures_util::stream::unfold(rx, move |mut rx| {
+        let buffered = buffered.clone();
+        let drained = drained.clone();
+        async move {
+            let item = rx.recv().await?;
+            if let Ok(bytes) = &item {
+                let left = buffered.fetch_sub(bytes.len(), Ordering::AcqRel) - bytes.len();
+                if left <= low_watermark {
+                    drained.notify_one();
+                }
+            }
+            Some((item, rx))
+        }
+    })
+}
+
+/// 每个代理请求的关联ID，出现在日志、`X-Request-Id` 响应头和错误页中，方便对照排查
+fn next_correlation_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:08x}-{:04x}", secs as u32, seq as u16)
+}
+
+/// 代理请求失败的原因
+#[derive(Debug)]
+enum ProxyError {
+    /// 路径不是合法的 BV 号
+    InvalidBv(String),
+    /// 无法从 B 站解析出视频直链
+    Resolve(String),
+    /// 上游在超时时间内没有响应
+    UpstreamTimeout,
+    /// 连接上游失败
+    Upstream(String),
+    /// 无法启动 ffmpeg 转码
+    Transcode(String),
+}
+
+impl ProxyError {
+    fn status(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            ProxyError::InvalidBv(_) => StatusCode::BAD_REQUEST,
+            ProxyError::Resolve(_) | ProxyError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::Transcode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self, english: bool) -> String {
+        match (self, english) {
+            (ProxyError::InvalidBv(id), false) => format!("“{}”不是有效的 BV 号", id),
+            (ProxyError::InvalidBv(id), true) => format!("\"{}\" is not a valid BV id", id),
+            (ProxyError::Resolve(e), false) => format!("无法解析视频直链：{}", e),
+            (ProxyError::Resolve(e), true) => format!("Failed to resolve the video link: {}", e),
+            (ProxyError::UpstreamTimeout, false) => "B 站服务器响应超时".to_string(),
+            (ProxyError::UpstreamTimeout, true) => "Bilibili did not respond in time".to_string(),
+            (ProxyError::Upstream(e), false) => format!("连接 B 站服务器失败：{}", e),
+            (ProxyError::Upstream(e), true) => format!("Failed to reach Bilibili: {}", e),
+            (ProxyError::Transcode(e), false) => format!("无法启动 ffmpeg 转码：{}", e),
+            (ProxyError::Transcode(e), true) => format!("Failed to start ffmpeg: {}", e),
+        }
+    }
+
+    /// 生成人可读的错误响应：浏览器（Accept 含 text/html）得到 HTML，其余得到纯文本
+    fn render(&self, req: &HttpRequest, correlation_id: &str) -> HttpResponse {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+        };
+        let english = prefers_english(header(actix_web::http::header::ACCEPT_LANGUAGE));
+        let message = self.message(english);
+        let (title, id_label) = if english {
+            ("ktv-casting proxy error", "Request ID")
+        } else {
+            ("ktv-casting 代理错误", "请求 ID")
+        };
+
+        let mut resp = HttpResponse::build(self.status());
+        resp.insert_header(("X-Request-Id", correlation_id));
+        resp.insert_header((
+            actix_web::http::header::CONTENT_LANGUAGE,
+            if english { "en" } else { "zh-CN" },
+        ));
+        // HEAD 请求只需要状态码
+        if *req.method() == actix_web::http::Method::HEAD {
+            return resp.finish();
+        }
+        if header(actix_web::http::header::ACCEPT).contains("text/html") {
+            resp.content_type("text/html; charset=utf-8").body(format!(
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+                 <body><h1>{status}</h1><p>{message}</p><p>{id_label}: <code>{id}</code></p></body></html>\n",
+                title = title,
+                status = self.status(),
+                message = escape_html(&message),
+                id_label = id_label,
+                id = correlation_id,
+            ))
+        } else {
+            resp.content_type("text/plain; charset=utf-8").body(format!(
+                "{}\n{}\n{}: {}\n",
+                self.status(),
+                message,
+                id_label,
+                correlation_id
+            ))
+        }
+    }
+}
+
+/// 根据 Accept-Language 判断是否应使用英文；中文优先级不低于英文或都未出现时使用中文
+fn prefers_english(accept_language: &str) -> bool {
+    let mut zh = None::<f32>;
+    let mut en = None::<f32>;
+    for entry in accept_language.split(',') {
+        let mut parts = entry.trim().split(';');
+        let lang = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        let slot = if lang.starts_with("zh") {
+            &mut zh
+        } else if lang.starts_with("en") {
+            &mut en
+        } else {
+            continue;
+        };
+        *slot = Some(slot.map_or(q, |old: f32| old.max(q)));
+    }
+    match (zh, en) {
+        (Some(zh), Some(en)) => en > zh,
+        (None, Some(_)) => true,
+        _ => false,
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// 按功能开关决定是否经过预读缓冲
+fn response_body(
+    body: impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+    config: ProxyConfig,
+    features: &FeatureFlags,
+) -> BoxStream<'static, Result<Bytes, std::io::Error>> {
+    if features.is_enabled(Feature::ProxyReadAhead) {
+        read_ahead(body, config).boxed()
+    } else {
+        body.boxed()
+    }
+}
+
+// 本服务上某个路径的完整地址，供 ffmpeg 读取
+fn self_url(req: &HttpRequest, path: &str) -> String {
+    format!(
+        "http://127.0.0.1:{}/{}",
+        req.app_config().local_addr().port(),
+        path
+    )
+}
+
+/// 经 ffmpeg 把多个分段合并成一个分片 MP4 后提供，分段由本服务的 [`segment_path`] 逐个读取
+///
+/// 合并输出同样没有总长度，不支持 Range：每个请求都从头合并，以 200 返回完整的流；
+/// 同一首歌的新请求会结束旧请求的 ffmpeg。
+fn serve_joined(
+    req: &HttpRequest,
+    song: &str,
+    segments: usize,
+    shared_state: &SharedState,
+    correlation_id: &str,
+) -> Result<HttpResponse, ProxyError> {
+    let mut resp = HttpResponse::Ok();
+    resp.content_type("video/mp4");
+    if *req.method() == actix_web::http::Method::HEAD {
+        return Ok(resp.finish());
+    }
+    let inputs: Vec<String> = (0..segments)
+        .map(|index| self_url(req, &segment_path(song, index)))
+        .collect();
+    info!(
+        "[{}] ffmpeg 合并{}个分段: {}",
+        correlation_id, segments, song
+    );
+    let body = transcode::spawn(
+        &shared_state.proxy.ffmpeg,
+        transcode::concat_args(),
+        Some(transcode::concat_list(&inputs)),
+        shared_state.proxy.chunk_size(),
+        shared_state.ffmpeg_jobs.replace(&joined_path(song)),
+    )
+    .map_err(|e| ProxyError::Transcode(e.to_string()))?;
+    Ok(resp.streaming(body))
+}
+
+/// 经 ffmpeg 转码后提供：输入是本服务上未转码的同一首歌，输出边转边发
+///
+/// 转码输出没有总长度，不支持 Range：每个请求都从头转码，以 200 返回完整的流；
+/// 同一首歌的新请求会结束旧请求的 ffmpeg，不会有多个转码同时抢 CPU。
+fn serve_transcoded(
+    req: &HttpRequest,
+    song: &str,
+    shared_state: &SharedState,
+    correlation_id: &str,
+) -> Result<HttpResponse, ProxyError> {
+    let mut resp = HttpResponse::Ok();
+    resp.content_type("video/mp4");
+    if *req.method() == actix_web::http::Method::HEAD {
+        return Ok(resp.finish());
+    }
+    let input = self_url(req, song);
+    info!("[{}] ffmpeg 转码: {}", correlation_id, input);
+    let crossfade = shared_state.crossfades.lock().unwrap().get(song).cloned();
+    let args = match crossfade {
+        Some(crossfade) if shared_state.proxy.crossfade_secs > 0 => {
+            let next = crossfade.next.map(|next| self_url(req, &next));
+            info!(
+                "[{}] 交叉淡化: 跳过开头={} 下一首={:?}",
+                correlation_id, crossfade.skip_intro, next
+            );
+            transcode::crossfade_args(
+                &input,
+                shared_state.proxy.crossfade_secs,
+                crossfade.skip_intro,
+                next.as_deref(),
+            )
+        }
+        _ => transcode::ffmpeg_args(&input),
+    };
+    let body = transcode::spawn(
+        &shared_state.proxy.ffmpeg,
+        args,
+        None,
+        shared_state.proxy.chunk_size(),
+        shared_state.ffmpeg_jobs.replace(&transcode_path(song)),
+    )
+    .map_err(|e| ProxyError::Transcode(e.to_string()))?;
+    Ok(resp.streaming(body))
+}
+
+/// 健康检查，供看门狗确认服务仍在响应；需在 `proxy_handler` 之前注册
+#[get("/healthz")]
+pub async fn health_handler() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// 提供字幕文件，需在 `proxy_handler` 之前注册
+#[get("/subtitles/{file}")]
+pub async fn subtitle_handler(
+    path: web::Path<(String,)>,
+    shared_state: web::Data<SharedState>,
+) -> HttpResponse {
+    let (file,) = path.into_inner();
+    let Some(song) = file.strip_suffix(".srt") else {
+        return HttpResponse::NotFound().finish();
+    };
+    match shared_state.subtitles.lock().await.get(song) {
+        Some(srt) => HttpResponse::Ok()
+            .content_type("application/x-subrip; charset=utf-8")
+            .body(srt.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
 
 #[get("/{url:.*}")]
 pub async fn proxy_handler(
@@ -12,8 +571,33 @@ pub async fn proxy_handler(
     path: web::Path<(String,)>,
     client: web::Data<reqwest::Client>,
     shared_state: web::Data<SharedState>,
-) -> Result<HttpResponse, actix_web::Error> {
+) -> HttpResponse {
     let (origin_url,) = path.into_inner();
+    let correlation_id = next_correlation_id();
+    match proxy(&req, origin_url, &client, &shared_state, &correlation_id).await {
+        Ok(mut resp) => {
+            if let Ok(value) = actix_web::http::header::HeaderValue::from_str(&correlation_id) {
+                resp.headers_mut().insert(
+                    actix_web::http::header::HeaderName::from_static("x-request-id"),
+                    value,
+                );
+            }
+            resp
+        }
+        Err(e) => {
+            log::error!("[{}] 代理请求失败: {:?}", correlation_id, e);
+            e.render(&req, &correlation_id)
+        }
+    }
+}
+
+async fn proxy(
+    req: &HttpRequest,
+    origin_url: String,
+    client: &reqwest::Client,
+    shared_state: &SharedState,
+    correlation_id: &str,
+) -> Result<HttpResponse, ProxyError> {
     let range_hdr = req
         .headers()
         .get(actix_web::http::header::RANGE)
@@ -26,7 +610,8 @@ pub async fn proxy_handler(
         .unwrap_or("<none>");
 
     info!(
-        "Proxy request: method={} path={} origin_url={} Range={} If-Range={}",
+        "[{}] Proxy request: method={} path={} origin_url={} Range={} If-Range={}",
+        correlation_id,
         req.method(),
         req.path(),
         origin_url,
@@ -34,38 +619,134 @@ pub async fn proxy_handler(
         if_range_hdr
     );
 
-    let bv_id = &origin_url[..origin_url.find('-').unwrap_or(origin_url.len())];
-    let page: Option<u32> = if let Some(pos) = origin_url.find("-page") {
-        origin_url[pos + 5..].parse().ok()
-    } else {
-        None
+    if let Some(song) = origin_url.strip_suffix(TRANSCODE_SUFFIX) {
+        song_source::source_for(song).ok_or_else(|| ProxyError::InvalidBv(song.to_string()))?;
+        return serve_transcoded(req, song, shared_state, correlation_id);
+    }
+    if let Some((song, index)) = parse_segment_path(&origin_url) {
+        let source =
+            song_source::source_for(song).ok_or_else(|| ProxyError::InvalidBv(song.to_string()))?;
+        let stream = source.resolve(song).await.map_err(ProxyError::Resolve)?;
+        let segment = stream
+            .segments
+            .get(index)
+            .ok_or_else(|| ProxyError::Resolve(format!("视频没有第{}个分段", index + 1)))?;
+        info!(
+            "[{}] Proxy resolved segment {}/{} target_url={}",
+            correlation_id,
+            index + 1,
+            stream.segments.len(),
+            segment.url
+        );
+        return forward(
+            req,
+            song.to_string(),
+            segment.urls(),
+            client,
+            shared_state,
+            correlation_id,
+            false,
+        )
+        .await;
+    }
+    let (song, audio_only) = match origin_url.strip_suffix(AUDIO_SUFFIX) {
+        Some(song) => (song, true),
+        None => (origin_url.as_str(), false),
     };
+    let song = song.strip_suffix(JOINED_SUFFIX).unwrap_or(song);
+    let source =
+        song_source::source_for(song).ok_or_else(|| ProxyError::InvalidBv(song.to_string()))?;
 
-    info!("Proxy parsed: bv_id={} page={:?}", bv_id, page);
+    info!(
+        "[{}] Proxy parsed: source={} song={} audio_only={}",
+        correlation_id,
+        source.name(),
+        song,
+        audio_only
+    );
 
-    let target_url = get_bilibili_direct_link(bv_id, page)
-        .await
-        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if audio_only {
+        let target_url = source.audio_url(song).await.map_err(ProxyError::Resolve)?;
+        info!(
+            "[{}] Proxy resolved audio target_url={}",
+            correlation_id, target_url
+        );
+        return forward(
+            req,
+            origin_url.clone(),
+            vec![target_url],
+            client,
+            shared_state,
+            correlation_id,
+            true,
+        )
+        .await;
+    }
+
+    let stream = source.resolve(song).await.map_err(ProxyError::Resolve)?;
+    if stream.quality > 0 {
+        shared_state.stats.record_quality(song, stream.quality);
+    }
+    let segments = stream.segments;
+
+    if segments.len() > 1 {
+        // 只探测第一段得到的是该段的时长，这里直接使用各分段时长之和
+        let total_ms: u64 = segments.iter().map(|s| s.length_ms).sum();
+        if total_ms > 0 {
+            let mut cache = shared_state.duration_cache.lock().await;
+            cache.insert(song.to_string(), (total_ms / 1000) as u32);
+        }
+        match serve_joined(req, song, segments.len(), shared_state, correlation_id) {
+            Ok(resp) => return Ok(resp),
+            Err(e) => log::warn!(
+                "[{}] 视频有{}个分段但无法合并，只能提供第一段: {}",
+                correlation_id,
+                segments.len(),
+                e.message(false)
+            ),
+        }
+        // 只在首个请求时计一次降级，渲染器后续的 Range 请求不重复计数
+        if req.headers().get(actix_web::http::header::RANGE).is_none() {
+            shared_state.stats.record_downgrade(song);
+        }
+    }
+    let target_url = segments[0].url.clone();
 
-    info!("Proxy resolved target_url={}", target_url);
+    info!(
+        "[{}] Proxy resolved target_url={}",
+        correlation_id, target_url
+    );
 
     // 异步获取视频时长并存入缓存
     let duration_cache = shared_state.duration_cache.clone();
-    let origin_url_clone = origin_url.clone();
+    let song_clone = song.to_string();
     let target_url_clone = target_url.clone();
     tokio::spawn(async move {
+        // 直播没有时长，也不能当作文件探测
+        if source.is_live(&song_clone) {
+            return;
+        }
         // 先检查缓存中是否已有该视频的时长
         {
             let cache = duration_cache.lock().await;
-            if cache.contains_key(&origin_url_clone) {
+            if cache.contains_key(&song_clone) {
                 return;
             }
         }
 
+        // 来源的信息接口给出了时长时直接使用，省去下载 MP4 文件头
+        if let Ok(info) = source.info(&song_clone).await
+            && info.duration_secs > 0
+        {
+            let mut cache = duration_cache.lock().await;
+            cache.insert(song_clone, info.duration_secs);
+            return;
+        }
+
         match get_mp4_duration(&target_url_clone).await {
             Ok(duration) => {
                 let mut cache = duration_cache.lock().await;
-                cache.insert(origin_url_clone, duration.as_secs() as u32);
+                cache.insert(song_clone, duration.as_secs() as u32);
                 info!(
                     "成功获取并缓存视频时长: {} -> {}s",
                     target_url_clone,
@@ -78,28 +759,48 @@ pub async fn proxy_handler(
         }
     });
 
-    // DLNA renderers often probe with HEAD and/or send Range requests.
-    let mut upstream = match *req.method() {
-        actix_web::http::Method::HEAD => client.head(&target_url),
-        _ => client.get(&target_url),
-    };
-
-    upstream = upstream
-        .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36")
-        .header("Referer", "https://www.bilibili.com/");
+    forward(
+        req,
+        song.to_string(),
+        segments[0].urls(),
+        client,
+        shared_state,
+        correlation_id,
+        false,
+    )
+    .await
+}
 
-    // Forward Range-related headers to support seek/probe.
-    if let Some(range) = req.headers().get(actix_web::http::header::RANGE) {
-        upstream = upstream.header("Range", range.as_bytes());
-    }
-    if let Some(if_range) = req.headers().get(actix_web::http::header::IF_RANGE) {
-        upstream = upstream.header("If-Range", if_range.as_bytes());
-    }
+// 把请求转发到解析出的上游地址（`target_urls` 为主链接和备用链接）；`song` 为代理路径，用于统计卡顿
+// `audio_only` 时响应的 Content-Type 固定为 audio/mp4（DASH 音轨上游常标成 video/mp4）
+async fn forward(
+    req: &HttpRequest,
+    song: String,
+    target_urls: Vec<String>,
+    client: &reqwest::Client,
+    shared_state: &SharedState,
+    correlation_id: &str,
+    audio_only: bool,
+) -> Result<HttpResponse, ProxyError> {
+    let proxy_config = shared_state.proxy.clone();
+    let (target_url, response) = send_upstream(
+        &target_urls,
+        |url| {
+            // DLNA renderers often probe with HEAD and/or send Range requests.
+            let mut upstream = upstream_request(client, req.method(), url);
 
-    let response = upstream
-        .send()
-        .await
-        .map_err(actix_web::error::ErrorInternalServerError)?;
+            // Forward Range-related headers to support seek/probe.
+            if let Some(range) = req.headers().get(actix_web::http::header::RANGE) {
+                upstream = upstream.header("Range", range.as_bytes());
+            }
+            if let Some(if_range) = req.headers().get(actix_web::http::header::IF_RANGE) {
+                upstream = upstream.header("If-Range", if_range.as_bytes());
+            }
+            upstream
+        },
+        Duration::from_millis(proxy_config.upstream_timeout_ms),
+    )
+    .await?;
 
     let ct = response
         .headers()
@@ -123,7 +824,8 @@ pub async fn proxy_handler(
         .unwrap_or("<none>");
 
     info!(
-        "Proxy upstream: status={} Content-Type={} Content-Length={} Accept-Ranges={} Content-Range={}",
+        "[{}] Proxy upstream: status={} Content-Type={} Content-Length={} Accept-Ranges={} Content-Range={}",
+        correlation_id,
         response.status(),
         ct,
         cl,
@@ -147,6 +849,10 @@ pub async fn proxy_handler(
         }
     }
 
+    if audio_only {
+        client_resp.insert_header(("content-type", "audio/mp4"));
+    }
+
     // Some renderers require this header to decide whether they can seek.
     if !response.headers().contains_key("accept-ranges") {
         client_resp.insert_header(("accept-ranges", "bytes"));
@@ -157,19 +863,104 @@ pub async fn proxy_handler(
         return Ok(client_resp.finish());
     }
 
-    let body_stream = response
-        .bytes_stream()
-        .map(|item| item.map_err(std::io::Error::other));
+    // 记录当前响应在文件中的位置，卡住时才能从断点续传
+    let (next_offset, range_end, total) =
+        if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            let content_range = response
+                .headers()
+                .get("content-range")
+                .and_then(|v| v.to_str().ok());
+            let total = content_range.and_then(content_range_total);
+            match content_range.and_then(parse_content_range) {
+                Some((start, end)) => (Some(start), Some(end), total),
+                None => (None, None, total),
+            }
+        } else {
+            (Some(0), None, response.content_length())
+        };
+
+    let body = ResumableBody {
+        client: client.clone(),
+        target_url,
+        stream: response.bytes_stream().boxed(),
+        next_offset,
+        range_end,
+        reconnects: 0,
+        config: proxy_config.clone(),
+        finished: false,
+        song,
+        stats: shared_state.stats.clone(),
+        total,
+    };
 
-    Ok(client_resp.streaming(body_stream))
+    Ok(client_resp.streaming(response_body(
+        body.into_stream(),
+        proxy_config,
+        &shared_state.features,
+    )))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::media_server::proxy_handler;
+    use crate::config::ProxyConfig;
+    use crate::media_server::{
+        content_range_total, parse_content_range, parse_segment_path, prefers_english,
+        proxy_handler, read_ahead, segment_path,
+    };
+    use actix_web::web::Bytes;
     use actix_web::{App, HttpServer, web};
+    use futures_util::StreamExt;
     use reqwest::Client;
 
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 100-199/1000"), Some((100, 199)));
+        assert_eq!(parse_content_range("bytes */1000"), None);
+        assert_eq!(content_range_total("bytes 100-199/1000"), Some(1000));
+        assert_eq!(content_range_total("bytes 100-199/*"), None);
+    }
+
+    #[test]
+    fn test_segment_path() {
+        assert_eq!(
+            segment_path("BV1xx411c7mD-page2", 1),
+            "BV1xx411c7mD-page2-seg1"
+        );
+        assert_eq!(
+            parse_segment_path("BV1xx411c7mD-page2-seg1"),
+            Some(("BV1xx411c7mD-page2", 1))
+        );
+        assert_eq!(parse_segment_path("BV1xx411c7mD-page2"), None);
+        assert_eq!(parse_segment_path("BV1xx411c7mD-segx"), None);
+    }
+
+    #[test]
+    fn test_prefers_english() {
+        assert!(prefers_english("en-US,en;q=0.9"));
+        assert!(prefers_english("en-US,zh-CN;q=0.5"));
+        assert!(!prefers_english("zh-CN,zh;q=0.9,en;q=0.8"));
+        assert!(!prefers_english(""));
+    }
+
+    #[tokio::test]
+    async fn test_read_ahead_rechunks() {
+        let config = ProxyConfig {
+            chunk_size: 4,
+            read_ahead_bytes: 8,
+            high_watermark_bytes: 8,
+            low_watermark_bytes: 4,
+            ..ProxyConfig::default()
+        };
+        let upstream = futures_util::stream::iter(
+            ["ab", "cdefghij", "k"].map(|s| Ok(Bytes::from_static(s.as_bytes()))),
+        );
+        let chunks: Vec<Bytes> = read_ahead(upstream, config)
+            .map(|item| item.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks, vec!["abcd", "efgh", "ijk"]);
+    }
+
     #[tokio::test]
     async fn test_https() {
         let client = reqwest::Client::new();
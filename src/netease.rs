@@ -0,0 +1,299 @@
+//! 网易云音乐来源：房间歌单里的网易云单曲和歌单链接只投送音频，适合在 MV 之间放背景音乐
+//!
+//! 单曲的代理路径为 `netease-<歌曲ID>`；歌单为 `netease-playlist-<歌单ID>`，
+//! 像多P视频一样依次播放歌单中的每首歌（第 N 首为 `netease-playlist-<歌单ID>-pageN`）。
+
+use crate::bilibili_parser::{SongInfo, VideoCodec, VideoSegment, VideoStream, parse_song};
+use crate::song_source::SongSource;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+const SONG_PREFIX: &str = "netease-";
+const PLAYLIST_PREFIX: &str = "netease-playlist-";
+
+// 歌单的歌曲ID列表，按歌单ID缓存；播放歌单时每首歌都要查一次
+static PLAYLIST_CACHE: LazyLock<Mutex<HashMap<u64, Vec<u64>>>> = LazyLock::new(Default::default);
+
+/// 代理路径指向的网易云歌曲
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NeteaseSong {
+    Song(u64),
+    /// 歌单中的第 `index` 首（从 0 开始）
+    Track {
+        playlist: u64,
+        index: u32,
+    },
+}
+
+impl NeteaseSong {
+    fn parse(song: &str) -> Option<Self> {
+        let (base, page) = parse_song(song);
+        if let Some(id) = base.strip_prefix(PLAYLIST_PREFIX) {
+            return Some(NeteaseSong::Track {
+                playlist: id.parse().ok()?,
+                index: page.unwrap_or(0),
+            });
+        }
+        let id = base.strip_prefix(SONG_PREFIX)?.parse().ok()?;
+        page.is_none().then_some(NeteaseSong::Song(id))
+    }
+
+    // 歌曲ID；歌单中的歌需要先查询歌单
+    async fn song_id(self) -> Result<u64, String> {
+        match self {
+            NeteaseSong::Song(id) => Ok(id),
+            NeteaseSong::Track { playlist, index } => playlist_tracks(playlist)
+                .await?
+                .get(index as usize)
+                .copied()
+                .ok_or_else(|| format!("歌单{}中没有第{}首", playlist, index + 1)),
+        }
+    }
+}
+
+/// 网易云音乐的单曲和歌单
+pub struct NeteaseSource;
+
+impl SongSource for NeteaseSource {
+    fn name(&self) -> &'static str {
+        "网易云音乐"
+    }
+
+    fn match_url(&self, url: &str) -> Option<String> {
+        parse_netease_url(url)
+    }
+
+    fn owns(&self, song: &str) -> bool {
+        NeteaseSong::parse(song).is_some()
+    }
+
+    // 只有音频（mp3），没有画质
+    fn resolve<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>> {
+        async move {
+            let url = self.audio_url(song).await?;
+            Ok(VideoStream {
+                quality: 0,
+                codec: VideoCodec::Unknown,
+                segments: vec![VideoSegment {
+                    url,
+                    size: 0,
+                    length_ms: 0,
+                    backup_urls: Vec::new(),
+                }],
+            })
+        }
+        .boxed()
+    }
+
+    fn info<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>> {
+        async move {
+            let id = parse(song)?.song_id().await?;
+            let json = get_json(format!(
+                "https://music.163.com/api/song/detail/?id={}&ids=[{}]",
+                id, id
+            ))
+            .await?;
+            parse_song_detail(&json["songs"][0])
+        }
+        .boxed()
+    }
+
+    fn audio_url<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<String, String>> {
+        async move { song_url(parse(song)?.song_id().await?).await }.boxed()
+    }
+
+    fn page_count<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<u32, String>> {
+        async move {
+            match parse(song)? {
+                NeteaseSong::Track { playlist, .. } if parse_song(song).1.is_none() => {
+                    Ok(playlist_tracks(playlist).await?.len() as u32)
+                }
+                _ => Ok(1),
+            }
+        }
+        .boxed()
+    }
+
+    fn page_url(&self, song: &str) -> Option<String> {
+        Some(match NeteaseSong::parse(song)? {
+            NeteaseSong::Song(id) => format!("https://music.163.com/#/song?id={}", id),
+            NeteaseSong::Track { playlist, .. } => {
+                format!("https://music.163.com/#/playlist?id={}", playlist)
+            }
+        })
+    }
+}
+
+fn parse(song: &str) -> Result<NeteaseSong, String> {
+    NeteaseSong::parse(song).ok_or_else(|| format!("不是网易云音乐的歌曲: {}", song))
+}
+
+/// 网易云的单曲或歌单链接（如 `https://music.163.com/#/song?id=186016`、
+/// `https://y.music.163.com/m/playlist?id=2829883282`）转换成代理路径
+fn parse_netease_url(url: &str) -> Option<String> {
+    if !url.contains("music.163.com") {
+        return None;
+    }
+    // 只认 `?id=` / `&id=`，不把 `userid=` 之类的参数当成歌曲ID
+    let start = url.find("?id=").or_else(|| url.find("&id="))? + "?id=".len();
+    let digits = &url[start..];
+    let id: u64 = digits[..digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len())]
+        .parse()
+        .ok()?;
+    if url.contains("/playlist") {
+        Some(format!("{}{}", PLAYLIST_PREFIX, id))
+    } else if url.contains("/song") {
+        Some(format!("{}{}", SONG_PREFIX, id))
+    } else {
+        None
+    }
+}
+
+async fn get_json(url: String) -> Result<Value, String> {
+    let json: Value = Client::new()
+        .get(&url)
+        .header("User-Agent", "Mozilla/5.0")
+        .header("Referer", "https://music.163.com/")
+        .send()
+        .await
+        .map_err(|e| format!("请求网易云音乐失败: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("解析JSON失败: {}", e))?;
+    // 网易云的接口成功时 code 为 200
+    if json["code"].as_i64() != Some(200) {
+        return Err(format!(
+            "网易云音乐接口错误: {}",
+            json["message"]
+                .as_str()
+                .or_else(|| json["msg"].as_str())
+                .unwrap_or("未知错误")
+        ));
+    }
+    Ok(json)
+}
+
+// 外链接口会 302 跳转到 CDN 上的 mp3；需要会员或已下架的歌曲跳转到 404 页面
+async fn song_url(id: u64) -> Result<String, String> {
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+    let response = client
+        .get(format!(
+            "https://music.163.com/song/media/outer/url?id={}.mp3",
+            id
+        ))
+        .header("User-Agent", "Mozilla/5.0")
+        .send()
+        .await
+        .map_err(|e| format!("请求网易云音乐失败: {}", e))?;
+    response
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .filter(|location| !location.contains("/404"))
+        .map(str::to_string)
+        .ok_or_else(|| format!("歌曲{}需要会员或已下架", id))
+}
+
+// 歌单中全部歌曲的ID，按歌单顺序
+async fn playlist_tracks(id: u64) -> Result<Vec<u64>, String> {
+    if let Some(tracks) = PLAYLIST_CACHE.lock().unwrap().get(&id) {
+        return Ok(tracks.clone());
+    }
+    let json = get_json(format!(
+        "https://music.163.com/api/v6/playlist/detail?id={}",
+        id
+    ))
+    .await?;
+    // tracks 只有前若干首的详情，trackIds 是完整的列表
+    let tracks: Vec<u64> = json["playlist"]["trackIds"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|track| track["id"].as_u64())
+        .collect();
+    if tracks.is_empty() {
+        return Err(format!("歌单{}是空的", id));
+    }
+    PLAYLIST_CACHE.lock().unwrap().insert(id, tracks.clone());
+    Ok(tracks)
+}
+
+// song/detail 接口中的一首歌
+fn parse_song_detail(song: &Value) -> Result<SongInfo, String> {
+    let title = song["name"]
+        .as_str()
+        .ok_or_else(|| "无法获取歌曲名称".to_string())?;
+    let artists: Vec<&str> = song["artists"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|artist| artist["name"].as_str())
+        .collect();
+    Ok(SongInfo {
+        title: title.to_string(),
+        uploader: artists.join("/"),
+        // 时长以毫秒为单位
+        duration_secs: (song["duration"].as_u64().unwrap_or(0) / 1000) as u32,
+        cover: song["album"]["picUrl"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_netease() {
+        assert_eq!(
+            parse_netease_url("https://music.163.com/#/song?id=186016").as_deref(),
+            Some("netease-186016")
+        );
+        assert_eq!(
+            parse_netease_url("https://y.music.163.com/m/playlist?userid=1&id=2829883282&x=1")
+                .as_deref(),
+            Some("netease-playlist-2829883282")
+        );
+        assert_eq!(parse_netease_url("https://music.163.com/#/user?id=1"), None);
+        assert_eq!(
+            parse_netease_url("https://www.bilibili.com/video/BV1xx411c7mD"),
+            None
+        );
+
+        assert_eq!(
+            NeteaseSong::parse("netease-186016"),
+            Some(NeteaseSong::Song(186016))
+        );
+        assert_eq!(
+            NeteaseSong::parse("netease-playlist-7-page3"),
+            Some(NeteaseSong::Track {
+                playlist: 7,
+                index: 3
+            })
+        );
+        assert_eq!(NeteaseSong::parse("BV1xx411c7mD"), None);
+
+        let detail = serde_json::json!({
+            "name": "晴天",
+            "duration": 269000,
+            "artists": [{"name": "周杰伦"}],
+            "album": {"picUrl": "https://p1.music.126.net/cover.jpg"}
+        });
+        let info = parse_song_detail(&detail).unwrap();
+        assert_eq!(info.display_name(), "《晴天》- 周杰伦");
+        assert_eq!(info.duration_secs, 269);
+    }
+}
@@ -1,137 +1,779 @@
+use crate::bilibili_parser::parse_song;
+use crate::song_source;
+use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::pin::Pin;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, Interval};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, Notify, broadcast};
+use tokio::time::{Interval, sleep};
 use tokio_tungstenite::tungstenite::Message;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
-use futures_util::{SinkExt, StreamExt};
-use crate::utils::extract_bv_id;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{
+    HeaderMap, HeaderName, HeaderValue, header::AUTHORIZATION,
+};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-#[derive(Clone)]
-pub struct PlaylistManager {
+// 没有歌单时的 hash；用它请求 songListInfo 总能拿到完整歌单
+const EMPTY_LIST_HASH: &str = "EMPTY_LIST_HASH";
+// WebSocket 重连的最长等待时间（秒）
+const MAX_RECONNECT_DELAY_SECS: u64 = 60;
+// 歌单事件广播通道容量；订阅者落后超过该数量时会收到 Lagged 错误并丢弃旧事件
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+// 本地记住的最近歌曲数量，用于重放上一首
+const RECENT_SONGS: usize = 10;
+// WebSocket 心跳的默认发送间隔和超时：超过超时时间没有收到 pong 视为连接已断开
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_PONG_TIMEOUT: Duration = Duration::from_secs(60);
+// 默认的房间状态文件（当前目录）
+const DEFAULT_ROOM_STATE_FILE: &str = "ktv-casting-rooms.json";
+
+/// 某个房间最近一次的歌单 hash、正在演唱的歌曲和待唱列表
+///
+/// 重启后用它继续跟随房间：以保存的 hash 请求歌单，当前歌曲没变时不会从头重新投屏。
+/// 房间服务器无法访问时，按缓存的待唱列表在本地继续切歌。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomState {
+    pub hash: Option<String>,
+    pub song: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<QueuedSong>,
+}
+
+impl RoomState {
+    /// 读取 `path` 中房间 `key` 的状态；文件不存在、损坏或没有该房间时为空
+    pub fn load(path: &Path, key: &str) -> Self {
+        load_room_states(path).remove(key).unwrap_or_default()
+    }
+
+    /// 写入房间 `key` 的状态，其他房间的状态保持不变
+    pub fn save(&self, path: &Path, key: &str) -> Result<(), String> {
+        let mut states = load_room_states(path);
+        states.insert(key.to_string(), self.clone());
+        let text = serde_json::to_string_pretty(&states)
+            .map_err(|e| format!("序列化房间状态失败: {}", e))?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("无法写入房间状态 {}: {}", path.display(), e))
+    }
+}
+
+// 全部房间的状态：房间地址 → 状态
+fn load_room_states(path: &Path) -> BTreeMap<String, RoomState> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// 房间歌单中的一首歌
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedSong {
+    /// 房间中记录的原始链接，重新点歌时原样提交
+    pub url: String,
+    /// 代理路径，例如 `BV1xx411c7mD-page2`
+    pub song: String,
+    /// 点歌时记录的标题，房间没有提供时为 None
+    pub title: Option<String>,
+    /// 房间中的歌曲编号，调整顺序等操作用它指定歌曲；旧接口没有提供时为 None
+    pub id: Option<String>,
+    /// 点歌人的昵称，房间没有提供时为 None
+    pub requester: Option<String>,
+    /// 时长（秒），房间没有提供时为 None
+    pub duration_secs: Option<u32>,
+}
+
+impl QueuedSong {
+    fn from_item(item: &serde_json::Value) -> Option<Self> {
+        let url = item["url"].as_str()?;
+        Some(Self {
+            url: url.to_string(),
+            song: song_source::song_for_url(url),
+            title: item["title"].as_str().map(str::to_string),
+            id: match &item["id"] {
+                serde_json::Value::String(id) => Some(id.clone()),
+                serde_json::Value::Number(id) => Some(id.to_string()),
+                _ => None,
+            },
+            requester: item["nickname"]
+                .as_str()
+                .filter(|nickname| !nickname.is_empty())
+                .map(str::to_string),
+            duration_secs: item["duration"]
+                .as_u64()
+                .and_then(|secs| u32::try_from(secs).ok()),
+        })
+    }
+}
+
+/// 房间正在演唱的歌曲，来自 songListInfo 的歌单
+///
+/// 投屏和媒体代理仍以代理路径（[`path`](Self::path)）区分歌曲；
+/// 需要 BV 号、分P 或点歌人时直接取字段，不必再拆代理路径。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentSong {
+    pub bv_id: String,
+    /// 从 0 开始的分P，链接没有指定时为 None
+    pub page: Option<u32>,
+    /// 点歌时记录的标题，房间没有提供时为 None
+    pub title: Option<String>,
+    /// 点歌人的昵称，房间没有提供时为 None
+    pub requester: Option<String>,
+    /// 时长（秒），房间没有提供时为 None
+    pub duration_secs: Option<u32>,
+}
+
+impl CurrentSong {
+    /// 由代理路径（如 `BV1xx411c7mD-page2`）还原，标题等信息为空；用于恢复保存的房间状态
+    pub fn from_path(song: &str) -> Self {
+        let (bv_id, page) = parse_song(song);
+        Self {
+            bv_id: bv_id.to_string(),
+            page,
+            title: None,
+            requester: None,
+            duration_secs: None,
+        }
+    }
+
+    /// 代理路径，例如 `BV1xx411c7mD-page2`
+    pub fn path(&self) -> String {
+        match self.page {
+            Some(page) => format!("{}-page{}", self.bv_id, page),
+            None => self.bv_id.clone(),
+        }
+    }
+}
+
+impl From<&QueuedSong> for CurrentSong {
+    fn from(song: &QueuedSong) -> Self {
+        Self {
+            title: song.title.clone(),
+            requester: song.requester.clone(),
+            duration_secs: song.duration_secs,
+            ..Self::from_path(&song.song)
+        }
+    }
+}
+
+// 两首歌是否为同一首（只比较代理路径，恢复的状态没有标题等信息）
+fn same_song(a: Option<&CurrentSong>, b: Option<&CurrentSong>) -> bool {
+    a.map(CurrentSong::path) == b.map(CurrentSong::path)
+}
+
+/// 房间的完整歌单
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SongList {
+    /// 已唱，按点唱顺序；最后一首即正在演唱的歌曲
+    pub sung: Vec<QueuedSong>,
+    /// 待唱，按播放顺序
+    pub pending: Vec<QueuedSong>,
+}
+
+impl SongList {
+    /// 解析 songListInfo 响应中的 `list`
+    ///
+    /// 兼容两种格式：带 `state` 字段的数组（WebSocket 模式下的接口），
+    /// 以及按状态分组的对象（`{"sung": [...], ...}`，轮询模式下的旧接口）。
+    pub fn from_response(resp_json: &serde_json::Value) -> Self {
+        let mut list = Self::default();
+        match &resp_json["list"] {
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    let Some(song) = QueuedSong::from_item(item) else {
+                        continue;
+                    };
+                    if item["state"].as_str() == Some("sung") {
+                        list.sung.push(song);
+                    } else {
+                        list.pending.push(song);
+                    }
+                }
+            }
+            serde_json::Value::Object(groups) => {
+                for (state, items) in groups {
+                    let songs = items
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(QueuedSong::from_item);
+                    if state == "sung" {
+                        list.sung.extend(songs);
+                    } else {
+                        list.pending.extend(songs);
+                    }
+                }
+            }
+            _ => {}
+        }
+        list
+    }
+
+    /// 正在演唱的歌曲
+    pub fn current(&self) -> Option<&QueuedSong> {
+        self.sung.last()
+    }
+}
+
+// `Authorization: Bearer <令牌>` 的头部值；令牌含有不能放进请求头的字符时为 None
+fn bearer(token: &str) -> Option<HeaderValue> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token)).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+// 配置的附加请求头；名称或值无效的跳过并记录警告。值可能是 Cookie 等凭据，一律标为敏感
+fn parse_headers<'a>(headers: impl IntoIterator<Item = (&'a String, &'a String)>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(mut value)) => {
+                value.set_sensitive(true);
+                map.insert(name, value);
+            }
+            _ => warn!("房间请求头 {} 无效，已忽略", name),
+        }
+    }
+    map
+}
+
+// 房间接口的 HTTP 客户端：带上附加请求头，有令牌时再带上认证头
+fn build_client(token: Option<&str>, extra_headers: &HeaderMap) -> Client {
+    let mut headers = extra_headers.clone();
+    if let Some(value) = token.and_then(bearer) {
+        headers.insert(AUTHORIZATION, value);
+    }
+    Client::builder()
+        .use_rustls_tls()
+        .default_headers(headers)
+        .build()
+        .expect("Failed to create HTTP client")
+}
+
+/// 歌单管理器的事件，见 [`PlaylistManager::subscribe`]
+///
+/// 新增变体不视为破坏性变更，匹配时请保留 `_ =>` 分支。
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum PlaylistEvent {
+    /// 房间的当前歌曲发生变化
+    SongChanged { song: CurrentSong },
+    /// 歌单（已唱、正在演唱、待唱）发生变化
+    QueueUpdated { list: SongList },
+    /// 已连接到房间的 WebSocket（包括断线后重连成功）
+    Connected,
+    /// 与房间的连接已断开，`retry_in_secs` 秒后进行第 `attempt` 次重连
+    Disconnected { attempt: u32, retry_in_secs: u64 },
+    /// 房间已关闭或过期，不再重连；`reason` 为服务器给出的原因
+    RoomClosed { reason: Option<String> },
+    /// 房间网页上有人投票切歌（`{"type": "SKIP_VOTE", "nickname": ...}`）
+    SkipVote { voter: String },
+    /// 收到心跳的 pong，`rtt_ms` 为从发送 ping 到收到 pong 的往返时间（毫秒）
+    Latency { rtt_ms: u32 },
+}
+
+type RoomSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// 与房间的 WebSocket 连接状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomConnection {
+    /// 已连接（包括断线后重连成功）
+    Connected,
+    /// 连接已断开，`retry_in_secs` 秒后进行第 `attempt` 次重连
+    Reconnecting { attempt: u32, retry_in_secs: u64 },
+    /// 房间已关闭或过期，不再重连；`reason` 为服务器给出的原因
+    Closed { reason: Option<String> },
+}
+
+// 房间 HTTP 接口的错误：连接不上服务器，或服务器拒绝/返回了无法解析的响应
+enum RequestError {
+    Unreachable(String),
+    Rejected(String),
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Unreachable(e) | RequestError::Rejected(e) => f.write_str(e),
+        }
+    }
+}
+
+// 一次 WebSocket 连接的结束方式
+enum ListenerEnd {
+    // 断线、手动重连或程序关闭
+    Disconnected,
+    // 房间已关闭，附带服务器给出的原因
+    RoomClosed(Option<String>),
+}
+
+// 是否为房间关闭/过期的通知：`{"type": "ROOM_CLOSED"}`（或 ROOM_EXPIRED、ROOM_DESTROYED），
+// 以及带 `"destroyed": true` 的 UPDATE 消息；原因在 `reason` 字段
+fn is_room_closed(json: &serde_json::Value) -> bool {
+    match json["type"].as_str() {
+        Some("ROOM_CLOSED" | "ROOM_EXPIRED" | "ROOM_DESTROYED") => true,
+        Some("UPDATE") => json["destroyed"].as_bool().unwrap_or(false),
+        _ => false,
+    }
+}
+
+// 房间地址，进入新房间时整体替换
+#[derive(Debug, Clone)]
+struct RoomAddress {
     url: String,
     room_id: String,
+}
+
+impl RoomAddress {
+    // 房间状态文件中的键
+    fn key(&self) -> String {
+        format!("{}/{}", self.url, self.room_id)
+    }
+}
+
+/// 第 `attempt` 次重连前的等待时间：1、2、4…秒指数增长，最长 60 秒，
+/// 再随机缩短最多一半，避免房间重启后所有客户端同时涌入
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    let base_ms = (1u64 << attempt.saturating_sub(1).min(6)).min(MAX_RECONNECT_DELAY_SECS) * 1000;
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    Duration::from_millis(base_ms - seed % (base_ms / 2 + 1))
+}
+
+#[derive(Clone)]
+pub struct PlaylistManager {
+    room: Arc<std::sync::RwLock<RoomAddress>>,
     nickname: String,
     hash: Arc<Mutex<Option<String>>>,
-    song_playing: Arc<Mutex<Option<String>>>,
-    on_song_change: Arc<Mutex<Option<Arc<dyn Fn(String) + Send + Sync>>>>,
+    song_playing: Arc<Mutex<Option<CurrentSong>>>,
+    // 最近一次从 songListInfo 解析出的完整歌单
+    song_list: Arc<Mutex<SongList>>,
+    // 房间服务器无法访问期间在本地切到的歌曲，服务器恢复后同步回房间
+    offline_played: Arc<Mutex<Vec<QueuedSong>>>,
+    // 最近成为当前歌曲的歌，最近的在前
+    recent: Arc<Mutex<VecDeque<CurrentSong>>>,
+    events: broadcast::Sender<PlaylistEvent>,
+    // 手动重连：断开当前连接（或跳过重连等待）立即重连
+    reconnect: Arc<Notify>,
+    // WebSocket 监听任务是否在运行；房间关闭后结束
+    websocket_running: Arc<AtomicBool>,
+    // 是否已退回到轮询模式
+    polling: Arc<AtomicBool>,
+    // WebSocket 心跳的发送间隔和超时
+    ping_interval: Duration,
+    pong_timeout: Duration,
     client: Client,
+    // 私密房间的令牌，以 `Authorization: Bearer` 头随每个请求发送
+    token: Option<String>,
+    // 附加到每个 HTTP 和 WebSocket 请求的请求头（User-Agent、Cookie 等）
+    extra_headers: HeaderMap,
+    // 保存 hash 和当前歌曲的文件，重启后恢复
+    state_file: PathBuf,
+    // 取消后即已关闭：WebSocket 发送 Close 帧后断开，轮询停止，不再重连
+    shutdown: CancellationToken,
+    // WebSocket 监听和轮询的后台任务，关闭时等待它们结束
+    tasks: TaskTracker,
 }
 
 impl PlaylistManager {
+    /// 创建房间的歌单管理器，并恢复该房间上次保存的 hash 和当前歌曲
     pub fn new(url: &str, room_id: String, nickname: Option<String>) -> Self {
-        let client = Client::builder()
-            .use_rustls_tls()
-            .build()
-            .expect("Failed to create HTTP client");
-        
-        Self {
+        let state_file = PathBuf::from(DEFAULT_ROOM_STATE_FILE);
+        let room = RoomAddress {
             url: url.to_string(),
             room_id,
+        };
+        let state = RoomState::load(&state_file, &room.key());
+        if let Some(song) = &state.song {
+            info!("恢复上次的房间状态，当前歌曲: {}", song);
+        }
+        Self {
+            room: Arc::new(std::sync::RwLock::new(room)),
             nickname: nickname.unwrap_or_else(|| "ktv-casting".to_string()),
-            hash: Arc::new(Mutex::new(None)),
-            song_playing: Arc::new(Mutex::new(None)),
-            on_song_change: Arc::new(Mutex::new(None)),
-            client,
+            hash: Arc::new(Mutex::new(state.hash)),
+            song_playing: Arc::new(Mutex::new(
+                state.song.as_deref().map(CurrentSong::from_path),
+            )),
+            song_list: Arc::new(Mutex::new(SongList {
+                sung: Vec::new(),
+                pending: state.pending,
+            })),
+            offline_played: Default::default(),
+            recent: Default::default(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            reconnect: Arc::new(Notify::new()),
+            websocket_running: Arc::new(AtomicBool::new(false)),
+            polling: Arc::new(AtomicBool::new(false)),
+            ping_interval: DEFAULT_PING_INTERVAL,
+            pong_timeout: DEFAULT_PONG_TIMEOUT,
+            client: build_client(None, &HeaderMap::new()),
+            token: None,
+            extra_headers: HeaderMap::new(),
+            state_file,
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    /// 丢弃恢复的 hash 和当前歌曲，像第一次进入房间一样重新同步（当前歌曲会从头投屏）
+    pub fn with_resync(mut self, resync: bool) -> Self {
+        if resync {
+            info!("忽略上次的房间状态，重新同步");
+            self.hash = Arc::new(Mutex::new(None));
+            self.song_playing = Arc::new(Mutex::new(None));
+        }
+        self
+    }
+
+    /// WebSocket 心跳：每隔 `ping_interval` 发送一次 ping，超过 `pong_timeout` 没有收到 pong
+    /// 视为连接已断开并重连。反向代理空闲超时较短（如 30 秒）时应调小间隔，间隔至少 1 秒
+    pub fn with_heartbeat(mut self, ping_interval: Duration, pong_timeout: Duration) -> Self {
+        self.ping_interval = ping_interval.max(Duration::from_secs(1));
+        self.pong_timeout = pong_timeout;
+        self
+    }
+
+    /// 以同样的昵称和令牌进入另一个房间，得到独立的歌单管理器（同时跟随多个房间时使用）
+    pub fn for_room(&self, url: &str, room_id: String) -> Self {
+        let mut manager =
+            Self::new(url, room_id, Some(self.nickname.clone())).with_token(self.token.clone());
+        manager.extra_headers = self.extra_headers.clone();
+        manager.ping_interval = self.ping_interval;
+        manager.pong_timeout = self.pong_timeout;
+        manager.client = self.client.clone();
+        manager.state_file = self.state_file.clone();
+        manager
+    }
+
+    /// 房间号
+    pub fn room_id(&self) -> String {
+        self.room().room_id
+    }
+
+    // 保存 hash 和当前歌曲，失败只记录日志
+    async fn save_state(&self) {
+        let state = RoomState {
+            hash: self.hash.lock().await.clone(),
+            song: self
+                .song_playing
+                .lock()
+                .await
+                .as_ref()
+                .map(CurrentSong::path),
+            pending: self.song_list.lock().await.pending.clone(),
+        };
+        if let Err(e) = state.save(&self.state_file, &self.room().key()) {
+            warn!("{}", e);
+        }
+    }
+
+    fn room(&self) -> RoomAddress {
+        self.room.read().unwrap().clone()
+    }
+
+    // 房间 HTTP 接口的地址：`{url}/api/{endpoint}?roomId={room_id}`
+    fn api_url(&self, endpoint: &str) -> String {
+        let room = self.room();
+        format!("{}/api/{}?roomId={}", room.url, endpoint, room.room_id)
+    }
+
+    /// 进入另一个房间，恢复该房间保存的 hash 和当前歌曲（与 [`new`](Self::new) 相同）
+    ///
+    /// 轮询模式下下一次轮询即请求新房间；WebSocket 仍连着旧房间时断开并重连到新房间；
+    /// 旧房间已关闭、监听已结束时重新开始监听，此时连接失败会返回错误，调用方可退回到轮询。
+    pub async fn switch_room(self: Arc<Self>, url: &str, room_id: String) -> Result<(), String> {
+        let room = RoomAddress {
+            url: url.to_string(),
+            room_id,
+        };
+        let state = RoomState::load(&self.state_file, &room.key());
+        info!("进入新房间: {}", room.key());
+        *self.room.write().unwrap() = room;
+        *self.hash.lock().await = state.hash;
+        *self.song_playing.lock().await = state.song.as_deref().map(CurrentSong::from_path);
+        *self.song_list.lock().await = SongList {
+            sung: Vec::new(),
+            pending: state.pending,
+        };
+        self.offline_played.lock().await.clear();
+
+        if self.polling.load(Ordering::Acquire) {
+            Ok(())
+        } else if self.websocket_running.load(Ordering::Acquire) {
+            self.reconnect_now();
+            Ok(())
+        } else {
+            self.start_websocket_listener().await
         }
     }
 
-    /// 设置歌曲变化回调函数（异步版本）
-    pub async fn set_on_song_change<F>(&self, callback: F)
-    where
-        F: Fn(String) + Send + Sync + 'static,
-    {
-        let mut on_song_change = self.on_song_change.lock().await;
-        *on_song_change = Some(Arc::new(callback));
+    /// 设置私密房间的令牌；WebSocket 连接和每个 HTTP 请求都会带上 `Authorization: Bearer <令牌>`
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        if token.as_deref().is_some_and(|t| bearer(t).is_none()) {
+            warn!("房间令牌含有无法放进请求头的字符，已忽略");
+        }
+        self.client = build_client(token.as_deref(), &self.extra_headers);
+        self.token = token;
+        self
+    }
+
+    /// 附加到每个房间请求（HTTP 和 WebSocket）的请求头，例如自定义 `User-Agent`、
+    /// Cloudflare Access 的 `CF-Access-Client-Id`/`CF-Access-Client-Secret` 或 nginx 认证的 `Cookie`
+    pub fn with_headers<'a>(
+        mut self,
+        headers: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Self {
+        self.extra_headers = parse_headers(headers);
+        self.client = build_client(self.token.as_deref(), &self.extra_headers);
+        self
+    }
+
+    /// 停止跟随房间：关闭 WebSocket（发送 Close 帧）或停止轮询，之后不再发出歌曲变化
+    ///
+    /// 只发出关闭通知，不等待；需要确认连接已关闭时用 [`shutdown`](Self::shutdown)。
+    pub fn close(&self) {
+        self.shutdown.cancel();
+        self.tasks.close();
     }
 
-    /// 启动WebSocket连接并监听（包含自动重连）
+    /// 关闭并等待 WebSocket 监听（含重连）和轮询任务结束，返回时 Close 帧已发出
+    pub async fn shutdown(&self) {
+        self.close();
+        self.tasks.wait().await;
+        info!("房间连接已关闭");
+    }
+
+    /// 是否已调用过 [`close`](Self::close)
+    pub fn is_closed(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// 订阅歌曲变化、歌单变化和连接状态事件
+    ///
+    /// 每个订阅者独立收到全部事件；在开始监听（或轮询）之前订阅，就不会错过第一首歌。
+    pub fn subscribe(&self) -> broadcast::Receiver<PlaylistEvent> {
+        self.events.subscribe()
+    }
+
+    // 当前歌曲变了：记入最近歌曲并发出 SongChanged
+    async fn song_changed(&self, song: CurrentSong) {
+        let mut recent = self.recent.lock().await;
+        recent.push_front(song.clone());
+        recent.truncate(RECENT_SONGS);
+        drop(recent);
+        self.emit(PlaylistEvent::SongChanged { song });
+    }
+
+    /// 最近成为当前歌曲的歌（最多 10 首），最近的在前
+    pub async fn recent_songs(&self) -> Vec<CurrentSong> {
+        self.recent.lock().await.iter().cloned().collect()
+    }
+
+    /// 当前歌曲之前的最后一首歌
+    pub async fn previous_song(&self) -> Option<CurrentSong> {
+        let current = self.get_song_playing().await;
+        self.recent
+            .lock()
+            .await
+            .iter()
+            .find(|song| Some(song.path()) != current)
+            .cloned()
+    }
+
+    fn emit(&self, event: PlaylistEvent) {
+        // 没有订阅者时 send 会返回错误，忽略即可
+        let _ = self.events.send(event);
+    }
+
+    /// 立即重连：已连接时断开重连，正在等待重连时跳过剩余的等待
+    pub fn reconnect_now(&self) {
+        info!("手动重连房间");
+        self.reconnect.notify_one();
+    }
+
+    /// 启动WebSocket连接并监听
+    ///
+    /// 第一次连接失败时返回错误，由调用方退回到轮询；连接成功后由后台任务持续监听，
+    /// 之后断线会一直重连（见 [`reconnect_delay`]），直到调用 [`close`](Self::close)。
     pub async fn start_websocket_listener(self: Arc<Self>) -> Result<(), String> {
-        let mut backoff = 1;
-        
+        if self.is_closed() {
+            return Err("播放列表已关闭".to_string());
+        }
+        let ws_stream = self.connect_websocket().await?;
+        self.emit(PlaylistEvent::Connected);
+        self.websocket_running.store(true, Ordering::Release);
+        let tasks = self.tasks.clone();
+        tasks.spawn(async move {
+            Arc::clone(&self).supervise_websocket(ws_stream).await;
+            self.websocket_running.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+
+    // 监听连接，断开后按退避时间不断重连
+    async fn supervise_websocket(self: Arc<Self>, mut ws_stream: RoomSocket) {
         loop {
-            match Arc::clone(&self).connect_websocket_internal().await {
-                Ok(_) => {
-                    info!("WebSocket连接成功");
-                    backoff = 1; // 重置退避
+            if let ListenerEnd::RoomClosed(reason) =
+                Arc::clone(&self).message_listener(ws_stream).await
+            {
+                warn!(
+                    "房间已关闭: {}",
+                    reason.as_deref().unwrap_or("服务器没有给出原因")
+                );
+                self.emit(PlaylistEvent::RoomClosed { reason });
+                return;
+            }
+            let mut attempt = 0;
+            ws_stream = loop {
+                if self.is_closed() {
+                    return;
+                }
+                attempt += 1;
+                let delay = reconnect_delay(attempt);
+                warn!(
+                    "与房间的WebSocket连接已断开，{:.1}秒后第{}次重连",
+                    delay.as_secs_f32(),
+                    attempt
+                );
+                self.emit(PlaylistEvent::Disconnected {
+                    attempt,
+                    retry_in_secs: delay.as_secs_f32().ceil() as u64,
+                });
+                tokio::select! {
+                    _ = sleep(delay) => {}
+                    _ = self.reconnect.notified() => info!("跳过等待，立即重连"),
+                    _ = self.shutdown.cancelled() => return,
                 }
-                Err(e) => {
-                    warn!("WebSocket连接失败: {}，{}秒后重试", e, backoff);
-                    sleep(Duration::from_secs(backoff)).await;
-                    backoff = (backoff * 2).min(60); // 指数退避，最大60秒
-                    continue;
+                let connected = tokio::select! {
+                    connected = self.connect_websocket() => connected,
+                    _ = self.shutdown.cancelled() => return,
+                };
+                match connected {
+                    Ok(ws_stream) => break ws_stream,
+                    Err(e) => warn!("{}", e),
                 }
+            };
+            info!("第{}次重连成功", attempt);
+            if let Err(e) = self.reconcile_offline().await {
+                warn!("同步离线期间播放的歌曲失败: {}", e);
             }
-
-            // 连接成功后，等待断开后再重连
-            break;
+            self.emit(PlaylistEvent::Connected);
         }
-
-        Ok(())
     }
 
-    /// 内部连接方法（不包含重连逻辑）
-    async fn connect_websocket_internal(self: Arc<Self>) -> Result<(), String> {
+    /// 建立 WebSocket 连接（不包含重连逻辑）
+    async fn connect_websocket(&self) -> Result<RoomSocket, String> {
         // 从HTTP URL构建WebSocket URL
         // 例如：https://ktv.starfreedomx.top -> wss://ktv.starfreedomx.top
-        let ws_protocol = if self.url.starts_with("https://") { "wss:" } else { "ws:" };
-        
+        let room = self.room();
+        let ws_protocol = if room.url.starts_with("https://") {
+            "wss:"
+        } else {
+            "ws:"
+        };
+
         // 提取主机部分（去除协议）
-        let host_part = if self.url.starts_with("http://") {
-            &self.url[7..] // 跳过 "http://"
-        } else if self.url.starts_with("https://") {
-            &self.url[8..] // 跳过 "https://"
+        let host_part = if room.url.starts_with("http://") {
+            &room.url[7..] // 跳过 "http://"
+        } else if room.url.starts_with("https://") {
+            &room.url[8..] // 跳过 "https://"
         } else {
-            &self.url
+            &room.url
         };
-        
-        let ws_url = format!("{}//{}/api/ws?roomId={}&nickname={}", 
-            ws_protocol, 
-            host_part, 
-            self.room_id,
+
+        let ws_url = format!(
+            "{}//{}/api/ws?roomId={}&nickname={}",
+            ws_protocol,
+            host_part,
+            room.room_id,
             urlencoding::encode(&self.nickname)
         );
-        
+
         info!("正在连接到WebSocket: {}", ws_url);
 
-        let (ws_stream, _) = connect_async(&ws_url)
+        let mut request = ws_url
+            .as_str()
+            .into_client_request()
+            .map_err(|e| format!("WebSocket地址无效: {}", e))?;
+        request.headers_mut().extend(self.extra_headers.clone());
+        if let Some(value) = self.token.as_deref().and_then(bearer) {
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+
+        let (ws_stream, _) = connect_async(request)
             .await
             .map_err(|e| format!("WebSocket连接失败: {}", e))?;
 
         info!("WebSocket连接成功，开始监听消息...");
-
-        // 启动消息监听任务
-        tokio::spawn(async move {
-            Self::message_listener(self, ws_stream).await;
-        });
-
-        Ok(())
+        Ok(ws_stream)
     }
 
-    /// 消息监听循环
-    async fn message_listener(
-        self: Arc<Self>,
-        mut ws_stream: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
-    ) {
-        let mut ping_interval: Interval = tokio::time::interval(Duration::from_secs(30));
+    /// 消息监听循环，连接断开、手动重连、关闭或房间关闭时返回
+    async fn message_listener(self: Arc<Self>, mut ws_stream: RoomSocket) -> ListenerEnd {
+        let mut ping_interval: Interval = tokio::time::interval(self.ping_interval);
         let mut last_pong_time = std::time::Instant::now();
+        // 最近一次发送 ping 的时间，收到 pong 后据此计算往返时间
+        let mut ping_sent: Option<std::time::Instant> = None;
+        if self.is_closed() {
+            let _ = ws_stream.close(None).await;
+            return ListenerEnd::Disconnected;
+        }
 
+        let mut end = ListenerEnd::Disconnected;
         loop {
             tokio::select! {
+                _ = self.shutdown.cancelled() => {
+                    info!("正在关闭WebSocket连接");
+                    if let Err(e) = ws_stream.close(None).await {
+                        warn!("发送Close帧失败: {}", e);
+                    }
+                    break;
+                }
+                _ = self.reconnect.notified() => {
+                    info!("断开当前连接以便重连");
+                    let _ = ws_stream.close(None).await;
+                    break;
+                }
                 msg = ws_stream.next() => {
                     match msg {
                         Some(Ok(Message::Text(text))) => {
                             debug!("收到WebSocket消息: {}", text);
-                            
+
                             // 处理心跳响应
                             if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text)
                                 && json.get("type").and_then(|t| t.as_str()) == Some("pong") {
                                     last_pong_time = std::time::Instant::now();
                                     debug!("收到pong响应");
+                                    self.record_latency(ping_sent.take());
+                                    continue;
+                                }
+
+                            // 房间关闭/过期
+                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text)
+                                && is_room_closed(&json) {
+                                    let reason = json["reason"].as_str().filter(|r| !r.is_empty());
+                                    end = ListenerEnd::RoomClosed(reason.map(str::to_string));
+                                    break;
+                                }
+
+                            // 投票切歌，没有昵称的按匿名计
+                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text)
+                                && json["type"].as_str() == Some("SKIP_VOTE") {
+                                    let voter = json["nickname"].as_str().unwrap_or("").to_string();
+                                    self.emit(PlaylistEvent::SkipVote { voter });
                                     continue;
                                 }
 
@@ -153,9 +795,17 @@ impl PlaylistManager {
                         Some(Ok(Message::Pong(_))) => {
                             last_pong_time = std::time::Instant::now();
                             debug!("收到pong");
+                            self.record_latency(ping_sent.take());
                         }
-                        Some(Ok(Message::Close(_))) => {
-                            info!("WebSocket连接已关闭");
+                        Some(Ok(Message::Close(frame))) => {
+                            info!("WebSocket连接已关闭: {:?}", frame);
+                            // 服务器重启（1001 Going Away）之外、带原因的关闭视为房间已关闭
+                            if let Some(frame) = frame
+                                && frame.code != CloseCode::Away
+                                && !frame.reason.is_empty()
+                            {
+                                end = ListenerEnd::RoomClosed(Some(frame.reason.to_string()));
+                            }
                             break;
                         }
                         Some(Err(e)) => {
@@ -172,8 +822,8 @@ impl PlaylistManager {
                 _ = ping_interval.tick() => {
                     // 定时发送ping并检查连接状态
                     let now = std::time::Instant::now();
-                    if now.duration_since(last_pong_time) > Duration::from_secs(60) {
-                        warn!("超过60秒未收到pong，连接可能已断开");
+                    if now.duration_since(last_pong_time) > self.pong_timeout {
+                        warn!("超过{}秒未收到pong，连接可能已断开", self.pong_timeout.as_secs());
                         break;
                     }
 
@@ -181,12 +831,23 @@ impl PlaylistManager {
                         warn!("发送ping失败，连接可能已断开");
                         break;
                     }
+                    ping_sent = Some(now);
                     debug!("发送ping");
                 }
             }
         }
 
         info!("WebSocket监听结束");
+        end
+    }
+
+    // 收到 pong 时发出往返时间；没有对应的 ping（如服务器主动发来的 pong）时不计
+    fn record_latency(&self, ping_sent: Option<std::time::Instant>) {
+        if let Some(sent) = ping_sent {
+            let rtt_ms = sent.elapsed().as_millis().min(u32::MAX as u128) as u32;
+            debug!("心跳往返时间: {}ms", rtt_ms);
+            self.emit(PlaylistEvent::Latency { rtt_ms });
+        }
     }
 
     /// 处理UPDATE消息
@@ -199,39 +860,38 @@ impl PlaylistManager {
         // 如果hash发生变化，获取当前播放的歌曲
         if old_hash != Some(new_hash.clone()) {
             info!("检测到歌单更新，hash: {}", new_hash);
-            
+
             // 调用HTTP接口获取完整歌单信息
-            if let Ok(Some(song_url)) = self.fetch_current_song_from_hash(&new_hash).await {
+            if let Ok(Some(song)) = self.fetch_current_song_from_hash(&new_hash).await {
                 let mut song_playing = self.song_playing.lock().await;
-                let old_song = song_playing.clone();
-                *song_playing = Some(song_url.clone());
+                let old_song = song_playing.replace(song.clone());
                 drop(song_playing);
 
-                if old_song != Some(song_url.clone()) {
-                    info!("歌曲已切换为: {}", song_url);
-                    if let Some(callback) = self.on_song_change.lock().await.as_ref() {
-                        callback(song_url);
-                    }
+                self.save_state().await;
+                if !same_song(old_song.as_ref(), Some(&song)) {
+                    info!("歌曲已切换为: {}", song.path());
+                    self.song_changed(song).await;
                 }
             }
         }
     }
 
     /// 根据hash获取当前播放的歌曲（通过HTTP接口）
-    async fn fetch_current_song_from_hash(&self, hash: &str) -> Result<Option<String>, String> {
-        let url = format!(
-            "{}/api/songListInfo?roomId={}&lastHash={}",
-            self.url, self.room_id, hash
-        );
+    async fn fetch_current_song_from_hash(
+        &self,
+        hash: &str,
+    ) -> Result<Option<CurrentSong>, String> {
+        let url = format!("{}&lastHash={}", self.api_url("songListInfo"), hash);
 
         debug!("获取当前歌曲: {}", url);
 
-        let resp = self.client
+        let resp = self
+            .client
             .get(&url)
             .send()
             .await
             .map_err(|e| format!("发送请求失败: {}", e))?;
-        
+
         if !resp.status().is_success() {
             return Err(format!("请求失败，状态码: {}", resp.status()));
         }
@@ -246,82 +906,340 @@ impl PlaylistManager {
         }
 
         // 提取正在演唱的歌曲
-        let sung_url: Option<String> = if let Some(list_array) = resp_json["list"].as_array() {
-            list_array
-                .iter()
-                .rev()
-                .find(|item| {
-                    item.get("state")
-                        .is_some_and(|s| s.as_str().unwrap_or("") == "sung")
-                })
-                .and_then(|item| item["url"].as_str())
-                .map(extract_bv_id)
-        } else {
-            None
-        };
+        let list = self.record_list(&resp_json).await;
+        Ok(list.current().map(CurrentSong::from))
+    }
+
+    // 保存 songListInfo 响应中的完整歌单，有变化时发出 QueueUpdated
+    async fn record_list(&self, resp_json: &serde_json::Value) -> SongList {
+        let list = SongList::from_response(resp_json);
+        let mut stored = self.song_list.lock().await;
+        if *stored == list {
+            return list;
+        }
+        *stored = list.clone();
+        drop(stored);
+        self.emit(PlaylistEvent::QueueUpdated { list: list.clone() });
+        list
+    }
+
+    /// 获取房间的完整歌单（已唱与待唱）
+    pub async fn fetch_song_list(&self) -> Result<SongList, String> {
+        let url = format!(
+            "{}&lastHash={}",
+            self.api_url("songListInfo"),
+            EMPTY_LIST_HASH
+        );
+
+        debug!("获取完整歌单: {}", url);
+
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("发送请求失败: {}", e))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("请求失败，状态码: {}", resp.status()));
+        }
+
+        let resp_json: serde_json::Value = resp
+            .json()
+            .await
+            .map_err(|e| format!("解析JSON失败: {}", e))?;
+
+        Ok(SongList::from_response(&resp_json))
+    }
+
+    /// 以本程序的昵称点歌（HTTP接口），`url` 为房间歌单中记录的链接或B站视频页面链接
+    pub async fn add_song(&self, url: &str) -> Result<(), String> {
+        let api = self.api_url("addSong");
+
+        let resp = self
+            .client
+            .post(&api)
+            .json(&json!({"url": url, "nickname": self.nickname}))
+            .send()
+            .await
+            .map_err(|e| format!("发送请求失败: {}", e))?;
+
+        let resp_json: serde_json::Value = resp
+            .json()
+            .await
+            .map_err(|e| format!("解析JSON失败: {}", e))?;
 
-        Ok(sung_url)
+        if !resp_json["success"].as_bool().unwrap_or(false) {
+            return Err(format!("请求失败: {}", resp_json));
+        }
+
+        info!("已点歌: {}", url);
+        Ok(())
+    }
+
+    // 对待唱的歌曲调用房间的 HTTP 接口，请求体附带当前歌单 hash，返回 success 为 true 时成功
+    async fn post_song_action(
+        &self,
+        endpoint: &str,
+        mut body: serde_json::Value,
+    ) -> Result<(), String> {
+        let api = self.api_url(endpoint);
+        body["idArrayHash"] = json!(
+            self.hash
+                .lock()
+                .await
+                .clone()
+                .unwrap_or_else(|| EMPTY_LIST_HASH.to_string())
+        );
+
+        let resp = self
+            .client
+            .post(&api)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("发送请求失败: {}", e))?;
+
+        let resp_json: serde_json::Value = resp
+            .json()
+            .await
+            .map_err(|e| format!("解析JSON失败: {}", e))?;
+
+        if !resp_json["success"].as_bool().unwrap_or(false) {
+            return Err(format!("请求失败: {}", resp_json));
+        }
+        Ok(())
+    }
+
+    /// 把待唱的歌曲 `id` 移到待唱列表的第 `to` 位（从 0 开始，HTTP接口）
+    pub async fn move_song(&self, id: &str, to: usize) -> Result<(), String> {
+        self.post_song_action("reorderSong", json!({"id": id, "to": to}))
+            .await?;
+        info!("已调整歌曲 {} 到待唱第 {} 位", id, to + 1);
+        Ok(())
+    }
+
+    /// 置顶待唱的歌曲 `id`，使它成为下一首；正在演唱的歌曲不受影响（HTTP接口）
+    pub async fn prioritize(&self, id: &str) -> Result<(), String> {
+        self.post_song_action("topSong", json!({"id": id})).await?;
+        info!("已置顶歌曲 {}", id);
+        Ok(())
+    }
+
+    /// 从待唱列表删除歌曲 `id`（HTTP接口）
+    pub async fn remove_song(&self, id: &str) -> Result<(), String> {
+        self.post_song_action("deleteSong", json!({"id": id}))
+            .await?;
+        info!("已删除歌曲 {}", id);
+        Ok(())
+    }
+
+    /// 撤销删除：重新点回 `song` 并移回待唱列表的第 `index` 位（从 0 开始）
+    ///
+    /// 重新点的歌排在末尾且编号是新的，需要再取一次歌单找到它。
+    pub async fn restore_song(&self, song: &QueuedSong, index: usize) -> Result<(), String> {
+        self.add_song(&song.url).await?;
+        let list = self.fetch_song_list().await?;
+        let restored = list
+            .pending
+            .iter()
+            .rposition(|pending| pending.url == song.url);
+        match restored.and_then(|at| Some((at, list.pending[at].id.as_deref()?))) {
+            Some((at, id)) if at > index => self.move_song(id, index).await,
+            _ => Ok(()),
+        }
     }
 
     /// 请求下一首歌曲（HTTP接口）
+    ///
+    /// 房间服务器无法访问时按缓存的待唱列表在本地切到下一首（发出 SongChanged），
+    /// 服务器恢复后再同步回房间。
     pub async fn next_song(&self) -> Result<(), String> {
-        let url = format!("{}/api/nextSong?roomId={}", self.url, self.room_id);
+        match self.request_next().await {
+            Err(RequestError::Unreachable(e)) => {
+                warn!("房间服务器无法访问（{}），按缓存的歌单切歌", e);
+                self.advance_offline().await
+            }
+            result => result.map_err(|e| e.to_string()),
+        }
+    }
+
+    // 在缓存的待唱列表中切到下一首
+    async fn advance_offline(&self) -> Result<(), String> {
+        let mut stored = self.song_list.lock().await;
+        if stored.pending.is_empty() {
+            return Err("房间服务器无法访问，缓存的歌单中也没有下一首".to_string());
+        }
+        let song = stored.pending.remove(0);
+        stored.sung.push(song.clone());
+        let list = stored.clone();
+        drop(stored);
+        *self.song_playing.lock().await = Some(CurrentSong::from(&song));
+        self.offline_played.lock().await.push(song.clone());
+        self.save_state().await;
+        info!("离线切歌: {}", song.song);
+        self.emit(PlaylistEvent::QueueUpdated { list });
+        self.song_changed(CurrentSong::from(&song)).await;
+        Ok(())
+    }
+
+    /// 把离线期间本地切到的歌曲同步回房间：依次置顶并切到它，完成后房间的当前歌曲与本地一致；
+    /// 房间歌单里已经没有的歌跳过。没有离线切过歌时什么也不做。
+    async fn reconcile_offline(&self) -> Result<(), String> {
+        let played = self.offline_played.lock().await.clone();
+        if played.is_empty() {
+            return Ok(());
+        }
+        info!("房间服务器已恢复，同步离线期间播放的{}首歌", played.len());
+        for song in &played {
+            let list = self.refresh_list().await?;
+            let Some(id) = list
+                .pending
+                .iter()
+                .find(|pending| pending.url == song.url)
+                .and_then(|pending| pending.id.clone())
+            else {
+                warn!("房间歌单中已没有 {}，跳过同步", song.song);
+                continue;
+            };
+            self.prioritize(&id).await?;
+            self.request_next().await.map_err(|e| e.to_string())?;
+        }
+        self.offline_played.lock().await.clear();
+        self.refresh_list().await?;
+        Ok(())
+    }
+
+    // 重新获取完整歌单并更新 hash（后续操作的请求体需要最新的 hash）
+    async fn refresh_list(&self) -> Result<SongList, String> {
+        let url = format!(
+            "{}&lastHash={}",
+            self.api_url("songListInfo"),
+            EMPTY_LIST_HASH
+        );
+        let resp_json: serde_json::Value = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("发送请求失败: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("解析JSON失败: {}", e))?;
+        if let Some(hash) = resp_json["hash"].as_str() {
+            *self.hash.lock().await = Some(hash.to_string());
+        }
+        Ok(self.record_list(&resp_json).await)
+    }
+
+    // nextSong 接口；连接不上服务器与服务器拒绝分开报告
+    async fn request_next(&self) -> Result<(), RequestError> {
+        let url = self.api_url("nextSong");
         let temp_hash = self
             .hash
             .lock()
             .await
             .clone()
-            .unwrap_or_else(|| "EMPTY_LIST_HASH".to_string());
-        
-        let resp = self.client
+            .unwrap_or_else(|| EMPTY_LIST_HASH.to_string());
+
+        let resp = self
+            .client
             .post(&url)
             .json(&json!({"idArrayHash": temp_hash}))
             .send()
             .await
-            .map_err(|e| format!("发送请求失败: {}", e))?;
-        
+            .map_err(|e| RequestError::Unreachable(format!("发送请求失败: {}", e)))?;
+
         let resp_json: serde_json::Value = resp
             .json()
             .await
-            .map_err(|e| format!("解析JSON失败: {}", e))?;
+            .map_err(|e| RequestError::Rejected(format!("解析JSON失败: {}", e)))?;
 
         if !resp_json["success"].as_bool().unwrap_or(false) {
-            return Err(format!("请求失败: {}", resp_json));
+            return Err(RequestError::Rejected(format!("请求失败: {}", resp_json)));
         }
 
         info!("成功请求下一首歌曲");
         Ok(())
     }
 
-    /// 获取当前播放的歌曲
+    /// 获取当前播放的歌曲的代理路径
     pub async fn get_song_playing(&self) -> Option<String> {
+        self.song_playing
+            .lock()
+            .await
+            .as_ref()
+            .map(CurrentSong::path)
+    }
+
+    /// 获取当前播放的歌曲（BV 号、分P、标题、点歌人等）
+    pub async fn current_song(&self) -> Option<CurrentSong> {
         self.song_playing.lock().await.clone()
     }
 
+    /// 正在演唱的歌曲的点歌人
+    pub async fn current_requester(&self) -> Option<String> {
+        self.song_list
+            .lock()
+            .await
+            .current()
+            .and_then(|song| song.requester.clone())
+    }
+
+    /// 最近一次收到的完整歌单（尚未收到时为空）
+    pub async fn song_list(&self) -> SongList {
+        self.song_list.lock().await.clone()
+    }
+
+    /// 立即与房间重新同步：不管 hash 是否变化，重新拉取完整歌单并确定当前歌曲，
+    /// 用于漏掉了 UPDATE 消息、投屏与房间不一致的情况；当前歌曲变了时照常发出 SongChanged
+    pub async fn resync(&self) -> Result<Option<CurrentSong>, String> {
+        info!("手动与房间同步");
+        let list = self.refresh_list().await?;
+        let current = list.current().map(CurrentSong::from);
+        let old_song = std::mem::replace(&mut *self.song_playing.lock().await, current.clone());
+        self.save_state().await;
+        if !same_song(old_song.as_ref(), current.as_ref())
+            && let Some(song) = current.clone()
+        {
+            info!("同步后歌曲已切换为: {}", song.path());
+            self.song_changed(song).await;
+        }
+        Ok(current)
+    }
+
     /// 获取当前hash
     pub async fn get_hash(&self) -> Option<String> {
         self.hash.lock().await.clone()
     }
 
-    /// 遗留的轮询方法（当WebSocket不可用时使用）
-    pub fn start_periodic_update_legacy<F>(&self, f_on_update: F)
-    where
-        F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + 'static,
-    {
+    /// 遗留的轮询方法（当WebSocket不可用时使用），当前歌曲变化时同样发出 SongChanged
+    pub fn start_periodic_update_legacy(&self) {
+        self.polling.store(true, Ordering::Release);
         let self_clone = self.clone();
-        tokio::spawn(async move {
+        self.tasks.spawn(async move {
             let mut interval = tokio::time::interval(std::time::Duration::from_millis(300));
-            let mut song_playing: Option<String> = None;
             loop {
-                interval.tick().await;
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = self_clone.shutdown.cancelled() => {
+                        info!("播放列表已关闭，停止轮询");
+                        break;
+                    }
+                }
+                // 离线切过歌时先同步回房间，服务器仍无法访问时下次再试
+                if let Err(e) = self_clone.reconcile_offline().await {
+                    debug!("同步离线期间播放的歌曲失败: {}", e);
+                }
+                // 与本次请求前的当前歌曲比较：重启后恢复的、离线切到的歌都不会被当成新歌
+                let song_playing = self_clone.song_playing.lock().await.clone();
                 match self_clone.fetch_playlist().await {
                     Err(e) => error!("定时更新播放列表失败: {}", e),
                     Ok(song_playing_new) => {
-                        if song_playing_new != song_playing {
-                            if let Some(url) = song_playing_new.clone() {
-                                f_on_update(url).await;
-                            }
-                            song_playing = song_playing_new;
+                        if !same_song(song_playing_new.as_ref(), song_playing.as_ref())
+                            && let Some(song) = song_playing_new
+                        {
+                            self_clone.song_changed(song).await;
                         }
                     }
                 }
@@ -330,19 +1248,17 @@ impl PlaylistManager {
     }
 
     /// 旧的fetch_playlist方法（用于轮询模式）
-    async fn fetch_playlist(&self) -> Result<Option<String>, String> {
+    async fn fetch_playlist(&self) -> Result<Option<CurrentSong>, String> {
         let hash_guard = self.hash.lock().await;
-        let last_hash = hash_guard.clone().unwrap_or("EMPTY_LIST_HASH".to_string());
+        let last_hash = hash_guard.clone().unwrap_or(EMPTY_LIST_HASH.to_string());
         drop(hash_guard);
 
-        let url = format!(
-            "{}/api/songListInfo?roomId={}&lastHash={}",
-            self.url, self.room_id, last_hash
-        );
+        let url = format!("{}&lastHash={}", self.api_url("songListInfo"), last_hash);
 
         debug!("正在获取播放列表: {}", url);
 
-        let resp = self.client
+        let resp = self
+            .client
             .get(&url)
             .send()
             .await
@@ -365,34 +1281,267 @@ impl PlaylistManager {
         // 获取新的 hash 值
         let new_hash = resp_json["hash"]
             .as_str()
-            .unwrap_or("EMPTY_LIST_HASH")
+            .unwrap_or(EMPTY_LIST_HASH)
             .to_string();
 
-        // 从 list 对象中提取 sung 数组的最后一项作为当前播放的歌曲
-        let sung_url: Option<String> = if let Some(list_obj) = resp_json["list"].as_object() {
-            if let Some(sung_array) = list_obj.get("sung").and_then(|s| s.as_array()) {
-                sung_array
-                    .last()
-                    .and_then(|item| item["url"].as_str())
-                    .map(extract_bv_id)
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        // 已唱列表的最后一项即当前播放的歌曲
+        let list = self.record_list(&resp_json).await;
+        let current = list.current().map(CurrentSong::from);
 
         // 更新当前歌曲
         let mut song_playing = self.song_playing.lock().await;
-        *song_playing = sung_url.clone();
+        *song_playing = current.clone();
         drop(song_playing);
 
         // 更新 hash 值
         let mut hash = self.hash.lock().await;
         *hash = Some(new_hash);
         drop(hash);
+        self.save_state().await;
 
-        Ok(sung_url)
+        Ok(current)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_song_list_from_both_formats() {
+        let array = json!({
+            "changed": true,
+            "list": [
+                {"url": "BV1aa411c7mD", "state": "sung", "title": "晴天"},
+                {"url": "bilibili://video/BV1bb411c7mD?page=2", "state": "sung"},
+                {"url": "BV1cc411c7mD", "state": "pending", "title": "后来", "nickname": "小明", "duration": 245},
+                {"state": "pending"}
+            ]
+        });
+        let list = SongList::from_response(&array);
+        assert_eq!(list.sung.len(), 2);
+        assert_eq!(list.current().unwrap().song, "BV1bb411c7mD-page2");
+        assert_eq!(
+            list.pending,
+            [QueuedSong {
+                url: "BV1cc411c7mD".to_string(),
+                song: "BV1cc411c7mD".to_string(),
+                title: Some("后来".to_string()),
+                id: None,
+                requester: Some("小明".to_string()),
+                duration_secs: Some(245),
+            }]
+        );
+
+        let grouped = json!({
+            "list": {
+                "sung": [{"url": "BV1aa411c7mD"}],
+                "unsung": [{"url": "BV1cc411c7mD"}]
+            }
+        });
+        let list = SongList::from_response(&grouped);
+        assert_eq!(list.current().unwrap().song, "BV1aa411c7mD");
+        assert_eq!(list.pending[0].song, "BV1cc411c7mD");
+        assert_eq!(SongList::from_response(&json!({})), SongList::default());
+    }
+
+    #[test]
+    fn test_current_song_from_queue() {
+        let queued = QueuedSong::from_item(&json!({
+            "url": "bilibili://video/BV1bb411c7mD?page=2",
+            "title": "后来",
+            "nickname": "小明"
+        }))
+        .unwrap();
+        let song = CurrentSong::from(&queued);
+        assert_eq!(song.bv_id, "BV1bb411c7mD");
+        assert_eq!(song.page, Some(2));
+        assert_eq!(song.requester.as_deref(), Some("小明"));
+        assert_eq!(song.path(), queued.song);
+        // 只有代理路径的恢复状态与歌单中的同一首歌视为相同
+        assert!(same_song(
+            Some(&CurrentSong::from_path("BV1bb411c7mD-page2")),
+            Some(&song)
+        ));
+        assert!(!same_song(None, Some(&song)));
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        // 抖动只会缩短等待，最多一半
+        for (attempt, max_secs) in [(1, 1), (2, 2), (3, 4), (7, 60), (50, 60)] {
+            let delay = reconnect_delay(attempt);
+            assert!(delay <= Duration::from_secs(max_secs), "{:?}", delay);
+            assert!(
+                delay >= Duration::from_millis(max_secs * 500),
+                "{:?}",
+                delay
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_polling_task() {
+        let manager = PlaylistManager::new("http://127.0.0.1:0", "1".to_string(), None);
+        manager.start_periodic_update_legacy();
+        tokio::time::timeout(Duration::from_secs(5), manager.shutdown())
+            .await
+            .expect("轮询任务没有结束");
+        assert!(manager.is_closed());
+        assert!(manager.tasks.is_empty());
+    }
+
+    #[test]
+    fn test_is_room_closed() {
+        use serde_json::json;
+        assert!(is_room_closed(
+            &json!({"type": "ROOM_CLOSED", "reason": "房主已解散"})
+        ));
+        assert!(is_room_closed(&json!({"type": "ROOM_EXPIRED"})));
+        assert!(is_room_closed(
+            &json!({"type": "UPDATE", "destroyed": true})
+        ));
+        assert!(!is_room_closed(&json!({"type": "UPDATE", "hash": "abc"})));
+        assert!(!is_room_closed(&json!({"type": "PING"})));
+    }
+
+    #[test]
+    fn test_room_state_persists_per_room() {
+        let path =
+            std::env::temp_dir().join(format!("ktv-casting-rooms-{}.json", std::process::id()));
+        let room = RoomState {
+            hash: Some("abc".to_string()),
+            song: Some("BV1aa411c7mD".to_string()),
+            pending: Vec::new(),
+        };
+        room.save(&path, "http://ktv/102").unwrap();
+        RoomState::default().save(&path, "http://ktv/103").unwrap();
+
+        assert_eq!(RoomState::load(&path, "http://ktv/102"), room);
+        assert_eq!(
+            RoomState::load(&path, "http://ktv/104"),
+            RoomState::default()
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            RoomState::load(&path, "http://ktv/102"),
+            RoomState::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_list_notifies_queue_change() {
+        let playlist = PlaylistManager::new("http://127.0.0.1:0", "1".to_string(), None);
+        let mut events = playlist.subscribe();
+
+        let resp = json!({
+            "list": [
+                {"url": "BV1aa411c7mD", "state": "sung", "nickname": "小明"},
+                {"url": "BV1cc411c7mD", "state": "pending"}
+            ]
+        });
+        let list = playlist.record_list(&resp).await;
+        // 相同的歌单不重复通知
+        playlist.record_list(&resp).await;
+        assert_eq!(playlist.song_list().await, list);
+        assert_eq!(
+            events.try_recv(),
+            Ok(PlaylistEvent::QueueUpdated { list: list.clone() })
+        );
+        assert!(events.try_recv().is_err());
+        assert_eq!(playlist.current_requester().await.as_deref(), Some("小明"));
+    }
+
+    #[tokio::test]
+    async fn test_previous_song() {
+        let playlist = PlaylistManager::new("http://127.0.0.1:0", "1".to_string(), None);
+        assert_eq!(playlist.previous_song().await, None);
+        for song in ["BV1aa411c7mD", "BV1bb411c7mD", "BV1cc411c7mD"] {
+            let song = CurrentSong::from_path(song);
+            *playlist.song_playing.lock().await = Some(song.clone());
+            playlist.song_changed(song).await;
+        }
+        assert_eq!(playlist.recent_songs().await.len(), 3);
+        assert_eq!(
+            playlist.previous_song().await.map(|song| song.path()),
+            Some("BV1bb411c7mD".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_advance_offline_uses_cached_queue() {
+        let mut playlist = PlaylistManager::new("http://127.0.0.1:0", "1".to_string(), None);
+        playlist.state_file =
+            std::env::temp_dir().join(format!("ktv-casting-offline-{}.json", std::process::id()));
+        playlist
+            .record_list(&json!({
+                "list": [
+                    {"url": "BV1aa411c7mD", "state": "sung"},
+                    {"url": "BV1bb411c7mD", "state": "pending", "id": 7},
+                    {"url": "BV1cc411c7mD", "state": "pending", "id": 8}
+                ]
+            }))
+            .await;
+        let mut events = playlist.subscribe();
+
+        playlist.advance_offline().await.unwrap();
+        assert_eq!(
+            playlist.get_song_playing().await.as_deref(),
+            Some("BV1bb411c7mD")
+        );
+        assert!(matches!(
+            events.try_recv(),
+            Ok(PlaylistEvent::QueueUpdated { list }) if list.pending.len() == 1
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(PlaylistEvent::SongChanged { song }) if song.bv_id == "BV1bb411c7mD"
+        ));
+        assert_eq!(playlist.offline_played.lock().await.len(), 1);
+        // 待唱列表随房间状态保存，重启后离线也能继续切歌
+        let state = RoomState::load(&playlist.state_file, &playlist.room().key());
+        assert_eq!(state.pending[0].song, "BV1cc411c7mD");
+
+        playlist.advance_offline().await.unwrap();
+        assert!(playlist.advance_offline().await.is_err());
+        std::fs::remove_file(&playlist.state_file).unwrap();
+    }
+
+    #[test]
+    fn test_parse_headers_skips_invalid() {
+        let headers: BTreeMap<String, String> = [
+            ("User-Agent", "ktv-casting/1.0"),
+            ("Cookie", "CF_Authorization=abc"),
+            ("Bad Name", "x"),
+            ("X-Bad-Value", "a\nb"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let map = parse_headers(&headers);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["user-agent"], "ktv-casting/1.0");
+        assert!(map["cookie"].is_sensitive());
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let value = bearer("s3cret").unwrap();
+        assert_eq!(value, "Bearer s3cret");
+        // 令牌不出现在调试输出中
+        assert!(value.is_sensitive());
+        assert!(bearer("bad\ntoken").is_none());
+    }
+
+    #[test]
+    fn test_queued_song_id() {
+        let list = SongList::from_response(&json!({
+            "list": [
+                {"url": "BV1aa411c7mD", "state": "pending", "id": "a1"},
+                {"url": "BV1bb411c7mD", "state": "pending", "id": 7}
+            ]
+        }));
+        assert_eq!(list.pending[0].id.as_deref(), Some("a1"));
+        assert_eq!(list.pending[1].id.as_deref(), Some("7"));
+    }
+}
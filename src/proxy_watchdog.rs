@@ -0,0 +1,129 @@
+//! 媒体代理看门狗
+//!
+//! 监督 actix-web 服务：绑定失败（例如休眠唤醒后端口被占用）或服务停止响应时自动重启，
+//! 连续失败时改用系统分配的新端口，并通过 [`ProxyStatus`] 通知调用方更新对外公布的地址。
+
+use crate::SharedState;
+use crate::media_server::{health_handler, proxy_handler, subtitle_handler};
+use crate::remote::remote_handler;
+use actix_web::dev::Server;
+use actix_web::{App, HttpServer, web};
+use std::time::Duration;
+
+// 自检间隔
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// 单次自检超时
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+// 连续自检失败多少次后重启服务
+const MAX_FAILED_CHECKS: u32 = 2;
+// 在首选端口上连续绑定失败多少次后改用新端口
+const MAX_BIND_FAILURES: u32 = 3;
+// 两次重启尝试之间的等待时间
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+/// 媒体代理的状态
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyStatus {
+    /// 服务正在该端口上正常监听
+    Listening { port: u16 },
+    /// 服务不可用，正在尝试重启
+    Down { message: String },
+}
+
+/// 在指定端口启动媒体代理，返回服务句柄和实际监听的端口（`port` 为 0 时由系统分配）
+pub fn start_server(
+    shared_state: web::Data<SharedState>,
+    client: web::Data<reqwest::Client>,
+    port: u16,
+) -> std::io::Result<(Server, u16)> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(client.clone())
+            .app_data(shared_state.clone())
+            .service(health_handler)
+            .service(subtitle_handler)
+            .service(remote_handler)
+            .service(proxy_handler)
+    })
+    .bind(("0.0.0.0", port))?;
+    let port = server.addrs().first().map(|a| a.port()).unwrap_or(port);
+    Ok((server.run(), port))
+}
+
+// 请求本机的健康检查接口
+async fn health_check(client: &reqwest::Client, port: u16) -> bool {
+    let url = format!("http://127.0.0.1:{}/healthz", port);
+    matches!(
+        client.get(&url).timeout(HEALTH_CHECK_TIMEOUT).send().await,
+        Ok(resp) if resp.status().is_success()
+    )
+}
+
+/// 运行并监督媒体代理，直到服务被正常关闭（例如 Ctrl-C）
+///
+/// 每次状态变化都会调用 `on_status`；端口变化时调用方需要更新投屏使用的媒体地址。
+pub async fn supervise(
+    shared_state: web::Data<SharedState>,
+    client: web::Data<reqwest::Client>,
+    preferred_port: u16,
+    on_status: impl Fn(ProxyStatus) + Send + 'static,
+) -> std::io::Result<()> {
+    let probe = reqwest::Client::new();
+    let mut bind_failures = 0;
+
+    loop {
+        let port = if bind_failures >= MAX_BIND_FAILURES {
+            0
+        } else {
+            preferred_port
+        };
+        let (server, port) = match start_server(shared_state.clone(), client.clone(), port) {
+            Ok(started) => started,
+            Err(e) => {
+                bind_failures += 1;
+                log::error!("媒体代理绑定端口{}失败: {}", port, e);
+                on_status(ProxyStatus::Down {
+                    message: format!("无法绑定端口{}: {}", port, e),
+                });
+                tokio::time::sleep(RESTART_DELAY).await;
+                continue;
+            }
+        };
+        bind_failures = 0;
+        log::info!("媒体代理已在端口{}上启动", port);
+        on_status(ProxyStatus::Listening { port });
+
+        let handle = server.handle();
+        let mut server = tokio::spawn(server);
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        interval.tick().await;
+        let mut failed_checks = 0;
+
+        let message = loop {
+            tokio::select! {
+                result = &mut server => match result {
+                    // 服务被正常关闭（收到退出信号）
+                    Ok(Ok(())) => return Ok(()),
+                    Ok(Err(e)) => break format!("媒体代理异常退出: {}", e),
+                    Err(e) => break format!("媒体代理任务崩溃: {}", e),
+                },
+                _ = interval.tick() => {
+                    if health_check(&probe, port).await {
+                        failed_checks = 0;
+                        continue;
+                    }
+                    failed_checks += 1;
+                    log::warn!("媒体代理自检失败（第{}次）", failed_checks);
+                    if failed_checks >= MAX_FAILED_CHECKS {
+                        handle.stop(false).await;
+                        break "媒体代理停止响应".to_string();
+                    }
+                }
+            }
+        };
+
+        log::error!("{}，正在重启", message);
+        on_status(ProxyStatus::Down { message });
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
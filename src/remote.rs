@@ -0,0 +1,314 @@
+//! Web 遥控：通过 HTTP 接口控制投屏
+//!
+//! 接口挂在媒体代理上（`POST /api/remote/{action}`），用令牌区分两种角色：
+//! 主持人令牌可以执行全部动作（暂停、继续、跳转、调音量、切歌、切换设备等）；
+//! 访客令牌只能点歌和投票切歌。令牌放在 `Authorization: Bearer <令牌>` 头或 `?token=` 参数中。
+
+use crate::SharedState;
+use crate::config::RemoteConfig;
+use crate::i18n::text;
+use crate::session::CastingSession;
+use crate::tr;
+use crate::volume_curve::VolumeCurveStore;
+use actix_web::{HttpRequest, HttpResponse, post, web};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{Arc, OnceLock};
+
+/// 遥控的角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    Guest,
+}
+
+/// 遥控接口支持的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteAction {
+    /// 当前歌曲（BV号与标题）与音量
+    Status,
+    Pause,
+    Resume,
+    /// 跳转到 `value` 秒
+    Seek,
+    /// 设置音量为 `value`（0-100）
+    Volume,
+    /// 切到下一首
+    Next,
+    /// 把 `url`（B站链接或 BV 号）点进房间
+    AddSong,
+    /// 投票切歌，票数够了才会切
+    VoteSkip,
+    /// 把画面切换到名称、UDN 或“厂商 型号”匹配 `device` 的设备
+    SwitchDevice,
+}
+
+impl RemoteAction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "status" => Some(RemoteAction::Status),
+            "pause" => Some(RemoteAction::Pause),
+            "resume" => Some(RemoteAction::Resume),
+            "seek" => Some(RemoteAction::Seek),
+            "volume" => Some(RemoteAction::Volume),
+            "next" => Some(RemoteAction::Next),
+            "add_song" => Some(RemoteAction::AddSong),
+            "vote_skip" => Some(RemoteAction::VoteSkip),
+            "switch_device" => Some(RemoteAction::SwitchDevice),
+            _ => None,
+        }
+    }
+
+    /// 该角色是否可以执行此动作
+    pub fn allowed_for(&self, role: Role) -> bool {
+        match role {
+            Role::Host => true,
+            Role::Guest => matches!(self, RemoteAction::AddSong | RemoteAction::VoteSkip),
+        }
+    }
+}
+
+/// 主持人与访客令牌
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteTokens {
+    pub host: String,
+    pub guest: String,
+}
+
+impl RemoteTokens {
+    /// 使用配置中的固定令牌，未设置的随机生成
+    pub fn from_config(config: &RemoteConfig) -> Self {
+        Self {
+            host: config.host_token.clone().unwrap_or_else(random_token),
+            guest: config.guest_token.clone().unwrap_or_else(random_token),
+        }
+    }
+
+    /// 令牌对应的角色；两个令牌相同时按主持人处理
+    pub fn role_of(&self, token: &str) -> Option<Role> {
+        if token.is_empty() {
+            None
+        } else if constant_time_eq(token, &self.host) {
+            Some(Role::Host)
+        } else if constant_time_eq(token, &self.guest) {
+            Some(Role::Guest)
+        } else {
+            None
+        }
+    }
+}
+
+// 128 位随机令牌（十六进制），取自操作系统的密码学安全随机数
+fn random_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("无法获取系统随机数");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 比较令牌，耗时与两者在第几个字节不同无关，避免逐字节猜出令牌
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// 已开启的遥控：令牌与被控制的会话
+pub struct Remote {
+    pub tokens: RemoteTokens,
+    pub session: CastingSession,
+}
+
+/// 媒体代理持有的遥控句柄；会话创建后才会设置，未开启遥控时一直为空
+pub type RemoteHandle = Arc<OnceLock<Remote>>;
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteQuery {
+    token: Option<String>,
+    value: Option<u32>,
+    url: Option<String>,
+    device: Option<String>,
+}
+
+// 请求携带的令牌：优先 Authorization 头，其次 token 参数
+fn request_token<'a>(req: &'a HttpRequest, query: &'a RemoteQuery) -> Option<&'a str> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or(query.token.as_deref())
+}
+
+fn error_response(mut builder: actix_web::HttpResponseBuilder, message: &str) -> HttpResponse {
+    builder.json(json!({ "success": false, "message": message }))
+}
+
+/// 遥控接口，需在 `proxy_handler` 之前注册
+#[post("/api/remote/{action}")]
+pub async fn remote_handler(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<RemoteQuery>,
+    shared_state: web::Data<SharedState>,
+) -> HttpResponse {
+    let Some(remote) = shared_state.remote.get() else {
+        return error_response(
+            HttpResponse::ServiceUnavailable(),
+            text("遥控未开启", "Remote control is disabled"),
+        );
+    };
+    let Some(role) = request_token(&req, &query).and_then(|t| remote.tokens.role_of(t)) else {
+        return error_response(
+            HttpResponse::Unauthorized(),
+            text("令牌无效", "Invalid token"),
+        );
+    };
+    let (name,) = path.into_inner();
+    let Some(action) = RemoteAction::from_name(&name) else {
+        return error_response(
+            HttpResponse::NotFound(),
+            text("未知的动作", "Unknown action"),
+        );
+    };
+    if !action.allowed_for(role) {
+        log::info!("拒绝访客的遥控动作: {}", name);
+        return error_response(
+            HttpResponse::Forbidden(),
+            text("访客无权执行该动作", "Guests may not perform this action"),
+        );
+    }
+
+    let session = &remote.session;
+    let result = match action {
+        RemoteAction::Status => {
+            let song = session.playlist().get_song_playing().await;
+            let volume = session.volume().await.ok();
+            let title = match &song {
+                Some(song) => session.song_info(song).await.map(|i| i.display_name()),
+                None => None,
+            };
+            return HttpResponse::Ok().json(json!({
+                "success": true,
+                "song": song,
+                "title": title,
+                "volume": volume,
+            }));
+        }
+        RemoteAction::Pause => session.pause().await,
+        RemoteAction::Resume => session.resume().await,
+        RemoteAction::Next => session.next().await,
+        RemoteAction::AddSong => {
+            let Some(url) = query.url.as_deref().filter(|url| !url.is_empty()) else {
+                return error_response(
+                    HttpResponse::BadRequest(),
+                    text("缺少 url 参数", "Missing url parameter"),
+                );
+            };
+            session
+                .playlist()
+                .add_song(url)
+                .await
+                .map_err(|e| anyhow::anyhow!(e))
+        }
+        RemoteAction::VoteSkip => {
+            // 同一台手机只算一票
+            let voter = req
+                .peer_addr()
+                .map(|addr| format!("remote:{}", addr.ip()))
+                .unwrap_or_else(|| "remote".to_string());
+            session.vote_skip(voter).await;
+            Ok(())
+        }
+        RemoteAction::SwitchDevice => {
+            let Some(pattern) = query.device.as_deref().filter(|p| !p.is_empty()) else {
+                return error_response(
+                    HttpResponse::BadRequest(),
+                    text("缺少 device 参数", "Missing device parameter"),
+                );
+            };
+            switch_device(session, pattern).await
+        }
+        RemoteAction::Seek | RemoteAction::Volume => {
+            let Some(value) = query.value else {
+                return error_response(
+                    HttpResponse::BadRequest(),
+                    text("缺少 value 参数", "Missing value parameter"),
+                );
+            };
+            if action == RemoteAction::Seek {
+                session.seek(value).await
+            } else {
+                session.set_volume(value).await
+            }
+        }
+    };
+    match result {
+        Ok(()) => HttpResponse::Ok().json(json!({ "success": true })),
+        Err(e) => error_response(HttpResponse::InternalServerError(), &e.to_string()),
+    }
+}
+
+// 重新搜索设备并把画面切换到第一个匹配 `pattern` 的设备
+async fn switch_device(session: &CastingSession, pattern: &str) -> anyhow::Result<()> {
+    let current = session.device().udn;
+    let device = session
+        .renderer()
+        .discover_devices()
+        .await
+        .map_err(|e| anyhow::anyhow!(tr!("搜索设备失败: {}", "Device search failed: {}", e)))?
+        .into_iter()
+        .find(|d| d.udn != current && d.matches(pattern))
+        .ok_or_else(|| anyhow::anyhow!(tr!("没有找到设备: {}", "No device found: {}", pattern)))?;
+    session.switch_device(device).await?;
+    // 音量曲线按设备校准，换成新设备的曲线
+    if let Ok(curves) = VolumeCurveStore::load_default() {
+        session.set_volume_curve(curves.get(&session.volume_device().udn));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guest_cannot_control_playback() {
+        let tokens = RemoteTokens::from_config(&RemoteConfig {
+            enabled: true,
+            host_token: Some("host-secret".to_string()),
+            guest_token: None,
+        });
+        assert_eq!(tokens.role_of("host-secret"), Some(Role::Host));
+        assert_eq!(tokens.role_of(&tokens.guest), Some(Role::Guest));
+        assert_eq!(tokens.role_of(""), None);
+        assert_eq!(tokens.role_of("guess"), None);
+
+        for name in ["add_song", "vote_skip"] {
+            let action = RemoteAction::from_name(name).unwrap();
+            assert!(action.allowed_for(Role::Host));
+            assert!(action.allowed_for(Role::Guest));
+        }
+        for name in [
+            "status",
+            "pause",
+            "resume",
+            "seek",
+            "volume",
+            "next",
+            "switch_device",
+        ] {
+            let action = RemoteAction::from_name(name).unwrap();
+            assert!(action.allowed_for(Role::Host));
+            assert!(!action.allowed_for(Role::Guest));
+        }
+
+        // 随机令牌为 128 位，且每次不同
+        let random = RemoteTokens::from_config(&RemoteConfig::default());
+        assert_eq!(random.host.len(), 32);
+        assert_ne!(random.host, random.guest);
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+    }
+}
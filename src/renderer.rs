@@ -0,0 +1,237 @@
+//! 渲染器抽象
+//!
+//! [`CastingSession`](crate::session::CastingSession) 只通过 [`MediaRenderer`] 操作渲染器，
+//! 不直接依赖 DLNA。接入其他后端或在测试中使用模拟渲染器时，实现该 trait 即可，
+//! 不需要改动会话的主循环。
+//!
+//! 核心动作（加载、播放、暂停、停止、跳转、音量、进度、状态）必须实现；
+//! VolumeDB、音效、传输动作查询等可选能力有默认实现，表示“不支持”。
+
+use crate::bilibili_parser::VideoStream;
+use crate::dlna_controller::{
+    DlnaController, DlnaDevice, TransportActions, TransportState, VolumeDbRange,
+};
+use crate::features::FeatureFlags;
+use crate::session::AudioSetting;
+use anyhow::{Result, bail};
+use std::future::Future;
+
+/// 可被会话驱动的媒体渲染器
+///
+/// 所有动作都带上目标设备，同一个渲染器实现可以服务多个设备。
+pub trait MediaRenderer: Clone + Send + Sync + 'static {
+    /// 加载媒体（不自动播放），`title` 用于元数据，`subtitle_url` 为外挂字幕的完整地址
+    fn load(
+        &self,
+        device: &DlnaDevice,
+        title: &str,
+        media_url: &str,
+        subtitle_url: Option<&str>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// 加载纯音频（用于音视频分离时的音箱）；不支持时返回错误
+    fn load_audio(
+        &self,
+        _device: &DlnaDevice,
+        _title: &str,
+        _media_url: &str,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async { bail!("渲染器不支持纯音频") }
+    }
+
+    fn play(&self, device: &DlnaDevice) -> impl Future<Output = Result<()>> + Send;
+
+    fn pause(&self, device: &DlnaDevice) -> impl Future<Output = Result<()>> + Send;
+
+    fn stop(&self, device: &DlnaDevice) -> impl Future<Output = Result<()>> + Send;
+
+    /// 清除媒体地址（退出时可选），避免电视停在上一首的画面上；不支持时返回错误
+    fn clear(&self, _device: &DlnaDevice) -> impl Future<Output = Result<()>> + Send {
+        async { bail!("渲染器不支持清除媒体地址") }
+    }
+
+    /// 跳转到 `position_secs` 秒
+    fn seek(
+        &self,
+        device: &DlnaDevice,
+        position_secs: u32,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// 设置设备音量（0-100）
+    fn set_volume(
+        &self,
+        device: &DlnaDevice,
+        volume: u32,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// 读取设备音量（0-100）
+    fn volume(&self, device: &DlnaDevice) -> impl Future<Output = Result<u32>> + Send;
+
+    /// 播放进度，返回 (当前时间秒, 总时长秒)
+    fn position(&self, device: &DlnaDevice) -> impl Future<Output = Result<(u32, u32)>> + Send;
+
+    /// 传输状态
+    fn state(&self, device: &DlnaDevice) -> impl Future<Output = Result<TransportState>> + Send;
+
+    /// 运行时功能开关（与会话、媒体代理共享）
+    fn features(&self) -> &FeatureFlags;
+
+    /// 是否为模拟渲染器（dry-run）：不解析播放地址、不获取歌词，时长取渲染器报告的值
+    fn is_simulated(&self) -> bool {
+        false
+    }
+
+    /// 是否应把 CDN 直链直接交给该设备，而不是经过本地代理
+    fn prefers_direct_link(&self, _device: &DlnaDevice) -> bool {
+        false
+    }
+
+    /// 是否应把该视频经 ffmpeg 转码（H.264/AAC MP4）后再交给设备
+    fn needs_transcode(
+        &self,
+        _device: &DlnaDevice,
+        _stream: &VideoStream,
+    ) -> impl Future<Output = bool> + Send {
+        async { false }
+    }
+
+    /// 当前允许的传输动作；不支持查询时返回错误，会话视为全部允许
+    fn transport_actions(
+        &self,
+        _device: &DlnaDevice,
+    ) -> impl Future<Output = Result<TransportActions>> + Send {
+        async { bail!("渲染器不支持查询传输动作") }
+    }
+
+    /// 分贝音量范围；不支持 VolumeDB 时返回错误
+    fn volume_db_range(
+        &self,
+        _device: &DlnaDevice,
+    ) -> impl Future<Output = Result<VolumeDbRange>> + Send {
+        async { bail!("渲染器不支持VolumeDB") }
+    }
+
+    fn volume_db(&self, _device: &DlnaDevice) -> impl Future<Output = Result<f32>> + Send {
+        async { bail!("渲染器不支持VolumeDB") }
+    }
+
+    fn set_volume_db(
+        &self,
+        _device: &DlnaDevice,
+        _db: f32,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async { bail!("渲染器不支持VolumeDB") }
+    }
+
+    /// 调节音效，`value` 已按 [`AudioSetting`] 的范围裁剪
+    fn set_audio_setting(
+        &self,
+        _device: &DlnaDevice,
+        setting: AudioSetting,
+        _value: i32,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move { bail!("渲染器不支持调节{:?}", setting) }
+    }
+}
+
+impl MediaRenderer for DlnaController {
+    async fn load(
+        &self,
+        device: &DlnaDevice,
+        title: &str,
+        media_url: &str,
+        subtitle_url: Option<&str>,
+    ) -> Result<()> {
+        Ok(self
+            .set_avtransport_url(device, title, media_url, subtitle_url)
+            .await?)
+    }
+
+    async fn load_audio(&self, device: &DlnaDevice, title: &str, media_url: &str) -> Result<()> {
+        Ok(self
+            .set_avtransport_audio_url(device, title, media_url)
+            .await?)
+    }
+
+    async fn play(&self, device: &DlnaDevice) -> Result<()> {
+        Ok(DlnaController::play(self, device).await?)
+    }
+
+    async fn pause(&self, device: &DlnaDevice) -> Result<()> {
+        Ok(DlnaController::pause(self, device).await?)
+    }
+
+    async fn stop(&self, device: &DlnaDevice) -> Result<()> {
+        Ok(DlnaController::stop(self, device).await?)
+    }
+
+    async fn clear(&self, device: &DlnaDevice) -> Result<()> {
+        Ok(self.clear_avtransport_uri(device).await?)
+    }
+
+    async fn seek(&self, device: &DlnaDevice, position_secs: u32) -> Result<()> {
+        Ok(DlnaController::seek(self, device, position_secs).await?)
+    }
+
+    async fn set_volume(&self, device: &DlnaDevice, volume: u32) -> Result<()> {
+        Ok(DlnaController::set_volume(self, device, volume).await?)
+    }
+
+    async fn volume(&self, device: &DlnaDevice) -> Result<u32> {
+        Ok(self.get_volume(device).await?)
+    }
+
+    async fn position(&self, device: &DlnaDevice) -> Result<(u32, u32)> {
+        Ok(self.get_secs(device).await?)
+    }
+
+    async fn state(&self, device: &DlnaDevice) -> Result<TransportState> {
+        Ok(self.get_transport_info(device).await?)
+    }
+
+    fn features(&self) -> &FeatureFlags {
+        DlnaController::features(self)
+    }
+
+    fn is_simulated(&self) -> bool {
+        self.is_dry_run()
+    }
+
+    fn prefers_direct_link(&self, device: &DlnaDevice) -> bool {
+        DlnaController::prefers_direct_link(self, device)
+    }
+
+    async fn needs_transcode(&self, device: &DlnaDevice, stream: &VideoStream) -> bool {
+        DlnaController::needs_transcode(self, device, stream).await
+    }
+
+    async fn transport_actions(&self, device: &DlnaDevice) -> Result<TransportActions> {
+        Ok(self.get_current_transport_actions(device).await?)
+    }
+
+    async fn volume_db_range(&self, device: &DlnaDevice) -> Result<VolumeDbRange> {
+        Ok(self.get_volume_db_range(device).await?)
+    }
+
+    async fn volume_db(&self, device: &DlnaDevice) -> Result<f32> {
+        Ok(self.get_volume_db(device).await?)
+    }
+
+    async fn set_volume_db(&self, device: &DlnaDevice, db: f32) -> Result<()> {
+        Ok(DlnaController::set_volume_db(self, device, db).await?)
+    }
+
+    async fn set_audio_setting(
+        &self,
+        device: &DlnaDevice,
+        setting: AudioSetting,
+        value: i32,
+    ) -> Result<()> {
+        let result = match setting {
+            AudioSetting::Bass => self.set_bass(device, value).await,
+            AudioSetting::Treble => self.set_treble(device, value).await,
+            AudioSetting::Balance => self.set_balance(device, value).await,
+        };
+        Ok(result?)
+    }
+}
@@ -0,0 +1,2276 @@
+//! 投屏会话：供嵌入方（CLI、Tauri/egui 等 GUI）使用的控制句柄与事件流
+//!
+//! [`CastingSession`] 把“房间歌单 → DLNA 渲染器”的整条流水线封装起来：
+//! 命令通过异步方法下发（[`cast`](CastingSession::cast)、[`pause`](CastingSession::pause)、
+//! [`next`](CastingSession::next)、[`set_volume`](CastingSession::set_volume) 等），
+//! 状态变化通过 [`subscribe`](CastingSession::subscribe) 得到的 [`SessionEvent`] 广播流推送。
+//!
+//! 句柄可以廉价地 `clone`，所有克隆共享同一个会话。
+
+use crate::bilibili_parser::{SongInfo, is_charging_exclusive, is_region_locked};
+use crate::dlna_controller::{
+    DlnaController, DlnaDevice, TransportActions, TransportState, VolumeDbRange,
+};
+use crate::features::Feature;
+use crate::history::{PlaybackHistory, ProxyStats, SongRecord};
+use crate::i18n::text;
+use crate::lyrics::{Lyrics, search_netease_lrc};
+use crate::media_server::{SubtitleStore, audio_path, joined_path, subtitle_path, transcode_path};
+use crate::playlist_manager::{
+    CurrentSong, PlaylistEvent, PlaylistManager, RoomConnection, SongList,
+};
+use crate::proxy_watchdog::ProxyStatus;
+use crate::renderer::MediaRenderer;
+use crate::song_source::{RegisteredSources, SongSource};
+use crate::tr;
+use crate::transcode::{Crossfade, CrossfadeStore};
+use crate::update_check::Release;
+use crate::utils::{retry_async, retry_until_success};
+use crate::volume_curve::VolumeCurve;
+use anyhow::{Result, anyhow, bail};
+use log::{error, info};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, broadcast};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+// 事件广播通道容量；订阅者落后超过该数量时会收到 Lagged 错误并丢弃旧事件
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+// 离歌曲结尾不足该秒数时渲染器停止视为正常播完，不触发自动恢复
+const WATCHDOG_END_MARGIN_SECS: u32 = 5;
+// 两次自动恢复之间的最短间隔，避免渲染器反复拒绝时刷屏
+const WATCHDOG_COOLDOWN: Duration = Duration::from_secs(10);
+// 连续查询进度失败超过该次数视为渲染器离线（如电视断电重启）
+const RENDERER_LOST_RETRIES: usize = 3;
+// 看门狗没有查询传输状态时，每隔该秒数查询一次，以发现电视遥控器等在本程序之外的操作
+const TRANSPORT_STATE_POLL_SECS: u32 = 5;
+
+// 投票切歌的计票窗口
+const SKIP_VOTE_WINDOW: Duration = Duration::from_secs(10);
+
+// 普通音量步进（0-100）
+const VOLUME_STEP: u32 = 5;
+// 微调步进：渲染器支持 VolumeDB 时按分贝调节，否则按 1 级设备音量调节
+const FINE_VOLUME_STEP: u32 = 1;
+const FINE_VOLUME_DB_STEP: f32 = 0.5;
+
+/// 会话事件
+///
+/// 新增变体不视为破坏性变更，匹配时请保留 `_ =>` 分支。
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SessionEvent {
+    /// 房间的当前歌曲发生变化（尚未投屏）
+    SongChanged { song: String },
+    /// 房间歌单（已唱、正在演唱、待唱）发生变化，包括点歌、切歌、调整顺序和删除
+    QueueUpdated { list: SongList },
+    /// 与房间的 WebSocket 连接状态变化（轮询模式下不发出）
+    RoomConnectionStatus { status: RoomConnection },
+    /// 歌曲已成功投送到渲染器并开始播放
+    Casting { song: String },
+    /// 已暂停
+    Paused,
+    /// 已恢复播放
+    Resumed,
+    /// 播放进度（秒）
+    Progress { current_secs: u32, total_secs: u32 },
+    /// 媒体代理已下载到文件的百分比（0-100），在每次进度报告之前发出；
+    /// 直链投送和 dry-run 不经过代理，不发出
+    Buffered { percent: u8 },
+    /// 正在跳转到 `position_secs` 秒；界面可以先把进度显示到目标位置，不必等渲染器响应
+    Seeking { position_secs: u32 },
+    /// 渲染器音量（0-100）
+    VolumeChanged { volume: u32 },
+    /// 渲染器音量（dB），仅在按分贝微调时发出
+    VolumeDbChanged { db: f32 },
+    /// 功能开关被切换
+    FeatureToggled { feature: Feature, enabled: bool },
+    /// 音效设置已修改
+    AudioSettingChanged { setting: AudioSetting, value: i32 },
+    /// 渲染器当前允许的传输动作，界面可据此禁用暂停/跳转等按钮
+    TransportActions(TransportActions),
+    /// 审核模式下有新歌等待主持人同意
+    ApprovalRequested { song: String },
+    /// 有人投票切歌，当前 `votes` 票，需要 `needed` 票；票数够了随即切歌
+    SkipVoted { votes: u32, needed: u32 },
+    /// 与房间 WebSocket 的心跳往返时间（毫秒），每次收到 pong 时发出
+    RoomLatency { rtt_ms: u32 },
+    /// 主持人拒绝了该歌曲
+    SongRejected { song: String },
+    /// 歌曲无法播放，已让房间切到下一首
+    SongSkipped { song: String, reason: SkipReason },
+    /// 媒体代理不可用（正在自动重启），恢复前投屏会失败
+    ProxyDown { message: String },
+    /// 媒体代理已恢复，`port` 为当前监听端口
+    ProxyRestored { port: u16 },
+    /// 视频实际使用的画质（qn，见 [`quality_name`](crate::bilibili_parser::quality_name)）；
+    /// 直链投送时在加载前发出，经媒体代理时在代理解析出链接后发出
+    QualitySelected { song: String, quality: u32 },
+    /// 渲染器在播放中途意外停止（如电视弹窗），正在从 `position_secs` 处重新投送
+    Recovering { song: String, position_secs: u32 },
+    /// 渲染器无法访问（断电、重启或断网），恢复后会自动续播
+    RendererLost,
+    /// 渲染器重新上线
+    RendererReconnected,
+    /// 渲染器实际的传输状态发生了变化；`expected_playing` 为本程序此刻的预期，
+    /// 两者不一致通常说明有人用电视遥控器操作过
+    TransportStateChanged {
+        state: TransportState,
+        expected_playing: bool,
+    },
+    /// 画面已切换到另一台设备（设备名称）
+    DeviceSwitched { device: String },
+    /// 获取到了歌曲的标题、UP主和时长
+    SongInfo { song: String, info: SongInfo },
+    /// 当前歌曲的歌词已加载，`lines` 为行数
+    LyricsLoaded { song: String, lines: usize },
+    /// 播放进度走到了新的一行歌词，`next` 为下一行（供提词预览）
+    LyricLine {
+        index: usize,
+        text: String,
+        next: Option<String>,
+    },
+    /// 有可用的新版本（启动时的更新检查）
+    UpdateAvailable(Release),
+    /// 可恢复的错误，`operation` 为失败的操作名
+    Error { operation: String, message: String },
+}
+
+/// 歌曲被自动跳过的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// 视频仅限部分地区观看
+    RegionLocked,
+    /// 充电专属视频，只能看试看片段
+    ChargingExclusive,
+}
+
+/// 可调节的音效项（RenderingControl）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioSetting {
+    /// 低音，-10..10
+    Bass,
+    /// 高音，-10..10
+    Treble,
+    /// 左右平衡，-100（左）..100（右）
+    Balance,
+}
+
+// 一次投屏使用的媒体地址
+enum MediaUrl {
+    // 本地媒体代理
+    Proxy,
+    // 本地媒体代理，经 ffmpeg 转码
+    Transcode,
+    // 本地媒体代理，经 ffmpeg 把多个分段合并成一个文件
+    Joined,
+    // B站 CDN 直链
+    Direct(String),
+}
+
+impl MediaUrl {
+    // 渲染器能否在该地址上跳转：转码与合并的输出没有总长度，只能从头播放
+    fn seekable(&self) -> bool {
+        !matches!(self, MediaUrl::Transcode | MediaUrl::Joined)
+    }
+}
+
+// 音视频分离时同步给音箱的动作
+#[derive(Debug, Clone, Copy)]
+enum AudioSync<'a> {
+    Load(&'a str),
+    Play,
+    Pause,
+    Stop,
+    Seek(u32),
+}
+
+/// 主持人审核模式下的待审核/已同意歌曲
+#[derive(Debug, Default)]
+struct ApprovalQueue {
+    pending: VecDeque<String>,
+    approved: HashSet<String>,
+}
+
+/// 投票切歌的计票：窗口内同一个人只算一票
+#[derive(Debug, Default)]
+struct SkipVotes {
+    votes: VecDeque<(Instant, String)>,
+}
+
+impl SkipVotes {
+    // 记一票，返回窗口内的票数；达到所需的 `needed` 票时清空，下一首重新计票
+    fn vote(&mut self, voter: String, needed: u32, now: Instant) -> u32 {
+        self.votes
+            .retain(|(at, who)| now.duration_since(*at) < SKIP_VOTE_WINDOW && *who != voter);
+        self.votes.push_back((now, voter));
+        let count = self.votes.len() as u32;
+        if count >= needed {
+            self.votes.clear();
+        }
+        count
+    }
+}
+
+/// 房间里没有指定分P的多P视频：依次播放全部分P，放完最后一个才切歌
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PageProgress {
+    // 房间里的歌曲（不带分P的代理路径）
+    song: String,
+    // 正在播放的分P，从 0 开始
+    page: u32,
+    pages: u32,
+}
+
+impl PageProgress {
+    // 正在播放的分P的代理路径；第一个分P即房间里的歌曲本身
+    fn path(&self) -> String {
+        match self.page {
+            0 => self.song.clone(),
+            page => format!("{}-page{}", self.song, page),
+        }
+    }
+}
+
+/// 渲染器的实际状态是否符合预期；过渡中和厂商自定义的状态不算不一致
+pub fn state_matches(state: &TransportState, expected_playing: bool) -> bool {
+    match state {
+        TransportState::Playing => expected_playing,
+        TransportState::PausedPlayback
+        | TransportState::Stopped
+        | TransportState::NoMediaPresent => !expected_playing,
+        TransportState::Transitioning | TransportState::Other(_) => true,
+    }
+}
+
+/// 投屏会话句柄
+///
+/// 渲染器后端由类型参数 `R` 决定，默认是 DLNA（含 Chromecast）控制器。
+#[derive(Clone)]
+pub struct CastingSession<R = DlnaController> {
+    renderer: R,
+    // 解析播放地址、歌曲信息和字幕的来源，默认为注册表中的全部来源
+    source: Arc<dyn SongSource>,
+    // 画面所在的设备；切换设备（见 switch_device）后所有克隆一起生效
+    device: Arc<std::sync::RwLock<DlnaDevice>>,
+    // 音视频分离时播放声音的设备（音箱/回音壁）；音量命令作用于该设备
+    audio_device: Option<DlnaDevice>,
+    playlist: Arc<PlaylistManager>,
+    server_ip: IpAddr,
+    // 媒体代理重启后可能换到新端口，见 report_proxy_status
+    server_port: Arc<AtomicU16>,
+    proxy_healthy: Arc<AtomicBool>,
+    transport_actions: Arc<Mutex<TransportActions>>,
+    // 最近一次查询到的渲染器实际传输状态（GetTransportInfo）
+    transport_state: Arc<Mutex<Option<TransportState>>>,
+    // 当前歌曲是否以直链投送；直链不经过代理，时长只能取渲染器报告的值
+    direct_link: Arc<AtomicBool>,
+    // 渲染器此刻应当在播放；用户暂停或正在切歌时为 false，看门狗据此判断停止是否意外
+    expect_playing: Arc<AtomicBool>,
+    // Some 表示开启了主持人审核模式
+    approval: Option<Arc<Mutex<ApprovalQueue>>>,
+    // 控制台切歌前是否需要确认
+    confirm_skip: bool,
+    // 正在重放的上一首（代理路径）；放完后回到房间当前的歌曲，而不是切歌
+    replaying: Arc<Mutex<Option<String>>>,
+    // 当前歌曲是多P视频时的分P进度
+    pages: Arc<Mutex<Option<PageProgress>>>,
+    // 投票切歌所需的票数，0 表示不接受投票
+    skip_votes_needed: u32,
+    skip_votes: Arc<Mutex<SkipVotes>>,
+    // 媒体服务提供的字幕；有对应字幕的歌曲投屏时会附带字幕地址
+    subtitles: Option<SubtitleStore>,
+    history: Arc<Mutex<PlaybackHistory>>,
+    // 当前歌曲的歌词，键为代理路径
+    lyrics: Arc<Mutex<Option<(String, Lyrics)>>>,
+    // 已获取的歌曲信息（标题、UP主），键为代理路径
+    song_info: Arc<Mutex<HashMap<String, SongInfo>>>,
+    // GetVolumeDBRange 的结果，首次微调音量时查询，切换设备后重新查询；
+    // 内层 None 表示渲染器不支持 VolumeDB
+    volume_db_range: Arc<Mutex<Option<Option<VolumeDbRange>>>>,
+    // 界面音量到设备音量的校准曲线
+    volume_curve: Arc<std::sync::RwLock<VolumeCurve>>,
+    // 媒体代理记录的卡顿/降级次数；未设置时历史中这两项恒为0
+    proxy_stats: Option<ProxyStats>,
+    // Some 表示开启了交叉淡化：与媒体代理共享的存储和淡化秒数
+    crossfade: Option<(CrossfadeStore, u32)>,
+    // 上一首自动放完时片尾混进了开头的歌曲（代理路径）
+    crossfaded_into: Arc<Mutex<Option<String>>>,
+    events: broadcast::Sender<SessionEvent>,
+}
+
+impl<R: MediaRenderer> CastingSession<R> {
+    /// 创建会话
+    ///
+    /// `server_ip`/`server_port` 为本地媒体代理的地址，渲染器会从这里拉取视频。
+    pub fn new(
+        renderer: R,
+        device: DlnaDevice,
+        playlist: Arc<PlaylistManager>,
+        server_ip: IpAddr,
+        server_port: u16,
+    ) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            renderer,
+            source: Arc::new(RegisteredSources),
+            device: Arc::new(std::sync::RwLock::new(device)),
+            audio_device: None,
+            playlist,
+            server_ip,
+            server_port: Arc::new(AtomicU16::new(server_port)),
+            proxy_healthy: Arc::new(AtomicBool::new(true)),
+            transport_actions: Arc::new(Mutex::new(TransportActions::ALL)),
+            transport_state: Default::default(),
+            direct_link: Arc::new(AtomicBool::new(false)),
+            expect_playing: Arc::new(AtomicBool::new(false)),
+            approval: None,
+            confirm_skip: false,
+            replaying: Default::default(),
+            pages: Default::default(),
+            skip_votes_needed: 0,
+            skip_votes: Default::default(),
+            subtitles: None,
+            history: Default::default(),
+            lyrics: Default::default(),
+            song_info: Default::default(),
+            volume_db_range: Default::default(),
+            volume_curve: Default::default(),
+            proxy_stats: None,
+            crossfade: None,
+            crossfaded_into: Default::default(),
+            events,
+        }
+    }
+
+    /// 为另一个房间创建会话，投到 `device`（同时跟随多个房间时使用）
+    ///
+    /// 渲染器后端、媒体代理、字幕和审核模式与本会话相同；歌单、播放历史和事件流是新的，
+    /// 音视频分离和音量曲线不继承。
+    pub fn for_room(&self, device: DlnaDevice, playlist: Arc<PlaylistManager>) -> Self {
+        let mut session = Self::new(
+            self.renderer.clone(),
+            device,
+            playlist,
+            self.server_ip,
+            self.server_port(),
+        )
+        .with_approval(self.approval.is_some())
+        .with_skip_confirmation(self.confirm_skip)
+        .with_skip_votes(self.skip_votes_needed);
+        session.proxy_healthy = Arc::new(AtomicBool::new(self.proxy_healthy()));
+        session.subtitles = self.subtitles.clone();
+        session.proxy_stats = self.proxy_stats.clone();
+        session.source = self.source.clone();
+        session.crossfade = self.crossfade.clone();
+        session
+    }
+
+    /// 所有歌曲都由 `source` 解析，不再查找注册表（测试中用于离线投屏）
+    pub fn with_song_source(mut self, source: Arc<dyn SongSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// 音视频分离：声音投到 `device`（纯音频流），画面仍投到会话的设备并静音
+    ///
+    /// 播放、暂停、跳转会同步发给两台设备，音量与音效命令作用于音箱。
+    pub fn with_audio_device(mut self, device: DlnaDevice) -> Self {
+        self.audio_device = Some(device);
+        self
+    }
+
+    /// 音量、音效命令作用的设备：音视频分离时为音箱，否则为会话的设备
+    pub fn volume_device(&self) -> DlnaDevice {
+        match &self.audio_device {
+            Some(device) => device.clone(),
+            None => self.device(),
+        }
+    }
+
+    // 本地服务上某个路径的完整地址
+    fn local_url(&self, path: &str) -> String {
+        format!("http://{}:{}/{}", self.server_ip, self.server_port(), path)
+    }
+
+    // 音视频分离时把动作同步给音箱；音箱失败只记录警告，不影响画面
+    async fn sync_audio(&self, action: AudioSync<'_>) {
+        let Some(audio) = &self.audio_device else {
+            return;
+        };
+        let result = match action {
+            AudioSync::Load(song) => {
+                self.renderer
+                    .load_audio(audio, song, &self.local_url(&audio_path(song)))
+                    .await
+            }
+            AudioSync::Play => self.renderer.play(audio).await,
+            AudioSync::Pause => self.renderer.pause(audio).await,
+            AudioSync::Stop => self.renderer.stop(audio).await,
+            AudioSync::Seek(secs) => self.renderer.seek(audio, secs).await,
+        };
+        if let Err(e) = result {
+            log::warn!("音箱同步{:?}失败: {}", action, e);
+        }
+    }
+
+    /// 开启/关闭主持人审核模式：开启后房间的新歌需要 [`approve`](Self::approve) 后才会投屏
+    pub fn with_approval(mut self, enabled: bool) -> Self {
+        self.approval = enabled.then(|| Arc::new(Mutex::new(ApprovalQueue::default())));
+        self
+    }
+
+    /// 控制台切歌前先确认，见 [`confirms_skip`](Self::confirms_skip)
+    pub fn with_skip_confirmation(mut self, enabled: bool) -> Self {
+        self.confirm_skip = enabled;
+        self
+    }
+
+    /// 接受房间的投票切歌：10 秒内有 `needed` 个人投票时切到下一首，0 表示不接受
+    pub fn with_skip_votes(mut self, needed: u32) -> Self {
+        self.skip_votes_needed = needed;
+        self
+    }
+
+    /// 使用媒体服务的字幕存储：投屏时如果该歌曲有字幕，会通过 DIDL-Lite 一并告诉渲染器
+    pub fn with_subtitles(mut self, subtitles: SubtitleStore) -> Self {
+        self.subtitles = Some(subtitles);
+        self
+    }
+
+    /// 使用媒体代理的统计：卡顿续传和降级次数会计入每首歌的健康分
+    pub fn with_proxy_stats(mut self, stats: ProxyStats) -> Self {
+        self.proxy_stats = Some(stats);
+        self
+    }
+
+    /// 交叉淡化：经转码投送的歌曲自动切到下一首时，片尾最后 `secs` 秒与下一首的开头混音
+    ///
+    /// `crossfades` 为媒体代理的存储（[`SharedState::crossfades`](crate::SharedState::crossfades)），
+    /// 只有下一首同样需要转码时才混音；插歌或手动切歌不受影响。
+    pub fn with_crossfade(mut self, crossfades: CrossfadeStore, secs: u32) -> Self {
+        self.crossfade = Some((crossfades, secs));
+        self
+    }
+
+    /// 使用该渲染器校准过的音量曲线
+    pub fn with_volume_curve(self, curve: VolumeCurve) -> Self {
+        self.set_volume_curve(curve);
+        self
+    }
+
+    /// 替换音量曲线（例如校准完成后），之后的音量操作按新曲线换算
+    pub fn set_volume_curve(&self, curve: VolumeCurve) {
+        *self.volume_curve.write().unwrap() = curve;
+    }
+
+    /// 当前的音量曲线
+    pub fn volume_curve(&self) -> VolumeCurve {
+        self.volume_curve.read().unwrap().clone()
+    }
+
+    /// 播放历史（按投屏先后排列），正在播放的歌曲包含截至目前的统计
+    pub async fn history(&self) -> Vec<SongRecord> {
+        let mut records = self.history.lock().await.records().to_vec();
+        if let (Some(stats), Some(current)) = (&self.proxy_stats, records.last_mut()) {
+            let counters = stats.peek(&current.song);
+            current.stalls += counters.stalls;
+            current.downgrades += counters.downgrades;
+        }
+        records
+    }
+
+    // 开始一条新的历史记录，并把代理统计归入上一首歌
+    async fn start_record(&self, song: &str) {
+        let mut history = self.history.lock().await;
+        if let (Some(stats), Some(last)) = (&self.proxy_stats, history.records().last()) {
+            let last_song = last.song.clone();
+            let counters = stats.take(&last_song);
+            if let Some(last) = history.current_mut(&last_song) {
+                last.stalls += counters.stalls;
+                last.downgrades += counters.downgrades;
+            }
+        }
+        history.start(song, self.playlist.current_requester().await);
+    }
+
+    // 歌曲在本地服务上的字幕路径，没有字幕时为 None
+    async fn subtitle_for(&self, song: &str) -> Option<String> {
+        let subtitles = self.subtitles.as_ref()?;
+        subtitles
+            .lock()
+            .await
+            .contains_key(song)
+            .then(|| subtitle_path(song))
+    }
+
+    // 后台获取歌曲的CC字幕作为歌词，不拖慢起播；同时作为外挂字幕供之后的重新投送使用
+    fn spawn_lyrics_fetch(&self, song: &str) {
+        let session = self.clone();
+        let song = song.to_string();
+        tokio::spawn(async move {
+            if session
+                .lyrics
+                .lock()
+                .await
+                .as_ref()
+                .is_some_and(|(loaded, _)| *loaded == song)
+            {
+                return;
+            }
+            let lyrics = match session.source.subtitle(&song).await {
+                Ok(Some(json)) => Lyrics::from_bcc(&json),
+                Ok(None) => None,
+                Err(e) => {
+                    log::debug!("获取歌词失败: {}", e);
+                    None
+                }
+            };
+            // 没有CC字幕时按歌名搜索网易云音乐的 LRC 歌词
+            let lyrics = match lyrics {
+                Some(lyrics) => Some(lyrics),
+                None => session.search_lrc(&song).await,
+            };
+            let Some(lyrics) = lyrics else {
+                info!("没有可用的歌词: {}", song);
+                return;
+            };
+            if let Some(subtitles) = &session.subtitles {
+                subtitles
+                    .lock()
+                    .await
+                    .entry(song.clone())
+                    .or_insert_with(|| lyrics.to_srt());
+            }
+            let lines = lyrics.lines().len();
+            *session.lyrics.lock().await = Some((song.clone(), lyrics));
+            session.emit(SessionEvent::LyricsLoaded { song, lines });
+        });
+    }
+
+    // 后台获取歌曲的标题和UP主，界面拿到后替换掉BV号
+    fn spawn_song_info_fetch(&self, song: &str) {
+        let session = self.clone();
+        let song = song.to_string();
+        tokio::spawn(async move {
+            if let Some(info) = session.fetch_song_info(&song).await {
+                session.emit(SessionEvent::SongInfo { song, info });
+            }
+        });
+    }
+
+    // 获取歌曲信息并缓存，已获取过的直接返回；获取失败时为 None
+    async fn fetch_song_info(&self, song: &str) -> Option<SongInfo> {
+        if let Some(info) = self.song_info.lock().await.get(song).cloned() {
+            return Some(info);
+        }
+        let info = match self.source.info(song).await {
+            Ok(info) => info,
+            Err(e) => {
+                log::debug!("获取歌曲信息失败: {}", e);
+                return None;
+            }
+        };
+        self.song_info
+            .lock()
+            .await
+            .insert(song.to_string(), info.clone());
+        Some(info)
+    }
+
+    // 按歌曲标题搜索 LRC 歌词；标题优先用已获取的歌曲信息
+    async fn search_lrc(&self, song: &str) -> Option<Lyrics> {
+        let info = match self.song_info(song).await {
+            Some(info) => info,
+            None => self.source.info(song).await.ok()?,
+        };
+        match search_netease_lrc(&info.title).await {
+            Ok(lrc) => lrc.as_deref().and_then(Lyrics::from_lrc),
+            Err(e) => {
+                log::debug!("搜索歌词失败: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 已获取的歌曲信息；尚未获取或获取失败时为 None
+    pub async fn song_info(&self, song: &str) -> Option<SongInfo> {
+        self.song_info.lock().await.get(song).cloned()
+    }
+
+    // 按渲染器能力选择媒体地址；获取直链失败时退回本地代理
+    async fn resolve_media_url(&self, song: &str) -> Result<MediaUrl, SkipReason> {
+        if self.renderer.is_simulated() {
+            return Ok(MediaUrl::Proxy);
+        }
+        // 经代理投屏时也先解析一次：取流结果有缓存，代理收到请求时直接复用；
+        // 地区受限、充电专属在投屏前就能发现，不必等渲染器播放失败后反复重试
+        let device = self.device();
+        let direct = self.renderer.prefers_direct_link(&device);
+        let stream = match self.source.resolve(song).await {
+            Ok(stream) => stream,
+            Err(e) if is_region_locked(&e) => return Err(SkipReason::RegionLocked),
+            Err(e) if is_charging_exclusive(&e) => return Err(SkipReason::ChargingExclusive),
+            Err(e) => {
+                if direct {
+                    log::warn!("获取直链失败，改用本地代理: {}", e);
+                }
+                return Ok(MediaUrl::Proxy);
+            }
+        };
+        if self.renderer.needs_transcode(&device, &stream).await {
+            info!("渲染器不支持该视频的格式，经 ffmpeg 转码后投送: {}", song);
+            return Ok(MediaUrl::Transcode);
+        }
+        // 多段视频只能由代理合并，直链只有第一段，歌会放到一半就断
+        if stream.segments.len() > 1 {
+            info!(
+                "视频有{}个分段，经本地代理合并后投送",
+                stream.segments.len()
+            );
+            return Ok(MediaUrl::Joined);
+        }
+        if !direct {
+            return Ok(MediaUrl::Proxy);
+        }
+        if stream.quality > 0 {
+            self.emit(SessionEvent::QualitySelected {
+                song: song.to_string(),
+                quality: stream.quality,
+            });
+        }
+        Ok(MediaUrl::Direct(stream.segments[0].url.clone()))
+    }
+
+    // 歌曲无法播放：通知界面并让房间切到下一首；重放上一首时只停在这里，不动房间歌单
+    async fn skip_unplayable(&self, song: &str, reason: SkipReason) {
+        log::warn!("歌曲无法播放（{:?}），跳过: {}", reason, song);
+        self.emit(SessionEvent::SongSkipped {
+            song: song.to_string(),
+            reason,
+        });
+        if self.replaying.lock().await.take().is_some() {
+            return;
+        }
+        if let Err(e) = self.next().await {
+            error!("跳过无法播放的歌曲失败: {}", e);
+            self.emit_error(text("切歌", "Skip"), e);
+        }
+    }
+
+    // 加载媒体：代理地址或直链，字幕总是由本地服务提供
+    async fn set_media(&self, song: &str, media: &MediaUrl, subtitle: Option<&str>) -> Result<()> {
+        self.direct_link
+            .store(matches!(media, MediaUrl::Direct(_)), Ordering::Release);
+        if let Some((store, _)) = &self.crossfade {
+            match media {
+                MediaUrl::Transcode => self.prepare_crossfade(store, song).await,
+                _ => {
+                    store.lock().unwrap().remove(song);
+                }
+            }
+        }
+        let media_url = match media {
+            MediaUrl::Proxy => self.local_url(song),
+            MediaUrl::Transcode => self.local_url(&transcode_path(song)),
+            MediaUrl::Joined => self.local_url(&joined_path(song)),
+            MediaUrl::Direct(url) => url.clone(),
+        };
+        let subtitle_url = subtitle.map(|path| self.local_url(path));
+        self.renderer
+            .load(&self.device(), song, &media_url, subtitle_url.as_deref())
+            .await
+    }
+
+    // 转码投送前写入交叉淡化：上一首自动放完切到这首时，跳过已混进片尾的开头；
+    // 房间的下一首同样需要转码时，片尾与它的开头混音
+    async fn prepare_crossfade(&self, store: &CrossfadeStore, song: &str) {
+        let skip_intro = self.crossfaded_into.lock().await.as_deref() == Some(song);
+        let next = self.crossfade_next(song).await;
+        if let Some(next) = &next {
+            info!("{} 的片尾将与下一首 {} 交叉淡化", song, next);
+        }
+        store
+            .lock()
+            .unwrap()
+            .insert(song.to_string(), Crossfade { next, skip_intro });
+    }
+
+    // 片尾要混音的下一首：只在放房间的当前歌曲（不是重放或多P视频）时，且下一首也经转码投送
+    async fn crossfade_next(&self, song: &str) -> Option<String> {
+        if self.replaying.lock().await.is_some() || self.pages.lock().await.is_some() {
+            return None;
+        }
+        if self.playlist.get_song_playing().await.as_deref() != Some(song) {
+            return None;
+        }
+        let next = self
+            .playlist
+            .song_list()
+            .await
+            .pending
+            .first()?
+            .song
+            .clone();
+        let stream = self.source.resolve(&next).await.ok()?;
+        self.renderer
+            .needs_transcode(&self.device(), &stream)
+            .await
+            .then_some(next)
+    }
+
+    // 自动切歌前调用：刚放完的歌曲片尾已混进下一首的开头，下一首转码时跳过这段
+    async fn finish_crossfade(&self, finished: &str) {
+        let Some((store, _)) = &self.crossfade else {
+            return;
+        };
+        let next = store
+            .lock()
+            .unwrap()
+            .get(finished)
+            .and_then(|crossfade| crossfade.next.clone());
+        *self.crossfaded_into.lock().await = next;
+    }
+
+    // 转码时跳过的开头秒数，播放时长相应缩短
+    fn skipped_intro_secs(&self, song: &str) -> u32 {
+        match &self.crossfade {
+            Some((store, secs))
+                if store
+                    .lock()
+                    .unwrap()
+                    .get(song)
+                    .is_some_and(|crossfade| crossfade.skip_intro) =>
+            {
+                *secs
+            }
+            _ => 0,
+        }
+    }
+
+    /// 是否开启了主持人审核模式
+    pub fn requires_approval(&self) -> bool {
+        self.approval.is_some()
+    }
+
+    /// 界面切歌前是否应先请用户确认（会话本身不拦截 [`next`](Self::next)）
+    pub fn confirms_skip(&self) -> bool {
+        self.confirm_skip
+    }
+
+    /// 房间里或遥控上有人投票切歌（`voter` 区分投票人）；票数够了切到下一首
+    pub async fn vote_skip(&self, voter: String) {
+        let needed = self.skip_votes_needed;
+        if needed == 0 {
+            return;
+        }
+        let votes = self
+            .skip_votes
+            .lock()
+            .await
+            .vote(voter, needed, Instant::now());
+        info!("投票切歌: {}/{}", votes, needed);
+        self.emit(SessionEvent::SkipVoted { votes, needed });
+        if votes >= needed
+            && let Err(e) = self.next().await
+        {
+            self.emit_error(text("投票切歌", "Vote skip"), e);
+        }
+    }
+
+    /// 等待审核的歌曲，按到达顺序排列
+    pub async fn pending_songs(&self) -> Vec<String> {
+        match &self.approval {
+            Some(approval) => approval.lock().await.pending.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// 同意一首待审核的歌曲；如果它正是房间当前的歌曲则立即投屏
+    pub async fn approve(&self, song: &str) -> Result<()> {
+        let Some(approval) = &self.approval else {
+            bail!(tr!("未开启审核模式", "Approval mode is not enabled"));
+        };
+        {
+            let mut approval = approval.lock().await;
+            let Some(pos) = approval.pending.iter().position(|s| s == song) else {
+                bail!(tr!(
+                    "{} 不在待审核列表中",
+                    "{} is not awaiting approval",
+                    song
+                ));
+            };
+            approval.pending.remove(pos);
+            approval.approved.insert(song.to_string());
+        }
+        info!("主持人同意了歌曲: {}", song);
+
+        if self.playlist.get_song_playing().await.as_deref() == Some(song) {
+            self.cast(song).await;
+        }
+        Ok(())
+    }
+
+    /// 拒绝一首待审核的歌曲；如果它正是房间当前的歌曲则让房间切到下一首
+    pub async fn reject(&self, song: &str) -> Result<()> {
+        let Some(approval) = &self.approval else {
+            bail!(tr!("未开启审核模式", "Approval mode is not enabled"));
+        };
+        {
+            let mut approval = approval.lock().await;
+            let Some(pos) = approval.pending.iter().position(|s| s == song) else {
+                bail!(tr!(
+                    "{} 不在待审核列表中",
+                    "{} is not awaiting approval",
+                    song
+                ));
+            };
+            approval.pending.remove(pos);
+        }
+        info!("主持人拒绝了歌曲: {}", song);
+        self.emit(SessionEvent::SongRejected {
+            song: song.to_string(),
+        });
+
+        if self.playlist.get_song_playing().await.as_deref() == Some(song) {
+            self.next().await?;
+        }
+        Ok(())
+    }
+
+    // 房间当前歌曲变化：审核模式下未同意的歌曲进入待审核列表，其余直接投屏
+    async fn on_song_change(&self, song: CurrentSong) {
+        if self.playlist.is_closed() {
+            return;
+        }
+        // 审核、投屏、历史和媒体代理都以代理路径区分歌曲
+        let song = song.path();
+        self.replaying.lock().await.take();
+        self.pages.lock().await.take();
+        // 插歌或手动切歌换来的歌曲，开头没有混进上一首
+        {
+            let mut crossfaded_into = self.crossfaded_into.lock().await;
+            if crossfaded_into.as_deref() != Some(song.as_str()) {
+                *crossfaded_into = None;
+            }
+        }
+        self.emit(SessionEvent::SongChanged { song: song.clone() });
+        if !self.renderer.is_simulated() {
+            self.detect_pages(&song).await;
+        }
+
+        if let Some(approval) = &self.approval {
+            let mut approval = approval.lock().await;
+            if !approval.approved.contains(&song) {
+                if !approval.pending.contains(&song) {
+                    approval.pending.push_back(song.clone());
+                }
+                drop(approval);
+                info!("歌曲等待主持人审核: {}", song);
+                // 待审核列表显示标题用，不是正在播放的歌曲，不发出 SongInfo 事件
+                if !self.renderer.is_simulated() {
+                    let session = self.clone();
+                    let song = song.clone();
+                    tokio::spawn(async move { session.fetch_song_info(&song).await });
+                }
+                self.expect_playing.store(false, Ordering::Release);
+                self.emit(SessionEvent::ApprovalRequested { song });
+                return;
+            }
+        }
+
+        self.cast(&song).await;
+    }
+
+    // 房间里的歌曲没有指定分P且视频有多个分P时，记下分P数，放完一个接着放下一个
+    async fn detect_pages(&self, song: &str) {
+        match self.source.page_count(song).await {
+            Ok(pages) if pages > 1 => {
+                // 查询期间房间可能已经换了歌
+                if self.playlist.get_song_playing().await.as_deref() == Some(song) {
+                    info!("{} 有{}个分P，将依次播放", song, pages);
+                    *self.pages.lock().await = Some(PageProgress {
+                        song: song.to_string(),
+                        page: 0,
+                        pages,
+                    });
+                }
+            }
+            Ok(_) => {}
+            Err(e) => log::debug!("获取分P数量失败: {}", e),
+        }
+    }
+
+    // 电视上实际在放的歌曲：重放中的上一首、多P视频的当前分P，否则为房间的当前歌曲
+    async fn casting_song(&self, playing: Option<String>) -> Option<String> {
+        if let Some(replaying) = self.replaying.lock().await.clone() {
+            return Some(replaying);
+        }
+        match self.pages.lock().await.as_ref() {
+            Some(progress) if playing.as_deref() == Some(progress.song.as_str()) => {
+                Some(progress.path())
+            }
+            _ => playing,
+        }
+    }
+
+    // 多P视频还有下一个分P时前进一个分P并返回它的代理路径
+    async fn advance_page(&self, playing: &str) -> Option<String> {
+        let mut pages = self.pages.lock().await;
+        let progress = pages.as_mut().filter(|p| p.song == playing)?;
+        if progress.page + 1 >= progress.pages {
+            return None;
+        }
+        progress.page += 1;
+        Some(progress.path())
+    }
+
+    /// 订阅会话事件
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// 会话使用的渲染器后端，例如用来重新搜索设备
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    /// 当前使用的渲染器
+    pub fn device(&self) -> DlnaDevice {
+        self.device.read().unwrap().clone()
+    }
+
+    /// 会话绑定的房间歌单
+    pub fn playlist(&self) -> &Arc<PlaylistManager> {
+        &self.playlist
+    }
+
+    /// 媒体代理所在的本机地址
+    pub fn server_ip(&self) -> IpAddr {
+        self.server_ip
+    }
+
+    /// 当前用于投屏的媒体代理端口
+    pub fn server_port(&self) -> u16 {
+        self.server_port.load(Ordering::Acquire)
+    }
+
+    /// 媒体代理是否可用
+    pub fn proxy_healthy(&self) -> bool {
+        self.proxy_healthy.load(Ordering::Acquire)
+    }
+
+    /// 接收媒体代理看门狗的状态；端口变化后，之后的投屏会使用新端口
+    pub fn report_proxy_status(&self, status: ProxyStatus) {
+        match status {
+            ProxyStatus::Listening { port } => {
+                let old_port = self.server_port.swap(port, Ordering::AcqRel);
+                let was_healthy = self.proxy_healthy.swap(true, Ordering::AcqRel);
+                if old_port != port {
+                    log::warn!("媒体代理端口由{}变为{}", old_port, port);
+                }
+                if !was_healthy || old_port != port {
+                    self.emit(SessionEvent::ProxyRestored { port });
+                }
+            }
+            ProxyStatus::Down { message } => {
+                self.proxy_healthy.store(false, Ordering::Release);
+                self.emit(SessionEvent::ProxyDown { message });
+            }
+        }
+    }
+
+    /// 通知界面有可用的新版本
+    pub fn announce_update(&self, release: Release) {
+        info!("新版本 {} 可用: {}", release.version, release.url);
+        self.emit(SessionEvent::UpdateAvailable(release));
+    }
+
+    // 进度走到新的一行歌词时发出 LyricLine
+    async fn emit_lyric(&self, song: &str, current_secs: u32, last_line: &mut Option<usize>) {
+        let lyrics = self.lyrics.lock().await;
+        let Some((_, lyrics)) = lyrics.as_ref().filter(|(loaded, _)| loaded == song) else {
+            return;
+        };
+        let index = lyrics.line_at(current_secs as f64);
+        if index == *last_line {
+            return;
+        }
+        *last_line = index;
+        if let Some(index) = index {
+            let lines = lyrics.lines();
+            self.emit(SessionEvent::LyricLine {
+                index,
+                text: lines[index].text.clone(),
+                next: lines.get(index + 1).map(|line| line.text.clone()),
+            });
+        }
+    }
+
+    fn emit(&self, event: SessionEvent) {
+        // 没有订阅者时 send 会返回错误，忽略即可
+        let _ = self.events.send(event);
+    }
+
+    fn emit_error(&self, operation: &str, message: impl ToString) {
+        self.emit(SessionEvent::Error {
+            operation: operation.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// 把歌曲投送到渲染器：Stop → SetAVTransportURI → Play，每一步失败都会重试直到成功
+    ///
+    /// `song` 为代理路径，例如 `BV1xx411c7mD` 或 `BV1xx411c7mD-page2`。
+    pub async fn cast(&self, song: &str) {
+        let renderer = &self.renderer;
+        let device = &self.device();
+        self.expect_playing.store(false, Ordering::Release);
+        self.start_record(song).await;
+        if !renderer.is_simulated() {
+            self.spawn_song_info_fetch(song);
+            self.spawn_lyrics_fetch(song);
+        }
+        // 三个步骤的总尝试次数，超出3次的部分计为重试
+        let attempts = AtomicU32::new(0);
+
+        // 停止当前播放
+        retry_until_success("停止播放", 500, || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            renderer.stop(device).await.map_err(|e| e.to_string())
+        })
+        .await
+        .ok();
+        self.sync_audio(AudioSync::Stop).await;
+
+        // 设置AVTransport URI
+        let subtitle = self.subtitle_for(song).await;
+        let media = match self.resolve_media_url(song).await {
+            Ok(media) => media,
+            Err(reason) => return self.skip_unplayable(song, reason).await,
+        };
+        retry_until_success("设置AVTransport URI", 500, || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            self.set_media(song, &media, subtitle.as_deref())
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .ok();
+        self.sync_audio(AudioSync::Load(song)).await;
+
+        // 播放：画面和声音同时开始
+        let play_video = retry_until_success("播放", 500, || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            renderer.play(device).await.map_err(|e| e.to_string())
+        });
+        let _ = tokio::join!(play_video, self.sync_audio(AudioSync::Play));
+        // 两台设备都出声会有回声，电视只负责画面
+        if self.audio_device.is_some()
+            && let Err(e) = renderer.set_volume(device, 0).await
+        {
+            log::warn!("电视静音失败: {}", e);
+        }
+
+        if let Some(record) = self.history.lock().await.current_mut(song) {
+            record.retries = attempts.into_inner().saturating_sub(3);
+        }
+
+        self.expect_playing.store(true, Ordering::Release);
+        self.emit(SessionEvent::Casting {
+            song: song.to_string(),
+        });
+        self.refresh_transport_actions().await;
+    }
+
+    // 渲染器意外停止后重新投送当前歌曲，并跳回停止前的位置
+    async fn recover(&self, song: &str, position_secs: u32) -> Result<()> {
+        info!("渲染器意外停止，从{}秒处恢复播放: {}", position_secs, song);
+        self.emit(SessionEvent::Recovering {
+            song: song.to_string(),
+            position_secs,
+        });
+        if let Some(record) = self.history.lock().await.current_mut(song) {
+            record.recoveries += 1;
+        }
+        self.resume_at(song, position_secs).await
+    }
+
+    // 重新设置媒体地址并从 `position_secs` 处播放（画面和声音同时）
+    async fn resume_at(&self, song: &str, position_secs: u32) -> Result<()> {
+        let device = self.device();
+        // 直链可能已过期，重新解析
+        let subtitle = self.subtitle_for(song).await;
+        let media = match self.resolve_media_url(song).await {
+            Ok(media) => media,
+            Err(reason) => {
+                self.skip_unplayable(song, reason).await;
+                return Ok(());
+            }
+        };
+        self.set_media(song, &media, subtitle.as_deref())
+            .await
+            .map_err(|e| {
+                anyhow!(tr!(
+                    "设置AVTransport URI失败: {}",
+                    "Failed to set the AVTransport URI: {}",
+                    e
+                ))
+            })?;
+        self.sync_audio(AudioSync::Load(song)).await;
+        let (result, _) = tokio::join!(
+            self.renderer.play(&device),
+            self.sync_audio(AudioSync::Play)
+        );
+        result.map_err(|e| anyhow!(tr!("播放失败: {}", "Play failed: {}", e)))?;
+        if position_secs > 0 && !media.seekable() {
+            log::warn!("该地址不支持跳转，将从头播放: {}", song);
+        } else if position_secs > 0 {
+            let (result, _) = tokio::join!(
+                self.renderer.seek(&device, position_secs),
+                self.sync_audio(AudioSync::Seek(position_secs))
+            );
+            if let Err(e) = result {
+                // 不支持跳转的渲染器只能从头播放
+                log::warn!("恢复播放时跳转失败，将从头播放: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 渲染器实际的传输状态（最近一次查询的结果），尚未查询过时为 None
+    pub async fn transport_state(&self) -> Option<TransportState> {
+        self.transport_state.lock().await.clone()
+    }
+
+    /// 本程序认为渲染器此刻应当在播放（用户暂停或正在切歌时为 false）
+    pub fn expects_playing(&self) -> bool {
+        self.expect_playing.load(Ordering::Acquire)
+    }
+
+    // 记录查询到的传输状态，变化时发出事件
+    async fn record_transport_state(&self, state: TransportState) {
+        let mut last = self.transport_state.lock().await;
+        if last.as_ref() == Some(&state) {
+            return;
+        }
+        *last = Some(state.clone());
+        drop(last);
+        let expected_playing = self.expects_playing();
+        if !state_matches(&state, expected_playing) {
+            log::warn!(
+                "渲染器实际状态 {:?} 与预期（{}）不一致",
+                state,
+                if expected_playing {
+                    "播放"
+                } else {
+                    "不播放"
+                }
+            );
+        }
+        self.emit(SessionEvent::TransportStateChanged {
+            state,
+            expected_playing,
+        });
+    }
+
+    /// 渲染器当前允许的传输动作（最近一次查询的结果）
+    pub async fn transport_actions(&self) -> TransportActions {
+        *self.transport_actions.lock().await
+    }
+
+    /// 重新查询渲染器允许的传输动作；设备不支持该动作时视为全部允许
+    pub async fn refresh_transport_actions(&self) -> TransportActions {
+        let actions = match self.renderer.transport_actions(&self.device()).await {
+            Ok(actions) => actions,
+            Err(e) => {
+                log::debug!("GetCurrentTransportActions不可用，视为全部允许: {}", e);
+                TransportActions::ALL
+            }
+        };
+        *self.transport_actions.lock().await = actions;
+        self.emit(SessionEvent::TransportActions(actions));
+        actions
+    }
+
+    /// 暂停
+    pub async fn pause(&self) -> Result<()> {
+        if !self.transport_actions().await.pause {
+            bail!(tr!(
+                "渲染器当前不允许暂停",
+                "The renderer does not allow pausing right now"
+            ));
+        }
+        let device = self.device();
+        let (result, _) = tokio::join!(
+            self.renderer.pause(&device),
+            self.sync_audio(AudioSync::Pause)
+        );
+        result.map_err(|e| anyhow!(tr!("暂停失败: {}", "Pause failed: {}", e)))?;
+        self.expect_playing.store(false, Ordering::Release);
+        self.emit(SessionEvent::Paused);
+        Ok(())
+    }
+
+    /// 恢复播放
+    pub async fn resume(&self) -> Result<()> {
+        if !self.transport_actions().await.play {
+            bail!(tr!(
+                "渲染器当前不允许播放",
+                "The renderer does not allow playing right now"
+            ));
+        }
+        let device = self.device();
+        let (result, _) = tokio::join!(
+            self.renderer.play(&device),
+            self.sync_audio(AudioSync::Play)
+        );
+        result.map_err(|e| anyhow!(tr!("播放失败: {}", "Play failed: {}", e)))?;
+        self.expect_playing.store(true, Ordering::Release);
+        self.emit(SessionEvent::Resumed);
+        Ok(())
+    }
+
+    /// 跳转到 `position_secs` 秒
+    pub async fn seek(&self, position_secs: u32) -> Result<()> {
+        if !self.transport_actions().await.seek {
+            bail!(tr!(
+                "渲染器当前不允许跳转",
+                "The renderer does not allow seeking right now"
+            ));
+        }
+        self.emit(SessionEvent::Seeking { position_secs });
+        let device = self.device();
+        let (result, _) = tokio::join!(
+            self.renderer.seek(&device, position_secs),
+            self.sync_audio(AudioSync::Seek(position_secs))
+        );
+        result.map_err(|e| anyhow!(tr!("跳转失败: {}", "Seek failed: {}", e)))
+    }
+
+    /// 立即重连房间的 WebSocket（断线后不必等待退避时间）
+    pub fn reconnect_room(&self) {
+        self.playlist.reconnect_now();
+    }
+
+    /// 立即与房间重新同步歌单和当前歌曲（漏掉 UPDATE 消息时使用），返回房间当前的歌曲；
+    /// 当前歌曲与本地记录的不同时会照常切歌投屏
+    pub async fn resync_room(&self) -> Result<Option<String>> {
+        let song = self.playlist.resync().await.map_err(|e| {
+            anyhow!(tr!(
+                "与房间同步失败: {}",
+                "Failed to resync with the room: {}",
+                e
+            ))
+        })?;
+        Ok(song.as_ref().map(CurrentSong::path))
+    }
+
+    /// 进入另一个房间（如原房间已关闭），`url` 为服务器地址；
+    /// 连接新房间的 WebSocket 失败时退回到轮询模式
+    pub async fn switch_room(&self, url: &str, room_id: String) {
+        if let Err(e) = self.playlist.clone().switch_room(url, room_id).await {
+            error!("WebSocket连接失败: {}，将退回到轮询模式", e);
+            self.start_polling();
+        }
+    }
+
+    /// 重新投屏上一首歌曲，不改动房间歌单；放完后回到房间当前的歌曲（从头播放）
+    pub async fn replay_previous(&self) -> Result<()> {
+        let Some(song) = self.playlist.previous_song().await else {
+            bail!(tr!(
+                "没有可以重放的上一首歌曲",
+                "There is no previous song to replay"
+            ));
+        };
+        let song = song.path();
+        info!("重放上一首: {}", song);
+        *self.replaying.lock().await = Some(song.clone());
+        self.cast(&song).await;
+        Ok(())
+    }
+
+    /// 请求房间切到下一首；新歌曲会通过歌单更新触发投屏
+    pub async fn next(&self) -> Result<()> {
+        // 先记下当前歌曲，房间的更新可能比响应先到
+        let playing = self.playlist.get_song_playing().await;
+        self.playlist.next_song().await.map_err(|e| anyhow!(e))?;
+        if let Some(song) = playing
+            && let Some(record) = self.history.lock().await.current_mut(&song)
+        {
+            record.skipped = true;
+        }
+        Ok(())
+    }
+
+    /// 设置音量（界面音量 0-100，经校准曲线换算成设备音量）
+    pub async fn set_volume(&self, volume: u32) -> Result<()> {
+        let volume = volume.min(100);
+        let device_volume = self.volume_curve.read().unwrap().to_device(volume);
+        self.set_device_volume(device_volume).await?;
+        self.emit(SessionEvent::VolumeChanged { volume });
+        Ok(())
+    }
+
+    /// 直接设置设备音量，不经过校准曲线（校准试听时使用）
+    pub async fn set_device_volume(&self, volume: u32) -> Result<()> {
+        self.renderer
+            .set_volume(&self.volume_device(), volume.min(100))
+            .await
+            .map_err(|e| anyhow!(tr!("设置音量失败: {}", "Failed to set the volume: {}", e)))
+    }
+
+    /// 按步进调高/调低音量；`fine` 为微调，支持 VolumeDB 的功放按 0.5 dB 调节
+    pub async fn step_volume(&self, up: bool, fine: bool) -> Result<()> {
+        if fine && let Some(range) = self.volume_db_range().await {
+            let db = self
+                .renderer
+                .volume_db(&self.volume_device())
+                .await
+                .map_err(|e| anyhow!(tr!("获取音量失败: {}", "Failed to get the volume: {}", e)))?;
+            let step = if up {
+                FINE_VOLUME_DB_STEP
+            } else {
+                -FINE_VOLUME_DB_STEP
+            };
+            let db = range.clamp(db + step);
+            self.renderer
+                .set_volume_db(&self.volume_device(), db)
+                .await
+                .map_err(|e| anyhow!(tr!("设置音量失败: {}", "Failed to set the volume: {}", e)))?;
+            self.emit(SessionEvent::VolumeDbChanged { db });
+            return Ok(());
+        }
+
+        if fine {
+            let device_volume = self
+                .renderer
+                .volume(&self.volume_device())
+                .await
+                .map_err(|e| anyhow!(tr!("获取音量失败: {}", "Failed to get the volume: {}", e)))?;
+            let device_volume = if up {
+                device_volume + FINE_VOLUME_STEP
+            } else {
+                device_volume.saturating_sub(FINE_VOLUME_STEP)
+            };
+            self.set_device_volume(device_volume).await?;
+            let volume = self.volume_curve.read().unwrap().to_ui(device_volume);
+            self.emit(SessionEvent::VolumeChanged { volume });
+            return Ok(());
+        }
+
+        let volume = self.volume().await?;
+        let volume = if up {
+            volume + VOLUME_STEP
+        } else {
+            volume.saturating_sub(VOLUME_STEP)
+        };
+        self.set_volume(volume).await
+    }
+
+    // 渲染器的分贝音量范围，只查询一次
+    async fn volume_db_range(&self) -> Option<VolumeDbRange> {
+        let mut cached = self.volume_db_range.lock().await;
+        if let Some(range) = *cached {
+            return range;
+        }
+        let range = match self.renderer.volume_db_range(&self.volume_device()).await {
+            Ok(range) => Some(range),
+            Err(e) => {
+                log::debug!("渲染器不支持VolumeDB，微调将按音量级别进行: {}", e);
+                None
+            }
+        };
+        *cached = Some(range);
+        range
+    }
+
+    /// 切换运行时功能开关（与控制器、媒体代理共享）
+    pub fn set_feature(&self, feature: Feature, enabled: bool) {
+        self.renderer.features().set(feature, enabled);
+        info!("功能开关 {} -> {}", feature.name(), enabled);
+        self.emit(SessionEvent::FeatureToggled { feature, enabled });
+    }
+
+    /// 功能开关是否开启
+    pub fn feature_enabled(&self, feature: Feature) -> bool {
+        self.renderer.features().is_enabled(feature)
+    }
+
+    /// 调节音效（低音/高音/左右平衡），多用于通过 DLNA 暴露的音箱/回音壁
+    pub async fn set_audio_setting(&self, setting: AudioSetting, value: i32) -> Result<()> {
+        let value = match setting {
+            AudioSetting::Bass | AudioSetting::Treble => value.clamp(-10, 10),
+            AudioSetting::Balance => value.clamp(-100, 100),
+        };
+        self.renderer
+            .set_audio_setting(&self.volume_device(), setting, value)
+            .await
+            .map_err(|e| {
+                anyhow!(tr!(
+                    "设置{:?}失败: {}",
+                    "Failed to set {:?}: {}",
+                    setting,
+                    e
+                ))
+            })?;
+        self.emit(SessionEvent::AudioSettingChanged { setting, value });
+        Ok(())
+    }
+
+    /// 读取当前音量（界面音量，经校准曲线从设备音量换算）
+    pub async fn volume(&self) -> Result<u32> {
+        let device_volume = self
+            .renderer
+            .volume(&self.volume_device())
+            .await
+            .map_err(|e| anyhow!(tr!("获取音量失败: {}", "Failed to get the volume: {}", e)))?;
+        Ok(self.volume_curve.read().unwrap().to_ui(device_volume))
+    }
+
+    /// 结束会话但不操作渲染器：停止跟随房间歌单，看门狗不再自动恢复播放
+    pub fn detach(&self) {
+        info!("正在结束会话");
+        self.playlist.close();
+        self.expect_playing.store(false, Ordering::Release);
+    }
+
+    /// 结束会话：停止跟随房间歌单（等待与房间的连接关闭），停止渲染器（音视频分离时连同音箱），
+    /// `clear_uri` 为 true 时再清除媒体地址
+    ///
+    /// 之后看门狗不会再自动恢复播放；进度监控任务由调用方中止。
+    pub async fn shutdown(&self, clear_uri: bool) {
+        self.detach();
+        let device = self.device();
+        let (result, _, _) = tokio::join!(
+            self.renderer.stop(&device),
+            self.sync_audio(AudioSync::Stop),
+            self.playlist.shutdown()
+        );
+        if let Err(e) = result {
+            log::warn!("退出时停止播放失败: {}", e);
+        }
+        if clear_uri {
+            for device in std::iter::once(&device).chain(&self.audio_device) {
+                if let Err(e) = self.renderer.clear(device).await {
+                    log::warn!("清除{}的媒体地址失败: {}", device.friendly_name, e);
+                }
+            }
+        }
+    }
+
+    /// 把画面切换到另一台设备，房间连接、歌单和播放历史保持不变
+    ///
+    /// 正在播放时先读取旧设备的进度并停止它，再在新设备上重新投送当前歌曲并跳到该位置。
+    /// 音视频分离时音箱不受影响。
+    pub async fn switch_device(&self, device: DlnaDevice) -> Result<()> {
+        let old = self.device();
+        if old.udn == device.udn {
+            bail!(tr!(
+                "已经在使用 {}",
+                "Already using {}",
+                device.friendly_name
+            ));
+        }
+        info!(
+            "切换设备: {} -> {}",
+            old.friendly_name, device.friendly_name
+        );
+        let was_playing = self.expect_playing.swap(false, Ordering::AcqRel);
+        let position_secs = match self.renderer.position(&old).await {
+            Ok((current_secs, _)) => current_secs,
+            Err(e) => {
+                log::warn!("获取旧设备的播放进度失败，将从头播放: {}", e);
+                0
+            }
+        };
+        if let Err(e) = self.renderer.stop(&old).await {
+            log::warn!("停止旧设备失败: {}", e);
+        }
+
+        *self.device.write().unwrap() = device.clone();
+        if self.audio_device.is_none() {
+            *self.volume_db_range.lock().await = None;
+        }
+        self.emit(SessionEvent::DeviceSwitched {
+            device: device.friendly_name.clone(),
+        });
+
+        // 重放中的上一首、多P视频的当前分P都要原样接上，不能换成房间的歌曲
+        let playing = self.playlist.get_song_playing().await;
+        let Some(song) = self.casting_song(playing).await else {
+            return Ok(());
+        };
+        self.resume_at(&song, position_secs).await?;
+        if self.audio_device.is_some()
+            && let Err(e) = self.renderer.set_volume(&device, 0).await
+        {
+            log::warn!("电视静音失败: {}", e);
+        }
+        if !was_playing && let Err(e) = self.renderer.pause(&device).await {
+            log::warn!("切换设备后保持暂停失败: {}", e);
+        }
+        self.expect_playing.store(was_playing, Ordering::Release);
+        self.refresh_transport_actions().await;
+        Ok(())
+    }
+
+    /// 开始跟随房间歌单：优先 WebSocket，失败时退回到 HTTP 轮询
+    pub async fn start(&self) {
+        // 先订阅再连接房间，不会错过第一首歌
+        let mut events = self.playlist.subscribe();
+        let session = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let status = match events.recv().await {
+                    Ok(PlaylistEvent::SongChanged { song }) => {
+                        let session = session.clone();
+                        tokio::spawn(async move {
+                            session.on_song_change(song).await;
+                        });
+                        continue;
+                    }
+                    Ok(PlaylistEvent::QueueUpdated { list }) => {
+                        session.emit(SessionEvent::QueueUpdated { list });
+                        continue;
+                    }
+                    Ok(PlaylistEvent::Connected) => RoomConnection::Connected,
+                    Ok(PlaylistEvent::Disconnected {
+                        attempt,
+                        retry_in_secs,
+                    }) => RoomConnection::Reconnecting {
+                        attempt,
+                        retry_in_secs,
+                    },
+                    Ok(PlaylistEvent::RoomClosed { reason }) => RoomConnection::Closed { reason },
+                    Ok(PlaylistEvent::SkipVote { voter }) => {
+                        let session = session.clone();
+                        tokio::spawn(async move {
+                            session.vote_skip(voter).await;
+                        });
+                        continue;
+                    }
+                    Ok(PlaylistEvent::Latency { rtt_ms }) => {
+                        session.emit(SessionEvent::RoomLatency { rtt_ms });
+                        continue;
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("歌单事件处理不及，丢弃了{}个事件", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                session.emit(SessionEvent::RoomConnectionStatus { status });
+            }
+        });
+
+        match self.playlist.clone().start_websocket_listener().await {
+            Ok(_) => info!("WebSocket监听已启动"),
+            Err(e) => {
+                error!("WebSocket连接失败: {}，将退回到轮询模式", e);
+                self.start_polling();
+            }
+        }
+    }
+
+    // 如果WebSocket连接失败，退回到轮询模式
+    fn start_polling(&self) {
+        self.playlist.start_periodic_update_legacy();
+    }
+
+    /// 启动播放进度监控：每秒查询一次进度，歌曲快结束时自动请求下一首
+    ///
+    /// 同时充当传输状态看门狗：渲染器在歌曲中途变为 STOPPED/NO_MEDIA_PRESENT 时，
+    /// 自动重新投送并跳回最后已知的位置。渲染器断电重启后重新上线时同样处理，
+    /// 不会从头播放。
+    ///
+    /// `duration_cache` 为媒体代理解析出的视频时长（键为代理路径）。
+    pub fn spawn_progress_monitor(
+        &self,
+        duration_cache: Arc<Mutex<HashMap<String, u32>>>,
+    ) -> JoinHandle<()> {
+        let session = self.clone();
+        tokio::spawn(async move {
+            let renderer = &session.renderer;
+            let playlist_manager = &session.playlist;
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            let mut total_secs: u32 = 0;
+            let mut last_song: Option<String> = None;
+            let mut last_position: u32 = 0;
+            let mut last_recovery: Option<Instant> = None;
+            let mut last_lyric: Option<usize> = None;
+            // 当前歌曲经代理拿到的画质是否已经报告过
+            let mut quality_reported = false;
+            // 渲染器离线期间为 true，重新上线后检查是否需要续播
+            let mut renderer_lost = false;
+            // 距上次查询传输状态的秒数
+            let mut state_ticks: u32 = 0;
+            loop {
+                interval.tick().await;
+                // 每次都取当前设备，切换设备后立即跟随
+                let device = &session.device();
+
+                // 首先尝试从缓存中获取总长度
+                let mut cached_total = 0;
+                // 重放和多P视频的后续分P不是房间的当前歌曲，以电视上实际在放的为准
+                let room_song = playlist_manager.get_song_playing().await;
+                let playing = session.casting_song(room_song.clone()).await;
+                if let Some(playing) = &playing {
+                    let cache = duration_cache.lock().await;
+                    if let Some(&d) = cache.get(playing) {
+                        cached_total = d.saturating_sub(session.skipped_intro_secs(playing));
+                    }
+                }
+                if playing != last_song {
+                    last_song = playing.clone();
+                    last_position = 0;
+                    last_lyric = None;
+                    quality_reported = false;
+                }
+
+                // 使用重试逻辑获取播放进度；多次失败说明渲染器离线，下一秒继续探测
+                let result = retry_async("获取播放进度", RENDERER_LOST_RETRIES, 500, || async {
+                    renderer.position(device).await.map_err(|e| e.to_string())
+                })
+                .await;
+
+                match result {
+                    Ok(_) if renderer_lost => {
+                        renderer_lost = false;
+                        info!("渲染器重新上线");
+                        session.emit(SessionEvent::RendererReconnected);
+                        // 重启后的渲染器没有媒体，重新设置地址并跳回断开前的位置
+                        if let Some(song) = &playing
+                            && session.feature_enabled(Feature::TransportWatchdog)
+                            && session.expect_playing.load(Ordering::Acquire)
+                            && matches!(renderer.state(device).await, Ok(state) if state.is_idle())
+                        {
+                            last_recovery = Some(Instant::now());
+                            if let Err(e) = session.recover(song, last_position).await {
+                                error!("重新上线后续播失败: {}", e);
+                                session.emit_error(
+                                    text("重新上线后续播", "Resume after reconnect"),
+                                    e,
+                                );
+                            }
+                        }
+                    }
+                    Ok((current_secs, reported_total)) => {
+                        // 如果从缓存拿到了长度，
+                        if cached_total > 0 {
+                            total_secs = cached_total;
+                            info!("使用缓存的视频时长: {}s", total_secs);
+                        } else if renderer.is_simulated()
+                            || session.direct_link.load(Ordering::Acquire)
+                        {
+                            // dry-run 和直链都不经过代理，时长只能来自模拟时钟/渲染器
+                            total_secs = reported_total;
+                        }
+
+                        let remaining_secs = total_secs.saturating_sub(current_secs);
+
+                        info!(
+                            "获取播放进度成功，当前时间{}秒，总时间{}秒，剩余时间{}秒",
+                            current_secs, total_secs, remaining_secs
+                        );
+                        if let (Some(stats), Some(song)) = (&session.proxy_stats, &playing)
+                            && !renderer.is_simulated()
+                            && !session.direct_link.load(Ordering::Acquire)
+                        {
+                            if !quality_reported && let Some(quality) = stats.quality(song) {
+                                quality_reported = true;
+                                session.emit(SessionEvent::QualitySelected {
+                                    song: song.clone(),
+                                    quality,
+                                });
+                            }
+                            session.emit(SessionEvent::Buffered {
+                                percent: stats.downloaded_percent(song).unwrap_or(0),
+                            });
+                        }
+                        session.emit(SessionEvent::Progress {
+                            current_secs,
+                            total_secs,
+                        });
+                        if let Some(song) = &playing {
+                            session
+                                .emit_lyric(song, current_secs, &mut last_lyric)
+                                .await;
+                        }
+
+                        // 停止后的渲染器常把进度报告为0，只记录有效的位置
+                        if current_secs > 0 {
+                            last_position = current_secs;
+                            if let Some(song) = &playing
+                                && let Some(record) = session.history.lock().await.current_mut(song)
+                            {
+                                record.played_secs = record.played_secs.max(current_secs);
+                                // 第一次报告进度即视为起播
+                                if record.start_latency.is_none() {
+                                    record.start_latency =
+                                        (chrono::Local::now() - record.started_at).to_std().ok();
+                                }
+                            }
+                        }
+                        if let Some(song) = &playing
+                            && session.feature_enabled(Feature::TransportWatchdog)
+                            && session.expect_playing.load(Ordering::Acquire)
+                            && total_secs > 0
+                            && last_position + WATCHDOG_END_MARGIN_SECS < total_secs
+                            && last_recovery.is_none_or(|t| t.elapsed() >= WATCHDOG_COOLDOWN)
+                        {
+                            state_ticks = 0;
+                            match renderer.state(device).await {
+                                Ok(state) if state.is_idle() => {
+                                    session.record_transport_state(state.clone()).await;
+                                    log::warn!("渲染器在播放中途进入 {:?} 状态", state);
+                                    last_recovery = Some(Instant::now());
+                                    if let Err(e) = session.recover(song, last_position).await {
+                                        error!("自动恢复播放失败: {}", e);
+                                        session.emit_error(text("自动恢复播放", "Auto-resume"), e);
+                                    }
+                                    continue;
+                                }
+                                Ok(state) => session.record_transport_state(state).await,
+                                Err(e) => log::debug!("获取传输状态失败: {}", e),
+                            }
+                        }
+                        state_ticks += 1;
+                        if state_ticks >= TRANSPORT_STATE_POLL_SECS {
+                            state_ticks = 0;
+                            match renderer.state(device).await {
+                                Ok(state) => session.record_transport_state(state).await,
+                                Err(e) => log::debug!("获取传输状态失败: {}", e),
+                            }
+                        }
+
+                        // 等待审核或用户暂停时不自动切歌
+                        if remaining_secs <= 2
+                            && total_secs > 0
+                            && session.feature_enabled(Feature::AutoAdvance)
+                            && session.expect_playing.load(Ordering::Acquire)
+                        {
+                            info!(
+                                "剩余时间{}秒，总时间{}秒，准备切歌",
+                                remaining_secs, total_secs
+                            );
+                            let replayed = session.replaying.lock().await.take();
+                            let next_page = match &room_song {
+                                Some(song) if replayed.is_none() => {
+                                    session.advance_page(song).await
+                                }
+                                _ => None,
+                            };
+                            if let Some(replayed) = replayed {
+                                info!("{} 重放完毕，回到房间当前的歌曲", replayed);
+                                if let Some(progress) = session.pages.lock().await.as_mut() {
+                                    progress.page = 0;
+                                }
+                                if let Some(song) = room_song {
+                                    session.cast(&song).await;
+                                }
+                            } else if let Some(next_page) = next_page {
+                                info!("播放下一个分P: {}", next_page);
+                                session.cast(&next_page).await;
+                            } else {
+                                if let Some(song) = &room_song {
+                                    session.finish_crossfade(song).await;
+                                }
+                                // 重试next_song
+                                retry_until_success("下一首歌曲", 500, || async {
+                                    playlist_manager.next_song().await
+                                })
+                                .await
+                                .ok();
+                            }
+                            sleep(Duration::from_secs(5)).await;
+                        }
+                    }
+                    Err(e) if renderer_lost => log::debug!("渲染器仍离线: {}", e),
+                    Err(e) => {
+                        error!("获取播放进度失败，渲染器可能已离线: {}", e);
+                        renderer_lost = true;
+                        session.emit(SessionEvent::RendererLost);
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bilibili_parser::{REGION_LOCKED, VideoCodec, VideoSegment, VideoStream};
+    use futures::FutureExt;
+    use futures::future::BoxFuture;
+
+    fn dry_run_session() -> CastingSession {
+        let playlist = Arc::new(PlaylistManager::new(
+            "http://127.0.0.1:0",
+            "1".to_string(),
+            None,
+        ));
+        CastingSession::new(
+            DlnaController::new_dry_run(),
+            DlnaDevice::dry_run(),
+            playlist,
+            "127.0.0.1".parse().unwrap(),
+            8080,
+        )
+    }
+
+    // 只记录收到的动作的模拟渲染器
+    #[derive(Clone, Default)]
+    struct MockRenderer {
+        calls: Arc<std::sync::Mutex<Vec<String>>>,
+        features: crate::features::FeatureFlags,
+        // 为 true 时所有视频都经转码投送
+        transcode: bool,
+    }
+
+    impl MockRenderer {
+        fn record(&self, call: String) -> Result<()> {
+            self.calls.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    impl MediaRenderer for MockRenderer {
+        async fn load(
+            &self,
+            _device: &DlnaDevice,
+            title: &str,
+            media_url: &str,
+            _subtitle_url: Option<&str>,
+        ) -> Result<()> {
+            self.record(format!("load {} {}", title, media_url))
+        }
+
+        async fn load_audio(
+            &self,
+            device: &DlnaDevice,
+            _title: &str,
+            media_url: &str,
+        ) -> Result<()> {
+            self.record(format!("load_audio {} {}", device.udn, media_url))
+        }
+
+        async fn play(&self, _device: &DlnaDevice) -> Result<()> {
+            self.record("play".to_string())
+        }
+
+        async fn pause(&self, device: &DlnaDevice) -> Result<()> {
+            if device.udn == "uuid:speaker" {
+                return self.record("pause speaker".to_string());
+            }
+            self.record("pause".to_string())
+        }
+
+        async fn stop(&self, _device: &DlnaDevice) -> Result<()> {
+            self.record("stop".to_string())
+        }
+
+        async fn seek(&self, _device: &DlnaDevice, position_secs: u32) -> Result<()> {
+            self.record(format!("seek {}", position_secs))
+        }
+
+        async fn clear(&self, device: &DlnaDevice) -> Result<()> {
+            self.record(format!("clear {}", device.udn))
+        }
+
+        async fn set_volume(&self, _device: &DlnaDevice, volume: u32) -> Result<()> {
+            self.record(format!("volume {}", volume))
+        }
+
+        async fn volume(&self, _device: &DlnaDevice) -> Result<u32> {
+            Ok(50)
+        }
+
+        async fn position(&self, _device: &DlnaDevice) -> Result<(u32, u32)> {
+            Ok((0, 0))
+        }
+
+        async fn state(
+            &self,
+            _device: &DlnaDevice,
+        ) -> Result<crate::dlna_controller::TransportState> {
+            Ok(crate::dlna_controller::TransportState::Stopped)
+        }
+
+        fn features(&self) -> &crate::features::FeatureFlags {
+            &self.features
+        }
+
+        async fn needs_transcode(&self, _device: &DlnaDevice, _stream: &VideoStream) -> bool {
+            self.transcode
+        }
+    }
+
+    // 离线的歌曲来源：每首歌对应一个固定的 CDN 链接，`region_locked` 时取流报地区受限
+    #[derive(Default)]
+    struct MockSource {
+        region_locked: bool,
+    }
+
+    impl SongSource for MockSource {
+        fn name(&self) -> &'static str {
+            "模拟"
+        }
+
+        fn match_url(&self, url: &str) -> Option<String> {
+            Some(url.to_string())
+        }
+
+        fn owns(&self, _song: &str) -> bool {
+            true
+        }
+
+        fn resolve<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>> {
+            let result = if self.region_locked {
+                Err(REGION_LOCKED.to_string())
+            } else {
+                Ok(VideoStream {
+                    quality: 0,
+                    codec: VideoCodec::Avc,
+                    segments: vec![VideoSegment {
+                        url: format!("https://cdn.invalid/{}.mp4", song),
+                        size: 0,
+                        length_ms: 0,
+                        backup_urls: Vec::new(),
+                    }],
+                })
+            };
+            async move { result }.boxed()
+        }
+
+        // 没有歌曲信息，也就不会按歌名搜索歌词
+        fn info<'a>(&'a self, _song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>> {
+            async { Err("模拟来源没有歌曲信息".to_string()) }.boxed()
+        }
+    }
+
+    // 投到模拟渲染器、由离线的 MockSource 解析歌曲的会话，同时返回它跟随的房间歌单
+    fn mock_session(
+        renderer: &MockRenderer,
+    ) -> (CastingSession<MockRenderer>, Arc<PlaylistManager>) {
+        let playlist = Arc::new(PlaylistManager::new(
+            "http://127.0.0.1:0",
+            "1".to_string(),
+            None,
+        ));
+        let session = CastingSession::new(
+            renderer.clone(),
+            DlnaDevice::dry_run(),
+            playlist.clone(),
+            "127.0.0.1".parse().unwrap(),
+            8080,
+        )
+        .with_song_source(Arc::new(MockSource::default()));
+        (session, playlist)
+    }
+
+    #[tokio::test]
+    async fn test_cast_through_mock_renderer() {
+        let renderer = MockRenderer::default();
+        let (session, _) = mock_session(&renderer);
+
+        session.cast("BV1xx411c7mD").await;
+        session.pause().await.unwrap();
+        assert_eq!(
+            *renderer.calls.lock().unwrap(),
+            [
+                "stop",
+                "load BV1xx411c7mD http://127.0.0.1:8080/BV1xx411c7mD",
+                "play",
+                "pause"
+            ]
+        );
+        // 不支持 VolumeDB 的渲染器按音量级别微调
+        session.step_volume(true, true).await.unwrap();
+        assert_eq!(renderer.calls.lock().unwrap().last().unwrap(), "volume 51");
+    }
+
+    #[tokio::test]
+    async fn test_crossfade_skips_mixed_intro() {
+        let renderer = MockRenderer {
+            transcode: true,
+            ..Default::default()
+        };
+        let (session, _) = mock_session(&renderer);
+        let store = CrossfadeStore::default();
+        let session = session.with_crossfade(store.clone(), 5);
+
+        // 上一首自动放完时片尾混进了这首的开头
+        *session.crossfaded_into.lock().await = Some("BV1xx411c7mD".to_string());
+        session.cast("BV1xx411c7mD").await;
+        assert!(
+            renderer
+                .calls
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|call| call.ends_with("/BV1xx411c7mD-h264"))
+        );
+        assert_eq!(
+            store.lock().unwrap().get("BV1xx411c7mD"),
+            Some(&Crossfade {
+                next: None,
+                skip_intro: true
+            })
+        );
+        assert_eq!(session.skipped_intro_secs("BV1xx411c7mD"), 5);
+
+        // 不是自动切到的歌曲从头播放
+        session.cast("BV1ab411c7mD").await;
+        assert_eq!(session.skipped_intro_secs("BV1ab411c7mD"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_region_locked_song_is_skipped() {
+        let renderer = MockRenderer::default();
+        let (session, _) = mock_session(&renderer);
+        let session = session.with_song_source(Arc::new(MockSource {
+            region_locked: true,
+        }));
+        let mut events = session.subscribe();
+        // 重放中的歌曲被跳过时只结束重放，不让房间切歌
+        *session.replaying.lock().await = Some("BV1ab411c7mD".to_string());
+
+        session.cast("BV1ab411c7mD").await;
+        assert_eq!(*renderer.calls.lock().unwrap(), ["stop"]);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::SongSkipped {
+                song: "BV1ab411c7mD".to_string(),
+                reason: SkipReason::RegionLocked,
+            }
+        );
+        assert!(session.replaying.lock().await.is_none());
+    }
+
+    #[test]
+    fn test_skip_votes_within_window() {
+        let mut votes = SkipVotes::default();
+        let start = Instant::now();
+        assert_eq!(votes.vote("小明".to_string(), 3, start), 1);
+        // 同一个人重复投票只算一票
+        assert_eq!(votes.vote("小明".to_string(), 3, start), 1);
+        assert_eq!(votes.vote("小红".to_string(), 3, start), 2);
+        // 超过 10 秒的票作废
+        let later = start + SKIP_VOTE_WINDOW;
+        assert_eq!(votes.vote("小刚".to_string(), 3, later), 1);
+        assert_eq!(votes.vote("小明".to_string(), 3, later), 2);
+        assert_eq!(votes.vote("小红".to_string(), 3, later), 3);
+        // 切歌后重新计票
+        assert_eq!(votes.vote("小刚".to_string(), 3, later), 1);
+    }
+
+    #[tokio::test]
+    async fn test_multi_page_advance() {
+        let session = dry_run_session();
+        *session.pages.lock().await = Some(PageProgress {
+            song: "BV1xx411c7mD".to_string(),
+            page: 0,
+            pages: 3,
+        });
+        let room_song = Some("BV1xx411c7mD".to_string());
+        assert_eq!(session.casting_song(room_song.clone()).await, room_song);
+        // 其他歌曲不受影响
+        assert_eq!(session.advance_page("BV1ab411c7mD").await, None);
+
+        assert_eq!(
+            session.advance_page("BV1xx411c7mD").await.as_deref(),
+            Some("BV1xx411c7mD-page1")
+        );
+        assert_eq!(
+            session.casting_song(room_song).await.as_deref(),
+            Some("BV1xx411c7mD-page1")
+        );
+        assert_eq!(
+            session.advance_page("BV1xx411c7mD").await.as_deref(),
+            Some("BV1xx411c7mD-page2")
+        );
+        // 最后一个分P放完后切歌
+        assert_eq!(session.advance_page("BV1xx411c7mD").await, None);
+    }
+
+    #[test]
+    fn test_state_matches() {
+        assert!(state_matches(&TransportState::Playing, true));
+        // 电视遥控器暂停了，本程序仍认为在播放
+        assert!(!state_matches(&TransportState::PausedPlayback, true));
+        assert!(!state_matches(&TransportState::Playing, false));
+        assert!(state_matches(&TransportState::Transitioning, false));
+    }
+
+    #[tokio::test]
+    async fn test_switch_device_keeps_session() {
+        let renderer = MockRenderer::default();
+        let (session, _) = mock_session(&renderer);
+        let bedroom = DlnaDevice {
+            udn: "uuid:bedroom".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let mut events = session.subscribe();
+
+        session.switch_device(bedroom.clone()).await.unwrap();
+        // 克隆共享同一台设备，没有正在播放的歌曲时只停止旧设备
+        assert_eq!(session.clone().device().udn, "uuid:bedroom");
+        assert_eq!(*renderer.calls.lock().unwrap(), ["stop"]);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(SessionEvent::DeviceSwitched { .. })
+        ));
+        assert!(session.switch_device(bedroom).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_switch_device_keeps_replaying_song() {
+        let renderer = MockRenderer::default();
+        let (session, _) = mock_session(&renderer);
+        *session.replaying.lock().await = Some("BV1ab411c7mD".to_string());
+
+        session
+            .switch_device(DlnaDevice {
+                udn: "uuid:bedroom".to_string(),
+                ..DlnaDevice::dry_run()
+            })
+            .await
+            .unwrap();
+        // 新设备上接着放重放中的上一首，而不是房间的当前歌曲
+        assert!(
+            renderer
+                .calls
+                .lock()
+                .unwrap()
+                .contains(&"load BV1ab411c7mD http://127.0.0.1:8080/BV1ab411c7mD".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_renderer_and_playlist() {
+        let renderer = MockRenderer::default();
+        let (session, playlist) = mock_session(&renderer);
+
+        session.shutdown(true).await;
+        assert!(playlist.is_closed());
+        // 关闭后房间的歌曲变化不再投屏
+        session
+            .on_song_change(CurrentSong::from_path("BV1xx411c7mD"))
+            .await;
+        assert_eq!(
+            *renderer.calls.lock().unwrap(),
+            ["stop", "clear uuid:ktv-casting-dry-run"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_split_audio_follows_transport() {
+        let renderer = MockRenderer::default();
+        let speaker = DlnaDevice {
+            udn: "uuid:speaker".to_string(),
+            ..DlnaDevice::dry_run()
+        };
+        let (session, _) = mock_session(&renderer);
+        let session = session.with_audio_device(speaker);
+
+        session.cast("BV1xx411c7mD").await;
+        session.pause().await.unwrap();
+        let calls = renderer.calls.lock().unwrap().clone();
+        assert!(calls.contains(
+            &"load_audio uuid:speaker http://127.0.0.1:8080/BV1xx411c7mD-audio".to_string()
+        ));
+        // 电视静音，暂停同时发给两台设备
+        assert!(calls.contains(&"volume 0".to_string()));
+        assert!(calls.contains(&"pause".to_string()));
+        assert!(calls.contains(&"pause speaker".to_string()));
+        assert_eq!(session.volume_device().udn, "uuid:speaker");
+    }
+
+    #[test]
+    fn test_proxy_restart_updates_port() {
+        let session = dry_run_session();
+        let mut events = session.subscribe();
+
+        session.report_proxy_status(ProxyStatus::Down {
+            message: "端口被占用".to_string(),
+        });
+        assert!(!session.proxy_healthy());
+
+        session.report_proxy_status(ProxyStatus::Listening { port: 40123 });
+        assert!(session.proxy_healthy());
+        assert_eq!(session.server_port(), 40123);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(SessionEvent::ProxyDown { .. })
+        ));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::ProxyRestored { port: 40123 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fine_volume_step_uses_db() {
+        let session = dry_run_session();
+        let mut events = session.subscribe();
+
+        session.step_volume(true, false).await.unwrap();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SessionEvent::VolumeChanged { volume: 55 }
+        );
+        session.step_volume(false, true).await.unwrap();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SessionEvent::VolumeDbChanged { db: -20.5 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_volume_goes_through_curve() {
+        let session = dry_run_session()
+            .with_volume_curve(VolumeCurve::from_points(vec![(50, 20), (100, 60)]));
+        session.set_volume(75).await.unwrap();
+        assert_eq!(
+            session.renderer.volume(&session.device()).await.unwrap(),
+            40
+        );
+        assert_eq!(session.volume().await.unwrap(), 75);
+    }
+
+    #[tokio::test]
+    async fn test_unapproved_song_waits_for_host() {
+        let session = dry_run_session().with_approval(true);
+        let mut events = session.subscribe();
+
+        session
+            .on_song_change(CurrentSong::from_path("BV1xx411c7mD"))
+            .await;
+        assert_eq!(session.pending_songs().await, vec!["BV1xx411c7mD"]);
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SessionEvent::SongChanged {
+                song: "BV1xx411c7mD".to_string()
+            }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SessionEvent::ApprovalRequested {
+                song: "BV1xx411c7mD".to_string()
+            }
+        );
+
+        session.reject("BV1xx411c7mD").await.unwrap();
+        assert!(session.pending_songs().await.is_empty());
+        assert!(session.reject("BV1xx411c7mD").await.is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! 多房间：同时跟随几个房间（如相邻的两间包厢），每个房间投到各自的渲染器
+//!
+//! 每个房间是一个独立的 [`CastingSession`]，有自己的歌单管理器、设备、播放历史和事件流；
+//! 渲染器后端和媒体代理由所有会话共用（见 [`CastingSession::for_room`]）。
+//! [`SessionManager`] 记录这些会话以及当前选中的一个（界面上的“标签页”），
+//! 并把所有会话的事件合并成一个带序号的广播流。
+
+use crate::session::{CastingSession, SessionEvent};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, broadcast};
+use tokio::task::JoinHandle;
+
+// 合并事件流的容量，与单个会话的事件通道相同
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// 一个房间的会话（界面上的一个标签页）
+#[derive(Clone)]
+pub struct RoomTab {
+    /// 房间链接，用于分享
+    pub room_url: String,
+    pub session: CastingSession,
+}
+
+/// 多个房间会话的管理器
+///
+/// 句柄可以廉价地 `clone`，所有克隆共享同一组会话。序号从 0 开始，按添加顺序排列。
+#[derive(Clone)]
+pub struct SessionManager {
+    tabs: Arc<std::sync::RwLock<Vec<RoomTab>>>,
+    active: Arc<AtomicUsize>,
+    // 媒体代理解析出的视频时长，所有会话的进度监控共用
+    duration_cache: Arc<Mutex<HashMap<String, u32>>>,
+    monitors: Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>,
+    events: broadcast::Sender<(usize, SessionEvent)>,
+}
+
+impl SessionManager {
+    /// `duration_cache` 为媒体代理解析出的视频时长，见
+    /// [`spawn_progress_monitor`](CastingSession::spawn_progress_monitor)
+    pub fn new(duration_cache: Arc<Mutex<HashMap<String, u32>>>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            tabs: Default::default(),
+            active: Default::default(),
+            duration_cache,
+            monitors: Default::default(),
+            events,
+        }
+    }
+
+    /// 添加一个房间会话并转发它的事件，返回序号；之后调用 [`start`](Self::start) 开始跟随房间
+    ///
+    /// 在 `start` 之前订阅合并事件流，就不会错过连接房间时的事件。
+    pub fn add(&self, room_url: String, session: CastingSession) -> usize {
+        let mut session_events = session.subscribe();
+        let index = {
+            let mut tabs = self.tabs.write().unwrap();
+            tabs.push(RoomTab { room_url, session });
+            tabs.len() - 1
+        };
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            loop {
+                match session_events.recv().await {
+                    // 没有订阅者时 send 会返回错误，忽略即可
+                    Ok(event) => {
+                        let _ = events.send((index, event));
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        index
+    }
+
+    /// 开始跟随第 `index` 个房间，并启动它的播放进度监控
+    pub async fn start(&self, index: usize) {
+        let Some(tab) = self.get(index) else {
+            return;
+        };
+        tab.session.start().await;
+        let monitor = tab
+            .session
+            .spawn_progress_monitor(self.duration_cache.clone());
+        self.monitors.lock().unwrap().push(monitor);
+    }
+
+    /// 订阅所有会话的事件，每个事件附带会话的序号
+    pub fn subscribe(&self) -> broadcast::Receiver<(usize, SessionEvent)> {
+        self.events.subscribe()
+    }
+
+    /// 所有房间会话，按序号排列
+    pub fn tabs(&self) -> Vec<RoomTab> {
+        self.tabs.read().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tabs.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<RoomTab> {
+        self.tabs.read().unwrap().get(index).cloned()
+    }
+
+    /// 当前选中的会话的序号
+    pub fn active_index(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// 当前选中的会话；还没有添加任何会话时 panic
+    pub fn active(&self) -> RoomTab {
+        self.get(self.active_index()).expect("还没有添加会话")
+    }
+
+    /// 选中第 `index` 个会话，序号无效时返回 `None`
+    pub fn select(&self, index: usize) -> Option<RoomTab> {
+        let tab = self.get(index)?;
+        self.active.store(index, Ordering::Relaxed);
+        Some(tab)
+    }
+
+    /// 会话进入了新房间后更新它的房间链接
+    pub fn set_room_url(&self, index: usize, room_url: String) {
+        if let Some(tab) = self.tabs.write().unwrap().get_mut(index) {
+            tab.room_url = room_url;
+        }
+    }
+
+    /// 停止所有会话的播放进度监控（退出时调用）
+    pub fn stop_monitors(&self) {
+        for monitor in self.monitors.lock().unwrap().drain(..) {
+            monitor.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlna_controller::{DlnaController, DlnaDevice};
+    use crate::playlist_manager::PlaylistManager;
+    use crate::update_check::Release;
+
+    #[tokio::test]
+    async fn test_events_carry_session_index() {
+        let playlist = Arc::new(PlaylistManager::new(
+            "http://127.0.0.1:0",
+            "1".to_string(),
+            None,
+        ));
+        let first = CastingSession::new(
+            DlnaController::new_dry_run(),
+            DlnaDevice::dry_run(),
+            playlist.clone(),
+            "127.0.0.1".parse().unwrap(),
+            8080,
+        );
+        let second = first.for_room(
+            DlnaDevice::dry_run(),
+            Arc::new(playlist.for_room("http://127.0.0.1:0", "2".to_string())),
+        );
+        let manager = SessionManager::new(Default::default());
+        let mut events = manager.subscribe();
+        assert_eq!(manager.add("http://127.0.0.1:0/1".to_string(), first), 0);
+        assert_eq!(manager.add("http://127.0.0.1:0/2".to_string(), second), 1);
+
+        assert!(manager.select(2).is_none());
+        assert_eq!(manager.active_index(), 0);
+        let tab = manager.select(1).unwrap();
+        assert_eq!(manager.active().room_url, "http://127.0.0.1:0/2");
+
+        // 每个会话有自己的事件流，合并后带上序号
+        let release = Release {
+            version: "v9.9.9".to_string(),
+            url: String::new(),
+            summary: String::new(),
+        };
+        tab.session.announce_update(release.clone());
+        assert_eq!(
+            events.recv().await.unwrap(),
+            (1, SessionEvent::UpdateAvailable(release))
+        );
+    }
+}
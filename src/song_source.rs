@@ -0,0 +1,292 @@
+//! 歌曲来源
+//!
+//! 房间歌单里的每首歌是一个链接。[`SongSource`] 负责认领属于自己的链接，把它转换成歌曲标识
+//! （即媒体代理的路径，如 `BV1xx411c7mD-page2`），再由标识解析出播放地址和显示信息。
+//! 会话和媒体代理只通过这里的注册表查找来源，不直接调用某个网站的接口。
+//!
+//! 内置的来源有网易云音乐（[`NeteaseSource`]）、B站直播（[`BilibiliLiveSource`]）和B站（[`BilibiliSource`]），
+//! B站同时是兜底：其他来源都不认领的链接按B站处理。
+//! 接入其他来源时实现该 trait 并调用 [`register`] 即可。
+//! 会话默认通过 [`RegisteredSources`] 使用注册表，测试中可以换成离线的来源。
+//!
+//! 与 [`MediaRenderer`](crate::renderer::MediaRenderer) 不同，这里的方法返回 `BoxFuture`，
+//! 注册表才能以 trait 对象保存不同的来源。
+
+use crate::bilibili_live::BilibiliLiveSource;
+use crate::bilibili_parser::{
+    SongInfo, VideoStream, get_bilibili_audio_url, get_bilibili_stream, get_bilibili_subtitle,
+    get_page_count, get_song_info, is_valid_bv, parse_song, video_page_url,
+};
+use crate::netease::NeteaseSource;
+use crate::utils::extract_bv_id;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// 一个歌曲来源（网站）
+///
+/// 链接认领、播放地址和显示信息必须实现；纯音频、字幕、分P和分享链接有默认实现，表示“不支持”。
+pub trait SongSource: Send + Sync + 'static {
+    /// 来源名称，用于日志
+    fn name(&self) -> &'static str;
+
+    /// 房间歌单中的链接属于该来源时，返回歌曲标识
+    fn match_url(&self, url: &str) -> Option<String>;
+
+    /// 歌曲标识是否属于该来源
+    fn owns(&self, song: &str) -> bool;
+
+    /// 解析播放地址及画质
+    fn resolve<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>>;
+
+    /// 标题、上传者、时长等显示信息
+    fn info<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>>;
+
+    /// 纯音频地址，音视频分离时投给音箱；不支持时返回错误
+    fn audio_url<'a>(&'a self, _song: &'a str) -> BoxFuture<'a, Result<String, String>> {
+        let name = self.name();
+        async move { Err(format!("{}不支持纯音频", name)) }.boxed()
+    }
+
+    /// 字幕（BCC JSON，见 [`Lyrics::from_bcc`](crate::lyrics::Lyrics::from_bcc)），没有时为 None
+    fn subtitle<'a>(&'a self, _song: &'a str) -> BoxFuture<'a, Result<Option<Value>, String>> {
+        async { Ok(None) }.boxed()
+    }
+
+    /// 没有指定分P的歌曲有几个分P，多于 1 个时依次播放
+    fn page_count<'a>(&'a self, _song: &'a str) -> BoxFuture<'a, Result<u32, String>> {
+        async { Ok(1) }.boxed()
+    }
+
+    /// 分享用的网页链接
+    fn page_url(&self, _song: &str) -> Option<String> {
+        None
+    }
+
+    /// 是否为直播：没有时长，不探测文件头，放到房间切歌为止
+    fn is_live(&self, _song: &str) -> bool {
+        false
+    }
+}
+
+/// B站视频和番剧剧集
+pub struct BilibiliSource;
+
+impl SongSource for BilibiliSource {
+    fn name(&self) -> &'static str {
+        "B站"
+    }
+
+    // 作为兜底认领所有链接，与引入来源之前的行为一致
+    fn match_url(&self, url: &str) -> Option<String> {
+        Some(extract_bv_id(url))
+    }
+
+    fn owns(&self, song: &str) -> bool {
+        let (bv_id, _) = parse_song(song);
+        is_valid_bv(bv_id)
+    }
+
+    fn resolve<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>> {
+        let (bv_id, page) = parse_song(song);
+        get_bilibili_stream(bv_id, page).boxed()
+    }
+
+    fn info<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>> {
+        let (bv_id, page) = parse_song(song);
+        get_song_info(bv_id, page).boxed()
+    }
+
+    fn audio_url<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<String, String>> {
+        let (bv_id, page) = parse_song(song);
+        get_bilibili_audio_url(bv_id, page).boxed()
+    }
+
+    fn subtitle<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<Option<Value>, String>> {
+        let (bv_id, page) = parse_song(song);
+        get_bilibili_subtitle(bv_id, page).boxed()
+    }
+
+    fn page_count<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<u32, String>> {
+        let (bv_id, page) = parse_song(song);
+        // 链接指定了分P时只放这一个
+        if page.is_some() {
+            return async { Ok(1) }.boxed();
+        }
+        get_page_count(bv_id).boxed()
+    }
+
+    fn page_url(&self, song: &str) -> Option<String> {
+        Some(video_page_url(song))
+    }
+}
+
+// 已注册的来源，按匹配顺序排列；B站始终在最后
+static SOURCES: LazyLock<RwLock<Vec<Arc<dyn SongSource>>>> = LazyLock::new(|| {
+    RwLock::new(vec![
+        Arc::new(NeteaseSource),
+        Arc::new(BilibiliLiveSource),
+        Arc::new(BilibiliSource),
+    ])
+});
+
+/// 注册一个来源，排在已注册的来源之前（B站兜底，始终最后匹配）
+pub fn register(source: Arc<dyn SongSource>) {
+    SOURCES.write().unwrap().insert(0, source);
+}
+
+/// 把房间歌单中的链接转换成歌曲标识
+pub fn song_for_url(url: &str) -> String {
+    SOURCES
+        .read()
+        .unwrap()
+        .iter()
+        .find_map(|source| source.match_url(url))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// 歌曲标识所属的来源
+pub fn source_for(song: &str) -> Option<Arc<dyn SongSource>> {
+    SOURCES
+        .read()
+        .unwrap()
+        .iter()
+        .find(|source| source.owns(song))
+        .cloned()
+}
+
+fn find(song: &str) -> Result<Arc<dyn SongSource>, String> {
+    source_for(song).ok_or_else(|| format!("不支持的歌曲: {}", song))
+}
+
+/// 解析歌曲的播放地址及画质
+pub async fn resolve(song: &str) -> Result<VideoStream, String> {
+    find(song)?.resolve(song).await
+}
+
+/// 歌曲的显示信息
+pub async fn info(song: &str) -> Result<SongInfo, String> {
+    find(song)?.info(song).await
+}
+
+/// 歌曲的纯音频地址
+pub async fn audio_url(song: &str) -> Result<String, String> {
+    find(song)?.audio_url(song).await
+}
+
+/// 歌曲的字幕，没有时为 None
+pub async fn subtitle(song: &str) -> Result<Option<Value>, String> {
+    find(song)?.subtitle(song).await
+}
+
+/// 歌曲的分P数量
+pub async fn page_count(song: &str) -> Result<u32, String> {
+    find(song)?.page_count(song).await
+}
+
+/// 歌曲的分享链接；来源不提供时为 None
+pub fn page_url(song: &str) -> Option<String> {
+    source_for(song)?.page_url(song)
+}
+
+/// 注册表中的全部来源：每个请求转给认领该歌曲的来源，没有来源认领时返回错误
+pub struct RegisteredSources;
+
+impl SongSource for RegisteredSources {
+    fn name(&self) -> &'static str {
+        "注册表"
+    }
+
+    fn match_url(&self, url: &str) -> Option<String> {
+        Some(song_for_url(url))
+    }
+
+    fn owns(&self, song: &str) -> bool {
+        source_for(song).is_some()
+    }
+
+    fn resolve<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>> {
+        resolve(song).boxed()
+    }
+
+    fn info<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>> {
+        info(song).boxed()
+    }
+
+    fn audio_url<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<String, String>> {
+        audio_url(song).boxed()
+    }
+
+    fn subtitle<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<Option<Value>, String>> {
+        subtitle(song).boxed()
+    }
+
+    fn page_count<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<u32, String>> {
+        page_count(song).boxed()
+    }
+
+    fn page_url(&self, song: &str) -> Option<String> {
+        page_url(song)
+    }
+
+    fn is_live(&self, song: &str) -> bool {
+        source_for(song).is_some_and(|source| source.is_live(song))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestSource;
+
+    impl SongSource for TestSource {
+        fn name(&self) -> &'static str {
+            "测试"
+        }
+
+        fn match_url(&self, url: &str) -> Option<String> {
+            url.strip_prefix("test://").map(|id| format!("test-{}", id))
+        }
+
+        fn owns(&self, song: &str) -> bool {
+            song.starts_with("test-")
+        }
+
+        fn resolve<'a>(&'a self, _song: &'a str) -> BoxFuture<'a, Result<VideoStream, String>> {
+            async { Err("测试来源没有播放地址".to_string()) }.boxed()
+        }
+
+        fn info<'a>(&'a self, song: &'a str) -> BoxFuture<'a, Result<SongInfo, String>> {
+            async move {
+                Ok(SongInfo {
+                    title: song.to_string(),
+                    uploader: String::new(),
+                    duration_secs: 0,
+                    cover: String::new(),
+                })
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry() {
+        register(Arc::new(TestSource));
+        assert_eq!(song_for_url("test://42"), "test-42");
+        // 其他来源不认领的链接按B站处理
+        assert_eq!(
+            song_for_url("bilibili://video/BV1xx411c7mD?page=2"),
+            "BV1xx411c7mD-page2"
+        );
+        assert_eq!(source_for("BV1xx411c7mD-page2").unwrap().name(), "B站");
+        assert_eq!(info("test-42").await.unwrap().title, "test-42");
+        // 未实现的可选能力使用默认值
+        assert_eq!(page_count("test-42").await, Ok(1));
+        assert!(audio_url("test-42").await.is_err());
+        assert_eq!(page_url("test-42"), None);
+        assert!(source_for("favicon.ico").is_none());
+        assert!(resolve("favicon.ico").await.is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! 实时转码：渲染器不支持原始视频（HEVC/AV1 编码、FLV 封装或过高的 Profile）时，
+//! 媒体代理用 ffmpeg 把它转成 H.264/AAC 的 MP4，边转边发
+//!
+//! 转码的输入是本地代理自己的地址（`http://127.0.0.1:<端口>/<歌曲>`），Referer 和备用链接
+//! 都由代理处理。输出是分片 MP4，没有总长度，渲染器不能按字节跳转。
+//! 多段视频也用 ffmpeg 合并（只换封装、不重新编码），见 [`concat_args`]。
+//! 开启交叉淡化时，片尾与下一首的开头在转码时混音，见 [`crossfade_args`]。
+//! 需要本机装有 ffmpeg，路径见配置的 `[proxy] ffmpeg`。
+
+use crate::bilibili_parser::{VideoCodec, VideoStream};
+use actix_web::web::Bytes;
+use futures_util::StreamExt;
+use futures_util::stream::BoxStream;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::oneshot;
+
+/// 视频的 MIME 类型，按链接判断：FLV、MP3（纯音频的来源）或 MP4
+pub fn stream_mime(stream: &VideoStream) -> &'static str {
+    let url = &stream.segments[0].url;
+    if url.contains(".flv") {
+        "video/x-flv"
+    } else if url.contains(".mp3") {
+        "audio/mpeg"
+    } else {
+        "video/mp4"
+    }
+}
+
+/// 渲染器的 Sink protocolInfo（GetProtocolInfo）能否播放该视频
+///
+/// 每项形如 `http-get:*:video/mp4:DLNA.ORG_PN=AVC_MP4_HP_HD_AAC`，封装格式看第三个字段。
+/// DLNA 没有为 HEVC、AV1 定义 Profile 名，只有同一项的封装格式相符且明确提到该编码时才算支持
+/// （`video/mpeg` 项里的 HEVC 不代表能播放 MP4 封装的 HEVC）。
+pub fn sink_supports(sink: &[String], mime: &str, codec: VideoCodec) -> bool {
+    let keywords: &[&str] = match codec {
+        VideoCodec::Hevc => &["hevc", "h265"],
+        VideoCodec::Av1 => &["av1", "av01"],
+        VideoCodec::Avc | VideoCodec::Unknown => &[],
+    };
+    sink.iter().any(|entry| {
+        let mut fields = entry.trim().splitn(4, ':');
+        let protocol = fields.next().unwrap_or_default();
+        let format = fields.nth(1).unwrap_or_default();
+        let container = protocol.eq_ignore_ascii_case("http-get")
+            && (format == "*" || format.eq_ignore_ascii_case(mime));
+        let entry = entry.to_lowercase();
+        container && (keywords.is_empty() || keywords.iter().any(|keyword| entry.contains(keyword)))
+    })
+}
+
+/// ffmpeg 的参数：读取 `input`，视频转成 H.264 Main、音频转成 AAC，以分片 MP4 写到标准输出
+pub fn ffmpeg_args(input: &str) -> Vec<String> {
+    encode_args(&["-i", input], "0:a:0?")
+}
+
+/// 带交叉淡化的转码参数，淡化时长为 `secs` 秒
+///
+/// `skip_intro` 为 true 时跳过开头的 `secs` 秒（已混进上一首的片尾）；`next` 不为 None 时
+/// 读取下一首的开头 `secs` 秒，与片尾的最后 `secs` 秒混音。只处理声音，画面仍是这首歌的，
+/// 输出时长与原视频相同。
+pub fn crossfade_args(input: &str, secs: u32, skip_intro: bool, next: Option<&str>) -> Vec<String> {
+    let secs = secs.to_string();
+    let mut inputs = Vec::new();
+    if skip_intro {
+        inputs.extend(["-ss", secs.as_str()]);
+    }
+    inputs.extend(["-i", input]);
+    let Some(next) = next else {
+        return encode_args(&inputs, "0:a:0?");
+    };
+    let filter = format!("[0:a:0][1:a:0]acrossfade=d={}[a]", secs);
+    inputs.extend([
+        "-t",
+        secs.as_str(),
+        "-i",
+        next,
+        "-filter_complex",
+        filter.as_str(),
+    ]);
+    encode_args(&inputs, "[a]")
+}
+
+// 转码参数：`inputs` 为输入（及滤镜）选项，声音取自 `audio`
+fn encode_args(inputs: &[&str], audio: &str) -> Vec<String> {
+    let mut args = vec!["-hide_banner", "-loglevel", "error"];
+    args.extend(inputs);
+    args.extend([
+        "-map",
+        "0:v:0?",
+        "-map",
+        audio,
+        "-c:v",
+        "libx264",
+        "-preset",
+        "veryfast",
+        "-profile:v",
+        "main",
+        "-pix_fmt",
+        "yuv420p",
+        "-c:a",
+        "aac",
+        "-b:a",
+        "192k",
+        // 分片 MP4 不用回写文件头，可以边转边发
+        "-movflags",
+        "frag_keyframe+empty_moov+default_base_moof",
+        "-f",
+        "mp4",
+        "pipe:1",
+    ]);
+    args.into_iter().map(str::to_string).collect()
+}
+
+/// 转码时与相邻歌曲的交叉淡化，见 [`crossfade_args`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Crossfade {
+    /// 下一首（代理路径），片尾与它的开头混音
+    pub next: Option<String>,
+    /// 开头已混进上一首的片尾，转码时跳过
+    pub skip_intro: bool,
+}
+
+/// 各歌曲的交叉淡化，键为代理路径；由会话在投屏前写入，媒体代理转码时读取
+pub type CrossfadeStore = Arc<Mutex<HashMap<String, Crossfade>>>;
+
+/// ffmpeg 的参数：从标准输入读取 [`concat_list`] 给出的分段列表，按顺序合并成一个分片 MP4
+///
+/// 各分段是独立的 FLV/MP4 文件，不能按字节首尾相接；concat 分离器逐个解封装并接上时间戳，
+/// 音视频原样复制，不重新编码。
+pub fn concat_args() -> Vec<String> {
+    [
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-f",
+        "concat",
+        // 列表中是本地代理的 HTTP 地址，不是本地文件
+        "-safe",
+        "0",
+        "-protocol_whitelist",
+        "pipe,http,tcp",
+        "-i",
+        "pipe:0",
+        "-c",
+        "copy",
+        "-movflags",
+        "frag_keyframe+empty_moov+default_base_moof",
+        "-f",
+        "mp4",
+        "pipe:1",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+/// concat 分离器的分段列表（ffconcat 格式），按 `inputs` 的顺序播放
+pub fn concat_list(inputs: &[String]) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for input in inputs {
+        list.push_str(&format!("file '{}'\n", input.replace('\'', "'\\''")));
+    }
+    list
+}
+
+/// 正在运行的 ffmpeg，每个代理路径最多一个
+///
+/// 渲染器常常先发一个探测请求、随后又重新请求，每个请求都启动一个 ffmpeg 会让多个转码抢 CPU；
+/// 同一路径的新请求到来时，旧请求的输出随即结束，其 ffmpeg 随之退出。可廉价 `clone`。
+#[derive(Debug, Clone, Default)]
+pub struct FfmpegJobs {
+    // 丢弃发送端即通知对应的输出结束
+    jobs: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+}
+
+impl FfmpegJobs {
+    /// 为 `path` 登记一个新的 ffmpeg，结束该路径上旧的一个；返回值传给 [`spawn`]
+    pub fn replace(&self, path: &str) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .jobs
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), tx)
+            .is_some()
+        {
+            log::info!("同一路径有新的请求，结束旧的 ffmpeg: {}", path);
+        }
+        rx
+    }
+}
+
+/// 以 `args` 启动 ffmpeg，`stdin` 不为 None 时写入其标准输入；返回其标准输出，
+/// 数据流被丢弃（渲染器断开）或 `stop` 被通知（见 [`FfmpegJobs`]）时 ffmpeg 随之结束
+pub fn spawn(
+    ffmpeg: &str,
+    args: Vec<String>,
+    stdin: Option<String>,
+    chunk_size: usize,
+    stop: oneshot::Receiver<()>,
+) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+    let mut child = Command::new(ffmpeg)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // 写完后关闭标准输入，ffmpeg 才知道列表结束
+        tokio::spawn(async move {
+            if let Err(e) = pipe.write_all(input.as_bytes()).await {
+                log::warn!("写入 ffmpeg 标准输入失败: {}", e);
+            }
+        });
+    }
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| std::io::Error::other("无法读取 ffmpeg 的输出"))?;
+    let stream = futures_util::stream::unfold(Some((child, stdout)), move |state| async move {
+        let (mut child, mut stdout) = state?;
+        let mut buf = vec![0; chunk_size];
+        match stdout.read(&mut buf).await {
+            Ok(0) => {
+                if let Ok(status) = child.wait().await
+                    && !status.success()
+                {
+                    log::warn!("ffmpeg 转码异常退出: {}", status);
+                }
+                None
+            }
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), Some((child, stdout))))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    Ok(stream.take_until(stop).boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_supports() {
+        let sink: Vec<String> = [
+            "http-get:*:video/mp4:DLNA.ORG_PN=AVC_MP4_HP_HD_AAC",
+            "http-get:*:audio/mpeg:*",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert!(sink_supports(&sink, "video/mp4", VideoCodec::Avc));
+        assert!(sink_supports(&sink, "audio/mpeg", VideoCodec::Unknown));
+        // 不支持 FLV 封装，也没有提到 HEVC
+        assert!(!sink_supports(&sink, "video/x-flv", VideoCodec::Avc));
+        assert!(!sink_supports(&sink, "video/mp4", VideoCodec::Hevc));
+
+        let hevc = vec!["http-get:*:video/mp4:DLNA.ORG_PN=HEVC_MP4_MAIN".to_string()];
+        assert!(sink_supports(&hevc, "video/mp4", VideoCodec::Hevc));
+        assert!(sink_supports(
+            &["http-get:*:*:*".to_string()],
+            "video/x-flv",
+            VideoCodec::Avc
+        ));
+        // 提到 HEVC 的是另一种封装，不能算作支持 MP4 封装的 HEVC
+        let mixed: Vec<String> = [
+            "http-get:*:video/mp4:DLNA.ORG_PN=AVC_MP4_HP_HD_AAC",
+            "http-get:*:video/mpeg:DLNA.ORG_PN=HEVC_TS_MAIN",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert!(!sink_supports(&mixed, "video/mp4", VideoCodec::Hevc));
+    }
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let args = ffmpeg_args("http://127.0.0.1:8080/BV1xx411c7mD");
+        assert_eq!(args[4], "http://127.0.0.1:8080/BV1xx411c7mD");
+        assert_eq!(args.last().unwrap(), "pipe:1");
+    }
+
+    #[test]
+    fn test_crossfade_args() {
+        let input = "http://127.0.0.1:8080/BV1xx411c7mD";
+        let next = "http://127.0.0.1:8080/BV1ab411c7mD";
+        // 既不跳过开头也没有下一首时与普通转码相同
+        assert_eq!(crossfade_args(input, 5, false, None), ffmpeg_args(input));
+
+        let args = crossfade_args(input, 5, true, Some(next));
+        let joined = args.join(" ");
+        assert!(joined.contains("-ss 5 -i http://127.0.0.1:8080/BV1xx411c7mD"));
+        assert!(joined.contains("-t 5 -i http://127.0.0.1:8080/BV1ab411c7mD"));
+        assert!(joined.contains("[0:a:0][1:a:0]acrossfade=d=5[a]"));
+        assert!(joined.contains("-map 0:v:0? -map [a]"));
+        assert_eq!(args.last().unwrap(), "pipe:1");
+    }
+
+    #[test]
+    fn test_ffmpeg_jobs_replace() {
+        let jobs = FfmpegJobs::default();
+        let mut first = jobs.replace("BV1xx411c7mD-h264");
+        let mut other = jobs.replace("BV1ab411c7mD-h264");
+        assert_eq!(first.try_recv(), Err(oneshot::error::TryRecvError::Empty));
+        // 同一路径的新请求结束旧的，其他路径不受影响
+        let _second = jobs.replace("BV1xx411c7mD-h264");
+        assert_eq!(first.try_recv(), Err(oneshot::error::TryRecvError::Closed));
+        assert_eq!(other.try_recv(), Err(oneshot::error::TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_concat_list() {
+        let list = concat_list(&[
+            "http://127.0.0.1:8080/BV1xx411c7mD-seg0".to_string(),
+            "http://127.0.0.1:8080/BV1xx411c7mD-seg1".to_string(),
+        ]);
+        assert_eq!(
+            list,
+            "ffconcat version 1.0\n\
+             file 'http://127.0.0.1:8080/BV1xx411c7mD-seg0'\n\
+             file 'http://127.0.0.1:8080/BV1xx411c7mD-seg1'\n"
+        );
+        assert_eq!(
+            concat_list(&["it's".to_string()]),
+            "ffconcat version 1.0\nfile 'it'\\''s'\n"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! 启动时的新版本检查（需在配置中开启）
+//!
+//! 查询 GitHub Releases 的最新版本，比当前版本新时通过
+//! [`SessionEvent::UpdateAvailable`](crate::session::SessionEvent::UpdateAvailable) 通知界面。
+
+use serde::Deserialize;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// 更新说明摘要最多保留的行数
+const SUMMARY_LINES: usize = 8;
+
+/// 一个可用的新版本
+#[derive(Debug, Clone, PartialEq)]
+pub struct Release {
+    /// 版本号，例如 `v0.2.0`
+    pub version: String,
+    /// 发布页面
+    pub url: String,
+    /// 更新说明的前几行
+    pub summary: String,
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    html_url: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+// "v1.2.3" / "1.2" -> [1, 2, 3]，无法解析的部分视为 0
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches(['v', 'V'])
+        .split(['.', '-', '+'])
+        .take(3)
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+fn is_newer(latest: &str, current: &str) -> bool {
+    parse_version(latest) > parse_version(current)
+}
+
+// 取更新说明中的前几行非空内容
+fn summarize(body: &str) -> String {
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(SUMMARY_LINES)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 查询 `repository`（如 `aspromise/ktv-casting`）的最新发布；比 `current_version` 新时返回该版本
+pub async fn check_latest(
+    repository: &str,
+    current_version: &str,
+) -> Result<Option<Release>, String> {
+    let url = format!(
+        "https://api.github.com/repos/{}/releases/latest",
+        repository
+    );
+    let release: GithubRelease = reqwest::Client::new()
+        .get(&url)
+        .header(
+            "User-Agent",
+            concat!("ktv-casting/", env!("CARGO_PKG_VERSION")),
+        )
+        .header("Accept", "application/vnd.github+json")
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("请求GitHub失败: {}", e))?
+        .error_for_status()
+        .map_err(|e| format!("GitHub返回错误: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("解析发布信息失败: {}", e))?;
+
+    if !is_newer(&release.tag_name, current_version) {
+        log::debug!("已是最新版本（最新发布 {}）", release.tag_name);
+        return Ok(None);
+    }
+    Ok(Some(Release {
+        version: release.tag_name,
+        url: release.html_url,
+        summary: summarize(release.body.as_deref().unwrap_or_default()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_comparison() {
+        assert!(is_newer("v0.2.0", "0.1.0"));
+        assert!(is_newer("v0.10.0", "0.9.3"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1", "0.1.0"));
+        assert_eq!(summarize("## 更新\n\n- 修复\n"), "## 更新\n- 修复");
+    }
+}
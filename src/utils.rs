@@ -1,7 +1,16 @@
 //! 通用工具函数
 
 /// 从B站URL中提取BV号与参数
+///
+/// 番剧链接 `bilibili.com/bangumi/play/ep123456` 提取为剧集 `ep123456`。
 pub fn extract_bv_id(url: &str) -> String {
+    if let Some(start) = url.find("/bangumi/play/ep") {
+        let after_prefix = &url[start + "/bangumi/play/".len()..];
+        let end = after_prefix[2..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(after_prefix.len(), |i| i + 2);
+        return after_prefix[..end].to_string();
+    }
     if let Some(start) = url.find("bilibili://video/") {
         let after_prefix = &url[start + "bilibili://video/".len()..];
         after_prefix.to_string().replace("?", "-").replace("=", "")
@@ -59,14 +68,20 @@ where
             }
             Err(e) => {
                 let error_msg = e.to_string();
-                
+
                 retries += 1;
                 if max_retries > 0 && retries > max_retries {
                     log::error!("{}失败，已达最大重试次数: {}", operation_name, error_msg);
                     return Err(format!("{}失败: {}", operation_name, error_msg));
                 }
 
-                log::warn!("{}失败: {}，{}ms后重试（第{}次）", operation_name, error_msg, delay_ms, retries);
+                log::warn!(
+                    "{}失败: {}，{}ms后重试（第{}次）",
+                    operation_name,
+                    error_msg,
+                    delay_ms,
+                    retries
+                );
                 tokio::time::sleep(tokio::time::Duration::from_millis(delay_ms)).await;
             }
         }
@@ -95,3 +110,71 @@ pub fn should_treat_as_upnp_success(error_msg: &str) -> bool {
     }
     false
 }
+
+/// 整理歌曲标题，供 DIDL-Lite、控制台和终端标题共用
+///
+/// - 还原已经转义过的 XML 实体（`&amp;` 等），避免之后再次转义成 `&amp;amp;`
+/// - 去掉控制字符（XML 1.0 不允许，写进终端还可能被当成转义序列）
+/// - 全角空格和连续空白合并为一个空格
+pub fn sanitize_title(title: &str) -> String {
+    let unescaped = title
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    unescaped
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 按终端显示宽度截断（中文、全角标点和 emoji 占两列），超出时以 `…` 结尾
+pub fn truncate_to_width(text: &str, max_width: usize) -> String {
+    use unicode_width::UnicodeWidthChar;
+
+    if unicode_width::UnicodeWidthStr::width(text) <= max_width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut width = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        // 预留一列给省略号
+        if width + w + 1 > max_width {
+            break;
+        }
+        out.push(c);
+        width += w;
+    }
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_bv_id() {
+        assert_eq!(
+            extract_bv_id("bilibili://video/BV1xx411c7mD?page=2"),
+            "BV1xx411c7mD-page2"
+        );
+        assert_eq!(
+            extract_bv_id("https://www.bilibili.com/bangumi/play/ep123456?from=search"),
+            "ep123456"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_and_truncate_title() {
+        assert_eq!(
+            sanitize_title("Tom &amp; Jerry\u{3000}主题曲\n\u{1b}[31m"),
+            "Tom & Jerry 主题曲 [31m"
+        );
+        assert_eq!(truncate_to_width("演唱会🎤现场版", 9), "演唱会🎤…");
+        assert_eq!(truncate_to_width("short", 9), "short");
+    }
+}
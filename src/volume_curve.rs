@@ -0,0 +1,235 @@
+//! 渲染器音量曲线校准
+//!
+//! 不同设备的音量刻度差别很大：回音壁上的 50% 可能震耳欲聋，电视上的 50% 却几乎听不见。
+//! 校准时用户按“阶梯”逐级调出自己觉得合适的响度，得到界面百分比到设备音量的分段线性曲线，
+//! 之后所有音量操作都经过这条曲线换算。曲线按设备 UDN 保存在本地文件中。
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CURVES_FILE: &str = "ktv-casting-volume.json";
+
+/// 校准阶梯：依次校准这些界面音量对应的设备音量
+pub const CALIBRATION_LADDER: [u32; 4] = [25, 50, 75, 100];
+
+/// 界面音量（0-100）到设备音量（0-100）的分段线性曲线
+///
+/// 曲线隐含经过 (0, 0)；最后一个点之后线性延伸到 (100, 100)。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VolumeCurve {
+    /// (界面音量, 设备音量)，按界面音量升序，设备音量不递减；为空表示不做换算
+    points: Vec<(u32, u32)>,
+}
+
+impl VolumeCurve {
+    /// 由校准得到的点构建曲线；点会被排序，设备音量被修正为单调不减
+    pub fn from_points(mut points: Vec<(u32, u32)>) -> Self {
+        points.retain(|&(ui, _)| ui > 0 && ui <= 100);
+        points.sort_by_key(|&(ui, _)| ui);
+        points.dedup_by_key(|&mut (ui, _)| ui);
+        let mut floor = 0;
+        for (_, device) in points.iter_mut() {
+            *device = (*device).clamp(floor, 100);
+            floor = *device;
+        }
+        Self { points }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    // 含两端隐含点的完整折线
+    fn segments(&self) -> Vec<(u32, u32)> {
+        let mut all = vec![(0, 0)];
+        all.extend(&self.points);
+        if all.last().is_some_and(|&(ui, _)| ui < 100) {
+            let floor = all.last().unwrap().1;
+            all.push((100, floor.max(100)));
+        }
+        all
+    }
+
+    /// 界面音量 → 设备音量
+    pub fn to_device(&self, ui: u32) -> u32 {
+        let ui = ui.min(100);
+        if self.is_identity() {
+            return ui;
+        }
+        let segments = self.segments();
+        for pair in segments.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            if ui <= x1 {
+                return interpolate(ui, x0, y0, x1, y1);
+            }
+        }
+        segments.last().map(|&(_, y)| y).unwrap_or(ui)
+    }
+
+    /// 设备音量 → 界面音量（用于显示渲染器当前音量）
+    pub fn to_ui(&self, device: u32) -> u32 {
+        let device = device.min(100);
+        if self.is_identity() {
+            return device;
+        }
+        let segments = self.segments();
+        for pair in segments.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            if device <= y1 {
+                if y0 == y1 {
+                    return x0;
+                }
+                return interpolate(device, y0, x0, y1, x1);
+            }
+        }
+        100
+    }
+}
+
+fn interpolate(v: u32, x0: u32, y0: u32, x1: u32, y1: u32) -> u32 {
+    if x1 == x0 {
+        return y1;
+    }
+    let t = (v - x0) as f64 / (x1 - x0) as f64;
+    (y0 as f64 + t * (y1 as f64 - y0 as f64)).round() as u32
+}
+
+/// 一次逐级校准：对阶梯中的每个界面音量，用户试听并确认一个设备音量
+#[derive(Debug, Clone)]
+pub struct VolumeCalibration {
+    step: usize,
+    device_volume: u32,
+    points: Vec<(u32, u32)>,
+}
+
+impl VolumeCalibration {
+    /// 从已有曲线出发开始校准，每一级的初始试听音量取自该曲线
+    pub fn start(current: &VolumeCurve) -> Self {
+        Self {
+            step: 0,
+            device_volume: current.to_device(CALIBRATION_LADDER[0]),
+            points: Vec::new(),
+        }
+    }
+
+    /// 当前正在校准的界面音量
+    pub fn target(&self) -> u32 {
+        CALIBRATION_LADDER[self.step]
+    }
+
+    /// 当前正在试听的设备音量
+    pub fn device_volume(&self) -> u32 {
+        self.device_volume
+    }
+
+    /// (当前第几级, 总级数)，从 1 开始
+    pub fn progress(&self) -> (usize, usize) {
+        (self.step + 1, CALIBRATION_LADDER.len())
+    }
+
+    /// 调整试听音量，不会低于上一级已确认的音量
+    pub fn set_device_volume(&mut self, volume: u32) {
+        let floor = self.points.last().map(|&(_, v)| v).unwrap_or(0);
+        self.device_volume = volume.clamp(floor, 100);
+    }
+
+    /// 确认当前级；全部完成时返回校准得到的曲线
+    pub fn confirm(&mut self) -> Option<VolumeCurve> {
+        self.points.push((self.target(), self.device_volume));
+        self.step += 1;
+        if self.step == CALIBRATION_LADDER.len() {
+            return Some(VolumeCurve::from_points(self.points.clone()));
+        }
+        // 下一级从按当前比例估算的音量开始试听
+        let estimate = self.device_volume * self.target() / CALIBRATION_LADDER[self.step - 1];
+        self.set_device_volume(estimate);
+        None
+    }
+}
+
+/// 按设备 UDN 保存的音量曲线
+#[derive(Debug, Default)]
+pub struct VolumeCurveStore {
+    path: PathBuf,
+    curves: HashMap<String, VolumeCurve>,
+}
+
+impl VolumeCurveStore {
+    /// 默认的曲线文件：当前目录下的 `ktv-casting-volume.json`
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(DEFAULT_CURVES_FILE)
+    }
+
+    /// 读取默认位置的曲线；文件不存在时为空
+    pub fn load_default() -> Result<Self> {
+        Self::load(&Self::default_path())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let curves = if path.exists() {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("无法读取音量曲线文件 {}", path.display()))?;
+            serde_json::from_str(&text)
+                .with_context(|| format!("音量曲线文件格式错误 {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            curves,
+        })
+    }
+
+    /// 某个设备的曲线，未校准过时为恒等曲线
+    pub fn get(&self, udn: &str) -> VolumeCurve {
+        self.curves.get(udn).cloned().unwrap_or_default()
+    }
+
+    /// 保存某个设备的曲线并写回文件
+    pub fn save(&mut self, udn: &str, curve: VolumeCurve) -> Result<()> {
+        self.curves.insert(udn.to_string(), curve);
+        let text = serde_json::to_string_pretty(&self.curves)?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("无法写入音量曲线文件 {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curve_maps_both_ways() {
+        let curve = VolumeCurve::from_points(vec![(50, 20), (100, 60)]);
+        assert_eq!(curve.to_device(0), 0);
+        assert_eq!(curve.to_device(25), 10);
+        assert_eq!(curve.to_device(50), 20);
+        assert_eq!(curve.to_device(75), 40);
+        assert_eq!(curve.to_ui(40), 75);
+        assert_eq!(curve.to_ui(80), 100);
+        assert_eq!(VolumeCurve::default().to_device(37), 37);
+    }
+
+    #[test]
+    fn test_calibration_ladder() {
+        let mut calibration = VolumeCalibration::start(&VolumeCurve::default());
+        assert_eq!(calibration.target(), 25);
+        calibration.set_device_volume(8);
+        assert!(calibration.confirm().is_none());
+        // 下一级按比例估算：8 * 50 / 25
+        assert_eq!(calibration.device_volume(), 16);
+        // 不能低于上一级
+        calibration.set_device_volume(3);
+        assert_eq!(calibration.device_volume(), 8);
+        calibration.set_device_volume(15);
+        assert!(calibration.confirm().is_none());
+        calibration.set_device_volume(25);
+        assert!(calibration.confirm().is_none());
+        calibration.set_device_volume(40);
+        let curve = calibration.confirm().unwrap();
+        assert_eq!(curve.to_device(50), 15);
+        assert_eq!(curve.to_device(100), 40);
+    }
+}